@@ -0,0 +1,115 @@
+//! How long a multi-megabyte response holds up the other tasks on its worker thread, with
+//! parsing and decryption on the worker (`offload_threshold: None`) and on the blocking pool
+//! (the default).
+//!
+//! ```sh
+//! cargo bench --bench executor_stall
+//! ```
+//!
+//! A single-worker runtime runs a task ticking every millisecond next to a loop of fetches;
+//! the ticker's lateness is the time the worker spent on something else.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use ho_api_rust_sdk::crypto::Crypto;
+use ho_api_rust_sdk::response::Timings;
+use ho_api_rust_sdk::transport::{Transport, TransportRequest, TransportResponse};
+use ho_api_rust_sdk::{ApiClient, ApiClientConfig, ApiClientError};
+use reqwest::{Method, StatusCode};
+use tokio::time::Instant;
+
+const SECRET: &str = "0123456789abcdef0123456789abcdef";
+const IV: &str = "fedcba9876543210";
+const FETCHES: usize = 20;
+const TICK: Duration = Duration::from_millis(1);
+
+/// Answers every request with the same encrypted envelope after a millisecond's round trip.
+struct Canned(Bytes);
+
+impl Transport for Canned {
+    fn execute(&self, _: TransportRequest) -> BoxFuture<'_, Result<TransportResponse, ApiClientError>> {
+        Box::pin(async move {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            Ok(TransportResponse {
+                status: StatusCode::OK,
+                headers: Default::default(),
+                body: self.0.clone(),
+                timings: Timings::default(),
+            })
+        })
+    }
+}
+
+/// The ticker's lateness while `FETCHES` fetches run, sorted.
+async fn lateness(client: ApiClient) -> Vec<Duration> {
+    let done = Arc::new(AtomicBool::new(false));
+    let ticker = {
+        let done = done.clone();
+        tokio::spawn(async move {
+            let mut late = Vec::new();
+            while !done.load(Ordering::Relaxed) {
+                let due = Instant::now() + TICK;
+                tokio::time::sleep_until(due).await;
+                late.push(Instant::now() - due);
+            }
+            late
+        })
+    };
+    for _ in 0..FETCHES {
+        client.send(Method::GET, "/v1/catalog", None).await.unwrap();
+    }
+    done.store(true, Ordering::Relaxed);
+    let mut late = ticker.await.unwrap();
+    late.sort();
+    late
+}
+
+fn main() {
+    let skins: Vec<_> = (0..60_000)
+        .map(|id| {
+            format!(
+                r#"{{"id":{},"name":"安妮 皮肤 {}","price":{{"amount":{}.5}}}}"#,
+                id,
+                id,
+                id * 10
+            )
+        })
+        .collect();
+    let plaintext = format!("[{}]", skins.join(","));
+    let ciphertext = Crypto::new(SECRET, IV).unwrap().encrypt_hex(plaintext.as_bytes());
+    let envelope = Bytes::from(format!(r#"{{"data":"{}"}}"#, ciphertext));
+    println!("{} byte envelope, {} fetches", envelope.len(), FETCHES);
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .unwrap();
+    for (name, offload_threshold) in [
+        ("inline", None),
+        ("offload", ApiClientConfig::default().offload_threshold),
+    ] {
+        let config = ApiClientConfig {
+            app_id: "bench-app-id".to_string(),
+            app_secret: SECRET.to_string(),
+            iv: IV.to_string(),
+            offload_threshold,
+            ..Default::default()
+        };
+        let client = ApiClient::with_transport(config, Arc::new(Canned(envelope.clone()))).unwrap();
+        // Spawned, since `block_on` would run the fetches on this thread rather than the worker.
+        let late = runtime.block_on(runtime.spawn(lateness(client))).unwrap();
+        let p99 = late[late.len() * 99 / 100];
+        println!(
+            "{:<8} {:>5} ticks  p99 {:>10.2?} late  max {:>10.2?} late",
+            name,
+            late.len(),
+            p99,
+            late.last().unwrap()
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Time and heap allocations per request for signing and decrypting a representative
+//! payload, comparing the SDK against the straightforward implementation it replaced.
+//!
+//! ```sh
+//! cargo bench --bench hot_path
+//! ```
+//!
+//! A plain `main` rather than a bench framework: allocations are counted exactly by a
+//! wrapping global allocator, which needs the process to itself.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use aes::Aes256;
+use block_modes::{block_padding::Pkcs7, BlockMode, Cbc};
+use ho_api_rust_sdk::crypto::Crypto;
+use ho_api_rust_sdk::signing::{sign, SignableBody};
+use sha1::{Digest, Sha1};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const APP_ID: &str = "bench-app-id";
+const SECRET: &str = "0123456789abcdef0123456789abcdef";
+const IV: &str = "fedcba9876543210";
+const NONCE: &str = "3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a13";
+const TIMESTAMP: i64 = 1717171717171;
+const URI: &str = "/v1/lol/champion/skin?region=cn&page=1";
+const ITERATIONS: u32 = 20_000;
+
+/// Signing and decryption as written before the allocation work: the sign string is
+/// concatenated, and ciphertext is decrypted into a second buffer.
+fn before(crypto: &Cbc<Aes256, Pkcs7>, body: &str, ciphertext: &str) -> (String, String) {
+    let sign_string = format!("{}{}{}{}{}{}", APP_ID, NONCE, TIMESTAMP, URI, body, SECRET);
+    let signature = format!("{:x}", Sha1::digest(sign_string.as_bytes()));
+    let decoded = hex::decode(ciphertext).unwrap();
+    let plaintext = String::from_utf8(crypto.clone().decrypt_vec(&decoded).unwrap()).unwrap();
+    (signature, plaintext)
+}
+
+fn after(crypto: &Crypto, body: &str, ciphertext: &str) -> (String, String) {
+    let signature = sign(APP_ID, SECRET, NONCE, TIMESTAMP, URI, SignableBody::Text(body));
+    (signature, crypto.decrypt_hex(ciphertext.as_bytes()).unwrap())
+}
+
+fn measure(name: &str, mut run: impl FnMut() -> (String, String)) -> (Duration, usize) {
+    black_box(run());
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(run());
+    }
+    let per_iteration = started.elapsed() / ITERATIONS;
+    let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / ITERATIONS as usize;
+    println!(
+        "{:<8} {:>10.2?}/iter {:>4} allocations/iter",
+        name, per_iteration, allocations
+    );
+    (per_iteration, allocations)
+}
+
+fn main() {
+    let skins: Vec<_> = (0..24)
+        .map(|id| {
+            format!(
+                r#"{{"id":{},"name":"安妮 皮肤 {}","price":{{"amount":{}.5}}}}"#,
+                id,
+                id,
+                id * 10
+            )
+        })
+        .collect();
+    let plaintext = format!("[{}]", skins.join(","));
+    let body = r#"{"champion_id":1,"region":"cn","page":1}"#;
+    let crypto = Crypto::new(SECRET, IV).unwrap();
+    let ciphertext = crypto.encrypt_hex(plaintext.as_bytes());
+    let cipher = Cbc::<Aes256, Pkcs7>::new_from_slices(SECRET.as_bytes(), IV.as_bytes()).unwrap();
+    println!("{} byte body, {} byte plaintext", body.len(), plaintext.len());
+
+    let expected = before(&cipher, body, &ciphertext);
+    assert_eq!(
+        after(&crypto, body, &ciphertext),
+        expected,
+        "outputs must stay byte-identical"
+    );
+    let (_, before_allocations) = measure("before", || before(&cipher, body, &ciphertext));
+    let (_, after_allocations) = measure("after", || after(&crypto, body, &ciphertext));
+    assert!(after_allocations < before_allocations);
+}
@@ -0,0 +1,40 @@
+#!/usr/bin/env sh
+# Builds, lints and tests every documented feature combination so feature gating doesn't rot.
+# Uses `cargo hack --each-feature` as well when it is installed.
+set -eu
+
+cd "$(dirname "$0")/.."
+
+check() {
+    echo "==> features: ${1:-<none>}"
+    cargo clippy --no-default-features --features "$1" --all-targets -- -D warnings
+    cargo test --no-default-features --features "$1"
+}
+
+check ""
+check "client"
+check "rustls-tls"
+check "native-tls"
+check "uuid-nonce"
+check "fastrand-nonce"
+check "rustls-tls,fastrand-nonce"
+check "native-tls,uuid-nonce"
+check "test-util"
+check "uuid-nonce,rustls-tls"
+check "disk-cache"
+check "charset"
+check "global-client"
+check "export"
+check "archive"
+check "gzip"
+check "cli"
+check "client,cli"
+check "rustls-tls,charset"
+
+echo "==> default features"
+cargo clippy --all-targets -- -D warnings
+cargo test
+
+if cargo hack --version >/dev/null 2>&1; then
+    cargo hack --each-feature --no-dev-deps check
+fi
@@ -0,0 +1,194 @@
+//! `HoApi`, the requests of `ApiClient` as a trait, so code can take `impl HoApi` and be
+//! handed an `ApiClient` in production and `testing::FakeApi`, or a fake of its own, in
+//! tests. The typed endpoints are reached through `HoApi::endpoints` and work on either.
+//!
+//! The methods return boxed futures, as `Transport` does, so the trait is object safe.
+//! `send_as` and `endpoints` need a sized implementation; `Box<dyn HoApi>` and
+//! `Arc<dyn HoApi>` are one, as is `&A` for any implementation `A`.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::endpoints::Endpoints;
+use crate::response::ApiResponse;
+use crate::{ApiClient, ApiClientError};
+
+pub trait HoApi: Send + Sync {
+    /// As `ApiClient::send_detailed`.
+    fn send_detailed<'a>(
+        &'a self,
+        method: Method,
+        uri: &'a str,
+        body: Option<Value>,
+    ) -> BoxFuture<'a, Result<ApiResponse, ApiClientError>>;
+
+    /// As `ApiClient::send`: the plaintext of the response.
+    fn send<'a>(
+        &'a self,
+        method: Method,
+        uri: &'a str,
+        body: Option<Value>,
+    ) -> BoxFuture<'a, Result<String, ApiClientError>> {
+        Box::pin(async move { Ok(self.send_detailed(method, uri, body).await?.body) })
+    }
+
+    /// As `ApiClient::send_as`: the plaintext deserialized as `T`.
+    fn send_as<'a, T: DeserializeOwned + Send + 'static>(
+        &'a self,
+        method: Method,
+        uri: &'a str,
+        body: Option<Value>,
+    ) -> BoxFuture<'a, Result<T, ApiClientError>>
+    where
+        Self: Sized,
+    {
+        Box::pin(async move { Ok(serde_json::from_str(&self.send(method, uri, body).await?)?) })
+    }
+
+    /// The time endpoints such as `current_rotation` go by; `ApiClient` uses
+    /// `ApiClientConfig::time`.
+    fn now_utc(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn endpoints(&self) -> Endpoints<'_, Self>
+    where
+        Self: Sized,
+    {
+        Endpoints::new(self)
+    }
+}
+
+impl HoApi for ApiClient {
+    fn send_detailed<'a>(
+        &'a self,
+        method: Method,
+        uri: &'a str,
+        body: Option<Value>,
+    ) -> BoxFuture<'a, Result<ApiResponse, ApiClientError>> {
+        Box::pin(ApiClient::send_detailed(self, method, uri, body))
+    }
+
+    fn send<'a>(
+        &'a self,
+        method: Method,
+        uri: &'a str,
+        body: Option<Value>,
+    ) -> BoxFuture<'a, Result<String, ApiClientError>> {
+        Box::pin(ApiClient::send(self, method, uri, body))
+    }
+
+    fn send_as<'a, T: DeserializeOwned + Send + 'static>(
+        &'a self,
+        method: Method,
+        uri: &'a str,
+        body: Option<Value>,
+    ) -> BoxFuture<'a, Result<T, ApiClientError>> {
+        Box::pin(ApiClient::send_as(self, method, uri, body))
+    }
+
+    fn now_utc(&self) -> DateTime<Utc> {
+        self.config.time.now_utc()
+    }
+}
+
+/// Implements `HoApi` for a pointer type by forwarding to what it points to.
+macro_rules! forward_ho_api {
+    ($($pointer:ty),*) => {$(
+        impl<A: HoApi + ?Sized> HoApi for $pointer {
+            fn send_detailed<'a>(
+                &'a self,
+                method: Method,
+                uri: &'a str,
+                body: Option<Value>,
+            ) -> BoxFuture<'a, Result<ApiResponse, ApiClientError>> {
+                (**self).send_detailed(method, uri, body)
+            }
+
+            fn send<'a>(
+                &'a self,
+                method: Method,
+                uri: &'a str,
+                body: Option<Value>,
+            ) -> BoxFuture<'a, Result<String, ApiClientError>> {
+                (**self).send(method, uri, body)
+            }
+
+            fn now_utc(&self) -> DateTime<Utc> {
+                (**self).now_utc()
+            }
+        }
+    )*};
+}
+
+forward_ho_api!(&A, Box<A>, Arc<A>);
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::endpoints::{OrderRequest, OrderResult, DUPLICATE_ORDER_CODE, ORDER_URI};
+    use crate::testing::{mock_client, FakeApi, MockResponse, MockTransport};
+    use crate::types::{ItemId, Money};
+
+    const ROTATION: &str =
+        r#"[{"champion_id":"1","start_time":"2024-06-01 00:00:00","end_time":"2024-06-08 00:00:00"}]"#;
+
+    /// Downstream code, generic over the client.
+    async fn rotation_and_order(api: impl HoApi) -> (usize, OrderResult) {
+        let rotation = api.endpoints().champion_rotation().await.unwrap();
+        let order = OrderRequest::builder()
+            .out_trade_no("T1")
+            .user_id("u-1")
+            .item(ItemId(3070))
+            .amount(Money::from_fen(2500))
+            .build()
+            .unwrap();
+        (
+            rotation.entries.len(),
+            api.endpoints().create_order(&order).await.unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_client_and_fake_behind_the_trait() {
+        let duplicate = json!({ "code": DUPLICATE_ORDER_CODE, "order_id": "O-9" });
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::GET,
+                "/v1/lol/champion/rotation",
+                MockResponse::encrypted(ROTATION),
+            )
+            .on(
+                Method::POST,
+                ORDER_URI,
+                MockResponse::json(reqwest::StatusCode::CONFLICT, &duplicate),
+            );
+        let client = mock_client(&transport, |_| {});
+        let fake = FakeApi::new();
+        fake.on(Method::GET, "/v1/lol/champion/rotation", ROTATION).on_status(
+            Method::POST,
+            ORDER_URI,
+            reqwest::StatusCode::CONFLICT,
+            duplicate.to_string(),
+        );
+
+        let expected = OrderResult::DuplicateOrder {
+            order_id: Some("O-9".to_string()),
+        };
+        assert_eq!(rotation_and_order(&client).await, (1, expected.clone()));
+        assert_eq!(rotation_and_order(&fake).await, (1, expected.clone()));
+        let boxed: Vec<Arc<dyn HoApi>> = vec![Arc::new(client), Arc::new(fake)];
+        for api in boxed {
+            assert_eq!(rotation_and_order(api).await, (1, expected.clone()));
+        }
+        assert_eq!(transport.requests().len(), 4);
+    }
+}
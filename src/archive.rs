@@ -0,0 +1,450 @@
+//! Records from endpoints that answer with an encrypted ZIP archive of NDJSON files, such as
+//! the match-history export.
+//!
+//! The envelope is decrypted whole, since its ciphertext only ends with the response. The
+//! entries are then read in archive order, each inflated and split into lines as the stream
+//! is polled, so no entry is held in memory uncompressed. Entries whose name does not end in
+//! one of `ArchiveOptions::extensions` and entries that fail to inflate, fail their CRC or use
+//! an unsupported compression method are reported with `ApiClientError::InvalidArchive`
+//! naming them, as are records that do not deserialize; directories are skipped.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
+
+use bytes::{Buf, Bytes};
+use flate2::read::DeflateDecoder;
+use flate2::CrcReader;
+use futures_util::stream::{self, Stream};
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::envelope;
+use crate::{ApiClient, ApiClientError};
+
+/// The name of an entry, as stored in the archive.
+pub type EntryName = String;
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+
+#[derive(Debug, Clone)]
+pub struct ArchiveOptions {
+    /// End the stream after the first error instead of going on with the next record or
+    /// entry.
+    pub fail_fast: bool,
+    /// Name endings of the entries holding records. `.ndjson` and `.jsonl` by default.
+    pub extensions: Vec<String>,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        Self {
+            fail_fast: false,
+            extensions: vec![".ndjson".to_string(), ".jsonl".to_string()],
+        }
+    }
+}
+
+impl ApiClient {
+    /// Downloads the archive at `uri`, with a POST carrying `body` or a GET without one, and
+    /// yields every record of its entries with the name of its entry. Errors do not end the
+    /// stream, except those about the archive as a whole.
+    pub fn download_archive<T: DeserializeOwned>(
+        &self,
+        uri: &str,
+        body: Option<Value>,
+    ) -> impl Stream<Item = Result<(EntryName, T), ApiClientError>> + '_ {
+        self.download_archive_with(uri, body, ArchiveOptions::default())
+    }
+
+    pub fn download_archive_with<T: DeserializeOwned>(
+        &self,
+        uri: &str,
+        body: Option<Value>,
+        options: ArchiveOptions,
+    ) -> impl Stream<Item = Result<(EntryName, T), ApiClientError>> + '_ {
+        let state = State {
+            client: self,
+            stage: Stage::Pending(uri.to_string(), body),
+            options,
+            failed: false,
+        };
+        stream::unfold(state, |mut state| async move {
+            if state.failed && state.options.fail_fast {
+                return None;
+            }
+            let item = state.next::<T>().await?;
+            state.failed |= item.is_err();
+            Some((item, state))
+        })
+    }
+
+    /// The decrypted archive at `uri`.
+    async fn fetch_archive(&self, uri: &str, body: Option<Value>) -> Result<Bytes, ApiClientError> {
+        let method = match body {
+            Some(_) => Method::POST,
+            None => Method::GET,
+        };
+        let mut request = self.request(method, uri).parse_envelope(false);
+        if let Some(body) = body {
+            request = request.body(body);
+        }
+        let response = request.send_detailed().await?;
+        let api_result = envelope::parse(response.body.as_bytes())?;
+        let (archive, _) = self
+            .decrypt_with_fallback(|crypto| envelope::open_parsed_bytes(crypto, &response.meta.headers, &api_result))?;
+        Ok(Bytes::from(archive))
+    }
+}
+
+struct State<'a> {
+    client: &'a ApiClient,
+    stage: Stage,
+    options: ArchiveOptions,
+    failed: bool,
+}
+
+enum Stage {
+    Pending(String, Option<Value>),
+    Reading {
+        archive: Bytes,
+        entries: VecDeque<Entry>,
+        current: Option<EntryReader>,
+    },
+    Done,
+}
+
+impl State<'_> {
+    async fn next<T: DeserializeOwned>(&mut self) -> Option<Result<(EntryName, T), ApiClientError>> {
+        loop {
+            match &mut self.stage {
+                Stage::Pending(uri, body) => {
+                    let (uri, body) = (std::mem::take(uri), body.take());
+                    let opened = self
+                        .client
+                        .fetch_archive(&uri, body)
+                        .await
+                        .and_then(|archive| Ok((central_directory(&archive)?, archive)));
+                    match opened {
+                        Ok((entries, archive)) => {
+                            self.stage = Stage::Reading {
+                                archive,
+                                entries: entries.into(),
+                                current: None,
+                            }
+                        }
+                        Err(e) => {
+                            self.stage = Stage::Done;
+                            return Some(Err(e));
+                        }
+                    }
+                }
+                Stage::Reading {
+                    current: current @ Some(_),
+                    ..
+                } => {
+                    let reader = current.as_mut().expect("matched `Some`");
+                    match reader.next_record() {
+                        Some(Ok((line, record))) => {
+                            let name = reader.name.clone();
+                            let record = serde_json::from_slice(&record).map_err(|e| invalid(&name, Some(line), e));
+                            return Some(record.map(|record| (name, record)));
+                        }
+                        // The rest of a corrupt entry cannot be trusted.
+                        Some(Err(e)) => {
+                            *current = None;
+                            return Some(Err(e));
+                        }
+                        None => *current = None,
+                    }
+                }
+                Stage::Reading {
+                    archive,
+                    entries,
+                    current,
+                } => {
+                    let entry = entries.pop_front()?;
+                    if entry.name.ends_with('/') {
+                        continue;
+                    }
+                    if !self
+                        .options
+                        .extensions
+                        .iter()
+                        .any(|extension| entry.name.ends_with(extension.as_str()))
+                    {
+                        return Some(Err(invalid(&entry.name, None, "unexpected extension")));
+                    }
+                    match EntryReader::open(archive, entry) {
+                        Ok(reader) => *current = Some(reader),
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+                Stage::Done => return None,
+            }
+        }
+    }
+}
+
+/// An entry as listed in the central directory.
+struct Entry {
+    name: EntryName,
+    method: u16,
+    crc32: u32,
+    compressed_size: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Lists the entries of `archive` from its central directory.
+fn central_directory(archive: &[u8]) -> Result<Vec<Entry>, ApiClientError> {
+    let corrupt = |reason: &str| ApiClientError::InvalidArchive {
+        entry: None,
+        line: None,
+        reason: reason.to_string(),
+    };
+    // The end record is 22 bytes followed by a comment of up to 64 KiB.
+    let end = (0..=archive.len().saturating_sub(22))
+        .rev()
+        .take(22 + u16::MAX as usize)
+        .find(|&at| read_u32(archive, at) == Some(END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| corrupt("no end of central directory record"))?;
+    let count = read_u16(archive, end + 10).ok_or_else(|| corrupt("truncated end record"))?;
+    let start = read_u32(archive, end + 16).ok_or_else(|| corrupt("truncated end record"))?;
+    if count == u16::MAX || start == u32::MAX {
+        return Err(corrupt("ZIP64 archives are not supported"));
+    }
+
+    let mut entries = Vec::with_capacity(count as usize);
+    let mut at = start as usize;
+    for _ in 0..count {
+        if read_u32(archive, at) != Some(CENTRAL_DIRECTORY_HEADER) {
+            return Err(corrupt("bad central directory header"));
+        }
+        let field16 =
+            |offset: usize| read_u16(archive, at + offset).ok_or_else(|| corrupt("truncated central directory"));
+        let field32 =
+            |offset: usize| read_u32(archive, at + offset).ok_or_else(|| corrupt("truncated central directory"));
+        let (name_len, extra_len, comment_len) = (field16(28)? as usize, field16(30)? as usize, field16(32)? as usize);
+        let name = archive
+            .get(at + 46..at + 46 + name_len)
+            .ok_or_else(|| corrupt("truncated central directory"))?;
+        entries.push(Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: field16(10)?,
+            crc32: field32(16)?,
+            compressed_size: field32(20)?,
+            size: field32(24)?,
+            offset: field32(42)?,
+        });
+        at += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+/// The records of one entry, inflated line by line.
+struct EntryReader {
+    name: EntryName,
+    lines: BufReader<CrcReader<Box<dyn Read + Send>>>,
+    line: usize,
+    crc32: u32,
+    size: u32,
+}
+
+impl EntryReader {
+    fn open(archive: &Bytes, entry: Entry) -> Result<Self, ApiClientError> {
+        let corrupt = |reason: &str| invalid(&entry.name, None, reason);
+        let at = entry.offset as usize;
+        if read_u32(archive, at) != Some(LOCAL_FILE_HEADER) {
+            return Err(corrupt("bad local file header"));
+        }
+        let name_len = read_u16(archive, at + 26).ok_or_else(|| corrupt("truncated local file header"))? as usize;
+        let extra_len = read_u16(archive, at + 28).ok_or_else(|| corrupt("truncated local file header"))? as usize;
+        let start = at + 30 + name_len + extra_len;
+        let end = start + entry.compressed_size as usize;
+        if end > archive.len() || entry.compressed_size == u32::MAX {
+            return Err(corrupt("data runs past the end of the archive"));
+        }
+        let data = archive.slice(start..end).reader();
+        let inflated: Box<dyn Read + Send> = match entry.method {
+            0 => Box::new(data),
+            8 => Box::new(DeflateDecoder::new(data)),
+            method => return Err(corrupt(&format!("unsupported compression method {}", method))),
+        };
+        Ok(Self {
+            name: entry.name,
+            lines: BufReader::new(CrcReader::new(inflated)),
+            line: 0,
+            crc32: entry.crc32,
+            size: entry.size,
+        })
+    }
+
+    /// The next non-blank line with its number, an error once the entry turns out corrupt,
+    /// or `None` at its end.
+    fn next_record(&mut self) -> Option<Result<(usize, Vec<u8>), ApiClientError>> {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match self.lines.read_until(b'\n', &mut line) {
+                Ok(0) => {
+                    let crc = self.lines.get_ref().crc();
+                    return match (crc.sum(), crc.amount()) {
+                        (sum, amount) if sum == self.crc32 && amount == self.size => None,
+                        _ => Some(Err(invalid(&self.name, None, "CRC mismatch"))),
+                    };
+                }
+                Ok(_) => {
+                    self.line += 1;
+                    if !line.iter().all(u8::is_ascii_whitespace) {
+                        return Some(Ok((self.line, line)));
+                    }
+                }
+                Err(e) => return Some(Err(invalid(&self.name, None, e))),
+            }
+        }
+    }
+}
+
+fn invalid(entry: &str, line: Option<usize>, reason: impl ToString) -> ApiClientError {
+    ApiClientError::InvalidArchive {
+        entry: Some(entry.to_string()),
+        line,
+        reason: reason.to_string(),
+    }
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{mock_client, test_crypto, MockResponse, MockTransport};
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct MatchRecord {
+        match_id: u64,
+        champion: String,
+        win: bool,
+    }
+
+    fn archive_response() -> MockResponse {
+        let archive = std::fs::read(format!("{}/fixtures/match_history.zip", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let envelope = json!({ "data": test_crypto().encrypt_hex(&archive) });
+        MockResponse::raw(reqwest::StatusCode::OK, envelope.to_string())
+    }
+
+    /// Records as `(entry, match_id)` and errors as `(entry, reason)`.
+    fn summarize(items: Vec<Result<(EntryName, MatchRecord), ApiClientError>>) -> Vec<Result<(String, u64), String>> {
+        items
+            .into_iter()
+            .map(|item| match item {
+                Ok((entry, record)) => Ok((entry, record.match_id)),
+                Err(ApiClientError::InvalidArchive { entry, reason, .. }) => {
+                    Err(format!("{}: {}", entry.unwrap_or_default(), reason))
+                }
+                Err(other) => panic!("unexpected {:?}", other),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_records_of_every_good_entry_and_named_errors() {
+        let transport = MockTransport::new();
+        transport.on(Method::POST, "/v1/match/history/export", archive_response());
+        let client = mock_client(&transport, |_| {});
+
+        let items: Vec<_> = client
+            .download_archive::<MatchRecord>("/v1/match/history/export", Some(json!({ "region": "cn" })))
+            .collect()
+            .await;
+        let summary = summarize(items);
+        let may = |id| Ok(("matches/2024-05.ndjson".to_string(), id));
+        let june = |id| Ok(("matches/2024-06.ndjson".to_string(), id));
+        assert_eq!(
+            summary[..7],
+            [
+                may(1000),
+                may(1001),
+                may(1002),
+                june(2000),
+                june(2001),
+                june(2002),
+                june(2003)
+            ]
+        );
+        match &summary[7..] {
+            [Err(corrupt), Err(readme)] => {
+                assert!(corrupt.starts_with("matches/2024-07.ndjson: "), "{}", corrupt);
+                assert_eq!(readme, "README.txt: unexpected extension");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fail_fast_stops_at_the_first_error() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/match/history/export", archive_response());
+        let client = mock_client(&transport, |_| {});
+
+        let options = ArchiveOptions {
+            fail_fast: true,
+            ..Default::default()
+        };
+        let items: Vec<_> = client
+            .download_archive_with::<MatchRecord>("/v1/match/history/export", None, options)
+            .collect()
+            .await;
+        let summary = summarize(items);
+        assert_eq!(summary.len(), 8);
+        assert!(summary[7]
+            .as_ref()
+            .is_err_and(|e| e.starts_with("matches/2024-07.ndjson")));
+    }
+
+    #[tokio::test]
+    async fn test_bad_records_and_archives_are_reported() {
+        #[derive(Debug, Deserialize)]
+        struct Strict {
+            #[allow(dead_code)]
+            match_id: String,
+        }
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/export", archive_response()).on(
+            Method::GET,
+            "/v1/not-an-archive",
+            MockResponse::encrypted("plain text"),
+        );
+        let client = mock_client(&transport, |_| {});
+
+        let mut records = std::pin::pin!(client.download_archive::<Strict>("/v1/export", None));
+        match records.next().await {
+            Some(Err(ApiClientError::InvalidArchive { entry, line, .. })) => {
+                assert_eq!((entry.as_deref(), line), (Some("matches/2024-05.ndjson"), Some(1)));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let items: Vec<_> = client
+            .download_archive::<MatchRecord>("/v1/not-an-archive", None)
+            .collect()
+            .await;
+        match items.as_slice() {
+            [Err(ApiClientError::InvalidArchive { entry: None, .. })] => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
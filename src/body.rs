@@ -0,0 +1,353 @@
+//! Request bodies, serialized once per request. The JSON text is signed as is and sent inside
+//! the `{"data": "<json text>"}` wire envelope; both buffers are shared by every retry and
+//! failover attempt.
+
+use std::io;
+
+use bytes::Bytes;
+use serde::de::IgnoredAny;
+use serde_json::Value;
+
+use crate::signing::SignableBody;
+use crate::ApiClientError;
+
+/// Length of `{"data":""}` around the escaped body.
+const ENVELOPE_OVERHEAD: usize = 11;
+const EMPTY_ENVELOPE: &[u8] = b"{}";
+/// Characters of a suspected double-encoded body kept in the error.
+const PREVIEW_CHARS: usize = 32;
+
+pub(crate) struct PreparedBody {
+    /// The body as serialized JSON text; `None` for requests without a body.
+    json: Option<Bytes>,
+    wire: Bytes,
+}
+
+impl PreparedBody {
+    /// Serializes `body`, failing with `RequestTooLarge` as soon as the wire envelope would
+    /// exceed `max_size` bytes.
+    pub(crate) fn new(body: Option<&Value>, max_size: Option<usize>) -> Result<Self, ApiClientError> {
+        let too_large = |limit| ApiClientError::RequestTooLarge { limit };
+        let Some(body) = body else {
+            return match max_size {
+                Some(limit) if EMPTY_ENVELOPE.len() > limit => Err(too_large(limit)),
+                _ => Ok(Self {
+                    json: None,
+                    wire: Bytes::from_static(EMPTY_ENVELOPE),
+                }),
+            };
+        };
+
+        let mut writer = LimitedWriter {
+            buf: Vec::new(),
+            limit: max_size.map_or(usize::MAX, |limit| limit.saturating_sub(ENVELOPE_OVERHEAD)),
+        };
+        if let Err(e) = serde_json::to_writer(&mut writer, body) {
+            return Err(match max_size {
+                Some(limit) if e.is_io() => too_large(limit),
+                _ => e.into(),
+            });
+        }
+        let json = writer.buf;
+
+        let wire_len = ENVELOPE_OVERHEAD + json.iter().map(|&b| escaped_len(b)).sum::<usize>();
+        if let Some(limit) = max_size.filter(|&limit| wire_len > limit) {
+            return Err(too_large(limit));
+        }
+        let mut wire = Vec::with_capacity(wire_len);
+        wire.extend_from_slice(b"{\"data\":");
+        let text = std::str::from_utf8(&json).expect("serde_json writes UTF-8");
+        serde_json::to_writer(&mut wire, text)?;
+        wire.push(b'}');
+        debug_assert_eq!(wire.len(), wire_len);
+
+        Ok(Self {
+            json: Some(Bytes::from(json)),
+            wire: Bytes::from(wire),
+        })
+    }
+
+    pub(crate) fn signable(&self) -> SignableBody<'_> {
+        match &self.json {
+            Some(json) => SignableBody::Text(std::str::from_utf8(json).expect("serde_json writes UTF-8")),
+            None => SignableBody::Empty,
+        }
+    }
+
+    /// The bytes to send. Cloning shares the buffer.
+    pub(crate) fn wire(&self) -> Bytes {
+        self.wire.clone()
+    }
+}
+
+/// `SuspectedDoubleEncoding` when `body` is a string whose whole text parses as a JSON
+/// object or array. Other strings, however much they look like JSON, pass.
+pub(crate) fn check_double_encoding(body: &Value) -> Result<(), ApiClientError> {
+    let Value::String(text) = body else {
+        return Ok(());
+    };
+    let kind = match text.trim_start().as_bytes().first() {
+        Some(b'{') => "object",
+        Some(b'[') => "array",
+        _ => return Ok(()),
+    };
+    if serde_json::from_str::<IgnoredAny>(text).is_err() {
+        return Ok(());
+    }
+    Err(ApiClientError::SuspectedDoubleEncoding {
+        kind: kind.to_string(),
+        preview: text.chars().take(PREVIEW_CHARS).collect(),
+    })
+}
+
+/// Bytes `b` takes up inside a JSON string, as escaped by serde_json.
+fn escaped_len(b: u8) -> usize {
+    match b {
+        b'"' | b'\\' | b'\n' | b'\r' | b'\t' | 0x08 | 0x0c => 2,
+        0x00..=0x1f => 6,
+        _ => 1,
+    }
+}
+
+struct LimitedWriter {
+    buf: Vec<u8>,
+    limit: usize,
+}
+
+impl io::Write for LimitedWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.buf.len() + data.len() > self.limit {
+            return Err(io::Error::other("request body over the size limit"));
+        }
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    use reqwest::{Method, StatusCode};
+    use serde_json::json;
+
+    use super::*;
+    use crate::failover::Endpoint;
+    use crate::request::RequestOptions;
+    use crate::retry::RetryConfig;
+    use crate::testing::{mock_client, MockResponse, MockTransport, TEST_BASE_URL};
+
+    /// Counts the bytes each thread allocates, so a test can measure its own allocations
+    /// while others run in parallel.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn count(size: usize) {
+        let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + size));
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count(layout.size());
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count(new_size);
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocated_by(f: impl FnOnce()) -> usize {
+        let before = ALLOCATED.with(Cell::get);
+        f();
+        ALLOCATED.with(Cell::get) - before
+    }
+
+    fn large_batch() -> Value {
+        let records: Vec<Value> = (0..10_000)
+            .map(|i| json!({ "id": i, "name": format!("记录 \"{}\"", i), "note": "line\nbreak" }))
+            .collect();
+        json!({ "records": records })
+    }
+
+    #[test]
+    fn test_wire_envelope_carries_the_signed_text() {
+        let body = json!({ "name": "安妮", "quote": "\"tibbers\"\n" });
+        let prepared = PreparedBody::new(Some(&body), None).unwrap();
+        let SignableBody::Text(signed) = prepared.signable() else {
+            panic!("expected a text body");
+        };
+        assert_eq!(signed, body.to_string());
+
+        let wire: Value = serde_json::from_slice(&prepared.wire()).unwrap();
+        assert_eq!(wire, json!({ "data": body.to_string() }));
+
+        let empty = PreparedBody::new(None, None).unwrap();
+        assert!(matches!(empty.signable(), SignableBody::Empty));
+        assert_eq!(&empty.wire()[..], b"{}");
+    }
+
+    #[test]
+    fn test_size_limit_counts_the_wire_envelope() {
+        let body = json!({ "key": "value" });
+        let wire_len = PreparedBody::new(Some(&body), None).unwrap().wire().len();
+        assert!(PreparedBody::new(Some(&body), Some(wire_len)).is_ok());
+        assert!(matches!(
+            PreparedBody::new(Some(&body), Some(wire_len - 1)),
+            Err(ApiClientError::RequestTooLarge { limit }) if limit == wire_len - 1
+        ));
+        assert!(matches!(
+            PreparedBody::new(Some(&large_batch()), Some(1024)),
+            Err(ApiClientError::RequestTooLarge { limit: 1024 })
+        ));
+    }
+
+    #[test]
+    fn test_large_body_is_serialized_once() {
+        let body = large_batch();
+        let json_len = body.to_string().len();
+
+        let mut prepared = None;
+        let preparing = allocated_by(|| prepared = Some(PreparedBody::new(Some(&body), None).unwrap()));
+        let prepared = prepared.unwrap();
+        let wire_len = prepared.wire().len();
+        // The JSON text, whose doubling growth allocates under 4x its length in total, plus
+        // one exactly sized wire buffer.
+        assert!(preparing < 4 * json_len + wire_len, "{}", preparing);
+
+        // Serializing for the signature and again for the wire, as the client used to.
+        let twice = allocated_by(|| {
+            let text = serde_json::to_string(&body).unwrap();
+            let _wire = serde_json::to_string(&json!({ "data": text })).unwrap();
+        });
+        assert!(preparing < twice, "{} >= {}", preparing, twice);
+
+        let client = mock_client(&MockTransport::new(), |_| {});
+        let endpoint = Endpoint::dns(TEST_BASE_URL);
+        let attempts = allocated_by(|| {
+            for _ in 0..3 {
+                let request = client
+                    .signed_request(
+                        &endpoint,
+                        Method::POST,
+                        "/v1/batch",
+                        &prepared,
+                        &RequestOptions::default(),
+                    )
+                    .unwrap();
+                assert_eq!(request.body.as_ptr(), prepared.wire.as_ptr());
+            }
+        });
+        assert!(attempts < 16 * 1024, "{}", attempts);
+    }
+
+    #[tokio::test]
+    async fn test_retries_share_one_buffer() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::POST,
+                "/v1/batch",
+                MockResponse::status(StatusCode::SERVICE_UNAVAILABLE),
+            )
+            .on(Method::POST, "/v1/batch", MockResponse::encrypted("ok"));
+        let client = mock_client(&transport, |config| {
+            config.retry = RetryConfig {
+                max_retries: 1,
+                initial_backoff: Duration::ZERO,
+                ..Default::default()
+            }
+        });
+
+        let body = json!({ "records": [1, 2, 3] });
+        assert_eq!(client.send(Method::POST, "/v1/batch", Some(body)).await.unwrap(), "ok");
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].body.as_ptr(), requests[1].body.as_ptr());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_request_is_not_sent() {
+        let transport = MockTransport::new();
+        let client = mock_client(&transport, |config| config.max_request_size = Some(4096));
+
+        match client.send(Method::POST, "/v1/batch", Some(large_batch())).await {
+            Err(ApiClientError::RequestTooLarge { limit }) => assert_eq!(limit, 4096),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(transport.requests().is_empty());
+    }
+
+    #[test]
+    fn test_only_strings_holding_objects_or_arrays_are_suspect() {
+        for body in [
+            json!("cn"),
+            json!(""),
+            json!("{not json}"),
+            json!("[1, 2"),
+            json!("{} trailing"),
+            json!("42"),
+            json!("\"quoted\""),
+            json!({ "sku": 7 }),
+            json!(["{\"sku\":7}"]),
+        ] {
+            assert!(check_double_encoding(&body).is_ok(), "{}", body);
+        }
+        for (body, expected) in [(json!(" {\"sku\":7}\n"), "object"), (json!("[1,2]"), "array")] {
+            match check_double_encoding(&body) {
+                Err(ApiClientError::SuspectedDoubleEncoding { kind, .. }) => assert_eq!(kind, expected),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_double_encoded_body_is_not_sent_unless_allowed() {
+        let transport = MockTransport::new();
+        transport.on(Method::POST, "/v1/order", MockResponse::encrypted("ok"));
+        let client = mock_client(&transport, |_| {});
+        let twice = json!(serde_json::to_string(&json!({ "sku": 7 })).unwrap());
+
+        match client.send(Method::POST, "/v1/order", Some(twice.clone())).await {
+            Err(ApiClientError::SuspectedDoubleEncoding { kind, preview }) => {
+                assert_eq!((kind.as_str(), preview.as_str()), ("object", r#"{"sku":7}"#));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(transport.requests().is_empty());
+        assert_eq!(client.stats().errors.invalid_request, 1);
+
+        let intended = client
+            .request(Method::POST, "/v1/order")
+            .body(twice.clone())
+            .allow_string_body();
+        assert_eq!(intended.send().await.unwrap(), "ok");
+        let unchecked = mock_client(&transport, |config| config.detect_double_encoding = false);
+        assert_eq!(
+            unchecked.send(Method::POST, "/v1/order", Some(twice)).await.unwrap(),
+            "ok"
+        );
+        assert_eq!(
+            client.send(Method::POST, "/v1/order", Some(json!("cn"))).await.unwrap(),
+            "ok"
+        );
+        assert_eq!(transport.requests().len(), 3);
+    }
+}
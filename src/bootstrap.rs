@@ -0,0 +1,298 @@
+//! Client settings the server recommends at `/v1/client/bootstrap`.
+//!
+//! `ApiClient::bootstrap` fetches them. With `ApiClientConfig::apply_bootstrap` set, the client
+//! also fetches them before its first request, and again every `bootstrap_refresh`, and merges
+//! them into itself. What the local configuration says explicitly always wins:
+//!
+//! - `max_concurrent_requests` can only lower the local ceiling, never raise it;
+//! - `preferred_hosts` are appended to the failover hosts, behind the configured ones;
+//! - `api_version` is sent only when neither the config nor the request names a version;
+//! - `max_page_size` caps the default page size of `ApiClient::paginate`, not an explicit one.
+//!
+//! A payload that fails to fetch or parse changes nothing.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::failover::Endpoint;
+use crate::request::check_api_version;
+use crate::{ApiClient, ApiClientConfig, ApiClientError};
+
+pub const BOOTSTRAP_URI: &str = "/v1/client/bootstrap";
+
+/// The payload of `GET /v1/client/bootstrap`. Every field is optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerRecommendedConfig {
+    /// Ceiling on requests in flight at once.
+    pub max_concurrent_requests: Option<usize>,
+    /// Base URLs to fail over to, in order.
+    pub preferred_hosts: Vec<String>,
+    /// Largest `page_size` list endpoints serve in full.
+    pub max_page_size: Option<u64>,
+    /// The `HO-VERSION` the server would like clients to send.
+    pub api_version: Option<String>,
+}
+
+/// The recommendations in effect and when they were last fetched.
+pub(crate) struct Recommendations {
+    refresh: Option<Duration>,
+    fetched: AtomicBool,
+    last_fetch: tokio::sync::Mutex<Option<Instant>>,
+    applied: Mutex<ServerRecommendedConfig>,
+}
+
+impl Recommendations {
+    pub(crate) fn new(refresh: Option<Duration>) -> Self {
+        Self {
+            refresh: refresh.filter(|every| !every.is_zero()),
+            fetched: AtomicBool::new(false),
+            last_fetch: tokio::sync::Mutex::new(None),
+            applied: Mutex::new(ServerRecommendedConfig::default()),
+        }
+    }
+
+    /// The version adopted from the server, if any.
+    pub(crate) fn api_version(&self) -> Option<String> {
+        self.applied.lock().unwrap().api_version.clone()
+    }
+
+    pub(crate) fn max_page_size(&self) -> Option<u64> {
+        self.applied.lock().unwrap().max_page_size
+    }
+}
+
+/// What of `recommended` applies on top of `config`: the fields local settings leave to the
+/// server, with unusable values dropped.
+pub(crate) fn merge(config: &ApiClientConfig, recommended: &ServerRecommendedConfig) -> ServerRecommendedConfig {
+    let max_concurrent_requests = recommended
+        .max_concurrent_requests
+        .filter(|&n| n > 0)
+        .filter(|&n| config.max_concurrent_requests.is_none_or(|local| n < local));
+    let configured = |url: &str| url == config.base_url || config.fallback_base_urls.iter().any(|own| own == url);
+    let mut preferred_hosts: Vec<String> = Vec::new();
+    for url in &recommended.preferred_hosts {
+        let url = url.trim().trim_end_matches('/');
+        let usable = url.starts_with("https://") || url.starts_with("http://");
+        if usable && !configured(url) && !preferred_hosts.iter().any(|seen| seen == url) {
+            preferred_hosts.push(url.to_string());
+        }
+    }
+    let api_version = match &config.api_version {
+        Some(_) => None,
+        None => recommended
+            .api_version
+            .clone()
+            .filter(|version| check_api_version(version).is_ok()),
+    };
+    ServerRecommendedConfig {
+        max_concurrent_requests,
+        preferred_hosts,
+        max_page_size: recommended.max_page_size.filter(|&n| n > 0),
+        api_version,
+    }
+}
+
+impl ApiClient {
+    /// Fetches the server's recommended settings. With `ApiClientConfig::apply_bootstrap`
+    /// they are applied too, as on a scheduled refresh.
+    pub async fn bootstrap(&self) -> Result<ServerRecommendedConfig, ApiClientError> {
+        let recommended: ServerRecommendedConfig = self
+            .request(Method::GET, BOOTSTRAP_URI)
+            .bootstrap_fetch()
+            .send_as()
+            .await?;
+        if self.config.apply_bootstrap {
+            self.apply_recommendations(&recommended);
+        }
+        Ok(recommended)
+    }
+
+    /// Fetches and applies the recommendations if none were fetched yet or the last fetch
+    /// is older than `bootstrap_refresh`. Requests wait for the first fetch; while a refresh
+    /// is under way, they go ahead with the settings in effect.
+    pub(crate) fn bootstrap_if_due(&self) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        // Boxed with a named type: the fetch is itself a request, which comes back through here.
+        Box::pin(async move {
+            let recommendations = &self.recommendations;
+            let mut last_fetch = match recommendations.fetched.load(Ordering::Acquire) {
+                false => recommendations.last_fetch.lock().await,
+                true if recommendations.refresh.is_none() => return,
+                true => match recommendations.last_fetch.try_lock() {
+                    Ok(last_fetch) => last_fetch,
+                    Err(_) => return,
+                },
+            };
+            let due = match (*last_fetch, recommendations.refresh) {
+                (None, _) => true,
+                (Some(at), Some(every)) => self.config.time.elapsed(at) >= every,
+                (Some(_), None) => false,
+            };
+            if !due {
+                return;
+            }
+            *last_fetch = Some(self.config.time.now());
+            if let Err(error) = self.bootstrap().await {
+                tracing::warn!(error = ?error, "ignoring the bootstrap response; keeping the current settings");
+            }
+            recommendations.fetched.store(true, Ordering::Release);
+        })
+    }
+
+    /// Merges `recommended` into the live client, logging each change once.
+    fn apply_recommendations(&self, recommended: &ServerRecommendedConfig) {
+        let merged = merge(&self.config, recommended);
+        let mut applied = self.recommendations.applied.lock().unwrap();
+        if merged.max_concurrent_requests != applied.max_concurrent_requests {
+            if let Some(limiter) = &self.limiter {
+                let local = self.config.max_concurrent_requests.unwrap_or(usize::MAX);
+                limiter.resize(merged.max_concurrent_requests.unwrap_or(local));
+            }
+            tracing::info!(
+                recommended = merged.max_concurrent_requests,
+                configured = self.config.max_concurrent_requests,
+                "applied the server's concurrency ceiling"
+            );
+        }
+        for url in &merged.preferred_hosts {
+            if self.hosts.add(Endpoint::dns(url)) {
+                tracing::info!(
+                    base_url = url.as_str(),
+                    "added a host the server recommends to failover"
+                );
+            }
+        }
+        if merged.api_version != applied.api_version {
+            tracing::info!(
+                api_version = merged.api_version.as_deref(),
+                "adopted the server's api version"
+            );
+        }
+        if merged.max_page_size != applied.max_page_size {
+            tracing::info!(
+                max_page_size = merged.max_page_size,
+                "adopted the server's page size limit"
+            );
+        }
+        *applied = merged;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport};
+
+    fn recommended(value: serde_json::Value) -> ServerRecommendedConfig {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_local_settings_win() {
+        let config = ApiClientConfig {
+            base_url: "https://api.example.com".to_string(),
+            max_concurrent_requests: Some(8),
+            api_version: Some("2024-01-01".to_string()),
+            ..Default::default()
+        };
+        let server = recommended(json!({
+            "max_concurrent_requests": 16,
+            "preferred_hosts": ["https://api.example.com/", "https://b.example.com", "ftp://c", "https://b.example.com"],
+            "api_version": "2024-06-01",
+            "max_page_size": 0,
+        }));
+        assert_eq!(
+            merge(&config, &server),
+            ServerRecommendedConfig {
+                preferred_hosts: vec!["https://b.example.com".to_string()],
+                ..Default::default()
+            }
+        );
+
+        let lower = recommended(json!({ "max_concurrent_requests": 4, "api_version": "2024-06-01" }));
+        assert_eq!(merge(&config, &lower).max_concurrent_requests, Some(4));
+        let unset = ApiClientConfig::default();
+        assert_eq!(merge(&unset, &server).max_concurrent_requests, Some(16));
+        assert_eq!(merge(&unset, &server).api_version.as_deref(), Some("2024-06-01"));
+        let spaced = recommended(json!({ "api_version": "2024 06" }));
+        assert_eq!(merge(&unset, &spaced).api_version, None);
+    }
+
+    #[tokio::test]
+    async fn test_first_request_applies_the_recommendations() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::GET,
+                BOOTSTRAP_URI,
+                MockResponse::encrypted(
+                    json!({
+                        "max_concurrent_requests": 2,
+                        "preferred_hosts": ["https://backup.example.com"],
+                        "api_version": "2024-06-01",
+                        "unknown": true,
+                    })
+                    .to_string(),
+                ),
+            )
+            .on(Method::GET, "/v1/x", MockResponse::encrypted("{}"));
+        let client = mock_client(&transport, |config| {
+            config.apply_bootstrap = true;
+            config.max_concurrent_requests = Some(4);
+        });
+
+        client.send(Method::GET, "/v1/x", None).await.unwrap();
+        client.send(Method::GET, "/v1/x", None).await.unwrap();
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 3, "bootstrapped once, without a refresh interval");
+        assert!(requests[0].url.ends_with(BOOTSTRAP_URI));
+        assert!(requests[0].headers.get("HO-VERSION").is_none());
+        assert_eq!(requests[1].headers["HO-VERSION"], "2024-06-01");
+        assert_eq!(client.limiter.as_ref().unwrap().size(), 2);
+        let hosts: Vec<_> = client.stats().hosts.into_iter().map(|host| host.base_url).collect();
+        assert_eq!(hosts.last().map(String::as_str), Some("https://backup.example.com"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_malformed_payloads_change_nothing() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::GET,
+                BOOTSTRAP_URI,
+                MockResponse::encrypted(json!({ "max_concurrent_requests": "two" }).to_string()),
+            )
+            .on(Method::GET, BOOTSTRAP_URI, MockResponse::status(StatusCode::NOT_FOUND))
+            .on(Method::GET, "/v1/x", MockResponse::encrypted("{}"));
+        let client = mock_client(&transport, |config| {
+            config.apply_bootstrap = true;
+            config.bootstrap_refresh = Some(Duration::from_secs(60));
+        });
+
+        assert!(client.bootstrap().await.is_err());
+        client.send(Method::GET, "/v1/x", None).await.unwrap();
+        tokio::time::advance(Duration::from_secs(61)).await;
+        let refreshed = client.send_detailed(Method::GET, "/v1/x", None).await.unwrap();
+        assert!(refreshed.meta.api_version.is_none());
+
+        let bootstraps = transport
+            .requests()
+            .iter()
+            .filter(|request| request.url.ends_with(BOOTSTRAP_URI))
+            .count();
+        assert_eq!(bootstraps, 3);
+        assert_eq!(client.limiter.as_ref().unwrap().size(), usize::MAX);
+        assert_eq!(client.stats().hosts.len(), 1);
+        assert_eq!(client.recommendations.api_version(), None);
+    }
+}
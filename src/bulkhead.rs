@@ -0,0 +1,270 @@
+//! Named concurrency pools (bulkheads). Each pool is its own semaphore, so a slow endpoint
+//! that fills its pool queues only its own requests; the endpoints of other pools keep their
+//! permits.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::priority::PriorityLimiter;
+use crate::time::TimeSource;
+use crate::validation::matches_path;
+use crate::ApiClientError;
+
+/// The pool that serves requests no route in `ApiClientConfig::pool_routes` matches.
+pub const DEFAULT_POOL: &str = "default";
+
+/// Counters of one pool; see `Stats::pools`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PoolStats {
+    pub name: String,
+    pub size: usize,
+    /// Permits held right now.
+    pub in_flight: usize,
+    /// Requests that got a permit.
+    pub admitted: u64,
+    /// Requests dropped with `QueueTimeout` after waiting `max_queue_wait`.
+    pub rejected: u64,
+    /// Time the admitted requests spent waiting for their permits, summed.
+    pub queue_time_total_ms: u64,
+    pub queue_time_max_ms: u64,
+}
+
+pub(crate) struct Pool {
+    name: String,
+    pub(crate) limiter: Arc<PriorityLimiter>,
+    admitted: AtomicU64,
+    rejected: AtomicU64,
+    queue_time_total_us: AtomicU64,
+    queue_time_max_us: AtomicU64,
+}
+
+impl Pool {
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn record_admitted(&self, queue_time: Duration) {
+        let us = queue_time.as_micros().min(u64::MAX as u128) as u64;
+        self.admitted.fetch_add(1, Ordering::Relaxed);
+        self.queue_time_total_us.fetch_add(us, Ordering::Relaxed);
+        self.queue_time_max_us.fetch_max(us, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> PoolStats {
+        PoolStats {
+            name: self.name.clone(),
+            size: self.limiter.size(),
+            in_flight: self.limiter.size() - self.limiter.available(),
+            admitted: self.admitted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            queue_time_total_ms: self.queue_time_total_us.load(Ordering::Relaxed) / 1000,
+            queue_time_max_ms: self.queue_time_max_us.load(Ordering::Relaxed) / 1000,
+        }
+    }
+
+    fn reset(&self) {
+        for counter in [
+            &self.admitted,
+            &self.rejected,
+            &self.queue_time_total_us,
+            &self.queue_time_max_us,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Bulkheads {
+    pools: Vec<Pool>,
+    /// Path patterns with the index of their pool, in configuration order.
+    routes: Vec<(String, usize)>,
+    default: Option<usize>,
+}
+
+impl Bulkheads {
+    pub(crate) fn new(
+        sizes: &BTreeMap<String, usize>,
+        routes: &[(String, String)],
+        priority_promotion: Option<Duration>,
+        time: &Arc<dyn TimeSource>,
+    ) -> Result<Self, ApiClientError> {
+        let mut pools = Vec::new();
+        for (name, &size) in sizes {
+            if size == 0 {
+                return Err(ApiClientError::InvalidConfig(format!(
+                    "pool `{}` must have at least 1 permit",
+                    name
+                )));
+            }
+            pools.push(Pool {
+                name: name.clone(),
+                limiter: PriorityLimiter::new(size, priority_promotion, time.clone()),
+                admitted: AtomicU64::new(0),
+                rejected: AtomicU64::new(0),
+                queue_time_total_us: AtomicU64::new(0),
+                queue_time_max_us: AtomicU64::new(0),
+            });
+        }
+        let index_of = |name: &str| pools.iter().position(|pool| pool.name == name);
+        let routes = routes
+            .iter()
+            .map(|(pattern, name)| match index_of(name) {
+                Some(index) => Ok((pattern.clone(), index)),
+                None => Err(ApiClientError::InvalidConfig(format!(
+                    "pool_routes sends `{}` to pool `{}`, which is not in pools",
+                    pattern, name
+                ))),
+            })
+            .collect::<Result<_, _>>()?;
+        let default = index_of(DEFAULT_POOL);
+        Ok(Self { pools, routes, default })
+    }
+
+    /// The pool for `uri`, matched on its path without the query string. `None` when no
+    /// pools are configured or nothing matches and there is no `default` pool.
+    pub(crate) fn route(&self, uri: &str) -> Option<&Pool> {
+        let path = uri.split('?').next().unwrap_or_default();
+        let index = self
+            .routes
+            .iter()
+            .find(|(pattern, _)| matches_path(pattern, path))
+            .map(|&(_, index)| index)
+            .or(self.default)?;
+        Some(&self.pools[index])
+    }
+
+    pub(crate) fn stats(&self) -> Vec<PoolStats> {
+        self.pools.iter().map(Pool::stats).collect()
+    }
+
+    pub(crate) fn reset(&self) {
+        self.pools.iter().for_each(Pool::reset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Method;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport};
+    use crate::time;
+
+    fn pools(sizes: &[(&str, usize)]) -> BTreeMap<String, usize> {
+        sizes.iter().map(|&(name, size)| (name.to_string(), size)).collect()
+    }
+
+    fn routes(routes: &[(&str, &str)]) -> Vec<(String, String)> {
+        routes
+            .iter()
+            .map(|&(pattern, pool)| (pattern.to_string(), pool.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_routes_match_like_validation_paths() {
+        let bulkheads = Bulkheads::new(
+            &pools(&[("reports", 4), ("default", 60)]),
+            &routes(&[("/v1/report/*", "reports"), ("/v1/export", "reports")]),
+            None,
+            &time::real(),
+        )
+        .unwrap();
+        let pool_of = |uri: &str| bulkheads.route(uri).map(Pool::name);
+        assert_eq!(pool_of("/v1/report/monthly?month=6"), Some("reports"));
+        assert_eq!(pool_of("/v1/export?all=1"), Some("reports"));
+        assert_eq!(pool_of("/v1/export/more"), Some("default"));
+
+        let without_default = Bulkheads::new(
+            &pools(&[("reports", 4)]),
+            &routes(&[("/v1/report/*", "reports")]),
+            None,
+            &time::real(),
+        )
+        .unwrap();
+        assert!(without_default.route("/v1/skin").is_none());
+        assert!(Bulkheads::default().route("/v1/skin").is_none());
+    }
+
+    #[test]
+    fn test_invalid_pools_are_config_errors() {
+        for (sizes, routes) in [
+            (pools(&[("reports", 0)]), Vec::new()),
+            (pools(&[("reports", 4)]), routes(&[("/v1/report/*", "report")])),
+        ] {
+            assert!(matches!(
+                Bulkheads::new(&sizes, &routes, None, &time::real()),
+                Err(ApiClientError::InvalidConfig(_))
+            ));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_saturated_pool_leaves_the_others_alone() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/report/monthly",
+            MockResponse::encrypted("{}").with_delay(Duration::from_secs(10)),
+        );
+        transport.on(
+            Method::GET,
+            "/v1/skin",
+            MockResponse::encrypted("{}").with_delay(Duration::from_millis(20)),
+        );
+        let client = mock_client(&transport, |config| {
+            config.max_concurrent_requests = Some(2);
+            config.max_queue_wait = Some(Duration::from_secs(1));
+            config.pools = pools(&[("reports", 2), ("default", 8)]);
+            config.pool_routes = routes(&[("/v1/report/*", "reports")]);
+        });
+
+        let reports = futures_util::future::join_all(
+            (0..6).map(|_| client.send_detailed(Method::GET, "/v1/report/monthly", None)),
+        );
+        let fast = async {
+            // Let the reports take both of their permits and queue the rest first.
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let mut responses = Vec::new();
+            for _ in 0..3 {
+                let batch = (0..8).map(|_| client.send_detailed(Method::GET, "/v1/skin", None));
+                responses.extend(futures_util::future::join_all(batch).await);
+            }
+            responses
+        };
+        let (reports, fast) = tokio::join!(reports, fast);
+
+        for response in fast {
+            let response = response.unwrap();
+            assert_eq!(response.meta.queue_time, Duration::ZERO);
+            assert_eq!(response.meta.elapsed, Duration::from_millis(20));
+            assert_eq!(response.meta.pool.as_deref(), Some("default"));
+        }
+        let (admitted, rejected): (Vec<_>, Vec<_>) = reports.into_iter().partition(Result::is_ok);
+        assert_eq!(admitted.len(), 2);
+        assert_eq!(admitted[0].as_ref().unwrap().meta.pool.as_deref(), Some("reports"));
+        assert!(rejected
+            .iter()
+            .all(|r| matches!(r, Err(ApiClientError::QueueTimeout { .. }))));
+
+        let stats = client.stats();
+        let reports = stats.pools.iter().find(|pool| pool.name == "reports").unwrap();
+        assert_eq!((reports.admitted, reports.rejected, reports.in_flight), (2, 4, 0));
+        let default = stats.pools.iter().find(|pool| pool.name == "default").unwrap();
+        assert_eq!((default.admitted, default.rejected), (24, 0));
+        assert_eq!(default.queue_time_max_ms, 0);
+        assert_eq!(stats.errors.queue_timeout, 4);
+
+        client.reset_stats();
+        assert_eq!(client.stats().pools[1].admitted, 0);
+    }
+}
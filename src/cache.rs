@@ -0,0 +1,782 @@
+//! Response caching for GET requests. The client stores decrypted plaintext under a
+//! `CacheKey` in whatever `CacheBackend` the config names; `MemoryCache` ships with the client
+//! and `DiskCache` with the `disk-cache` feature. Other stores (Redis, memcached) plug in by
+//! implementing the trait.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use reqwest::Method;
+use serde::Serialize;
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use tokio::time::Instant;
+
+use crate::time::{self, TimeSource};
+
+/// Stable identifier of a request: a SHA-1 over the method, path and query, and body. The
+/// same request hashes to the same key across processes and releases.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey(String);
+
+impl CacheKey {
+    pub fn new(method: &Method, path: &str, body: Option<&Value>) -> Self {
+        let mut hasher = Sha1::new();
+        hasher.update(method.as_str().as_bytes());
+        hasher.update(b"\n");
+        hasher.update(path.as_bytes());
+        hasher.update(b"\n");
+        if let Some(body) = body {
+            hasher.update(body.to_string().as_bytes());
+        }
+        CacheKey(hex::encode(hasher.finalize()))
+    }
+
+    /// 40 lowercase hex digits, safe to use as a file name or store key.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Storage for cached plaintext. Backends handle their own failures: a `get` that cannot
+/// read the store is a miss, and a failed `put` or `remove` is dropped.
+pub trait CacheBackend: Send + Sync {
+    fn get<'a>(&'a self, key: &'a CacheKey) -> BoxFuture<'a, Option<String>>;
+
+    /// Stores `value`, to be returned by `get` until `ttl` has passed.
+    fn put<'a>(&'a self, key: &'a CacheKey, value: String, ttl: Duration) -> BoxFuture<'a, ()>;
+
+    fn remove<'a>(&'a self, key: &'a CacheKey) -> BoxFuture<'a, ()>;
+
+    /// The value under `key` if its ttl passed at most `max_stale` ago, or has not passed,
+    /// for `CacheConfig::max_stale`. Backends that drop entries on expiry keep the default,
+    /// which finds nothing.
+    fn get_stale<'a>(&'a self, key: &'a CacheKey, max_stale: Duration) -> BoxFuture<'a, Option<String>> {
+        let _ = (key, max_stale);
+        Box::pin(async { None })
+    }
+
+    /// What the backend holds, reported in `Stats::cache`. `None`, the default, leaves it out.
+    fn usage(&self) -> Option<CacheUsage> {
+        None
+    }
+}
+
+/// Which GET responses are cached, and where.
+#[derive(Clone)]
+pub struct CacheConfig {
+    pub backend: Arc<dyn CacheBackend>,
+    pub ttl: Duration,
+    /// When set, a GET that fails because the server is unavailable (a timeout, a transport
+    /// error, a 5xx or a full queue) returns the cached body instead if it expired at most
+    /// this long ago, with `meta.stale` set. Errors that blame the request, such as 4xx and
+    /// auth failures, are returned as they are.
+    pub max_stale: Option<Duration>,
+}
+
+impl CacheConfig {
+    /// An in-process `MemoryCache` with the default bounds, lost when the client is dropped.
+    pub fn memory(ttl: Duration) -> Self {
+        Self {
+            backend: Arc::new(MemoryCache::new()),
+            ttl,
+            max_stale: None,
+        }
+    }
+
+    /// Serves entries up to `max_stale` past their ttl when the server is unavailable.
+    pub fn serve_stale(mut self, max_stale: Duration) -> Self {
+        self.max_stale = Some(max_stale);
+        self
+    }
+}
+
+/// How much a backend holds, for `Stats::cache`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CacheUsage {
+    pub entries: usize,
+    /// Plaintext bytes of the stored values.
+    pub bytes: usize,
+    /// Entries dropped to stay within the bounds, since the backend was created.
+    pub evictions: u64,
+}
+
+/// An in-process LRU holding at most `max_entries` entries and `max_bytes` bytes of
+/// plaintext, 10 000 and 64 MiB by default. Storing past either bound evicts the least
+/// recently read or written entries; a value larger than `max_bytes` is not stored.
+/// Expired entries stay, for `get_stale`, until they are overwritten, evicted or found too
+/// old by `get_stale`.
+pub struct MemoryCache {
+    max_entries: usize,
+    max_bytes: usize,
+    time: Arc<dyn TimeSource>,
+    lru: Mutex<Lru>,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<CacheKey, Entry>,
+    /// Keys by the tick of their last use, oldest first.
+    order: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    bytes: usize,
+    evictions: u64,
+}
+
+struct Entry {
+    value: String,
+    expires: Instant,
+    used: u64,
+}
+
+impl Default for MemoryCache {
+    fn default() -> Self {
+        Self {
+            max_entries: 10_000,
+            max_bytes: 64 << 20,
+            time: time::real(),
+            lru: Mutex::default(),
+        }
+    }
+}
+
+impl MemoryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// The clock entries expire on; share the client's `ApiClientConfig::time` with it.
+    pub fn time_source(mut self, time: Arc<dyn TimeSource>) -> Self {
+        self.time = time;
+        self
+    }
+
+    /// The value under `key`, marked as just used, if `live` accepts its expiry. An entry
+    /// `live` refuses is removed when `drop_refused` is set.
+    fn lookup(&self, key: &CacheKey, live: impl Fn(Instant) -> bool, drop_refused: bool) -> Option<String> {
+        let mut lru = self.lru.lock().unwrap();
+        let lru = &mut *lru;
+        let entry = lru.entries.get_mut(key)?;
+        if !live(entry.expires) {
+            if drop_refused {
+                lru.remove(key);
+            }
+            return None;
+        }
+        lru.tick += 1;
+        lru.order.remove(&entry.used);
+        lru.order.insert(lru.tick, key.clone());
+        entry.used = lru.tick;
+        Some(entry.value.clone())
+    }
+}
+
+impl Lru {
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.used);
+            self.bytes -= entry.value.len();
+        }
+    }
+}
+
+impl CacheBackend for MemoryCache {
+    fn get<'a>(&'a self, key: &'a CacheKey) -> BoxFuture<'a, Option<String>> {
+        let now = self.time.now();
+        let value = self.lookup(key, |expires| expires > now, false);
+        Box::pin(async move { value })
+    }
+
+    fn put<'a>(&'a self, key: &'a CacheKey, value: String, ttl: Duration) -> BoxFuture<'a, ()> {
+        let mut lru = self.lru.lock().unwrap();
+        lru.remove(key);
+        if value.len() <= self.max_bytes && self.max_entries > 0 {
+            while lru.entries.len() >= self.max_entries || lru.bytes + value.len() > self.max_bytes {
+                let Some((_, oldest)) = lru.order.pop_first() else {
+                    break;
+                };
+                lru.remove(&oldest);
+                lru.evictions += 1;
+            }
+            lru.tick += 1;
+            let used = lru.tick;
+            lru.bytes += value.len();
+            lru.order.insert(used, key.clone());
+            let expires = self.time.now() + ttl;
+            lru.entries.insert(key.clone(), Entry { value, expires, used });
+        }
+        Box::pin(async {})
+    }
+
+    fn remove<'a>(&'a self, key: &'a CacheKey) -> BoxFuture<'a, ()> {
+        self.lru.lock().unwrap().remove(key);
+        Box::pin(async {})
+    }
+
+    fn get_stale<'a>(&'a self, key: &'a CacheKey, max_stale: Duration) -> BoxFuture<'a, Option<String>> {
+        let now = self.time.now();
+        let value = self.lookup(key, |expires| expires + max_stale >= now, true);
+        Box::pin(async move { value })
+    }
+
+    fn usage(&self) -> Option<CacheUsage> {
+        let lru = self.lru.lock().unwrap();
+        Some(CacheUsage {
+            entries: lru.entries.len(),
+            bytes: lru.bytes,
+            evictions: lru.evictions,
+        })
+    }
+}
+
+#[cfg(feature = "disk-cache")]
+pub use disk::DiskCache;
+
+#[cfg(feature = "disk-cache")]
+mod disk {
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures_util::future::BoxFuture;
+    use tokio::sync::Mutex;
+
+    use super::{CacheBackend, CacheKey};
+    use crate::crypto::Crypto;
+    use crate::time::{self, TimeSource};
+
+    const INDEX_FILE: &str = "index.json";
+
+    /// One file per entry in a directory, plus `index.json` mapping keys to their expiry in
+    /// Unix milliseconds. Every file is written to a temporary name and renamed into place,
+    /// so a crash leaves either the old or the new content. Unreadable or undecryptable files
+    /// are treated as misses and replaced on the next `put`. Expired entries stay for
+    /// `get_stale` until `open` drops them.
+    pub struct DiskCache {
+        dir: PathBuf,
+        crypto: Option<Crypto>,
+        time: Arc<dyn TimeSource>,
+        index: Mutex<HashMap<String, u64>>,
+    }
+
+    impl DiskCache {
+        /// Opens or creates the cache in `dir`, dropping index entries that have expired by
+        /// the system clock.
+        pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+            let dir = dir.as_ref().to_path_buf();
+            std::fs::create_dir_all(&dir)?;
+            let mut index: HashMap<String, u64> = std::fs::read(dir.join(INDEX_FILE))
+                .ok()
+                .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+                .unwrap_or_default();
+            let time = time::real();
+            let now = unix_millis(&*time);
+            index.retain(|key, expires| {
+                let live = *expires > now;
+                if !live {
+                    let _ = std::fs::remove_file(dir.join(key));
+                }
+                live
+            });
+            Ok(Self {
+                dir,
+                crypto: None,
+                time,
+                index: Mutex::new(index),
+            })
+        }
+
+        /// Encrypts entries at rest. The key must be the same every time the directory is
+        /// opened; entries written with another key read as misses.
+        pub fn encrypted(mut self, crypto: Crypto) -> Self {
+            self.crypto = Some(crypto);
+            self
+        }
+
+        /// The clock entries expire on from now on; see `MemoryCache::time_source`.
+        pub fn time_source(mut self, time: Arc<dyn TimeSource>) -> Self {
+            self.time = time;
+            self
+        }
+
+        async fn read_entry(&self, key: &CacheKey) -> Option<String> {
+            let bytes = tokio::fs::read(self.dir.join(key.as_str())).await.ok()?;
+            match &self.crypto {
+                Some(crypto) => crypto.decrypt_hex(&bytes).ok(),
+                None => String::from_utf8(bytes).ok(),
+            }
+        }
+
+        /// The live entry under `key`, or with `grace` one that expired at most that long ago.
+        /// Expired entries are kept for `get_stale` and removed once past its grace.
+        async fn lookup(&self, key: &CacheKey, grace: Option<Duration>) -> Option<String> {
+            let expires = *self.index.lock().await.get(key.as_str())?;
+            let now = unix_millis(&*self.time);
+            match grace {
+                None if expires <= now => return None,
+                Some(grace) if expires.saturating_add(grace.as_millis() as u64) < now => {
+                    self.remove(key).await;
+                    return None;
+                }
+                _ => {}
+            }
+            let value = self.read_entry(key).await;
+            if value.is_none() {
+                self.remove(key).await;
+            }
+            value
+        }
+
+        async fn write_index(&self, index: &HashMap<String, u64>) -> io::Result<()> {
+            let bytes = serde_json::to_vec(index).map_err(io::Error::other)?;
+            write_atomic(&self.dir.join(INDEX_FILE), &bytes).await
+        }
+    }
+
+    impl CacheBackend for DiskCache {
+        fn get<'a>(&'a self, key: &'a CacheKey) -> BoxFuture<'a, Option<String>> {
+            Box::pin(self.lookup(key, None))
+        }
+
+        fn put<'a>(&'a self, key: &'a CacheKey, value: String, ttl: Duration) -> BoxFuture<'a, ()> {
+            Box::pin(async move {
+                let contents = match &self.crypto {
+                    Some(crypto) => crypto.encrypt_hex(value.as_bytes()).into_bytes(),
+                    None => value.into_bytes(),
+                };
+                let mut index = self.index.lock().await;
+                if write_atomic(&self.dir.join(key.as_str()), &contents).await.is_err() {
+                    return;
+                }
+                index.insert(
+                    key.as_str().to_string(),
+                    unix_millis(&*self.time).saturating_add(ttl.as_millis() as u64),
+                );
+                let _ = self.write_index(&index).await;
+            })
+        }
+
+        fn remove<'a>(&'a self, key: &'a CacheKey) -> BoxFuture<'a, ()> {
+            Box::pin(async move {
+                let mut index = self.index.lock().await;
+                let _ = tokio::fs::remove_file(self.dir.join(key.as_str())).await;
+                if index.remove(key.as_str()).is_some() {
+                    let _ = self.write_index(&index).await;
+                }
+            })
+        }
+
+        fn get_stale<'a>(&'a self, key: &'a CacheKey, max_stale: Duration) -> BoxFuture<'a, Option<String>> {
+            Box::pin(self.lookup(key, Some(max_stale)))
+        }
+    }
+
+    async fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, contents).await?;
+        tokio::fs::rename(&tmp, path).await
+    }
+
+    fn unix_millis(time: &dyn TimeSource) -> u64 {
+        time.now_utc().timestamp_millis().max(0) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport};
+    use crate::ApiClientError;
+
+    #[test]
+    fn test_key_is_stable() {
+        let key = CacheKey::new(&Method::GET, "/v1/lol/champion/skin?region=cn", None);
+        assert_eq!(key.as_str().len(), 40);
+        assert_eq!(
+            key,
+            CacheKey::new(&Method::GET, "/v1/lol/champion/skin?region=cn", None)
+        );
+        assert_ne!(
+            key,
+            CacheKey::new(&Method::GET, "/v1/lol/champion/skin?region=tw", None)
+        );
+        assert_ne!(
+            key,
+            CacheKey::new(&Method::POST, "/v1/lol/champion/skin?region=cn", None)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_get_responses_are_served_from_cache_until_expiry() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::GET, "/v1/lol/champion/skin", MockResponse::encrypted("first"))
+            .on(Method::GET, "/v1/lol/champion/skin", MockResponse::encrypted("second"));
+        let client = mock_client(&transport, |config| {
+            config.cache = Some(CacheConfig::memory(Duration::from_secs(60)))
+        });
+        let uri = "/v1/lol/champion/skin?region=cn";
+
+        assert_eq!(client.send(Method::GET, uri, None).await.unwrap(), "first");
+        let cached = client.send_detailed(Method::GET, uri, None).await.unwrap();
+        assert_eq!(cached.body, "first");
+        assert!(cached.meta.from_cache);
+        assert_eq!(transport.requests().len(), 1);
+        let stats = client.stats();
+        assert_eq!((stats.cache_hits, stats.cache_misses), (1, 1));
+        assert_eq!(stats.cache_hit_ratio, Some(0.5));
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert_eq!(client.send(Method::GET, uri, None).await.unwrap(), "second");
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_raw_bodies_are_cached_apart_from_opened_ones() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/news", MockResponse::encrypted("skins"));
+        let client = mock_client(&transport, |config| {
+            config.cache = Some(CacheConfig::memory(Duration::from_secs(60)))
+        });
+        let uri = "/v1/news";
+
+        let raw = client
+            .request(Method::GET, uri)
+            .parse_envelope(false)
+            .send()
+            .await
+            .unwrap();
+        assert!(raw.starts_with(r#"{"data":""#), "{}", raw);
+        assert_eq!(client.send(Method::GET, uri, None).await.unwrap(), "skins");
+        assert_eq!(transport.requests().len(), 2);
+        let cached = client
+            .request(Method::GET, uri)
+            .parse_envelope(false)
+            .send_detailed()
+            .await
+            .unwrap();
+        assert_eq!((cached.body, cached.meta.from_cache), (raw, true));
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_versions_are_cached_apart() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::GET, "/v1/news", MockResponse::encrypted("unversioned"))
+            .on(Method::GET, "/v1/news", MockResponse::encrypted("2025-01-15"));
+        let client = mock_client(&transport, |config| {
+            config.cache = Some(CacheConfig::memory(Duration::from_secs(60)))
+        });
+        let versioned = || client.request(Method::GET, "/v1/news").api_version("2025-01-15");
+
+        assert_eq!(client.send(Method::GET, "/v1/news", None).await.unwrap(), "unversioned");
+        let fresh = versioned().send_detailed().await.unwrap();
+        assert_eq!((fresh.body.as_str(), fresh.meta.from_cache), ("2025-01-15", false));
+        let cached = versioned().send_detailed().await.unwrap();
+        assert_eq!((cached.body.as_str(), cached.meta.from_cache), ("2025-01-15", true));
+        assert_eq!(client.send(Method::GET, "/v1/news", None).await.unwrap(), "unversioned");
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[cfg(feature = "charset")]
+    #[tokio::test]
+    async fn test_charsets_are_cached_apart() {
+        use crate::charset::Charset;
+
+        let (gbk, _, _) = encoding_rs::GBK.encode(r#"{"name":"安妮"}"#);
+        let envelope = serde_json::json!({ "data": crate::testing::test_crypto().encrypt_hex(&gbk) });
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/news", MockResponse::json(StatusCode::OK, &envelope));
+        let client = mock_client(&transport, |config| {
+            config.cache = Some(CacheConfig::memory(Duration::from_secs(60)))
+        });
+        let as_gbk = || client.request(Method::GET, "/v1/news").response_charset(Charset::Gbk);
+
+        assert_eq!(as_gbk().send().await.unwrap(), r#"{"name":"安妮"}"#);
+        assert!(as_gbk().send_detailed().await.unwrap().meta.from_cache);
+        assert!(matches!(
+            client.send(Method::GET, "/v1/news", None).await,
+            Err(ApiClientError::InvalidCharset {
+                charset: Charset::Utf8,
+                offset: 9
+            })
+        ));
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    fn key(i: usize) -> CacheKey {
+        CacheKey::new(&Method::GET, &format!("/v1/user/{}/profile", i), None)
+    }
+
+    #[tokio::test]
+    async fn test_distinct_keys_stay_within_the_bounds() {
+        let cache = MemoryCache::new().max_entries(1_000);
+        let ttl = Duration::from_secs(60);
+        for i in 0..100_000 {
+            cache.put(&key(i), format!("profile {:06}", i), ttl).await;
+            if i == 500 {
+                // Read, so the oldest entries are now the ones after it.
+                assert!(cache.get(&key(0)).await.is_some());
+            }
+        }
+        let usage = cache.usage().unwrap();
+        assert_eq!((usage.entries, usage.bytes, usage.evictions), (1_000, 14_000, 99_000));
+        assert_eq!(cache.lru.lock().unwrap().order.len(), 1_000);
+        assert_eq!(cache.get(&key(0)).await, None);
+        assert_eq!(cache.get(&key(98_999)).await, None);
+        for i in 99_000..100_000 {
+            assert!(cache.get(&key(i)).await.is_some(), "{}", i);
+        }
+
+        let cache = MemoryCache::new().max_entries(1_000);
+        for i in 0..10 {
+            cache.put(&key(i), format!("profile {:06}", i), ttl).await;
+        }
+        cache.get(&key(0)).await;
+        let cache = cache.max_entries(3);
+        cache.put(&key(10), String::new(), ttl).await;
+        let mut present = Vec::new();
+        for i in 0..11 {
+            present.push(cache.get(&key(i)).await.is_some());
+        }
+        // With ten already held, putting one more evicts down to two, least recently used first.
+        assert_eq!(
+            present,
+            [true, false, false, false, false, false, false, false, false, true, true]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_bytes_are_bounded() {
+        let cache = MemoryCache::new().max_bytes(10);
+        let ttl = Duration::from_secs(60);
+        cache.put(&key(1), "aaaa".to_string(), ttl).await;
+        cache.put(&key(2), "bbbb".to_string(), ttl).await;
+        cache.put(&key(3), "cccc".to_string(), ttl).await;
+        assert_eq!(cache.get(&key(1)).await, None);
+        cache.put(&key(2), "bb".to_string(), ttl).await;
+        assert_eq!(
+            cache.usage(),
+            Some(CacheUsage {
+                entries: 2,
+                bytes: 6,
+                evictions: 1
+            })
+        );
+        cache.put(&key(4), "x".repeat(11), ttl).await;
+        cache.put(&key(2), "y".repeat(11), ttl).await;
+        assert_eq!((cache.get(&key(4)).await, cache.get(&key(2)).await), (None, None));
+        assert_eq!(cache.usage().unwrap().bytes, 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_usage_is_reported_in_stats() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/user", MockResponse::encrypted("name"));
+        let client = mock_client(&transport, |config| {
+            config.cache = Some(CacheConfig {
+                backend: Arc::new(MemoryCache::new().max_entries(2)),
+                ttl: Duration::from_secs(60),
+                max_stale: None,
+            })
+        });
+        for id in 1..=3 {
+            client
+                .send(Method::GET, &format!("/v1/user?id={}", id), None)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            client.stats().cache,
+            Some(CacheUsage {
+                entries: 2,
+                bytes: 8,
+                evictions: 1
+            })
+        );
+        assert_eq!(mock_client(&transport, |_| {}).stats().cache, None);
+    }
+
+    fn serving_stale(config: &mut crate::ApiClientConfig) {
+        config.cache = Some(CacheConfig::memory(Duration::from_secs(60)).serve_stale(Duration::from_secs(300)));
+        config.attempt_timeout = Some(Duration::from_secs(1));
+        config.retry.max_retries = 0;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expired_entries_are_served_when_the_server_times_out() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::GET, "/v1/lol/champion", MockResponse::encrypted("catalog"))
+            .on(
+                Method::GET,
+                "/v1/lol/champion",
+                MockResponse::encrypted("late").with_delay(Duration::from_secs(10)),
+            );
+        let client = mock_client(&transport, serving_stale);
+        let uri = "/v1/lol/champion";
+
+        assert_eq!(client.send(Method::GET, uri, None).await.unwrap(), "catalog");
+        let fresh = client.send_detailed(Method::GET, uri, None).await.unwrap();
+        assert!(fresh.meta.from_cache && !fresh.meta.stale);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        let stale = client.send_detailed(Method::GET, uri, None).await.unwrap();
+        assert_eq!(stale.body, "catalog");
+        assert!(stale.meta.from_cache && stale.meta.stale);
+        let stats = client.stats();
+        assert_eq!((stats.stale_served, stats.errors.transport), (1, 1));
+
+        tokio::time::advance(Duration::from_secs(300)).await;
+        assert!(matches!(
+            client.send(Method::GET, uri, None).await,
+            Err(ApiClientError::AttemptTimeout { .. })
+        ));
+        assert_eq!(client.stats().stale_served, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_entries_never_answer_posts_or_rejected_requests() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::GET, "/v1/catalog", MockResponse::encrypted("catalog"))
+            .on(
+                Method::GET,
+                "/v1/catalog",
+                MockResponse::status(StatusCode::UNAUTHORIZED),
+            )
+            .on(
+                Method::POST,
+                "/v1/catalog",
+                MockResponse::status(StatusCode::SERVICE_UNAVAILABLE),
+            );
+        let client = mock_client(&transport, serving_stale);
+
+        assert_eq!(client.send(Method::GET, "/v1/catalog", None).await.unwrap(), "catalog");
+        tokio::time::advance(Duration::from_secs(61)).await;
+        match client.send(Method::POST, "/v1/catalog", None).await {
+            Err(ApiClientError::HttpStatus { status, .. }) => assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE),
+            other => panic!("unexpected {:?}", other),
+        }
+        match client.send(Method::GET, "/v1/catalog", None).await {
+            Err(ApiClientError::HttpStatus { status, .. }) => assert_eq!(status, StatusCode::UNAUTHORIZED),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(client.stats().stale_served, 0);
+    }
+
+    #[cfg(feature = "disk-cache")]
+    mod disk {
+        use std::path::PathBuf;
+
+        use super::*;
+        use crate::testing::test_crypto;
+
+        fn cache_dir(name: &str) -> PathBuf {
+            let dir = std::env::temp_dir().join(format!("ho-sdk-cache-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            dir
+        }
+
+        fn disk_cache(dir: &PathBuf) -> CacheConfig {
+            CacheConfig {
+                backend: Arc::new(DiskCache::open(dir).unwrap().encrypted(test_crypto())),
+                ttl: Duration::from_secs(60),
+                max_stale: None,
+            }
+        }
+
+        #[tokio::test]
+        async fn test_entries_survive_a_new_client() {
+            let dir = cache_dir("persist");
+            let uri = "/v1/lol/champion/skin?region=cn";
+            let transport = MockTransport::new();
+            transport.on(Method::GET, "/v1/lol/champion/skin", MockResponse::encrypted("安妮"));
+
+            let first = mock_client(&transport, |config| config.cache = Some(disk_cache(&dir)));
+            assert_eq!(first.send(Method::GET, uri, None).await.unwrap(), "安妮");
+            drop(first);
+
+            let second = mock_client(&transport, |config| config.cache = Some(disk_cache(&dir)));
+            assert_eq!(second.send(Method::GET, uri, None).await.unwrap(), "安妮");
+            assert_eq!(transport.requests().len(), 1);
+
+            let entries: Vec<PathBuf> = std::fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| !path.ends_with("index.json"))
+                .collect();
+            assert_eq!(entries.len(), 1);
+            assert!(!std::fs::read_to_string(&entries[0]).unwrap().contains("安妮"));
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+
+        #[tokio::test]
+        async fn test_entries_expire_on_disk() {
+            let dir = cache_dir("ttl");
+            let cache = DiskCache::open(&dir).unwrap();
+            let key = CacheKey::new(&Method::GET, "/v1/x", None);
+            cache.put(&key, "value".to_string(), Duration::from_millis(20)).await;
+            assert_eq!(cache.get(&key).await.as_deref(), Some("value"));
+
+            std::thread::sleep(Duration::from_millis(40));
+            assert_eq!(DiskCache::open(&dir).unwrap().get(&key).await, None);
+            assert_eq!(cache.get(&key).await, None);
+            assert!(!dir.join(key.as_str()).exists());
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+
+        #[tokio::test]
+        async fn test_expired_entries_stay_for_stale_reads() {
+            let dir = cache_dir("stale");
+            let cache = DiskCache::open(&dir).unwrap();
+            let key = CacheKey::new(&Method::GET, "/v1/x", None);
+            cache.put(&key, "value".to_string(), Duration::from_millis(20)).await;
+
+            std::thread::sleep(Duration::from_millis(40));
+            assert_eq!(cache.get(&key).await, None);
+            let stale = cache.get_stale(&key, Duration::from_secs(60)).await;
+            assert_eq!(stale.as_deref(), Some("value"));
+            assert_eq!(cache.get_stale(&key, Duration::from_millis(1)).await, None);
+            assert!(!dir.join(key.as_str()).exists());
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+
+        #[tokio::test]
+        async fn test_corrupted_files_are_misses() {
+            let dir = cache_dir("corrupt");
+            let key = CacheKey::new(&Method::GET, "/v1/x", None);
+            let cache = DiskCache::open(&dir).unwrap().encrypted(test_crypto());
+            cache.put(&key, "value".to_string(), Duration::from_secs(60)).await;
+
+            std::fs::write(dir.join(key.as_str()), "not ciphertext").unwrap();
+            assert_eq!(cache.get(&key).await, None);
+            cache.put(&key, "again".to_string(), Duration::from_secs(60)).await;
+            assert_eq!(cache.get(&key).await.as_deref(), Some("again"));
+
+            std::fs::write(dir.join("index.json"), "{ truncated").unwrap();
+            let reopened = DiskCache::open(&dir).unwrap().encrypted(test_crypto());
+            assert_eq!(reopened.get(&key).await, None);
+            reopened.put(&key, "fresh".to_string(), Duration::from_secs(60)).await;
+            assert_eq!(reopened.get(&key).await.as_deref(), Some("fresh"));
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+    }
+}
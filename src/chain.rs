@@ -0,0 +1,439 @@
+//! Dependent calls: values extracted from one decrypted response fill the path and body of
+//! the next. Created with `ApiClient::chain`:
+//!
+//! ```no_run
+//! # async fn run(client: &ho_api_rust_sdk::ApiClient) -> Result<(), ho_api_rust_sdk::ApiClientError> {
+//! use reqwest::Method;
+//! use serde_json::json;
+//!
+//! let result = client
+//!     .chain()
+//!     .call(Method::GET, "/v1/a", None)
+//!     .extract("token", "$.data.token")
+//!     .then(Method::POST, "/v1/b/{token}", Some(json!({ "token": "{token}" })))
+//!     .run()
+//!     .await?;
+//! println!("{}", result.body);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Paths are a JSONPath subset: `$`, then `.field` and `[index]` segments. In a uri template
+//! `{name}` is replaced by the percent-encoded value; in a body template a string that is
+//! exactly `"{name}"` becomes the extracted value with its JSON type, and `{name}` inside a
+//! longer string is replaced by its text.
+
+use std::collections::BTreeMap;
+
+use reqwest::Method;
+use serde_json::Value;
+
+use crate::{ApiClient, ApiClientError};
+
+pub struct Chain<'a> {
+    client: &'a ApiClient,
+    steps: Vec<Step>,
+}
+
+struct Step {
+    method: Method,
+    uri: String,
+    body: Option<Value>,
+    extracts: Vec<(String, String)>,
+}
+
+/// The plaintext of the last call and every value extracted along the way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainResult {
+    pub body: String,
+    pub values: BTreeMap<String, Value>,
+}
+
+impl ApiClient {
+    pub fn chain(&self) -> Chain<'_> {
+        Chain {
+            client: self,
+            steps: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Chain<'a> {
+    /// Adds a call. `uri` and `body` may refer to values extracted by earlier calls.
+    pub fn call(mut self, method: Method, uri: &str, body: Option<Value>) -> Self {
+        self.steps.push(Step {
+            method,
+            uri: uri.to_string(),
+            body,
+            extracts: Vec::new(),
+        });
+        self
+    }
+
+    /// Same as `call`; reads better after the first one.
+    pub fn then(self, method: Method, uri: &str, body: Option<Value>) -> Self {
+        self.call(method, uri, body)
+    }
+
+    /// Takes the value at `path` out of the response of the last call added and names it
+    /// `name` for the calls after it.
+    ///
+    /// # Panics
+    ///
+    /// If no call has been added yet.
+    pub fn extract(mut self, name: &str, path: &str) -> Self {
+        let step = self.steps.last_mut().expect("`extract` follows a `call`");
+        step.extracts.push((name.to_string(), path.to_string()));
+        self
+    }
+
+    /// Runs the calls in order, stopping at the first that fails. Errors are
+    /// `ChainStepFailed`, naming the step (from 1) and its uri.
+    pub async fn run(self) -> Result<ChainResult, ApiClientError> {
+        let mut values = BTreeMap::new();
+        let mut body = String::new();
+        for (i, step) in self.steps.into_iter().enumerate() {
+            let failed = |uri: &str, source| ApiClientError::ChainStepFailed {
+                step: i + 1,
+                uri: uri.to_string(),
+                source: Box::new(source),
+            };
+            let uri = render_uri(&step.uri, &values).map_err(|e| failed(&step.uri, e))?;
+            let request_body = match &step.body {
+                Some(template) => Some(render_body(template, &values).map_err(|e| failed(&uri, e))?),
+                None => None,
+            };
+            body = self
+                .client
+                .send(step.method, &uri, request_body)
+                .await
+                .map_err(|e| failed(&uri, e))?;
+            if step.extracts.is_empty() {
+                continue;
+            }
+            let payload: Value = serde_json::from_str(&body).map_err(|e| failed(&uri, e.into()))?;
+            for (name, path) in step.extracts {
+                let value = extract(&payload, &path).map_err(|e| failed(&uri, e))?;
+                values.insert(name, value.clone());
+            }
+        }
+        Ok(ChainResult { body, values })
+    }
+}
+
+pub(crate) enum Segment<'p> {
+    Field(&'p str),
+    Index(usize),
+}
+
+pub(crate) fn parse_path(path: &str) -> Result<Vec<Segment<'_>>, ApiClientError> {
+    let invalid = |message: &str| ApiClientError::InvalidPayload {
+        path: path.to_string(),
+        message: message.to_string(),
+    };
+    let mut rest = path.strip_prefix('$').unwrap_or(path);
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(invalid("empty field name"));
+            }
+            segments.push(Segment::Field(&after[..end]));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let (index, tail) = after.split_once(']').ok_or_else(|| invalid("unclosed `[`"))?;
+            let index = index.parse().map_err(|_| invalid("index is not a number"))?;
+            segments.push(Segment::Index(index));
+            rest = tail;
+        } else if segments.is_empty() {
+            // A leading field without `$.`, as in `data.token`.
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            segments.push(Segment::Field(&rest[..end]));
+            rest = &rest[end..];
+        } else {
+            return Err(invalid("expected `.field` or `[index]`"));
+        }
+    }
+    Ok(segments)
+}
+
+/// The value at the parsed path, if there is one.
+#[cfg(feature = "export")]
+pub(crate) fn lookup<'v>(value: &'v Value, segments: &[Segment<'_>]) -> Option<&'v Value> {
+    segments.iter().try_fold(value, |value, segment| match segment {
+        Segment::Field(field) => value.get(field),
+        Segment::Index(index) => value.get(index),
+    })
+}
+
+/// The value at `path`. A missing field lists the keys present where it was looked up.
+fn extract<'v>(payload: &'v Value, path: &str) -> Result<&'v Value, ApiClientError> {
+    let mut value = payload;
+    let mut seen = String::from("$");
+    for segment in parse_path(path)? {
+        let next = match segment {
+            Segment::Field(field) => value.get(field),
+            Segment::Index(index) => value.get(index),
+        };
+        let Some(next) = next else {
+            let message = match (&segment, value) {
+                (Segment::Field(field), Value::Object(map)) => {
+                    let keys: Vec<&str> = map.keys().map(String::as_str).collect();
+                    format!("no field `{}` at {}; available keys: {}", field, seen, keys.join(", "))
+                }
+                (Segment::Index(index), Value::Array(items)) => {
+                    format!("index {} out of range at {} of length {}", index, seen, items.len())
+                }
+                (_, other) => format!("{} is {}, not an object or array", seen, json_type(other)),
+            };
+            return Err(ApiClientError::InvalidPayload {
+                path: path.to_string(),
+                message,
+            });
+        };
+        match segment {
+            Segment::Field(field) => {
+                seen.push('.');
+                seen.push_str(field);
+            }
+            Segment::Index(index) => seen.push_str(&format!("[{}]", index)),
+        }
+        value = next;
+    }
+    Ok(value)
+}
+
+fn render_uri(template: &str, values: &BTreeMap<String, Value>) -> Result<String, ApiClientError> {
+    render(template, values, |value| Some(percent_encode(&scalar_text(value)?)))
+}
+
+fn render_body(template: &Value, values: &BTreeMap<String, Value>) -> Result<Value, ApiClientError> {
+    Ok(match template {
+        Value::String(s) => match placeholder(s).and_then(|name| values.get(name)) {
+            Some(value) => value.clone(),
+            None => Value::String(render(s, values, scalar_text)?),
+        },
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| render_body(item, values))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, item)| Ok((key.clone(), render_body(item, values)?)))
+                .collect::<Result<_, ApiClientError>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+/// The name in a template that is a single `{name}` placeholder.
+fn placeholder(s: &str) -> Option<&str> {
+    let name = s.strip_prefix('{')?.strip_suffix('}')?;
+    (!name.is_empty() && !name.contains(['{', '}'])).then_some(name)
+}
+
+/// Replaces every `{name}` in `template` with `text(value)`.
+fn render(
+    template: &str,
+    values: &BTreeMap<String, Value>,
+    text: impl Fn(&Value) -> Option<String>,
+) -> Result<String, ApiClientError> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 1..start + len];
+        let value = values.get(name).ok_or_else(|| ApiClientError::RequestValidation {
+            field: format!("{{{}}}", name),
+            constraint: "a value extracted by an earlier step".to_string(),
+            got: format!("nothing; extracted so far: {}", known(values)),
+        })?;
+        let replacement = text(value).ok_or_else(|| ApiClientError::RequestValidation {
+            field: format!("{{{}}}", name),
+            constraint: "a string, number or boolean inside a string template".to_string(),
+            got: json_type(value).to_string(),
+        })?;
+        out.push_str(&rest[..start]);
+        out.push_str(&replacement);
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn known(values: &BTreeMap<String, Value>) -> String {
+    match values.is_empty() {
+        true => "none".to_string(),
+        false => values.keys().map(String::as_str).collect::<Vec<_>>().join(", "),
+    }
+}
+
+fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters, so a value stays within
+/// one path segment.
+pub(crate) fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport};
+
+    #[test]
+    fn test_extract_follows_fields_and_indexes() {
+        let payload = json!({ "data": { "token": "t", "list": [{ "id": 1 }, { "id": 2 }] } });
+        assert_eq!(extract(&payload, "$.data.token").unwrap(), "t");
+        assert_eq!(extract(&payload, "data.list[1].id").unwrap(), 2);
+        assert_eq!(extract(&payload, "$").unwrap(), &payload);
+
+        let message = |path| match extract(&payload, path) {
+            Err(ApiClientError::InvalidPayload { message, .. }) => message,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(
+            message("$.data.tokn"),
+            "no field `tokn` at $.data; available keys: list, token"
+        );
+        assert_eq!(
+            message("$.data.list[5]"),
+            "index 5 out of range at $.data.list of length 2"
+        );
+        assert_eq!(
+            message("$.data.token.x"),
+            "$.data.token is a string, not an object or array"
+        );
+        assert_eq!(message("$.data[x"), "unclosed `[`");
+    }
+
+    #[test]
+    fn test_templates_keep_types_and_encode_paths() {
+        let values = BTreeMap::from([
+            ("token".to_string(), json!("a b/c")),
+            ("id".to_string(), json!(7)),
+            ("tags".to_string(), json!(["x"])),
+        ]);
+        assert_eq!(render_uri("/v1/b/{token}/{id}", &values).unwrap(), "/v1/b/a%20b%2Fc/7");
+        assert_eq!(
+            render_body(&json!({ "id": "{id}", "tags": ["{tags}"], "note": "id {id}" }), &values).unwrap(),
+            json!({ "id": 7, "tags": [["x"]], "note": "id 7" })
+        );
+        assert!(matches!(
+            render_uri("/v1/b/{tags}", &values),
+            Err(ApiClientError::RequestValidation { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_second_call_uses_values_from_the_first() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::GET,
+                "/v1/session",
+                MockResponse::encrypted(r#"{"data":{"token":"tok 1","user":{"id":42}}}"#),
+            )
+            .on(
+                Method::POST,
+                "/v1/orders/tok%201",
+                MockResponse::encrypted(r#"{"ok":true}"#),
+            );
+        let client = mock_client(&transport, |_| {});
+
+        let result = client
+            .chain()
+            .call(Method::GET, "/v1/session", None)
+            .extract("token", "$.data.token")
+            .extract("user", "$.data.user.id")
+            .then(Method::POST, "/v1/orders/{token}", Some(json!({ "user_id": "{user}" })))
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(result.body, r#"{"ok":true}"#);
+        assert_eq!(result.values["user"], 42);
+
+        let requests = transport.requests();
+        assert!(requests[1].url.ends_with("/v1/orders/tok%201"), "{}", requests[1].url);
+        assert_eq!(&requests[1].body[..], br#"{"data":"{\"user_id\":42}"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_errors_name_the_failing_step() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::GET,
+                "/v1/session",
+                MockResponse::encrypted(r#"{"data":{"tok":"t"}}"#),
+            )
+            .on(Method::GET, "/v1/orders/t", MockResponse::status(StatusCode::NOT_FOUND));
+        let client = mock_client(&transport, |_| {});
+
+        let err = client
+            .chain()
+            .call(Method::GET, "/v1/session", None)
+            .extract("token", "$.data.token")
+            .then(Method::GET, "/v1/orders/{token}", None)
+            .run()
+            .await
+            .unwrap_err();
+        match err {
+            ApiClientError::ChainStepFailed { step, uri, source } => {
+                assert_eq!((step, uri.as_str()), (1, "/v1/session"));
+                match *source {
+                    ApiClientError::InvalidPayload { message, .. } => {
+                        assert_eq!(message, "no field `token` at $.data; available keys: tok")
+                    }
+                    other => panic!("unexpected {:?}", other),
+                }
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let err = client
+            .chain()
+            .call(Method::GET, "/v1/session", None)
+            .extract("token", "$.data.tok")
+            .then(Method::GET, "/v1/orders/{token}", None)
+            .run()
+            .await
+            .unwrap_err();
+        assert!(matches!(&err, ApiClientError::ChainStepFailed { step: 2, uri, .. } if uri == "/v1/orders/t"));
+        assert_eq!(err.status(), Some(StatusCode::NOT_FOUND));
+        assert!(err.is_invalid_request());
+    }
+}
@@ -0,0 +1,100 @@
+//! Text encodings of decrypted payloads. Everything is UTF-8 except a few legacy endpoints
+//! that send GBK; those need the `charset` feature.
+
+use std::fmt;
+
+use crate::ApiClientError;
+
+/// Non-exhaustive, as the `charset` feature adds variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Charset {
+    #[default]
+    Utf8,
+    #[cfg(feature = "charset")]
+    Gbk,
+    /// UTF-8 if the bytes are valid UTF-8, GBK otherwise.
+    #[cfg(feature = "charset")]
+    Auto,
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Charset::Utf8 => "UTF-8",
+            #[cfg(feature = "charset")]
+            Charset::Gbk => "GBK",
+            #[cfg(feature = "charset")]
+            Charset::Auto => "UTF-8 or GBK",
+        })
+    }
+}
+
+/// Decodes `bytes` as `charset`. Invalid text is an `InvalidCharset` naming the charset and
+/// the offset of the first bad byte.
+pub(crate) fn decode(bytes: Vec<u8>, charset: Charset) -> Result<String, ApiClientError> {
+    match charset {
+        Charset::Utf8 => String::from_utf8(bytes).map_err(|e| ApiClientError::InvalidCharset {
+            charset: Charset::Utf8,
+            offset: e.utf8_error().valid_up_to(),
+        }),
+        #[cfg(feature = "charset")]
+        Charset::Gbk => decode_gbk(&bytes),
+        #[cfg(feature = "charset")]
+        Charset::Auto => String::from_utf8(bytes).or_else(|e| decode_gbk(e.as_bytes())),
+    }
+}
+
+#[cfg(feature = "charset")]
+fn decode_gbk(bytes: &[u8]) -> Result<String, ApiClientError> {
+    use encoding_rs::{DecoderResult, GBK};
+
+    let mut decoder = GBK.new_decoder_without_bom_handling();
+    let capacity = decoder
+        .max_utf8_buffer_length_without_replacement(bytes.len())
+        .expect("payload length fits in usize");
+    let mut text = String::with_capacity(capacity);
+    match decoder.decode_to_string_without_replacement(bytes, &mut text, true) {
+        (DecoderResult::InputEmpty, _) => Ok(text),
+        (DecoderResult::Malformed(bad, unread), read) => Err(ApiClientError::InvalidCharset {
+            charset: Charset::Gbk,
+            offset: read - bad as usize - unread as usize,
+        }),
+        (DecoderResult::OutputFull, _) => unreachable!("the buffer is sized for the worst case"),
+    }
+}
+
+#[cfg(all(test, feature = "charset"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gbk_and_auto_decode_chinese() {
+        let (gbk, _, _) = encoding_rs::GBK.encode("安妮");
+        assert_eq!(gbk.as_ref(), [0xb0, 0xb2, 0xc4, 0xdd]);
+        assert_eq!(decode(gbk.to_vec(), Charset::Gbk).unwrap(), "安妮");
+        assert_eq!(decode(gbk.to_vec(), Charset::Auto).unwrap(), "安妮");
+        assert_eq!(decode("安妮".as_bytes().to_vec(), Charset::Auto).unwrap(), "安妮");
+        assert!(matches!(
+            decode(gbk.to_vec(), Charset::Utf8),
+            Err(ApiClientError::InvalidCharset {
+                charset: Charset::Utf8,
+                offset: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_gbk_reports_offset() {
+        let bytes = b"{\"name\":\"\xb0\xb2\xff\"}".to_vec();
+        for charset in [Charset::Gbk, Charset::Auto] {
+            match decode(bytes.clone(), charset) {
+                Err(ApiClientError::InvalidCharset { charset, offset }) => {
+                    assert_eq!(charset, Charset::Gbk);
+                    assert_eq!(offset, 11);
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,1851 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE, USER_AGENT};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::time::Instant;
+
+use crate::body::{check_double_encoding, PreparedBody};
+use crate::bootstrap::Recommendations;
+use crate::bulkhead::Bulkheads;
+use crate::cache::{CacheConfig, CacheKey};
+use crate::charset::{self, Charset};
+use crate::connections::{Connections, StaleConnectionReset};
+use crate::crypto::Crypto;
+use crate::deprecation::{self, VERSION_HEADER};
+use crate::drift::DriftMonitor;
+use crate::envelope;
+use crate::failover::{Endpoint, FailoverConfig, HostSelector};
+use crate::keepalive::{Activity, Keepalive, KeepaliveConfig};
+use crate::maintenance::Maintenance;
+use crate::migrate::IntoValidatedConfig;
+use crate::nonce::{self, NonceGuard};
+use crate::offload::{self, DEFAULT_OFFLOAD_THRESHOLD};
+use crate::priority::{Priority, PriorityLimiter, PriorityPermit};
+use crate::processor::ResponseProcessor;
+use crate::quota::{QuotaReserve, QuotaTracker};
+use crate::redact::RedactionConfig;
+use crate::redirect;
+use crate::request::{check_api_version, AuthMode, RequestOptions};
+use crate::response::{ApiResponse, DecryptionKey, ResponseMeta, Timings};
+use crate::retry::RetryConfig;
+use crate::self_check;
+use crate::signing::{self, SignInputs, SignMode};
+use crate::stats::{ErrorClass, Stats, StatsCollector};
+use crate::time::{self, TimeSource};
+use crate::transport::{
+    self, ConnectionReuse, RedirectRefusal, ReqwestTransport, Route, Transport, TransportConfig, TransportRequest,
+    TransportResponse,
+};
+use crate::triage;
+use crate::validation::ValidationRegistry;
+use crate::ApiClientError;
+
+#[derive(Default)]
+struct TransferSizes {
+    sent: u64,
+    received: u64,
+}
+
+/// A request's place in the concurrency limits, held until the response body is read.
+pub(crate) struct Admission<'a> {
+    pub(crate) permit: Option<PriorityPermit>,
+    pub(crate) queue_time: Duration,
+    /// The bulkhead pool the permit came from.
+    pub(crate) pool: Option<&'a str>,
+}
+
+pub struct ApiClient {
+    pub(crate) config: ApiClientConfig,
+    pub(crate) crypto: Crypto,
+    pub(crate) secondary_crypto: Option<Crypto>,
+    pub(crate) stats: Arc<StatsCollector>,
+    pub(crate) hosts: HostSelector,
+    pub(crate) transport: Arc<dyn Transport>,
+    pub(crate) limiter: Option<Arc<PriorityLimiter>>,
+    pub(crate) bulkheads: Bulkheads,
+    pub(crate) processors: Vec<ResponseProcessor>,
+    pub(crate) drift: DriftMonitor,
+    pub(crate) activity: Arc<Activity>,
+    pub(crate) recommendations: Recommendations,
+    pub(crate) connections: Connections,
+    pub(crate) maintenance: Maintenance,
+    pub(crate) quota: QuotaTracker,
+    pub(crate) nonces: Option<Arc<NonceGuard>>,
+    keepalive: Option<Keepalive>,
+}
+
+/// A second key and IV for responses, tried when the primary fails to decrypt one.
+#[derive(Debug, Clone)]
+pub struct SecondaryKey {
+    pub app_secret: String,
+    pub iv: String,
+}
+
+#[derive(Clone)]
+pub struct ApiClientConfig {
+    pub app_id: String,
+    pub app_secret: String,
+    pub iv: String,
+    pub base_url: String,
+    pub content: String,
+    /// Tried in order of health after `base_url` when a host fails at the transport level
+    /// or answers with a 5xx status.
+    pub fallback_base_urls: Vec<String>,
+    pub failover: FailoverConfig,
+    /// Upper bound on requests in flight at once. A request holds its permit until the
+    /// response body has been read in full.
+    pub max_concurrent_requests: Option<usize>,
+    /// Fails requests that waited longer than this for a concurrency permit with
+    /// `QueueTimeout`, without sending them. `None` waits indefinitely.
+    pub max_queue_wait: Option<Duration>,
+    /// While requests queue for permits, `RequestBuilder::priority` decides who goes next.
+    /// A waiter counts one class higher for every period of this it has waited, so low
+    /// priority requests are not starved; 5 seconds by default, `None` never promotes.
+    pub priority_promotion: Option<Duration>,
+    /// Named concurrency pools (bulkheads) by size, such as `{"reports": 4, "default": 60}`.
+    /// A request in a pool waits only for that pool's permits, never for
+    /// `max_concurrent_requests` or another pool's; `max_queue_wait` applies to each.
+    pub pools: BTreeMap<String, usize>,
+    /// `(pattern, pool)` pairs assigning uris to `pools`, the first match winning. Patterns
+    /// are exact paths or prefixes ending in `*`, as in `ValidationRegistry`. Unmatched
+    /// requests go to the pool named `default` if there is one, else `max_concurrent_requests`.
+    pub pool_routes: Vec<(String, String)>,
+    /// Logs a warning when the clock offset estimated from response `Date` headers grows
+    /// beyond this. `None` disables the warning; the estimate is kept either way.
+    pub clock_drift_warn_threshold: Option<Duration>,
+    /// Rejects ciphertext with surrounding whitespace or a `0x` prefix instead of skipping
+    /// them.
+    pub strict_hex: bool,
+    /// Whether the content path is part of the signed uri. The HO server signs the uri
+    /// alone, so this is off by default; turn it on for gateways that sign the full path.
+    pub sign_content_path: bool,
+    /// Limit on a single network attempt, retries and failover hops each getting their own.
+    /// `None` leaves only the transport's own timeout.
+    pub attempt_timeout: Option<Duration>,
+    /// Limit on the whole request, from the moment it leaves the cache: waiting on
+    /// `quota_reserve` and for a concurrency permit, every attempt and the backoff between
+    /// retries. When only this is set, a single attempt may use all of it. Running out while
+    /// queued fails with `OverallTimeout`, whichever of it and `max_queue_wait` comes first.
+    pub overall_timeout: Option<Duration>,
+    pub retry: RetryConfig,
+    /// Checks requests against `validation` before signing them. On by default.
+    pub validate_requests: bool,
+    pub validation: ValidationRegistry,
+    /// Timeouts and DNS fallback for the default transport. Ignored by
+    /// `ApiClient::with_transport`.
+    pub transport: TransportConfig,
+    /// Caches the plaintext of successful GET responses. `None`, the default, disables it.
+    pub cache: Option<CacheConfig>,
+    /// Rejects requests whose body, as sent, would exceed this many bytes, before anything
+    /// is signed or sent.
+    pub max_request_size: Option<usize>,
+    /// Sent as `HO-VERSION` on every request to select versioned server behavior, e.g.
+    /// `"2024-06-01"`. `None` leaves the server default. `RequestBuilder::api_version`
+    /// overrides it per request.
+    pub api_version: Option<String>,
+    /// The previous or next secret during a key rotation. Responses that fail to decrypt
+    /// with `app_secret` and `iv` are decrypted with this instead; requests are still signed
+    /// with `app_secret` alone.
+    pub secondary_key: Option<SecondaryKey>,
+    /// Encoding of decrypted response bodies. GBK and auto-detection need the `charset`
+    /// feature.
+    pub response_charset: Charset,
+    /// Repeats an attempt once, on a newly opened connection, when the peer closes or resets
+    /// the connection before answering: the usual sign of a pooled connection that a
+    /// middle-box dropped while idle. Applies to buffered requests; streams are not repeated.
+    pub retry_once_on_stale_connection: bool,
+    /// Sends a HEAD ping whenever nothing has gone out for this long, so pooled connections
+    /// outlive a gateway idle timeout; set it below that timeout. Pings never overlap real
+    /// requests. `None`, the default, sends none. Needs a tokio runtime at construction.
+    pub keepalive_interval: Option<Duration>,
+    pub keepalive: KeepaliveConfig,
+    /// Resets the transport's connections, as `ApiClient::reset_connections` does, once this
+    /// many requests run into a closed connection within the window, and logs it. `None`, the
+    /// default, never resets on its own.
+    pub reset_connections_after: Option<StaleConnectionReset>,
+    /// Follows 301, 302, 307 and 308 responses, re-signing the request for the new location
+    /// and keeping its method and body. On by default; when off, a redirect is an
+    /// `HttpStatus` error.
+    pub follow_redirects: bool,
+    /// Redirects followed per attempt before giving up with `RedirectRefused`.
+    pub max_redirects: usize,
+    /// Follows signed requests to another scheme, host or port. Off by default, so the
+    /// app id and signature are only ever sent to the configured hosts; unsigned requests
+    /// follow such redirects regardless.
+    pub allow_cross_host_redirects: bool,
+    /// Runs `ApiClient::self_check` during construction and fails it on any warning as well
+    /// as on errors. Off by default.
+    pub strict_self_check: bool,
+    /// Fails responses the server marked as cut short (status 206 or `HO-TRUNCATED: true`)
+    /// with `ApiClientError::Truncated` instead of returning them with `meta.truncated`.
+    /// `RequestBuilder::fail_on_truncated` overrides it per request; pagination narrows the
+    /// page size instead, whatever the setting.
+    pub fail_on_truncated: bool,
+    /// Turns 401 and 403 responses with business code 40102 into
+    /// `ApiClientError::SignatureRejected`, with the component the server found at fault and
+    /// the lengths of the parts signed here; see `triage`. Costs a copy of the request
+    /// headers per attempt.
+    pub triage_signature_failures: bool,
+    /// The statuses that carry a payload, for gateways with unusual conventions. Any 2xx by
+    /// default; a list replaces that, so include 206 to keep truncated pages. Any other
+    /// status fails with `ApiClientError::HttpStatus`.
+    pub acceptable_statuses: Option<Vec<StatusCode>>,
+    /// Refuses request bodies that are a JSON string holding a JSON object or array, the
+    /// sign of a body serialized twice, with `ApiClientError::SuspectedDoubleEncoding`. On
+    /// by default; `RequestBuilder::allow_string_body` lets one request through.
+    pub detect_double_encoding: bool,
+    /// Fetches `ServerRecommendedConfig` from `/v1/client/bootstrap` before the first request
+    /// and merges it into the client, local settings taking precedence; see `bootstrap`.
+    /// Off by default.
+    pub apply_bootstrap: bool,
+    /// Fetches the recommendations again when a request finds them older than this. `None`,
+    /// the default, fetches them once.
+    pub bootstrap_refresh: Option<Duration>,
+    /// Fields masked in every body kept for observation, such as error bodies and recorded
+    /// exchanges; see `redact`. Returned payloads are left alone. `None` by default.
+    pub redaction: Option<RedactionConfig>,
+    /// The clock behind every timestamp, timeout, backoff and expiry of the client, its
+    /// failover scores and background tasks; see `time`. `RealTime` by default. A cache
+    /// backend keeps its own, such as `MemoryCache::time_source`.
+    pub time: Arc<dyn TimeSource>,
+    /// Parses, decrypts and deserializes response payloads of at least this many bytes on
+    /// tokio's blocking pool rather than the worker thread, so big pulls do not stall other
+    /// tasks; see `offload`. `DEFAULT_OFFLOAD_THRESHOLD` (256 KiB) by default, `None` keeps
+    /// every payload on the calling task.
+    pub offload_threshold: Option<usize>,
+    /// Holds requests of the given priorities back while the gateway reports little quota
+    /// left, keeping the rest for the others; see `quota`. `None` by default.
+    pub quota_reserve: Option<QuotaReserve>,
+    /// Remembers the last this many nonces signed with, failing a request about to reuse
+    /// one with `NonceReuse` before it is sent. `None`, the default, remembers none.
+    pub nonce_guard: Option<usize>,
+}
+
+impl Default for ApiClientConfig {
+    fn default() -> Self {
+        Self {
+            app_id: String::new(),
+            app_secret: String::new(),
+            iv: String::new(),
+            base_url: String::new(),
+            content: String::new(),
+            fallback_base_urls: Vec::new(),
+            failover: FailoverConfig::default(),
+            max_concurrent_requests: None,
+            max_queue_wait: None,
+            priority_promotion: Some(Duration::from_secs(5)),
+            pools: BTreeMap::new(),
+            pool_routes: Vec::new(),
+            clock_drift_warn_threshold: None,
+            strict_hex: false,
+            sign_content_path: false,
+            attempt_timeout: None,
+            overall_timeout: None,
+            retry: RetryConfig::default(),
+            validate_requests: true,
+            validation: ValidationRegistry::builtin(),
+            transport: TransportConfig::default(),
+            cache: None,
+            max_request_size: None,
+            api_version: None,
+            secondary_key: None,
+            response_charset: Charset::Utf8,
+            retry_once_on_stale_connection: true,
+            keepalive_interval: None,
+            keepalive: KeepaliveConfig::default(),
+            reset_connections_after: None,
+            follow_redirects: true,
+            max_redirects: 5,
+            allow_cross_host_redirects: false,
+            strict_self_check: false,
+            fail_on_truncated: false,
+            triage_signature_failures: false,
+            acceptable_statuses: None,
+            detect_double_encoding: true,
+            apply_bootstrap: false,
+            bootstrap_refresh: None,
+            redaction: None,
+            time: time::real(),
+            offload_threshold: Some(DEFAULT_OFFLOAD_THRESHOLD),
+            quota_reserve: None,
+            nonce_guard: None,
+        }
+    }
+}
+
+impl ApiClient {
+    /// Builds a client from an `ApiClientConfig`, migrated first as `ApiClientConfig::migrate`
+    /// does with its warnings logged, or from a `ValidatedConfig`.
+    pub fn new(config: impl IntoValidatedConfig) -> Result<Self, ApiClientError> {
+        let config = config.into_validated()?.into_inner();
+        let transport = ReqwestTransport::with_config(config.transport.clone())?;
+        Self::with_transport(config, Arc::new(transport))
+    }
+
+    pub fn with_transport(config: ApiClientConfig, transport: Arc<dyn Transport>) -> Result<Self, ApiClientError> {
+        if config.max_concurrent_requests == Some(0) {
+            return Err(ApiClientError::InvalidConfig(
+                "max_concurrent_requests must be at least 1".to_string(),
+            ));
+        }
+        if config.nonce_guard == Some(0) {
+            return Err(ApiClientError::InvalidConfig(
+                "nonce_guard must remember at least 1 nonce".to_string(),
+            ));
+        }
+        if config.acceptable_statuses.as_ref().is_some_and(Vec::is_empty) {
+            return Err(ApiClientError::InvalidConfig(
+                "acceptable_statuses must list at least one status".to_string(),
+            ));
+        }
+        if let Some(redaction) = &config.redaction {
+            redaction.check()?;
+        }
+        if let Some(version) = &config.api_version {
+            check_api_version(version)
+                .map_err(|constraint| ApiClientError::InvalidConfig(format!("api_version must be {}", constraint)))?;
+        }
+        if config.strict_self_check {
+            self_check::check_config(&config).into_result()?;
+        }
+        let crypto = Crypto::new(&config.app_secret, &config.iv)?
+            .strict_hex(config.strict_hex)
+            .charset(config.response_charset);
+        let secondary_crypto = match &config.secondary_key {
+            Some(key) => Some(
+                Crypto::new(&key.app_secret, &key.iv)
+                    .map_err(|_| {
+                        ApiClientError::InvalidConfig("secondary_key has an invalid secret or iv length".to_string())
+                    })?
+                    .strict_hex(config.strict_hex)
+                    .charset(config.response_charset),
+            ),
+            None => None,
+        };
+        let mut hosts = vec![Endpoint::dns(&config.base_url)];
+        if !config.transport.fallback_addrs.is_empty() && has_domain_host(&config.base_url) {
+            hosts.push(Endpoint {
+                base_url: config.base_url.clone(),
+                route: Route::FallbackAddrs,
+            });
+        }
+        hosts.extend(config.fallback_base_urls.iter().map(|url| Endpoint::dns(url)));
+        let hosts = HostSelector::new(hosts, config.failover.clone(), config.time.clone());
+        let drift = DriftMonitor::new(config.clock_drift_warn_threshold);
+        // Without a local ceiling, bootstrapping still needs a limiter to lower.
+        let config_limiter = match (config.max_concurrent_requests, config.apply_bootstrap) {
+            (Some(n), _) => Some(PriorityLimiter::new(n, config.priority_promotion, config.time.clone())),
+            (None, true) => Some(PriorityLimiter::new(
+                usize::MAX,
+                config.priority_promotion,
+                config.time.clone(),
+            )),
+            (None, false) => None,
+        };
+        let bulkheads = Bulkheads::new(
+            &config.pools,
+            &config.pool_routes,
+            config.priority_promotion,
+            &config.time,
+        )?;
+        let stats = Arc::new(StatsCollector::default());
+        let activity = Activity::new(config.time.clone());
+        let nonces = config.nonce_guard.map(|capacity| Arc::new(NonceGuard::new(capacity)));
+        let config_bootstrap_refresh = config.bootstrap_refresh;
+        let keepalive = match config.keepalive_interval {
+            Some(interval) => Some(Keepalive::spawn(
+                interval,
+                &config,
+                transport.clone(),
+                activity.clone(),
+                stats.clone(),
+                nonces.clone(),
+            )?),
+            None => None,
+        };
+        let client = Self {
+            config,
+            crypto,
+            secondary_crypto,
+            stats,
+            hosts,
+            transport,
+            limiter: config_limiter,
+            bulkheads,
+            processors: Vec::new(),
+            drift,
+            activity,
+            recommendations: Recommendations::new(config_bootstrap_refresh),
+            connections: Connections::default(),
+            maintenance: Maintenance::default(),
+            quota: QuotaTracker::default(),
+            nonces,
+            keepalive,
+        };
+        if client.config.strict_self_check {
+            client.self_check()?.into_result()?;
+        }
+        Ok(client)
+    }
+
+    /// Stops background work such as keepalive pings. Requests keep working; dropping the
+    /// client does the same.
+    pub fn shutdown(&self) {
+        if let Some(keepalive) = &self.keepalive {
+            keepalive.stop();
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.stats.snapshot();
+        stats.hosts = self.hosts.scores();
+        stats.pools = self.bulkheads.stats();
+        stats.cache = self.config.cache.as_ref().and_then(|cache| cache.backend.usage());
+        stats
+    }
+
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+        self.bulkheads.reset();
+    }
+
+    /// Smoothed server time minus local time, from the `Date` headers seen so far. Positive
+    /// when the server is ahead; `None` until a response with a usable `Date` arrives.
+    pub fn estimated_clock_drift(&self) -> Option<chrono::Duration> {
+        self.drift.estimate()
+    }
+
+    pub async fn send(&self, method: Method, uri: &str, body_option: Option<Value>) -> Result<String, ApiClientError> {
+        let mut request = self.request(method, uri);
+        if let Some(body) = body_option {
+            request = request.body(body);
+        }
+        request.send().await
+    }
+
+    pub async fn send_detailed(
+        &self,
+        method: Method,
+        uri: &str,
+        body_option: Option<Value>,
+    ) -> Result<ApiResponse, ApiClientError> {
+        let mut request = self.request(method, uri);
+        if let Some(body) = body_option {
+            request = request.body(body);
+        }
+        request.send_detailed().await
+    }
+
+    pub(crate) async fn dispatch(
+        &self,
+        method: Method,
+        uri: &str,
+        body_option: Option<Value>,
+        options: &RequestOptions,
+    ) -> Result<ApiResponse, ApiClientError> {
+        let adopted;
+        let mut options = options;
+        if self.config.apply_bootstrap && !options.bootstrap {
+            self.bootstrap_if_due().await;
+            if options.api_version.is_none() && self.config.api_version.is_none() {
+                if let Some(version) = self.recommendations.api_version() {
+                    adopted = RequestOptions {
+                        api_version: Some(version),
+                        ..options.clone()
+                    };
+                    options = &adopted;
+                }
+            }
+        }
+        let cache_key = match &self.config.cache {
+            Some(_) if method == Method::GET => {
+                let content = options.content_path.as_deref().unwrap_or(&self.config.content);
+                let charset = options.response_charset.unwrap_or(self.config.response_charset);
+                let scope = options.cache_scope(self.api_version(options), charset);
+                Some(CacheKey::new(
+                    &method,
+                    &format!("{}{}{}", content, uri, scope),
+                    body_option.as_ref(),
+                ))
+            }
+            _ => None,
+        };
+        if let (Some(cache), Some(key)) = (&self.config.cache, &cache_key) {
+            let cached = cache.backend.get(key).await;
+            self.stats.record_cache_lookup(cached.is_some());
+            if let Some(body) = cached {
+                let meta = ResponseMeta {
+                    status: StatusCode::OK,
+                    from_cache: true,
+                    ..Default::default()
+                };
+                return Ok(ApiResponse { body, meta });
+            }
+        }
+
+        let result = self.fetch(method, uri, body_option, options).await;
+        let (Some(cache), Some(key)) = (&self.config.cache, &cache_key) else {
+            return result;
+        };
+        match result {
+            Ok(response) if !response.meta.truncated => {
+                cache.backend.put(key, response.body.clone(), cache.ttl).await;
+                Ok(response)
+            }
+            Ok(response) => Ok(response),
+            Err(e) => self.serve_stale(cache, key, uri, e).await,
+        }
+    }
+
+    /// The cached body of a GET that failed with `error`, if the cache serves stale entries,
+    /// the failure lies with the server rather than the request, and an entry is recent
+    /// enough. `error` otherwise.
+    async fn serve_stale(
+        &self,
+        cache: &CacheConfig,
+        key: &CacheKey,
+        uri: &str,
+        error: ApiClientError,
+    ) -> Result<ApiResponse, ApiClientError> {
+        let unavailable = error.is_retryable()
+            || matches!(
+                error,
+                ApiClientError::QueueTimeout { .. } | ApiClientError::Maintenance { .. }
+            );
+        let Some(max_stale) = cache.max_stale.filter(|_| unavailable) else {
+            return Err(error);
+        };
+        let Some(body) = cache.backend.get_stale(key, max_stale).await else {
+            return Err(error);
+        };
+        tracing::warn!(uri, error = ?error, "serving a stale cached response");
+        self.stats.record_stale_served();
+        let meta = ResponseMeta {
+            status: StatusCode::OK,
+            from_cache: true,
+            stale: true,
+            ..Default::default()
+        };
+        Ok(ApiResponse { body, meta })
+    }
+
+    /// The request over the network, past the cache.
+    async fn fetch(
+        &self,
+        method: Method,
+        uri: &str,
+        body_option: Option<Value>,
+        options: &RequestOptions,
+    ) -> Result<ApiResponse, ApiClientError> {
+        let check = match (
+            &body_option,
+            self.config.detect_double_encoding && !options.allow_string_body,
+        ) {
+            (Some(body), true) => check_double_encoding(body),
+            _ => Ok(()),
+        };
+        let body = match check
+            .and_then(|()| self.maintenance.admit(uri, self.config.time.now_utc()))
+            .and_then(|()| PreparedBody::new(body_option.as_ref(), self.config.max_request_size))
+        {
+            Ok(body) => body,
+            Err(e) => {
+                self.stats
+                    .record_request(Duration::ZERO, 0, 0, Some(ErrorClass::of(&e)));
+                return Err(self.redacted(e));
+            }
+        };
+
+        let time = &*self.config.time;
+        let requested = time.now();
+        self.admit_quota(uri, options.priority, requested).await?;
+        let admission = self.acquire_permit(uri, options.priority, Some(requested)).await?;
+        let started = time.now();
+        let mut sizes = TransferSizes::default();
+        let result = self
+            .execute(method, uri, &body, options, requested, &mut sizes)
+            .await
+            .and_then(|mut response| {
+                if !options.raw_body || options.process_raw_body {
+                    response.body = self.process_response(uri, response.body)?;
+                }
+                if response.meta.truncated && options.fail_on_truncated.unwrap_or(self.config.fail_on_truncated) {
+                    return Err(ApiClientError::Truncated {
+                        uri: uri.to_string(),
+                        body: response.body,
+                    });
+                }
+                Ok(response)
+            });
+        let elapsed = time.elapsed(started);
+        self.stats.record_request(
+            elapsed,
+            sizes.sent,
+            sizes.received,
+            result.as_ref().err().map(ErrorClass::of),
+        );
+        let result = result.map_err(|e| self.redacted(e));
+        result.map(|mut response| {
+            response.meta.queue_time = admission.queue_time;
+            response.meta.priority = options.priority;
+            response.meta.pool = admission.pool.map(str::to_string);
+            response.meta.elapsed = elapsed;
+            response
+        })
+    }
+
+    /// Waits for a permit from the pool of `uri`, or from `max_concurrent_requests` when it
+    /// has none, for at most `max_queue_wait`, and at most what is left of `overall_timeout`
+    /// for a request begun at `requested`. Dropping the returned future while it waits gives
+    /// up the place in the queue without leaking a permit.
+    pub(crate) async fn acquire_permit(
+        &self,
+        uri: &str,
+        priority: Priority,
+        requested: Option<Instant>,
+    ) -> Result<Admission<'_>, ApiClientError> {
+        let time = &*self.config.time;
+        let queued = time.now();
+        let pool = self.bulkheads.route(uri);
+        let limiter = match pool {
+            Some(pool) => &pool.limiter,
+            None => match &self.limiter {
+                Some(limiter) => limiter,
+                None => {
+                    return Ok(Admission {
+                        permit: None,
+                        queue_time: Duration::ZERO,
+                        pool: None,
+                    })
+                }
+            },
+        };
+        let acquire = limiter.acquire(priority);
+        let overall_left = requested
+            .zip(self.config.overall_timeout)
+            .map(|(requested, timeout)| timeout.saturating_sub(time.elapsed(requested)));
+        let max_wait = match (self.config.max_queue_wait, overall_left) {
+            (Some(queue), Some(overall)) => Some(queue.min(overall)),
+            (queue, overall) => queue.or(overall),
+        };
+        let permit = match max_wait {
+            Some(max_wait) => match time::timeout(time, max_wait, acquire).await {
+                Some(permit) => permit,
+                None => {
+                    let err = match (overall_left == Some(max_wait), requested) {
+                        (true, Some(requested)) => ApiClientError::OverallTimeout {
+                            attempts: 0,
+                            elapsed: time.elapsed(requested),
+                        },
+                        _ => ApiClientError::QueueTimeout {
+                            waited: time.elapsed(queued),
+                        },
+                    };
+                    self.stats
+                        .record_request(Duration::ZERO, 0, 0, Some(ErrorClass::of(&err)));
+                    if let Some(pool) = pool {
+                        pool.record_rejected();
+                    }
+                    return Err(err);
+                }
+            },
+            None => acquire.await,
+        };
+        let queue_time = time.elapsed(queued);
+        tracing::trace!(uri, priority = priority.as_str(), ?queue_time, "admitted");
+        if let Some(pool) = pool {
+            pool.record_admitted(queue_time);
+        }
+        Ok(Admission {
+            permit: Some(permit),
+            queue_time,
+            pool: pool.map(|pool| pool.name()),
+        })
+    }
+
+    /// The retry loop, bounded by what is left of `overall_timeout` for a request begun at
+    /// `requested` when one is configured.
+    async fn execute(
+        &self,
+        method: Method,
+        uri: &str,
+        body: &PreparedBody,
+        options: &RequestOptions,
+        requested: Instant,
+        sizes: &mut TransferSizes,
+    ) -> Result<ApiResponse, ApiClientError> {
+        let time = &*self.config.time;
+        let mut attempts = 0;
+        let retries = self.retry_loop(method, uri, body, options, &mut attempts, sizes);
+        match self.config.overall_timeout {
+            Some(timeout) => {
+                match time::timeout(time, timeout.saturating_sub(time.elapsed(requested)), retries).await {
+                    Some(result) => result,
+                    None => Err(ApiClientError::OverallTimeout {
+                        attempts,
+                        elapsed: time.elapsed(requested),
+                    }),
+                }
+            }
+            None => retries.await,
+        }
+    }
+
+    async fn retry_loop(
+        &self,
+        method: Method,
+        uri: &str,
+        body: &PreparedBody,
+        options: &RequestOptions,
+        attempts: &mut u32,
+        sizes: &mut TransferSizes,
+    ) -> Result<ApiResponse, ApiClientError> {
+        let retry = options.retry.as_ref().unwrap_or(&self.config.retry);
+        let mut retries = 0;
+        loop {
+            let result = self.failover(method.clone(), uri, body, options, attempts, sizes).await;
+            match result {
+                Err(e) if e.is_retryable() && retries < retry.max_retries => {
+                    retries += 1;
+                    self.config.time.sleep(retry.backoff(retries)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// One pass over the hosts in order of health, stopping at the first that does not fail.
+    async fn failover(
+        &self,
+        method: Method,
+        uri: &str,
+        body: &PreparedBody,
+        options: &RequestOptions,
+        attempts: &mut u32,
+        sizes: &mut TransferSizes,
+    ) -> Result<ApiResponse, ApiClientError> {
+        let mut last_error = None;
+        for host in self.hosts.order() {
+            let result = self.attempt(host, method.clone(), uri, body, options, sizes).await;
+            *attempts += 1;
+            match result {
+                Ok(response) => {
+                    self.hosts.record(host, true);
+                    return Ok(response);
+                }
+                Err(ApiClientError::AttemptTimeout { .. }) => {
+                    self.hosts.record(host, false);
+                    last_error = Some(ApiClientError::AttemptTimeout { attempts: *attempts });
+                }
+                Err(e) if e.is_retryable() => {
+                    self.hosts.record(host, false);
+                    last_error = Some(e);
+                }
+                Err(e) => {
+                    self.hosts.record(host, true);
+                    return Err(e);
+                }
+            }
+        }
+        Err(last_error.expect("at least one host is configured"))
+    }
+
+    async fn attempt(
+        &self,
+        host: usize,
+        method: Method,
+        uri: &str,
+        body: &PreparedBody,
+        options: &RequestOptions,
+        sizes: &mut TransferSizes,
+    ) -> Result<ApiResponse, ApiClientError> {
+        let mut endpoint = self.hosts.endpoint(host);
+        let mut uri = Cow::Borrowed(uri);
+        let mut options = Cow::Borrowed(options);
+        let mut redirects = 0;
+        let (response, sent_headers) = loop {
+            let (url, response, sent_headers) = self
+                .exchange(&endpoint, method.clone(), &uri, body, &options, sizes)
+                .await?;
+            if !self.config.follow_redirects || !redirect::is_redirect(response.status) {
+                break (response, sent_headers);
+            }
+            let content = options.content_path.as_deref().unwrap_or(&self.config.content);
+            let Some(hop) = redirect::hop(&url, &endpoint.base_url, content, &response.headers) else {
+                break (response, sent_headers);
+            };
+            let leaks_credentials =
+                hop.cross_host && options.auth == AuthMode::Signed && !self.config.allow_cross_host_redirects;
+            if leaks_credentials || redirects == self.config.max_redirects {
+                let reason = match leaks_credentials {
+                    true => RedirectRefusal::CrossHost,
+                    false => RedirectRefusal::TooManyRedirects,
+                };
+                return Err(ApiClientError::RedirectRefused {
+                    status: response.status,
+                    location: hop.url,
+                    reason,
+                });
+            }
+            redirects += 1;
+            tracing::debug!(from = %url, to = %hop.url, status = %response.status, "following redirect");
+            if hop.base_url != endpoint.base_url {
+                endpoint = Endpoint::dns(&hop.base_url);
+            }
+            uri = Cow::Owned(hop.uri);
+            options.to_mut().content_path = Some(hop.content);
+        };
+        self.drift
+            .observe_headers(&response.headers, self.config.time.now_utc());
+        let quota = self.quota.observe(&response.headers, self.config.time.now());
+        if !is_acceptable(&self.config, response.status) {
+            if let Some(maintenance) = self.maintenance_error(&uri, &response.headers) {
+                return Err(maintenance);
+            }
+            let text = String::from_utf8_lossy(&response.body).into_owned();
+            if let Some(headers) = sent_headers.filter(|_| matches!(response.status.as_u16(), 401 | 403)) {
+                if let Some(rejected) =
+                    triage::triage(&self.config, &options, &uri, body, &headers, response.status, &text)
+                {
+                    return Err(rejected);
+                }
+            }
+            return Err(ApiClientError::HttpStatus {
+                status: response.status,
+                body: text,
+            });
+        }
+
+        let mut timings = response.timings;
+        let truncated = envelope::is_truncated(response.status, &response.headers);
+        let (body, key) = if response.status == StatusCode::NO_CONTENT {
+            (String::new(), DecryptionKey::Primary)
+        } else if options.raw_body {
+            let charset = options.response_charset.unwrap_or(self.config.response_charset);
+            (
+                charset::decode(Vec::from(response.body), charset)?,
+                DecryptionKey::Primary,
+            )
+        } else {
+            let (primary, secondary) = match &options.credentials {
+                Some(credentials) => (&credentials.crypto, None),
+                None => (&self.crypto, self.secondary_crypto.as_ref()),
+            };
+            let charset = options.response_charset;
+            let opened = if self.offloads(response.body.len()) {
+                let (primary, secondary) = (primary.clone(), secondary.cloned());
+                let (headers, body) = (response.headers.clone(), response.body.clone());
+                timings.offloaded = true;
+                offload::run(move || open_envelope(&primary, secondary.as_ref(), charset, &headers, &body, timings))
+                    .await
+            } else {
+                open_envelope(primary, secondary, charset, &response.headers, &response.body, timings)
+            };
+            let (body, key, opened_timings) = opened?;
+            timings = opened_timings;
+            (body, key)
+        };
+        let api_version = self.api_version(&options);
+        let path = uri.split_once('?').map_or(&*uri, |(path, _)| path);
+        let deprecation = deprecation::observe(path, api_version, &response.headers);
+        Ok(ApiResponse {
+            body,
+            meta: ResponseMeta {
+                status: response.status,
+                headers: response.headers,
+                base_url: endpoint.base_url.clone(),
+                api_version: api_version.map(str::to_string),
+                deprecation,
+                key,
+                timings,
+                truncated,
+                quota,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Sends one signed request to `endpoint`, repeating it once on a fresh connection when
+    /// a pooled one turns out to be closed. Returns the URL sent to with the response.
+    async fn exchange(
+        &self,
+        endpoint: &Endpoint,
+        method: Method,
+        uri: &str,
+        body: &PreparedBody,
+        options: &RequestOptions,
+        sizes: &mut TransferSizes,
+    ) -> Result<(String, TransportResponse, Option<HeaderMap>), ApiClientError> {
+        let attempt_timeout = options.attempt_timeout.or(self.config.attempt_timeout);
+        let mut fresh_connection = false;
+        loop {
+            let mut request = self.signed_request(endpoint, method.clone(), uri, body, options)?;
+            sizes.sent += request.body.len() as u64;
+            request.timeout = attempt_timeout;
+            request.fresh_connection = fresh_connection;
+            let url = request.url.clone();
+            let sent_headers = self.config.triage_signature_failures.then(|| request.headers.clone());
+            match self.send_once(request, attempt_timeout).await {
+                Err(ApiClientError::ReqwestError(e)) if transport::is_stale_connection(&e) => {
+                    let retry = !fresh_connection && self.config.retry_once_on_stale_connection;
+                    self.record_stale_connection(retry);
+                    if !retry {
+                        let connection = match fresh_connection {
+                            true => ConnectionReuse::Fresh,
+                            false => ConnectionReuse::MaybePooled,
+                        };
+                        return Err(ApiClientError::ConnectionClosed { connection, source: e });
+                    }
+                    self.stats.record_stale_connection_retry();
+                    tracing::debug!(
+                        url = %url,
+                        error = %e,
+                        "connection closed before the response; retrying on a fresh connection"
+                    );
+                    fresh_connection = true;
+                }
+                result => {
+                    let response = result?;
+                    sizes.received += response.body.len() as u64;
+                    return Ok((url, response, sent_headers));
+                }
+            }
+        }
+    }
+
+    async fn send_once(
+        &self,
+        request: TransportRequest,
+        attempt_timeout: Option<Duration>,
+    ) -> Result<TransportResponse, ApiClientError> {
+        let _active = self.activity.begin();
+        match attempt_timeout {
+            // The transport is asked to enforce the timeout too; this covers transports that
+            // ignore `TransportRequest::timeout`.
+            Some(timeout) => match time::timeout(&*self.config.time, timeout, self.transport.execute(request)).await {
+                Some(Err(ApiClientError::ReqwestError(e))) if e.is_timeout() => {
+                    Err(ApiClientError::AttemptTimeout { attempts: 0 })
+                }
+                Some(result) => result,
+                None => Err(ApiClientError::AttemptTimeout { attempts: 0 }),
+            },
+            None => self.transport.execute(request).await,
+        }
+    }
+
+    /// Runs `decrypt` with the primary key and, if that fails on padding or text decoding,
+    /// with the secondary key.
+    pub(crate) fn decrypt_with_fallback<T>(
+        &self,
+        decrypt: impl Fn(&Crypto) -> Result<T, ApiClientError>,
+    ) -> Result<(T, DecryptionKey), ApiClientError> {
+        decrypt_with_keys(&self.crypto, self.secondary_crypto.as_ref(), decrypt)
+    }
+
+    /// `error` with its body redacted, if `redaction` is configured.
+    pub(crate) fn redacted(&self, error: ApiClientError) -> ApiClientError {
+        match &self.config.redaction {
+            Some(redaction) => redaction.redact_error(error),
+            None => error,
+        }
+    }
+
+    fn api_version<'a>(&'a self, options: &'a RequestOptions) -> Option<&'a str> {
+        api_version(&self.config, options)
+    }
+
+    pub(crate) fn signed_request(
+        &self,
+        endpoint: &Endpoint,
+        method: Method,
+        uri: &str,
+        body: &PreparedBody,
+        options: &RequestOptions,
+    ) -> Result<TransportRequest, ApiClientError> {
+        build_request(
+            &self.config,
+            self.nonces.as_deref(),
+            endpoint,
+            method,
+            uri,
+            body,
+            options,
+        )
+    }
+
+    pub async fn send_as<T: DeserializeOwned + Send + 'static>(
+        &self,
+        method: Method,
+        uri: &str,
+        body_option: Option<Value>,
+    ) -> Result<T, ApiClientError> {
+        let decrypted_str = self.send(method, uri, body_option).await?;
+        self.deserialize(decrypted_str).await
+    }
+}
+
+/// Runs `decrypt` with `primary` and, if that fails on padding or text decoding, with
+/// `secondary`.
+fn decrypt_with_keys<T>(
+    primary: &Crypto,
+    secondary: Option<&Crypto>,
+    decrypt: impl Fn(&Crypto) -> Result<T, ApiClientError>,
+) -> Result<(T, DecryptionKey), ApiClientError> {
+    let primary_error = match decrypt(primary) {
+        Ok(plaintext) => return Ok((plaintext, DecryptionKey::Primary)),
+        Err(e) => e,
+    };
+    match (secondary, &primary_error) {
+        (
+            Some(secondary),
+            ApiClientError::AesError(_) | ApiClientError::Utf8Error(_) | ApiClientError::InvalidCharset { .. },
+        ) => match decrypt(secondary) {
+            Ok(plaintext) => Ok((plaintext, DecryptionKey::Secondary)),
+            Err(secondary) => Err(ApiClientError::BothKeysFailed {
+                primary: Box::new(primary_error),
+                secondary: Box::new(secondary),
+            }),
+        },
+        _ => Err(primary_error),
+    }
+}
+
+/// The plaintext of the envelope in `body` and the key that opened it, with the time spent
+/// parsing and decrypting added to `timings`.
+fn open_envelope(
+    primary: &Crypto,
+    secondary: Option<&Crypto>,
+    charset: Option<Charset>,
+    headers: &HeaderMap,
+    body: &[u8],
+    mut timings: Timings,
+) -> Result<(String, DecryptionKey, Timings), ApiClientError> {
+    let started = Instant::now();
+    let api_result = envelope::parse(body)?;
+    let parsed = Instant::now();
+    timings.parse = Some(parsed - started);
+    let (plaintext, key) = decrypt_with_keys(primary, secondary, |crypto| match charset {
+        Some(charset) => envelope::open_parsed(&crypto.clone().charset(charset), headers, &api_result),
+        None => envelope::open_parsed(crypto, headers, &api_result),
+    })?;
+    timings.decrypt = Some(parsed.elapsed());
+    Ok((plaintext, key, timings))
+}
+
+fn api_version<'a>(config: &'a ApiClientConfig, options: &'a RequestOptions) -> Option<&'a str> {
+    options.api_version.as_deref().or(config.api_version.as_deref())
+}
+
+/// Whether a response with `status` carries a payload rather than an error.
+pub(crate) fn is_acceptable(config: &ApiClientConfig, status: StatusCode) -> bool {
+    match &config.acceptable_statuses {
+        Some(statuses) => statuses.contains(&status),
+        None => status.is_success(),
+    }
+}
+
+/// The request for `uri` on `endpoint`, signed unless `options.auth` says otherwise. A free
+/// function so that background tasks, which cannot borrow the client, build requests too.
+pub(crate) fn build_request(
+    config: &ApiClientConfig,
+    nonces: Option<&NonceGuard>,
+    endpoint: &Endpoint,
+    method: Method,
+    uri: &str,
+    body: &PreparedBody,
+    options: &RequestOptions,
+) -> Result<TransportRequest, ApiClientError> {
+    let content = options.content_path.as_deref().unwrap_or(&config.content);
+    let mut url = String::with_capacity(endpoint.base_url.len() + content.len() + uri.len());
+    url.push_str(&endpoint.base_url);
+    url.push_str(content);
+    url.push_str(uri);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_static("H-RUST-SDK-1.0.0"));
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if !options.headers.is_empty() {
+        headers.extend(options.headers.clone());
+    }
+    if options.auth == AuthMode::Signed {
+        let (app_id, app_secret) = match &options.credentials {
+            Some(credentials) => (&credentials.app_id, &credentials.app_secret),
+            None => (&config.app_id, &config.app_secret),
+        };
+        let nonce = nonce::generate();
+        if let Some(nonces) = nonces {
+            nonces.issue(&nonce)?;
+        }
+        let now = config.time.now_utc().timestamp_millis();
+        let inputs = SignInputs {
+            app_id,
+            app_secret,
+            nonce: &nonce,
+            timestamp: now,
+            content_path: content,
+            uri,
+            body: body.signable(),
+        };
+        let signature = signing::signature(SignMode::for_request(config.sign_content_path), &inputs);
+        headers.insert("HO-APP-ID", header_value("app_id", app_id)?);
+        // Taking the `String` hands its buffer to the header instead of copying it.
+        let nonce = HeaderValue::try_from(nonce)
+            .map_err(|_| ApiClientError::InvalidConfig("nonce is not a valid header value".to_string()))?;
+        headers.insert("HO-NONCE", nonce);
+        headers.insert("HO-TIMESTAMP", HeaderValue::from(now));
+        headers.insert("HO-SIGNATURE", header_value("signature", signature.as_str())?);
+        if let Some(version) = api_version(config, options) {
+            headers.insert(VERSION_HEADER, header_value("api_version", version)?);
+        }
+        if let Some(key) = &options.idempotency_key {
+            headers.insert("HO-IDEMPOTENCY-KEY", header_value("idempotency_key", key)?);
+        }
+    }
+
+    Ok(TransportRequest {
+        method,
+        url,
+        headers,
+        body: body.wire(),
+        route: endpoint.route,
+        timeout: None,
+        fresh_connection: false,
+    })
+}
+
+/// The uri as it enters the sign string.
+pub(crate) fn signed_uri(content: &str, uri: &str, sign_content_path: bool) -> String {
+    match sign_content_path {
+        true => format!("{}{}", content, uri),
+        false => uri.to_string(),
+    }
+}
+
+fn header_value(name: &str, value: &str) -> Result<HeaderValue, ApiClientError> {
+    HeaderValue::from_str(value)
+        .map_err(|_| ApiClientError::InvalidConfig(format!("{} is not a valid header value", name)))
+}
+
+/// Whether `base_url` names its host by domain rather than by IP literal.
+fn has_domain_host(base_url: &str) -> bool {
+    reqwest::Url::parse(base_url).is_ok_and(|url| url.domain().is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use sha1::{Digest, Sha1};
+    use tokio::test;
+
+    use super::*;
+    use crate::signing::SignableBody;
+    use crate::testing::{
+        encrypted_envelope, mock_client, test_config, MockResponse, MockTransport, TEST_APP_ID, TEST_APP_SECRET,
+    };
+
+    #[test]
+    async fn test_send_request() {
+        let config = ApiClientConfig {
+            app_id: "your app id".to_string(),
+            app_secret: "your app secret (32 bytes long)!".to_string(),
+            iv: "your app iv (16)".to_string(),
+            base_url: "https://server.zelaser.com".to_string(),
+            content: "/server/common/api".to_string(),
+            ..Default::default()
+        };
+
+        let client = ApiClient::new(config).expect("Failed to create API client");
+
+        let body = json!({
+            "key": "value",
+        });
+
+        match client
+            .send(Method::GET, "/v1/lol/champion/skin?region=cn", Some(body))
+            .await
+        {
+            Ok(response) => {
+                println!("Response Body: {}", response);
+            }
+            Err(e) => eprintln!("Error: {:?}", e),
+        }
+    }
+
+    #[test]
+    async fn test_send_through_mock_transport() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/lol/champion/skin",
+            MockResponse::encrypted(r#"[{"id":1}]"#),
+        );
+        let client = mock_client(&transport, |_| {});
+
+        let response = client
+            .send_detailed(
+                Method::GET,
+                "/v1/lol/champion/skin?region=cn",
+                Some(json!({ "key": "value" })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.body, r#"[{"id":1}]"#);
+        assert_eq!(response.meta.status, StatusCode::OK);
+
+        let requests = transport.requests();
+        assert_eq!(
+            requests[0].url,
+            "https://mock.zelaser.test/server/common/api/v1/lol/champion/skin?region=cn"
+        );
+        assert_eq!(&requests[0].body[..], br#"{"data":"{\"key\":\"value\"}"}"#);
+        let header = |name: &str| requests[0].headers[name].to_str().unwrap().to_string();
+        assert_eq!(header("HO-APP-ID"), TEST_APP_ID);
+        let legacy_sign_str = format!(
+            "{}{}{}{}{}{}",
+            TEST_APP_ID,
+            header("HO-NONCE"),
+            header("HO-TIMESTAMP"),
+            "/v1/lol/champion/skin?region=cn",
+            r#"{"key":"value"}"#,
+            TEST_APP_SECRET
+        );
+        let expected = format!("{:x}", Sha1::digest(legacy_sign_str.as_bytes()));
+        assert_eq!(header("HO-SIGNATURE"), expected);
+    }
+
+    #[test]
+    async fn test_non_ok_status_is_an_error() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/x", MockResponse::raw(StatusCode::BAD_REQUEST, "bad"));
+        let client = mock_client(&transport, |_| {});
+
+        match client.send(Method::GET, "/v1/x", None).await {
+            Err(ApiClientError::HttpStatus { status, body }) => {
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+                assert_eq!(body, "bad");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(client.stats().errors.http_status, 1);
+    }
+
+    fn truncated(plaintext: &str) -> MockResponse {
+        MockResponse::raw(
+            StatusCode::PARTIAL_CONTENT,
+            crate::testing::encrypted_envelope(plaintext),
+        )
+    }
+
+    #[test]
+    async fn test_partial_content_is_a_truncated_success() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::GET, "/v1/list", truncated(r#"{"list":[1,2]}"#))
+            .on(
+                Method::GET,
+                "/v1/flagged",
+                MockResponse::encrypted(r#"{"list":[1]}"#).with_header("ho-truncated", "true"),
+            )
+            .on(Method::GET, "/v1/whole", MockResponse::encrypted(r#"{"list":[1]}"#));
+        let client = mock_client(&transport, |_| {});
+
+        let response = client.send_detailed(Method::GET, "/v1/list", None).await.unwrap();
+        assert_eq!(response.body, r#"{"list":[1,2]}"#);
+        assert!(response.meta.truncated);
+        let flagged = client.send_detailed(Method::GET, "/v1/flagged", None).await.unwrap();
+        assert!(flagged.meta.truncated);
+        let whole = client.send_detailed(Method::GET, "/v1/whole", None).await.unwrap();
+        assert!(!whole.meta.truncated);
+        assert_eq!(client.stats().errors.http_status, 0);
+    }
+
+    #[test]
+    async fn test_fail_on_truncated_is_opt_in() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/list", truncated(r#"{"list":[1,2]}"#));
+        let client = mock_client(&transport, |config| config.fail_on_truncated = true);
+
+        match client.send(Method::GET, "/v1/list", None).await {
+            Err(ApiClientError::Truncated { uri, body }) => {
+                assert_eq!((uri.as_str(), body.as_str()), ("/v1/list", r#"{"list":[1,2]}"#));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(transport.requests().len(), 1);
+        let allowed = client.request(Method::GET, "/v1/list").fail_on_truncated(false);
+        assert_eq!(allowed.send().await.unwrap(), r#"{"list":[1,2]}"#);
+
+        let lenient = mock_client(&transport, |_| {});
+        let strict = lenient.request(Method::GET, "/v1/list").fail_on_truncated(true);
+        assert!(matches!(strict.send().await, Err(ApiClientError::Truncated { .. })));
+    }
+
+    #[test]
+    async fn test_any_success_status_carries_a_payload() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::POST,
+                "/v1/order",
+                MockResponse::raw(StatusCode::CREATED, encrypted_envelope(r#"{"order_id":"A1"}"#)),
+            )
+            .on(
+                Method::DELETE,
+                "/v1/order/A1",
+                MockResponse::status(StatusCode::NO_CONTENT),
+            )
+            .on(
+                Method::GET,
+                "/v1/moved",
+                MockResponse::status(StatusCode::FOUND).with_header("location", "/server/common/api/v1/here"),
+            );
+        let client = mock_client(&transport, |config| config.follow_redirects = false);
+
+        let created = client
+            .send_detailed(Method::POST, "/v1/order", Some(json!({ "sku": 7 })))
+            .await;
+        let created = created.unwrap();
+        assert_eq!(
+            (created.meta.status, created.body.as_str()),
+            (StatusCode::CREATED, r#"{"order_id":"A1"}"#)
+        );
+        let deleted = client
+            .send_detailed(Method::DELETE, "/v1/order/A1", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            (deleted.meta.status, deleted.body.as_str()),
+            (StatusCode::NO_CONTENT, "")
+        );
+        match client.send(Method::GET, "/v1/moved", None).await {
+            Err(ApiClientError::HttpStatus { status, .. }) => assert_eq!(status, StatusCode::FOUND),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    async fn test_acceptable_statuses_replace_the_success_class() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::POST,
+                "/v1/order",
+                MockResponse::raw(StatusCode::CREATED, encrypted_envelope("{}")),
+            )
+            .on(
+                Method::GET,
+                "/v1/legacy",
+                MockResponse::raw(StatusCode::NOT_MODIFIED, encrypted_envelope(r#"{"id":1}"#)),
+            );
+        let client = mock_client(&transport, |config| {
+            config.acceptable_statuses = Some(vec![StatusCode::OK, StatusCode::NOT_MODIFIED]);
+            config.follow_redirects = false;
+        });
+
+        assert_eq!(
+            client.send(Method::GET, "/v1/legacy", None).await.unwrap(),
+            r#"{"id":1}"#
+        );
+        match client.send(Method::POST, "/v1/order", Some(json!({}))).await {
+            Err(ApiClientError::HttpStatus { status, .. }) => assert_eq!(status, StatusCode::CREATED),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut config = test_config();
+        config.acceptable_statuses = Some(Vec::new());
+        assert!(matches!(ApiClient::new(config), Err(ApiClientError::InvalidConfig(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_beyond_the_limit_wait_for_a_permit() {
+        let delay = Duration::from_millis(500);
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/slow", MockResponse::encrypted("{}").with_delay(delay));
+        let client = mock_client(&transport, |config| config.max_concurrent_requests = Some(64));
+
+        let requests = (0..65).map(|_| client.send_detailed(Method::GET, "/v1/slow", None));
+        let responses = futures_util::future::join_all(requests).await;
+
+        let queue_times: Vec<Duration> = responses.into_iter().map(|r| r.unwrap().meta.queue_time).collect();
+        assert!(queue_times[..64].iter().all(|t| t.is_zero()));
+        assert_eq!(queue_times[64], delay);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_overall_timeout_cuts_backoff_short() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/flaky",
+            MockResponse::status(StatusCode::SERVICE_UNAVAILABLE).with_delay(Duration::from_secs(1)),
+        );
+        let client = mock_client(&transport, |config| {
+            config.overall_timeout = Some(Duration::from_secs(6));
+            config.retry = RetryConfig {
+                max_retries: 2,
+                initial_backoff: Duration::from_secs(2),
+                multiplier: 2.0,
+                max_backoff: Duration::from_secs(60),
+            };
+        });
+
+        // Attempts end at 1s and 4s; the 4s backoff after the second would run past 6s.
+        let started = Instant::now();
+        match client.send(Method::GET, "/v1/flaky", None).await {
+            Err(ApiClientError::OverallTimeout { attempts, elapsed }) => {
+                assert_eq!(attempts, 2);
+                assert_eq!(elapsed, Duration::from_secs(6));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(started.elapsed(), Duration::from_secs(6));
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_attempt_timeout_is_retried_then_reported() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/slow",
+            MockResponse::encrypted("{}").with_delay(Duration::from_secs(10)),
+        );
+        let client = mock_client(&transport, |config| {
+            config.attempt_timeout = Some(Duration::from_secs(1));
+            config.retry.max_retries = 2;
+        });
+
+        match client.send(Method::GET, "/v1/slow", None).await {
+            Err(ApiClientError::AttemptTimeout { attempts }) => assert_eq!(attempts, 3),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(transport.requests().len(), 3);
+        assert_eq!(transport.requests()[0].timeout, Some(Duration::from_secs(1)));
+
+        let response = client
+            .request(Method::GET, "/v1/slow")
+            .timeout(Duration::from_secs(20))
+            .send()
+            .await;
+        assert!(response.is_ok());
+    }
+
+    const NEXT_SECRET: &str = "abcdef0123456789abcdef0123456789";
+    const NEXT_IV: &str = "0123456789abcdef";
+
+    fn rotating_client(transport: &Arc<MockTransport>) -> ApiClient {
+        mock_client(transport, |config| {
+            config.secondary_key = Some(SecondaryKey {
+                app_secret: NEXT_SECRET.to_string(),
+                iv: NEXT_IV.to_string(),
+            })
+        })
+    }
+
+    fn encrypted_with(secret: &str, iv: &str, plaintext: &str) -> MockResponse {
+        let data = Crypto::new(secret, iv).unwrap().encrypt_hex(plaintext.as_bytes());
+        MockResponse::json(StatusCode::OK, &json!({ "data": data }))
+    }
+
+    #[test]
+    async fn test_primary_key_decrypts_first() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/x", MockResponse::encrypted("old key"));
+        let response = rotating_client(&transport)
+            .send_detailed(Method::GET, "/v1/x", None)
+            .await
+            .unwrap();
+        assert_eq!(response.body, "old key");
+        assert_eq!(response.meta.key, DecryptionKey::Primary);
+    }
+
+    #[test]
+    async fn test_secondary_key_decrypts_after_rotation() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/x", encrypted_with(NEXT_SECRET, NEXT_IV, "new key"));
+        let client = rotating_client(&transport);
+
+        let response = client.send_detailed(Method::GET, "/v1/x", None).await.unwrap();
+        assert_eq!(response.body, "new key");
+        assert_eq!(response.meta.key, DecryptionKey::Secondary);
+
+        let requests = transport.requests();
+        let headers = &requests[0].headers;
+        let expected = signing::sign(
+            TEST_APP_ID,
+            TEST_APP_SECRET,
+            headers["HO-NONCE"].to_str().unwrap(),
+            headers["HO-TIMESTAMP"].to_str().unwrap().parse().unwrap(),
+            "/v1/x",
+            SignableBody::Empty,
+        );
+        assert_eq!(headers["HO-SIGNATURE"], expected.as_str());
+    }
+
+    #[test]
+    async fn test_both_keys_failing_reports_each_error() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/x",
+            encrypted_with("ffffffffffffffffffffffffffffffff", NEXT_IV, "third key"),
+        );
+        let client = rotating_client(&transport);
+
+        match client.send(Method::GET, "/v1/x", None).await {
+            Err(ApiClientError::BothKeysFailed { primary, secondary }) => {
+                assert!(matches!(
+                    *primary,
+                    ApiClientError::AesError(_) | ApiClientError::InvalidCharset { .. }
+                ));
+                assert!(matches!(
+                    *secondary,
+                    ApiClientError::AesError(_) | ApiClientError::InvalidCharset { .. }
+                ));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(client.stats().errors.decryption, 1);
+
+        let without_secondary = mock_client(&transport, |_| {});
+        assert!(matches!(
+            without_secondary.send(Method::GET, "/v1/x", None).await,
+            Err(ApiClientError::AesError(_) | ApiClientError::InvalidCharset { .. })
+        ));
+    }
+
+    #[test(start_paused = true)]
+    async fn test_timings_cover_the_sdk_phases_and_connection_reuse() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/skin",
+            MockResponse::encrypted(r#"{"id":1}"#).with_delay(Duration::from_millis(30)),
+        );
+        transport.on(
+            Method::GET,
+            "/v1/public/status",
+            MockResponse::raw(StatusCode::OK, "ok"),
+        );
+        let client = mock_client(&transport, |_| {});
+
+        let first = client.send_detailed(Method::GET, "/v1/skin", None).await.unwrap();
+        let second = client.send_detailed(Method::GET, "/v1/skin", None).await.unwrap();
+        let timings = first.meta.timings;
+        assert!(!timings.connection_reused);
+        assert_eq!((timings.dns, timings.connect, timings.tls), (None, None, None));
+        assert_eq!(timings.time_to_first_byte, Some(Duration::from_millis(30)));
+        assert!(timings.parse.is_some() && timings.decrypt.is_some());
+        assert!(second.meta.timings.connection_reused);
+        assert_eq!(second.meta.timings.connect, Some(Duration::ZERO));
+
+        assert_eq!(timings.deserialize, None);
+
+        let (skin, meta) = client
+            .request(Method::GET, "/v1/skin")
+            .send_detailed_as::<Value>()
+            .await
+            .unwrap();
+        assert_eq!(skin["id"], 1);
+        assert!(meta.timings.parse.is_some() && meta.timings.deserialize.is_some());
+        let stats = client.stats();
+        assert_eq!(stats.deserializations, 1);
+        assert_eq!(
+            stats.deserialize_micros,
+            meta.timings.deserialize.unwrap().as_micros() as u64
+        );
+
+        let raw = client.request(Method::GET, "/v1/public/status").parse_envelope(false);
+        let timings = raw.send_detailed().await.unwrap().meta.timings;
+        assert_eq!((timings.parse, timings.decrypt), (None, None));
+    }
+
+    #[test(start_paused = true)]
+    async fn test_requests_queued_too_long_are_dropped_unsent() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/slow",
+            MockResponse::encrypted("{}").with_delay(Duration::from_secs(10)),
+        );
+        let client = mock_client(&transport, |config| {
+            config.max_concurrent_requests = Some(1);
+            config.max_queue_wait = Some(Duration::from_secs(2));
+            config.overall_timeout = Some(Duration::from_secs(30));
+        });
+
+        let (first, second) = tokio::join!(
+            client.send(Method::GET, "/v1/slow", None),
+            client.send(Method::GET, "/v1/slow", None)
+        );
+        assert!(first.is_ok());
+        match second {
+            Err(ApiClientError::QueueTimeout { waited }) => assert_eq!(waited, Duration::from_secs(2)),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(transport.requests().len(), 1);
+
+        let stats = client.stats();
+        assert_eq!(stats.errors.queue_timeout, 1);
+        assert_eq!(stats.errors.transport, 0);
+    }
+
+    #[test(start_paused = true)]
+    async fn test_queue_wait_counts_toward_overall_timeout() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/slow",
+            MockResponse::encrypted("{}").with_delay(Duration::from_millis(1500)),
+        );
+        let client = mock_client(&transport, |config| {
+            config.max_concurrent_requests = Some(1);
+            config.max_queue_wait = Some(Duration::from_secs(2));
+            config.overall_timeout = Some(Duration::from_secs(2));
+        });
+
+        // 1.5s in the queue leaves 0.5s of the overall limit for a 1.5s transfer.
+        let (first, second) = tokio::join!(
+            client.send_detailed(Method::GET, "/v1/slow", None),
+            client.send_detailed(Method::GET, "/v1/slow", None)
+        );
+        assert!(first.is_ok());
+        match second {
+            Err(ApiClientError::OverallTimeout { attempts, elapsed }) => {
+                assert_eq!(attempts, 0);
+                assert_eq!(elapsed, Duration::from_secs(2));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[test(start_paused = true)]
+    async fn test_overall_timeout_running_out_in_the_queue_wins_over_max_queue_wait() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/slow",
+            MockResponse::encrypted("{}").with_delay(Duration::from_secs(10)),
+        );
+        let client = mock_client(&transport, |config| {
+            config.max_concurrent_requests = Some(1);
+            config.max_queue_wait = Some(Duration::from_secs(5));
+            config.overall_timeout = Some(Duration::from_secs(3));
+        });
+
+        let started = Instant::now();
+        let (first, second) = tokio::join!(
+            client.send(Method::GET, "/v1/slow", None),
+            client.send(Method::GET, "/v1/slow", None)
+        );
+        assert!(matches!(first, Err(ApiClientError::OverallTimeout { .. })));
+        match second {
+            Err(ApiClientError::OverallTimeout { attempts, elapsed }) => {
+                assert_eq!(attempts, 0);
+                assert_eq!(elapsed, Duration::from_secs(3));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+        assert_eq!(transport.requests().len(), 1);
+        assert_eq!(client.stats().errors.queue_timeout, 0);
+    }
+
+    #[test(start_paused = true)]
+    async fn test_high_priority_requests_overtake_queued_low_ones() {
+        let transport = MockTransport::new();
+        for path in ["/v1/held", "/v1/low/1", "/v1/low/2", "/v1/high"] {
+            transport.on(
+                Method::GET,
+                path,
+                MockResponse::encrypted("{}").with_delay(Duration::from_secs(1)),
+            );
+        }
+        let client = mock_client(&transport, |config| config.max_concurrent_requests = Some(1));
+
+        let queued = |path: &'static str, priority: Priority, after_ms: u64| {
+            let request = client.request(Method::GET, path).priority(priority);
+            async move {
+                tokio::time::sleep(Duration::from_millis(after_ms)).await;
+                request.send_detailed().await.unwrap()
+            }
+        };
+        let (_, low, _, high) = tokio::join!(
+            queued("/v1/held", Priority::Normal, 0),
+            queued("/v1/low/1", Priority::Low, 1),
+            queued("/v1/low/2", Priority::Low, 2),
+            queued("/v1/high", Priority::High, 3),
+        );
+        let sent: Vec<_> = transport.requests().into_iter().map(|request| request.url).collect();
+        let order = ["/v1/held", "/v1/high", "/v1/low/1", "/v1/low/2"];
+        assert_eq!(sent.len(), order.len());
+        for (url, path) in sent.iter().zip(order) {
+            assert!(url.ends_with(path), "{} sent where {} was expected", url, path);
+        }
+        assert_eq!((low.meta.priority, high.meta.priority), (Priority::Low, Priority::High));
+        assert_eq!(high.meta.queue_time, Duration::from_millis(997));
+    }
+
+    #[test]
+    async fn test_stale_connection_is_retried_once_on_a_fresh_connection() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/x", MockResponse::connection_reset()).on(
+            Method::GET,
+            "/v1/x",
+            MockResponse::encrypted("recovered"),
+        );
+        let client = mock_client(&transport, |_| {});
+
+        assert_eq!(client.send(Method::GET, "/v1/x", None).await.unwrap(), "recovered");
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[0].fresh_connection && requests[1].fresh_connection);
+        assert_ne!(requests[0].headers["HO-NONCE"], requests[1].headers["HO-NONCE"]);
+
+        let stats = client.stats();
+        assert_eq!(stats.stale_connection_retries, 1);
+        assert_eq!(stats.errors.transport, 0);
+    }
+
+    #[test]
+    async fn test_connection_closed_reports_whether_a_fresh_connection_failed_too() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/x", MockResponse::connection_reset());
+
+        let client = mock_client(&transport, |_| {});
+        match client.send(Method::GET, "/v1/x", None).await {
+            Err(ApiClientError::ConnectionClosed { connection, .. }) => assert_eq!(connection, ConnectionReuse::Fresh),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(transport.requests().len(), 2);
+
+        let client = mock_client(&transport, |config| config.retry_once_on_stale_connection = false);
+        match client.send(Method::GET, "/v1/x", None).await {
+            Err(ApiClientError::ConnectionClosed { connection, .. }) => {
+                assert_eq!(connection, ConnectionReuse::MaybePooled)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(transport.requests().len(), 3);
+        assert_eq!(client.stats().stale_connection_retries, 0);
+    }
+
+    fn signature_for(request: &TransportRequest, uri: &str, body: SignableBody<'_>) -> String {
+        let header = |name: &str| request.headers[name].to_str().unwrap().to_string();
+        signing::sign(
+            TEST_APP_ID,
+            TEST_APP_SECRET,
+            &header("HO-NONCE"),
+            header("HO-TIMESTAMP").parse().unwrap(),
+            uri,
+            body,
+        )
+    }
+
+    #[test]
+    async fn test_same_host_redirect_is_followed_with_a_new_signature() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::POST,
+                "/v1/orders",
+                MockResponse::status(StatusCode::TEMPORARY_REDIRECT)
+                    .with_header("location", "/server/common/api/v1/orders-standby?shard=2"),
+            )
+            .on(Method::POST, "/v1/orders-standby", MockResponse::encrypted("accepted"));
+        let client = mock_client(&transport, |_| {});
+
+        let response = client
+            .send(Method::POST, "/v1/orders", Some(json!({ "sku": 7 })))
+            .await
+            .unwrap();
+        assert_eq!(response, "accepted");
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[1].url,
+            "https://mock.zelaser.test/server/common/api/v1/orders-standby?shard=2"
+        );
+        assert_eq!(requests[1].method, Method::POST);
+        assert_eq!(requests[1].body, requests[0].body);
+        let body = SignableBody::Text(r#"{"sku":7}"#);
+        assert_eq!(
+            requests[0].headers["HO-SIGNATURE"],
+            signature_for(&requests[0], "/v1/orders", body).as_str()
+        );
+        assert_eq!(
+            requests[1].headers["HO-SIGNATURE"],
+            signature_for(&requests[1], "/v1/orders-standby?shard=2", body).as_str()
+        );
+        assert_ne!(requests[0].headers["HO-NONCE"], requests[1].headers["HO-NONCE"]);
+    }
+
+    #[test]
+    async fn test_cross_host_redirect_is_refused() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/x",
+            MockResponse::status(StatusCode::FOUND).with_header("location", "https://elsewhere.test/v1/x"),
+        );
+        let client = mock_client(&transport, |_| {});
+
+        match client.send(Method::GET, "/v1/x", None).await {
+            Err(ApiClientError::RedirectRefused {
+                status,
+                location,
+                reason,
+            }) => {
+                assert_eq!(status, StatusCode::FOUND);
+                assert_eq!(location, "https://elsewhere.test/v1/x");
+                assert_eq!(reason, RedirectRefusal::CrossHost);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(transport.requests().len(), 1);
+
+        let client = mock_client(&transport, |config| config.allow_cross_host_redirects = true);
+        transport.on(Method::GET, "/v1/x", MockResponse::encrypted("moved"));
+        let response = client.send_detailed(Method::GET, "/v1/x", None).await.unwrap();
+        assert_eq!(response.body, "moved");
+        assert_eq!(response.meta.base_url, "https://elsewhere.test");
+        let followed = transport.requests().pop().unwrap();
+        assert_eq!(followed.url, "https://elsewhere.test/v1/x");
+        assert_eq!(
+            followed.headers["HO-SIGNATURE"],
+            signature_for(&followed, "/v1/x", SignableBody::Empty).as_str()
+        );
+    }
+
+    #[test]
+    async fn test_redirect_limit_and_opt_out() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/loop",
+            MockResponse::status(StatusCode::PERMANENT_REDIRECT).with_header("location", "loop"),
+        );
+        let client = mock_client(&transport, |config| config.max_redirects = 2);
+        match client.send(Method::GET, "/v1/loop", None).await {
+            Err(ApiClientError::RedirectRefused { reason, .. }) => {
+                assert_eq!(reason, RedirectRefusal::TooManyRedirects)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(transport.requests().len(), 3);
+
+        let client = mock_client(&transport, |config| config.follow_redirects = false);
+        match client.send(Method::GET, "/v1/loop", None).await {
+            Err(ApiClientError::HttpStatus { status, .. }) => assert_eq!(status, StatusCode::PERMANENT_REDIRECT),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(transport.requests().len(), 4);
+    }
+}
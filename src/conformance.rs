@@ -0,0 +1,239 @@
+//! Worked examples of the HO signing and envelope scheme, and a self-test that runs them
+//! through this SDK with a caller's configuration. Gateway teams can compare the vectors with
+//! their own implementation; callers can check a custom configuration before going live:
+//!
+//! ```no_run
+//! # fn check(config: &ho_api_rust_sdk::ApiClientConfig) {
+//! let report = ho_api_rust_sdk::conformance::run_conformance_suite(config);
+//! assert!(report.passed(), "{:#?}", report.failures().collect::<Vec<_>>());
+//! # }
+//! ```
+
+use reqwest::header::HeaderMap;
+use serde_json::json;
+
+use crate::client::signed_uri;
+use crate::crypto::Crypto;
+use crate::envelope;
+use crate::signing::{sign, SignableBody};
+use crate::ApiClientConfig;
+
+pub const VECTOR_APP_ID: &str = "test-app-id";
+pub const VECTOR_APP_SECRET: &str = "0123456789abcdef0123456789abcdef";
+pub const VECTOR_IV: &str = "fedcba9876543210";
+pub const VECTOR_NONCE: &str = "3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a13";
+pub const VECTOR_TIMESTAMP: i64 = 1717171717171;
+pub const VECTOR_CONTENT: &str = "/server/common/api";
+
+/// One sign-string example with the vector app id, secret, nonce and timestamp.
+#[derive(Debug, Clone, Copy)]
+pub struct SignatureVector {
+    pub name: &'static str,
+    pub uri: &'static str,
+    pub body: SignableBody<'static>,
+    /// Lowercase hex SHA-1 of the sign string.
+    pub expected: &'static str,
+}
+
+const BINARY_BODY: [u8; 256] = {
+    let mut bytes = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        bytes[i] = i as u8;
+        i += 1;
+    }
+    bytes
+};
+
+pub const SIGNATURE_VECTORS: &[SignatureVector] = &[
+    SignatureVector {
+        name: "json body",
+        uri: "/v1/lol/champion/skin?region=cn",
+        body: SignableBody::Text(r#"{"key":"value"}"#),
+        expected: "1ae2672d3d67e434efe485b3cfb4f0510e7457c2",
+    },
+    SignatureVector {
+        name: "utf-8 json body",
+        uri: "/v1/lol/champion/skin?region=cn",
+        body: SignableBody::Text(r#"{"name":"安妮"}"#),
+        expected: "7f403d37ce6f1cedaef01b0f64d9b22133930796",
+    },
+    SignatureVector {
+        name: "no body",
+        uri: "/v1/lol/champion/skin?region=cn",
+        body: SignableBody::Empty,
+        expected: "132c2b49f73d079ac4b6ebd6e0b52751e5e97c79",
+    },
+    SignatureVector {
+        name: "binary body, bytes 0 to 255",
+        uri: "/v1/asset/upload/chunk",
+        body: SignableBody::Bytes(&BINARY_BODY),
+        expected: "1664a6425e4f8c21917e4baa730d2c7c4db61f51",
+    },
+];
+
+/// The "json body" vector sent under `VECTOR_CONTENT`, for each `sign_content_path` setting.
+const CONTENT_PATH_EXPECTED: [(bool, &str); 2] = [
+    (false, "1ae2672d3d67e434efe485b3cfb4f0510e7457c2"),
+    (true, "a76bc794e423c2ebfe64e8c384bd404a9670f22f"),
+];
+
+/// `ENVELOPE_PLAINTEXT` under AES-256-CBC/PKCS#7 with the vector secret and IV.
+pub const ENVELOPE_PLAINTEXT: &str = r#"{"name":"安妮"}"#;
+pub const ENVELOPE_CIPHERTEXT: &str = "d94109c1e092abb65187ad3e6151a7cee8c9e832e56c4ebc889df228a3ecbe26";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConformanceCheck {
+    pub name: String,
+    pub passed: bool,
+    /// What differed, for failed checks.
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConformanceReport {
+    pub checks: Vec<ConformanceCheck>,
+}
+
+impl ConformanceReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &ConformanceCheck> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+
+    fn check(&mut self, name: impl Into<String>, outcome: Result<(), String>) {
+        self.checks.push(ConformanceCheck {
+            name: name.into(),
+            passed: outcome.is_ok(),
+            detail: outcome.err(),
+        });
+    }
+}
+
+fn expect_eq(expected: &str, got: &str) -> Result<(), String> {
+    match expected == got {
+        true => Ok(()),
+        false => Err(format!("expected {:?}, got {:?}", expected, got)),
+    }
+}
+
+/// Runs the vectors through the signing, hex and envelope code paths the client uses, with
+/// `config`'s `sign_content_path` and `strict_hex` settings, and round-trips an envelope
+/// through `config`'s own secret and IV.
+pub fn run_conformance_suite(config: &ApiClientConfig) -> ConformanceReport {
+    let mut report = ConformanceReport::default();
+    let sign_vector = |uri: &str, body| {
+        sign(
+            VECTOR_APP_ID,
+            VECTOR_APP_SECRET,
+            VECTOR_NONCE,
+            VECTOR_TIMESTAMP,
+            uri,
+            body,
+        )
+    };
+
+    for vector in SIGNATURE_VECTORS {
+        let got = sign_vector(vector.uri, vector.body);
+        report.check(format!("signature: {}", vector.name), expect_eq(vector.expected, &got));
+    }
+
+    let json_body = &SIGNATURE_VECTORS[0];
+    let uri = signed_uri(VECTOR_CONTENT, json_body.uri, config.sign_content_path);
+    let (_, expected) = CONTENT_PATH_EXPECTED
+        .into_iter()
+        .find(|(signed, _)| *signed == config.sign_content_path)
+        .expect("both settings have a vector");
+    report.check(
+        "signature: content path",
+        expect_eq(expected, &sign_vector(&uri, json_body.body)),
+    );
+
+    let vector_crypto = Crypto::new(VECTOR_APP_SECRET, VECTOR_IV)
+        .expect("vector key and iv have valid lengths")
+        .strict_hex(config.strict_hex);
+    report.check(
+        "envelope: encrypt",
+        expect_eq(
+            ENVELOPE_CIPHERTEXT,
+            &vector_crypto.encrypt_hex(ENVELOPE_PLAINTEXT.as_bytes()),
+        ),
+    );
+    report.check(
+        "envelope: decrypt",
+        open(&vector_crypto, ENVELOPE_CIPHERTEXT).and_then(|got| expect_eq(ENVELOPE_PLAINTEXT, &got)),
+    );
+    report.check(
+        "hex: uppercase digits",
+        open(&vector_crypto, &ENVELOPE_CIPHERTEXT.to_uppercase()).and_then(|got| expect_eq(ENVELOPE_PLAINTEXT, &got)),
+    );
+    let decorated = format!(" 0x{}\n", ENVELOPE_CIPHERTEXT);
+    report.check(
+        "hex: whitespace and 0x prefix",
+        match (config.strict_hex, open(&vector_crypto, &decorated)) {
+            (false, Ok(got)) => expect_eq(ENVELOPE_PLAINTEXT, &got),
+            (false, Err(e)) => Err(format!("tolerant mode rejected the payload: {}", e)),
+            (true, Ok(_)) => Err("strict mode accepted a decorated payload".to_string()),
+            (true, Err(_)) => Ok(()),
+        },
+    );
+
+    report.check(
+        "envelope: round trip with the configured key",
+        match Crypto::new(&config.app_secret, &config.iv) {
+            Ok(crypto) => {
+                let crypto = crypto.strict_hex(config.strict_hex);
+                let ciphertext = crypto.encrypt_hex(ENVELOPE_PLAINTEXT.as_bytes());
+                open(&crypto, &ciphertext).and_then(|got| expect_eq(ENVELOPE_PLAINTEXT, &got))
+            }
+            Err(_) => Err("app_secret must be 32 bytes and iv 16 bytes".to_string()),
+        },
+    );
+    report
+}
+
+fn open(crypto: &Crypto, data: &str) -> Result<String, String> {
+    let body = json!({ "data": data }).to_string();
+    envelope::open(crypto, &HeaderMap::new(), body.as_bytes()).map_err(|e| format!("{:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_config;
+
+    fn failed(report: &ConformanceReport) -> Vec<&str> {
+        report.failures().map(|check| check.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_default_and_strict_configurations_conform() {
+        let report = run_conformance_suite(&test_config());
+        assert!(report.passed(), "{:?}", report.failures().collect::<Vec<_>>());
+        assert_eq!(report.checks.len(), SIGNATURE_VECTORS.len() + 6);
+
+        let strict = ApiClientConfig {
+            strict_hex: true,
+            sign_content_path: true,
+            ..test_config()
+        };
+        assert!(run_conformance_suite(&strict).passed());
+    }
+
+    #[test]
+    fn test_invalid_key_fails_only_the_round_trip() {
+        let config = ApiClientConfig {
+            app_secret: "too short".to_string(),
+            ..test_config()
+        };
+        let report = run_conformance_suite(&config);
+        assert_eq!(failed(&report), vec!["envelope: round trip with the configured key"]);
+        assert_eq!(
+            report.failures().next().unwrap().detail.as_deref(),
+            Some("app_secret must be 32 bytes and iv 16 bytes")
+        );
+    }
+}
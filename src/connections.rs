@@ -0,0 +1,234 @@
+//! Flushing the transport's connection pool without recreating the client, which would
+//! lose its stats, cache and clock offset. After a gateway-side rolling restart every
+//! pooled connection may point at a drained backend; `ApiClient::reset_connections`
+//! replaces the pool, and `ApiClientConfig::reset_connections_after` does so on its own
+//! once stale connections pile up.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::time::Instant;
+
+use crate::{ApiClient, ApiClientError};
+
+/// Resets the connections once `failures` requests have run into a closed connection within
+/// `window`. Counting starts over after each reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaleConnectionReset {
+    pub failures: usize,
+    pub window: Duration,
+}
+
+/// What the client knows about its connections, from `ApiClient::connection_info`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// How many times the connections have been reset: 0 for the ones the client started with.
+    pub generation: u64,
+    pub last_reset: Option<DateTime<Utc>>,
+    /// How many of those resets `reset_connections_after` made.
+    pub automatic_resets: u64,
+    /// Attempts repeated on a fresh connection since the last reset, or since the client was
+    /// created; see `retry_once_on_stale_connection`.
+    pub stale_connection_retries: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Connections {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    info: ConnectionInfo,
+    /// When requests ran into a closed connection since the last reset, within the window.
+    stale: VecDeque<Instant>,
+}
+
+impl Connections {
+    pub(crate) fn info(&self) -> ConnectionInfo {
+        self.state.lock().unwrap().info.clone()
+    }
+
+    /// Counts a request that ran into a closed connection, and whether `trigger` now calls for
+    /// a reset.
+    pub(crate) fn record_stale(&self, retried: bool, trigger: Option<StaleConnectionReset>, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if retried {
+            state.info.stale_connection_retries += 1;
+        }
+        let Some(trigger) = trigger else {
+            return false;
+        };
+        state.stale.push_back(now);
+        while state.stale.front().is_some_and(|&at| now - at > trigger.window) {
+            state.stale.pop_front();
+        }
+        state.stale.len() >= trigger.failures
+    }
+
+    fn record_reset(&self, automatic: bool, at: DateTime<Utc>) -> ConnectionInfo {
+        let mut state = self.state.lock().unwrap();
+        state.stale.clear();
+        let info = &mut state.info;
+        info.generation += 1;
+        info.last_reset = Some(at);
+        info.automatic_resets += automatic as u64;
+        info.stale_connection_retries = 0;
+        info.clone()
+    }
+}
+
+impl ApiClient {
+    /// Drops the transport's pooled connections: requests in flight finish on the old ones,
+    /// later requests open new ones. Transports without a pool, such as custom ones keeping
+    /// the default `Transport::reset_connections`, only have the generation counted.
+    pub fn reset_connections(&self) -> Result<ConnectionInfo, ApiClientError> {
+        self.transport.reset_connections()?;
+        Ok(self.connections.record_reset(false, self.config.time.now_utc()))
+    }
+
+    pub fn connection_info(&self) -> ConnectionInfo {
+        self.connections.info()
+    }
+
+    /// Counts a request that ran into a closed connection, resetting the connections when
+    /// `reset_connections_after` calls for it.
+    pub(crate) fn record_stale_connection(&self, retried: bool) {
+        let trigger = self.config.reset_connections_after;
+        if !self.connections.record_stale(retried, trigger, self.config.time.now()) {
+            return;
+        }
+        let Some(trigger) = trigger else {
+            return;
+        };
+        match self.transport.reset_connections() {
+            Ok(()) => {
+                let info = self.connections.record_reset(true, self.config.time.now_utc());
+                tracing::warn!(
+                    failures = trigger.failures,
+                    window = ?trigger.window,
+                    generation = info.generation,
+                    "stale connections piled up; reset the connection pool"
+                );
+            }
+            Err(e) => tracing::warn!(error = ?e, "failed to reset the connection pool"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use futures_util::future;
+    use reqwest::Method;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::testing::{encrypted_envelope, mock_client, test_config, MockResponse, MockTransport};
+
+    /// Serves keep-alive HTTP/1.1 connections, answering every request after `delay`, and
+    /// counts the connections accepted.
+    async fn serve(listener: TcpListener, delay: Duration, accepted: Arc<AtomicUsize>) {
+        let body = encrypted_envelope("{}");
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            accepted.fetch_add(1, Ordering::SeqCst);
+            let body = body.clone();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 4096];
+                loop {
+                    let mut received = Vec::new();
+                    while !received.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match socket.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => received.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    tokio::time::sleep(delay).await;
+                    let response = format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}", body.len(), body);
+                    if socket.write_all(response.as_bytes()).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_straddling_a_reset_complete() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let mut config = test_config();
+        config.base_url = format!("http://{}", listener.local_addr().unwrap());
+        let accepted = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(serve(listener, Duration::from_millis(100), accepted.clone()));
+        let client = ApiClient::new(config).unwrap();
+        assert_eq!(client.connection_info(), ConnectionInfo::default());
+
+        client.send(Method::GET, "/v1/ping", None).await.unwrap();
+        let in_flight = future::join_all((0..3).map(|_| client.send(Method::GET, "/v1/ping", None)));
+        let reset = async {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            let info = client.reset_connections().unwrap();
+            // Started after the reset, on a new pool.
+            let after = client.send(Method::GET, "/v1/ping", None).await;
+            (info, after)
+        };
+        let (results, (info, after)) = future::join(in_flight, reset).await;
+        assert!(results.iter().all(Result::is_ok), "{:?}", results);
+        assert!(after.is_ok());
+        assert_eq!(info.generation, 1);
+        assert!(info.last_reset.is_some());
+        let before = accepted.load(Ordering::SeqCst);
+        assert!(before >= 4, "{} connections", before);
+
+        client.reset_connections().unwrap();
+        client.send(Method::GET, "/v1/ping", None).await.unwrap();
+        assert_eq!(accepted.load(Ordering::SeqCst), before + 1);
+        assert_eq!(client.connection_info().generation, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_connections_within_the_window_reset_once() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/x", MockResponse::connection_reset()).on(
+            Method::GET,
+            "/v1/x",
+            MockResponse::encrypted("{}"),
+        );
+        transport.on(Method::GET, "/v1/y", MockResponse::connection_reset());
+        let client = mock_client(&transport, |config| {
+            config.reset_connections_after = Some(StaleConnectionReset {
+                failures: 3,
+                window: Duration::from_secs(10),
+            })
+        });
+
+        client.send(Method::GET, "/v1/x", None).await.unwrap();
+        tokio::time::advance(Duration::from_secs(11)).await;
+        // Two more, the first having left the window.
+        assert!(client.send(Method::GET, "/v1/y", None).await.is_err());
+        let info = client.connection_info();
+        assert_eq!((info.generation, info.stale_connection_retries), (0, 2));
+
+        // Its first attempt is the third; the retry on a fresh connection counts afresh.
+        client.send(Method::GET, "/v1/y", None).await.unwrap_err();
+        let info = client.connection_info();
+        assert_eq!((info.generation, info.automatic_resets), (1, 1));
+        assert_eq!(info.stale_connection_retries, 0);
+        assert_eq!(client.stats().stale_connection_retries, 3);
+
+        transport.on(Method::GET, "/v1/z", MockResponse::encrypted("{}"));
+        let reused = |response: crate::response::ApiResponse| response.meta.timings.connection_reused;
+        client.send(Method::GET, "/v1/z", None).await.unwrap();
+        assert!(reused(client.send_detailed(Method::GET, "/v1/z", None).await.unwrap()));
+        client.reset_connections().unwrap();
+        assert!(!reused(client.send_detailed(Method::GET, "/v1/z", None).await.unwrap()));
+        assert_eq!(client.connection_info().automatic_resets, 1);
+    }
+}
@@ -0,0 +1,249 @@
+use std::fmt;
+
+use aes::Aes256;
+use block_modes::{block_padding::Pkcs7, BlockMode, Cbc};
+
+use crate::charset::{self, Charset};
+use crate::ApiClientError;
+
+type Aes256Cbc = Cbc<Aes256, Pkcs7>;
+
+const BLOCK_LEN: usize = 16;
+
+/// Byte lengths of the AES-256 key and the CBC IV, for the tooling that checks key material
+/// before building a `Crypto`.
+#[cfg_attr(not(feature = "client"), allow(dead_code))]
+pub(crate) const SECRET_LEN: usize = 32;
+#[cfg_attr(not(feature = "client"), allow(dead_code))]
+pub(crate) const IV_LEN: usize = BLOCK_LEN;
+
+/// Characters of the payload shown from each end in a `HexPayloadError`.
+const EXCERPT_CHARS: usize = 8;
+
+/// AES-256-CBC with PKCS#7 padding over hex text, as used for the `data` envelope field.
+#[derive(Clone)]
+pub struct Crypto {
+    cipher: Aes256Cbc,
+    strict_hex: bool,
+    charset: Charset,
+}
+
+/// A hex payload that could not be decoded. Positions in `error` count from the start of the
+/// payload as received, before any whitespace or `0x` prefix was skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HexPayloadError {
+    pub error: hex::FromHexError,
+    pub len: usize,
+    pub head: String,
+    pub tail: String,
+}
+
+impl fmt::Display for HexPayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.error {
+            hex::FromHexError::InvalidHexCharacter { c, index } => {
+                write!(f, "invalid hex character {:?} at position {}", c, index)?
+            }
+            other => write!(f, "{}", other)?,
+        }
+        if self.len > 2 * EXCERPT_CHARS {
+            write!(f, " in {} byte payload {:?}...{:?}", self.len, self.head, self.tail)
+        } else {
+            write!(f, " in payload {:?}", self.head)
+        }
+    }
+}
+
+impl Crypto {
+    pub fn new(app_secret: &str, iv: &str) -> Result<Self, ApiClientError> {
+        let cipher = Aes256Cbc::new_from_slices(app_secret.as_bytes(), iv.as_bytes())
+            .map_err(|_| ApiClientError::InvalidConfig("AES config error".to_string()))?;
+        Ok(Self {
+            cipher,
+            strict_hex: false,
+            charset: Charset::Utf8,
+        })
+    }
+
+    /// By default ciphertext may be surrounded by ASCII whitespace and carry a `0x` prefix.
+    /// Strict mode accepts only bare hex digits.
+    pub fn strict_hex(mut self, strict: bool) -> Self {
+        self.strict_hex = strict;
+        self
+    }
+
+    /// The encoding of decrypted plaintext, UTF-8 by default.
+    pub fn charset(mut self, charset: Charset) -> Self {
+        self.charset = charset;
+        self
+    }
+
+    pub fn encrypt_hex(&self, plaintext: &[u8]) -> String {
+        hex::encode(self.cipher.clone().encrypt_vec(plaintext))
+    }
+
+    pub fn decrypt_hex(&self, hex_text: &[u8]) -> Result<String, ApiClientError> {
+        // The bytes become the returned `String` without a copy.
+        charset::decode(self.decrypt_hex_bytes(hex_text)?, self.charset)
+    }
+
+    /// `decrypt_hex` for binary plaintext, returned as it is instead of decoded as text.
+    pub fn decrypt_hex_bytes(&self, hex_text: &[u8]) -> Result<Vec<u8>, ApiClientError> {
+        let mut buffer = self.decode_hex(hex_text)?;
+        if buffer.is_empty() || buffer.len() % BLOCK_LEN != 0 {
+            return Err(ApiClientError::InvalidCiphertextLength { len: buffer.len() });
+        }
+        // Decrypted in place: the plaintext is the buffer minus its padding.
+        let plaintext_len = self.cipher.clone().decrypt(&mut buffer)?.len();
+        buffer.truncate(plaintext_len);
+        Ok(buffer)
+    }
+
+    fn decode_hex(&self, payload: &[u8]) -> Result<Vec<u8>, ApiClientError> {
+        let (offset, digits) = match self.strict_hex {
+            true => (0, payload),
+            false => tolerant_digits(payload),
+        };
+        // Look for a bad character before the length so the error points at it.
+        let invalid = digits.iter().position(|b| !b.is_ascii_hexdigit());
+        let decoded = match invalid {
+            Some(index) => Err(hex::FromHexError::InvalidHexCharacter {
+                c: digits[index] as char,
+                index: index + offset,
+            }),
+            // `hex::decode` collects without a size hint and reallocates as it grows.
+            None => {
+                let mut decoded = vec![0; digits.len() / 2];
+                hex::decode_to_slice(digits, &mut decoded).map(|()| decoded)
+            }
+        };
+        decoded.map_err(|error| {
+            let head = String::from_utf8_lossy(&payload[..payload.len().min(EXCERPT_CHARS)]).into_owned();
+            let tail = String::from_utf8_lossy(&payload[payload.len().saturating_sub(EXCERPT_CHARS)..]).into_owned();
+            ApiClientError::InvalidHex(HexPayloadError {
+                error,
+                len: payload.len(),
+                head,
+                tail,
+            })
+        })
+    }
+}
+
+/// Strips surrounding ASCII whitespace and a `0x`/`0X` prefix, returning how many leading
+/// bytes were skipped along with the remaining digits.
+fn tolerant_digits(payload: &[u8]) -> (usize, &[u8]) {
+    let start = payload.iter().take_while(|b| b.is_ascii_whitespace()).count();
+    let end = payload.len()
+        - payload[start..]
+            .iter()
+            .rev()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count();
+    let digits = &payload[start..end];
+    match digits {
+        [b'0', b'x' | b'X', rest @ ..] => (start + 2, rest),
+        _ => (start, digits),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let crypto = Crypto::new("0123456789abcdef0123456789abcdef", "fedcba9876543210").unwrap();
+        let ciphertext = crypto.encrypt_hex("{\"name\":\"安妮\"}".as_bytes());
+        assert_eq!(ciphertext.len() % 32, 0);
+        assert_eq!(
+            crypto.decrypt_hex(ciphertext.as_bytes()).unwrap(),
+            "{\"name\":\"安妮\"}"
+        );
+    }
+
+    fn crypto() -> Crypto {
+        Crypto::new("0123456789abcdef0123456789abcdef", "fedcba9876543210").unwrap()
+    }
+
+    #[test]
+    fn test_tolerates_trailing_newline_and_prefix() {
+        let ciphertext = crypto().encrypt_hex(b"{}");
+        for payload in [
+            format!("{}\n", ciphertext),
+            format!("  {}\r\n", ciphertext),
+            format!("0x{}", ciphertext),
+            format!("\t0X{}\n", ciphertext),
+        ] {
+            assert_eq!(crypto().decrypt_hex(payload.as_bytes()).unwrap(), "{}", "{:?}", payload);
+        }
+    }
+
+    #[test]
+    fn test_accepts_mixed_case() {
+        let ciphertext = crypto().encrypt_hex(b"{}");
+        let mixed: String = ciphertext
+            .chars()
+            .enumerate()
+            .map(|(i, c)| if i % 2 == 0 { c.to_ascii_uppercase() } else { c })
+            .collect();
+        assert_eq!(crypto().decrypt_hex(mixed.as_bytes()).unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_embedded_space_reports_position_and_excerpt() {
+        let ciphertext = crypto().encrypt_hex(b"{}");
+        let payload = format!(" 0x{} {}", &ciphertext[..10], &ciphertext[10..]);
+        match crypto().decrypt_hex(payload.as_bytes()) {
+            Err(ApiClientError::InvalidHex(e)) => {
+                assert_eq!(e.error, hex::FromHexError::InvalidHexCharacter { c: ' ', index: 13 });
+                assert_eq!(e.len, payload.len());
+                assert_eq!(e.head, &payload[..8]);
+                assert_eq!(e.tail, &payload[payload.len() - 8..]);
+                let message = e.to_string();
+                assert!(
+                    message.starts_with("invalid hex character ' ' at position 13"),
+                    "{}",
+                    message
+                );
+            }
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_strict_hex_rejects_whitespace() {
+        let strict = crypto().strict_hex(true);
+        let ciphertext = strict.encrypt_hex(b"{}");
+        assert_eq!(strict.decrypt_hex(ciphertext.as_bytes()).unwrap(), "{}");
+        match strict.decrypt_hex(format!("{}\n", ciphertext).as_bytes()) {
+            Err(ApiClientError::InvalidHex(e)) => assert_eq!(
+                e.error,
+                hex::FromHexError::InvalidHexCharacter {
+                    c: '\n',
+                    index: ciphertext.len()
+                }
+            ),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_partial_block_is_a_length_error() {
+        let ciphertext = crypto().encrypt_hex(b"{\"name\":\"Annie\"}");
+        for (payload, len) in [(&ciphertext[..62], 31), ("", 0)] {
+            match crypto().decrypt_hex(payload.as_bytes()) {
+                Err(ApiClientError::InvalidCiphertextLength { len: got }) => assert_eq!(got, len),
+                other => panic!("unexpected {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_wrong_key_length() {
+        assert!(matches!(
+            Crypto::new("short", "fedcba9876543210"),
+            Err(ApiClientError::InvalidConfig(_))
+        ));
+    }
+}
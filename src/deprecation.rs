@@ -0,0 +1,56 @@
+use std::collections::HashSet;
+use std::sync::{Mutex, OnceLock};
+
+use reqwest::header::HeaderMap;
+
+pub(crate) const VERSION_HEADER: &str = "HO-VERSION";
+pub(crate) const DEPRECATION_HEADER: &str = "HO-DEPRECATION";
+
+/// Endpoints whose deprecation warning has been logged, shared by every client in the
+/// process so a busy endpoint logs once rather than once per request or per client.
+static WARNED: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+/// The `HO-DEPRECATION` warning of a response, logged the first time `endpoint` reports one.
+pub(crate) fn observe(endpoint: &str, api_version: Option<&str>, headers: &HeaderMap) -> Option<String> {
+    let warning = headers.get(DEPRECATION_HEADER)?.to_str().ok()?.trim().to_string();
+    if first_warning(endpoint) {
+        tracing::warn!(
+            endpoint,
+            api_version,
+            warning = %warning,
+            "the API reports this endpoint or version as deprecated"
+        );
+    }
+    Some(warning)
+}
+
+fn first_warning(endpoint: &str) -> bool {
+    let warned = WARNED.get_or_init(Default::default);
+    warned.lock().unwrap().insert(endpoint.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_warning_is_logged_once_per_endpoint() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            DEPRECATION_HEADER,
+            HeaderValue::from_static(" version 2023-01-01 ends 2025-01-01 "),
+        );
+
+        assert_eq!(
+            observe("/v1/deprecation/a", None, &headers).as_deref(),
+            Some("version 2023-01-01 ends 2025-01-01")
+        );
+        assert!(!first_warning("/v1/deprecation/a"));
+        assert!(observe("/v1/deprecation/a", None, &headers).is_some());
+        assert!(first_warning("/v1/deprecation/b"));
+        assert_eq!(observe("/v1/deprecation/c", None, &HeaderMap::new()), None);
+        assert!(first_warning("/v1/deprecation/c"));
+    }
+}
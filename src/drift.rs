@@ -0,0 +1,135 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use reqwest::header::{HeaderMap, DATE};
+
+/// Weight of the newest sample in the smoothed offset.
+const SMOOTHING: f64 = 0.2;
+
+/// Samples further than this from local time are treated as broken `Date` headers.
+const MAX_PLAUSIBLE_OFFSET_MS: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+
+struct DriftState {
+    offset_ms: Option<f64>,
+    warned: bool,
+}
+
+/// Exponentially smoothed estimate of server time minus local time, fed from response
+/// `Date` headers. This is the client's only record of the server clock offset.
+pub(crate) struct DriftMonitor {
+    warn_threshold: Option<Duration>,
+    state: Mutex<DriftState>,
+}
+
+impl DriftMonitor {
+    pub(crate) fn new(warn_threshold: Option<Duration>) -> Self {
+        Self {
+            warn_threshold,
+            state: Mutex::new(DriftState {
+                offset_ms: None,
+                warned: false,
+            }),
+        }
+    }
+
+    /// Takes a sample from the `Date` header of a response received at `local_time`.
+    pub(crate) fn observe_headers(&self, headers: &HeaderMap, local_time: DateTime<Utc>) {
+        let server_time = headers
+            .get(DATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok());
+        if let Some(server_time) = server_time {
+            self.observe(server_time.with_timezone(&Utc), local_time);
+        }
+    }
+
+    pub(crate) fn observe(&self, server_time: DateTime<Utc>, local_time: DateTime<Utc>) {
+        let sample = (server_time - local_time).num_milliseconds() as f64;
+        if sample.abs() > MAX_PLAUSIBLE_OFFSET_MS {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let offset = match state.offset_ms {
+            Some(previous) => previous + SMOOTHING * (sample - previous),
+            None => sample,
+        };
+        state.offset_ms = Some(offset);
+
+        let Some(threshold) = self.warn_threshold else {
+            return;
+        };
+        let exceeded = offset.abs() > threshold.as_millis() as f64;
+        if exceeded && !state.warned {
+            tracing::warn!(
+                drift_ms = offset as i64,
+                threshold_ms = threshold.as_millis() as u64,
+                "local clock drifted from the HO server clock beyond the configured threshold"
+            );
+        }
+        state.warned = exceeded;
+    }
+
+    /// Positive when the server clock is ahead of the local one.
+    pub(crate) fn estimate(&self) -> Option<TimeDelta> {
+        let state = self.state.lock().unwrap();
+        state.offset_ms.map(|ms| TimeDelta::milliseconds(ms.round() as i64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_717_200_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn test_first_sample_initializes_then_smooths() {
+        let monitor = DriftMonitor::new(None);
+        assert_eq!(monitor.estimate(), None);
+
+        monitor.observe(at(10), at(0));
+        assert_eq!(monitor.estimate(), Some(TimeDelta::seconds(10)));
+
+        monitor.observe(at(20), at(0));
+        assert_eq!(monitor.estimate(), Some(TimeDelta::seconds(12)));
+
+        monitor.observe(at(-8), at(0));
+        assert_eq!(monitor.estimate(), Some(TimeDelta::milliseconds(8_000)));
+    }
+
+    #[test]
+    fn test_bogus_and_missing_dates_are_ignored() {
+        let monitor = DriftMonitor::new(None);
+        monitor.observe(at(-3 * 365 * 24 * 3600), at(0));
+        assert_eq!(monitor.estimate(), None);
+
+        monitor.observe_headers(&HeaderMap::new(), at(0));
+        let mut headers = HeaderMap::new();
+        headers.insert(DATE, HeaderValue::from_static("not a date"));
+        monitor.observe_headers(&headers, at(0));
+        assert_eq!(monitor.estimate(), None);
+    }
+
+    #[test]
+    fn test_parses_http_date_header() {
+        let monitor = DriftMonitor::new(Some(Duration::from_secs(1)));
+        let mut headers = HeaderMap::new();
+        let server_time = Utc::now() + TimeDelta::minutes(10);
+        let value = server_time.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        headers.insert(DATE, HeaderValue::from_str(&value).unwrap());
+        monitor.observe_headers(&headers, Utc::now());
+
+        let drift = monitor.estimate().unwrap();
+        assert!(
+            (drift - TimeDelta::minutes(10)).num_milliseconds().abs() < 2_000,
+            "{:?}",
+            drift
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Typed wrappers for endpoints whose payloads need more than `send_as`.
+//!
+//! Times in these payloads are Shanghai local time, `+08:00`, written either as
+//! `"2024-06-01 00:00:00"` or, by newer gateways, as Unix milliseconds. Both deserialize to
+//! a `DateTime<FixedOffset>` at `+08:00`, and serialize back in the dated form.
+
+use std::fmt;
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
+use reqwest::Method;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::api::HoApi;
+use crate::time::TimeSource;
+use crate::types::ChampionId;
+use crate::{ApiClient, ApiClientError};
+
+mod order;
+
+pub use order::{
+    Missing, Order, OrderRequest, OrderRequestBuilder, OrderResult, DUPLICATE_ORDER_CODE, INSUFFICIENT_BALANCE_CODE,
+    MAX_QUANTITY, ORDER_URI,
+};
+
+pub const CHAMPION_ROTATION_URI: &str = "/v1/lol/champion/rotation";
+
+/// The `%Y-%m-%d %H:%M:%S` form of gateway timestamps.
+const DATED_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// The offset of gateway timestamps.
+pub fn shanghai() -> FixedOffset {
+    FixedOffset::east_opt(8 * 3600).expect("+08:00 is a valid offset")
+}
+
+/// `GET /v1/lol/champion/rotation`: the free champions of the current and upcoming weeks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ChampionRotation {
+    pub entries: Vec<RotationEntry>,
+}
+
+impl ChampionRotation {
+    /// The entries whose window contains `at`.
+    pub fn active_at(&self, at: DateTime<Utc>) -> impl Iterator<Item = &RotationEntry> {
+        self.entries.iter().filter(move |entry| entry.is_active_at(at))
+    }
+
+    /// The entries free to play now by `time`, such as the client's `ApiClientConfig::time`.
+    pub fn current(&self, time: &dyn TimeSource) -> Vec<&RotationEntry> {
+        self.active_at(time.now_utc()).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RotationEntry {
+    pub champion_id: ChampionId,
+    #[serde(with = "gateway_time")]
+    pub start_time: DateTime<FixedOffset>,
+    #[serde(with = "gateway_time")]
+    pub end_time: DateTime<FixedOffset>,
+}
+
+impl RotationEntry {
+    /// Whether `at` falls in `[start_time, end_time)`, so that back-to-back windows never
+    /// overlap.
+    pub fn is_active_at(&self, at: DateTime<Utc>) -> bool {
+        self.start_time <= at && at < self.end_time
+    }
+}
+
+/// The typed endpoints on any `HoApi`, from `HoApi::endpoints`. `ApiClient` has them as
+/// methods of its own too.
+pub struct Endpoints<'a, A> {
+    api: &'a A,
+}
+
+impl<'a, A: HoApi> Endpoints<'a, A> {
+    pub fn new(api: &'a A) -> Self {
+        Self { api }
+    }
+
+    pub async fn champion_rotation(&self) -> Result<ChampionRotation, ApiClientError> {
+        self.api.send_as(Method::GET, CHAMPION_ROTATION_URI, None).await
+    }
+
+    /// The rotation entries free to play now.
+    pub async fn current_rotation(&self) -> Result<Vec<RotationEntry>, ApiClientError> {
+        let rotation = self.champion_rotation().await?;
+        let now = self.api.now_utc();
+        Ok(rotation
+            .entries
+            .into_iter()
+            .filter(|entry| entry.is_active_at(now))
+            .collect())
+    }
+}
+
+impl ApiClient {
+    pub async fn champion_rotation(&self) -> Result<ChampionRotation, ApiClientError> {
+        Endpoints::new(self).champion_rotation().await
+    }
+
+    /// The rotation entries free to play now.
+    pub async fn current_rotation(&self) -> Result<Vec<RotationEntry>, ApiClientError> {
+        Endpoints::new(self).current_rotation().await
+    }
+}
+
+/// `#[serde(with)]` for gateway timestamps, in either wire form.
+pub mod gateway_time {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(time: &DateTime<FixedOffset>, serializer: S) -> Result<S::Ok, S::Error> {
+        let local = time.with_timezone(&shanghai());
+        serializer.collect_str(&local.format(DATED_FORMAT))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<FixedOffset>, D::Error> {
+        deserializer.deserialize_any(GatewayTimeVisitor)
+    }
+}
+
+struct GatewayTimeVisitor;
+
+impl<'de> Visitor<'de> for GatewayTimeVisitor {
+    type Value = DateTime<FixedOffset>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "a Shanghai local time as \"YYYY-MM-DD HH:MM:SS\" or Unix milliseconds"
+        )
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        match shanghai().timestamp_millis_opt(v) {
+            chrono::LocalResult::Single(time) => Ok(time),
+            _ => Err(E::invalid_value(de::Unexpected::Signed(v), &self)),
+        }
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        let millis = i64::try_from(v).map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))?;
+        self.visit_i64(millis)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        NaiveDateTime::parse_from_str(v.trim(), DATED_FORMAT)
+            .ok()
+            .and_then(|local| shanghai().from_local_datetime(&local).single())
+            .ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport, SimulatedTime};
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_both_wire_forms_mean_shanghai_time() {
+        let dated: RotationEntry = serde_json::from_value(json!({
+            "champion_id": "266",
+            "start_time": "2024-06-01 00:00:00",
+            "end_time": "2024-06-08 00:00:00",
+        }))
+        .unwrap();
+        let millis: RotationEntry = serde_json::from_value(json!({
+            "champion_id": 266,
+            "start_time": 1717171200000u64,
+            "end_time": 1717776000000u64,
+        }))
+        .unwrap();
+        assert_eq!(dated, millis);
+        assert_eq!(dated.start_time, utc("2024-05-31T16:00:00Z"));
+        assert_eq!(dated.start_time.offset(), &shanghai());
+        assert_eq!(
+            serde_json::to_value(&millis).unwrap(),
+            json!({
+                "champion_id": 266,
+                "start_time": "2024-06-01 00:00:00",
+                "end_time": "2024-06-08 00:00:00",
+            })
+        );
+
+        let invalid = json!({ "champion_id": 1, "start_time": "2024-06-01", "end_time": 0 });
+        let message = serde_json::from_value::<RotationEntry>(invalid)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("Shanghai local time"), "{}", message);
+    }
+
+    #[test]
+    fn test_window_starts_at_shanghai_midnight_and_excludes_its_end() {
+        let local = |text: &str| gateway_time::deserialize(json!(text)).unwrap();
+        let week = |start: &str, end: &str, id: u64| RotationEntry {
+            champion_id: ChampionId(id),
+            start_time: local(start),
+            end_time: local(end),
+        };
+        let rotation = ChampionRotation {
+            entries: vec![
+                week("2024-05-25 00:00:00", "2024-06-01 00:00:00", 1),
+                week("2024-06-01 00:00:00", "2024-06-08 00:00:00", 2),
+            ],
+        };
+        let active = |at: &str| {
+            rotation
+                .active_at(utc(at))
+                .map(|entry| entry.champion_id.get())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(active("2024-05-31T15:59:59.999Z"), [1]);
+        assert_eq!(active("2024-05-31T16:00:00Z"), [2]);
+        // Midnight UTC is eight hours into the Shanghai day.
+        assert_eq!(active("2024-06-01T00:00:00Z"), [2]);
+        assert_eq!(active("2024-06-07T16:00:00Z"), Vec::<u64>::new());
+
+        // `SimulatedTime` starts at 2024-06-01 00:00 UTC.
+        let time = SimulatedTime::new();
+        let current = |time: &SimulatedTime| {
+            rotation
+                .current(time)
+                .iter()
+                .map(|entry| entry.champion_id.get())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(current(&time), [2]);
+        time.advance(std::time::Duration::from_secs(7 * 24 * 3600));
+        assert_eq!(current(&time), Vec::<u64>::new());
+    }
+
+    #[tokio::test]
+    async fn test_current_rotation_filters_by_now() {
+        let far = |year: i32| format!("{}-01-01 00:00:00", year);
+        let payload = json!([
+            { "champion_id": 1, "start_time": far(2000), "end_time": far(2001) },
+            { "champion_id": 2, "start_time": 946656000000u64, "end_time": far(9999) },
+        ]);
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            CHAMPION_ROTATION_URI,
+            MockResponse::encrypted(payload.to_string()),
+        );
+        let client = mock_client(&transport, |_| {});
+
+        assert_eq!(client.champion_rotation().await.unwrap().entries.len(), 2);
+        let current = client.current_rotation().await.unwrap();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].champion_id, ChampionId(2));
+    }
+}
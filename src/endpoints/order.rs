@@ -0,0 +1,386 @@
+//! `POST /v1/mall/order`: buys an item-mall item with the user's balance.
+//!
+//! The request body, with `remark` left out when unset:
+//!
+//! ```json
+//! {"out_trade_no":"T202406010001","user_id":"u-1001","item_id":3070,"quantity":2,"amount":2500,"remark":"gift"}
+//! ```
+//!
+//! `amount` is the total in fen and must equal the item price times `quantity`; the server
+//! refuses the order otherwise. `out_trade_no` is the caller's own order number and makes
+//! the request idempotent: sending it again fails with `DUPLICATE_ORDER_CODE` and the id of
+//! the order it created. A successful response is an `Order`.
+
+use chrono::{DateTime, FixedOffset};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use super::{gateway_time, Endpoints};
+use crate::api::HoApi;
+use crate::types::{ItemId, Money};
+use crate::{ApiClient, ApiClientError};
+
+pub const ORDER_URI: &str = "/v1/mall/order";
+
+/// The business code of an order the user's balance does not cover. The body carries the
+/// current `balance` in fen.
+pub const INSUFFICIENT_BALANCE_CODE: i64 = 40201;
+
+/// The business code of an `out_trade_no` already used. The body carries the `order_id` of
+/// the order created with it.
+pub const DUPLICATE_ORDER_CODE: i64 = 40901;
+
+/// Most items bought in one order.
+pub const MAX_QUANTITY: u32 = 99;
+
+/// A checked order, built with `OrderRequest::builder`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OrderRequest {
+    out_trade_no: String,
+    user_id: String,
+    item_id: ItemId,
+    quantity: u32,
+    amount: Money,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remark: Option<String>,
+}
+
+/// A required field not set yet.
+#[derive(Debug, Clone, Copy)]
+pub struct Missing;
+
+/// Builds an `OrderRequest`. `build` is only there once the trade number, user, item and
+/// amount are all set, so a missing one does not compile:
+///
+/// ```compile_fail
+/// use ho_api_rust_sdk::endpoints::OrderRequest;
+/// use ho_api_rust_sdk::types::ItemId;
+///
+/// let order = OrderRequest::builder().out_trade_no("T1").user_id("u-1").item(ItemId(7)).build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct OrderRequestBuilder<TradeNo, User, Item, Amount> {
+    out_trade_no: TradeNo,
+    user_id: User,
+    item_id: Item,
+    amount: Amount,
+    quantity: u32,
+    remark: Option<String>,
+}
+
+impl OrderRequest {
+    pub fn builder() -> OrderRequestBuilder<Missing, Missing, Missing, Missing> {
+        OrderRequestBuilder {
+            out_trade_no: Missing,
+            user_id: Missing,
+            item_id: Missing,
+            amount: Missing,
+            quantity: 1,
+            remark: None,
+        }
+    }
+
+    pub fn out_trade_no(&self) -> &str {
+        &self.out_trade_no
+    }
+
+    pub fn amount(&self) -> Money {
+        self.amount
+    }
+}
+
+impl<T, U, I, A> OrderRequestBuilder<T, U, I, A> {
+    /// The caller's order number: 1 to 64 ASCII letters, digits, `-` and `_`.
+    pub fn out_trade_no(self, out_trade_no: impl Into<String>) -> OrderRequestBuilder<String, U, I, A> {
+        OrderRequestBuilder {
+            out_trade_no: out_trade_no.into(),
+            user_id: self.user_id,
+            item_id: self.item_id,
+            amount: self.amount,
+            quantity: self.quantity,
+            remark: self.remark,
+        }
+    }
+
+    pub fn user_id(self, user_id: impl Into<String>) -> OrderRequestBuilder<T, String, I, A> {
+        OrderRequestBuilder {
+            out_trade_no: self.out_trade_no,
+            user_id: user_id.into(),
+            item_id: self.item_id,
+            amount: self.amount,
+            quantity: self.quantity,
+            remark: self.remark,
+        }
+    }
+
+    pub fn item(self, item_id: ItemId) -> OrderRequestBuilder<T, U, ItemId, A> {
+        OrderRequestBuilder {
+            out_trade_no: self.out_trade_no,
+            user_id: self.user_id,
+            item_id,
+            amount: self.amount,
+            quantity: self.quantity,
+            remark: self.remark,
+        }
+    }
+
+    /// The total to charge, for all of `quantity`.
+    pub fn amount(self, amount: Money) -> OrderRequestBuilder<T, U, I, Money> {
+        OrderRequestBuilder {
+            out_trade_no: self.out_trade_no,
+            user_id: self.user_id,
+            item_id: self.item_id,
+            amount,
+            quantity: self.quantity,
+            remark: self.remark,
+        }
+    }
+
+    /// 1 by default, at most `MAX_QUANTITY`.
+    pub fn quantity(mut self, quantity: u32) -> Self {
+        self.quantity = quantity;
+        self
+    }
+
+    /// Shown to the user with the order; at most 200 characters.
+    pub fn remark(mut self, remark: impl Into<String>) -> Self {
+        self.remark = Some(remark.into());
+        self
+    }
+}
+
+impl OrderRequestBuilder<String, String, ItemId, Money> {
+    /// Checks the values the types do not: each failure is a `RequestValidation` naming its
+    /// field.
+    pub fn build(self) -> Result<OrderRequest, ApiClientError> {
+        let invalid = |field: &str, constraint: &str, got: String| ApiClientError::RequestValidation {
+            field: field.to_string(),
+            constraint: constraint.to_string(),
+            got,
+        };
+        let trade_no_chars = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if !(1..=64).contains(&self.out_trade_no.len()) || !self.out_trade_no.chars().all(trade_no_chars) {
+            return Err(invalid(
+                "out_trade_no",
+                "1 to 64 ASCII letters, digits, `-` or `_`",
+                format!("{:?}", self.out_trade_no),
+            ));
+        }
+        if self.user_id.trim().is_empty() {
+            return Err(invalid("user_id", "not empty", format!("{:?}", self.user_id)));
+        }
+        if !(1..=MAX_QUANTITY).contains(&self.quantity) {
+            return Err(invalid(
+                "quantity",
+                &format!("between 1 and {}", MAX_QUANTITY),
+                self.quantity.to_string(),
+            ));
+        }
+        if self.amount == Money::ZERO {
+            return Err(invalid("amount", "more than 0 fen", self.amount.fen().to_string()));
+        }
+        if let Some(remark) = &self.remark {
+            if remark.chars().count() > 200 {
+                return Err(invalid(
+                    "remark",
+                    "at most 200 characters",
+                    format!("{} characters", remark.chars().count()),
+                ));
+            }
+        }
+        Ok(OrderRequest {
+            out_trade_no: self.out_trade_no,
+            user_id: self.user_id,
+            item_id: self.item_id,
+            quantity: self.quantity,
+            amount: self.amount,
+            remark: self.remark,
+        })
+    }
+}
+
+/// An order the server created.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Order {
+    pub order_id: String,
+    pub out_trade_no: String,
+    pub amount: Money,
+    /// What is left of the user's balance.
+    pub balance: Money,
+    #[serde(with = "gateway_time")]
+    pub created_at: DateTime<FixedOffset>,
+}
+
+/// How the server answered an `OrderRequest`. Refusals with their own business code are
+/// variants here; any other failure is an `ApiClientError`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderResult {
+    Created(Order),
+    /// `INSUFFICIENT_BALANCE_CODE`, with the balance when the server sent it.
+    InsufficientBalance {
+        balance: Option<Money>,
+    },
+    /// `DUPLICATE_ORDER_CODE`: `out_trade_no` was used before, for `order_id` when the
+    /// server sent it. Nothing was charged this time.
+    DuplicateOrder {
+        order_id: Option<String>,
+    },
+}
+
+impl<A: HoApi> Endpoints<'_, A> {
+    pub async fn create_order(&self, order: &OrderRequest) -> Result<OrderResult, ApiClientError> {
+        let body = serde_json::to_value(order)?;
+        let error = match self.api.send_as::<Order>(Method::POST, ORDER_URI, Some(body)).await {
+            Ok(order) => return Ok(OrderResult::Created(order)),
+            Err(error) => error,
+        };
+        let field = |name: &str| match &error {
+            ApiClientError::HttpStatus { body, .. } => serde_json::from_str::<serde_json::Value>(body)
+                .ok()
+                .and_then(|body| body.get(name).cloned()),
+            _ => None,
+        };
+        match error.business_code() {
+            Some(INSUFFICIENT_BALANCE_CODE) => Ok(OrderResult::InsufficientBalance {
+                balance: field("balance").and_then(|balance| serde_json::from_value(balance).ok()),
+            }),
+            Some(DUPLICATE_ORDER_CODE) => Ok(OrderResult::DuplicateOrder {
+                order_id: field("order_id").and_then(|id| id.as_str().map(str::to_string)),
+            }),
+            _ => Err(error),
+        }
+    }
+}
+
+impl ApiClient {
+    pub async fn create_order(&self, order: &OrderRequest) -> Result<OrderResult, ApiClientError> {
+        Endpoints::new(self).create_order(order).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport};
+
+    fn order() -> OrderRequestBuilder<String, String, ItemId, Money> {
+        OrderRequest::builder()
+            .out_trade_no("T202406010001")
+            .user_id("u-1001")
+            .item(ItemId(3070))
+            .amount(Money::from_yuan_str("25.00").unwrap())
+    }
+
+    #[test]
+    fn test_wire_format() {
+        let request = order().quantity(2).remark("gift").build().unwrap();
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"out_trade_no":"T202406010001","user_id":"u-1001","item_id":3070,"quantity":2,"amount":2500,"remark":"gift"}"#
+        );
+        // Set in any order; no remark, no field.
+        let request = OrderRequest::builder()
+            .amount(Money::from_fen(1))
+            .item(ItemId(7))
+            .user_id("u-1")
+            .out_trade_no("T1")
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"out_trade_no":"T1","user_id":"u-1","item_id":7,"quantity":1,"amount":1}"#
+        );
+    }
+
+    #[test]
+    fn test_values_are_checked() {
+        let field = |builder: OrderRequestBuilder<String, String, ItemId, Money>| match builder.build() {
+            Err(ApiClientError::RequestValidation { field, .. }) => field,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(field(order().out_trade_no("")), "out_trade_no");
+        assert_eq!(field(order().out_trade_no("T 1")), "out_trade_no");
+        assert_eq!(field(order().out_trade_no("T".repeat(65))), "out_trade_no");
+        assert_eq!(field(order().user_id(" ")), "user_id");
+        assert_eq!(field(order().quantity(0)), "quantity");
+        assert_eq!(field(order().quantity(MAX_QUANTITY + 1)), "quantity");
+        assert_eq!(field(order().amount(Money::ZERO)), "amount");
+        assert_eq!(field(order().remark("长".repeat(201))), "remark");
+        assert!(order().quantity(MAX_QUANTITY).remark("长".repeat(200)).build().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_refusals_map_to_their_variants() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::POST,
+                ORDER_URI,
+                MockResponse::encrypted(
+                    json!({
+                        "order_id": "O-88",
+                        "out_trade_no": "T202406010001",
+                        "amount": 2500,
+                        "balance": 7500,
+                        "created_at": "2024-06-01 12:00:00",
+                    })
+                    .to_string(),
+                ),
+            )
+            .on(
+                Method::POST,
+                ORDER_URI,
+                MockResponse::json(
+                    StatusCode::PAYMENT_REQUIRED,
+                    &json!({ "code": INSUFFICIENT_BALANCE_CODE, "msg": "余额不足", "balance": 300 }),
+                ),
+            )
+            .on(
+                Method::POST,
+                ORDER_URI,
+                MockResponse::json(
+                    StatusCode::CONFLICT,
+                    &json!({ "code": DUPLICATE_ORDER_CODE, "msg": "订单重复", "order_id": "O-88" }),
+                ),
+            )
+            .on(
+                Method::POST,
+                ORDER_URI,
+                MockResponse::json(StatusCode::BAD_REQUEST, &json!({ "code": 40001, "msg": "价格不符" })),
+            );
+        let client = mock_client(&transport, |_| {});
+        let request = order().build().unwrap();
+
+        match client.create_order(&request).await.unwrap() {
+            OrderResult::Created(order) => {
+                assert_eq!(
+                    (order.amount.fen(), order.balance.to_string()),
+                    (2500, "75.00".to_string())
+                );
+                assert_eq!(order.created_at.to_rfc3339(), "2024-06-01T12:00:00+08:00");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            client.create_order(&request).await.unwrap(),
+            OrderResult::InsufficientBalance {
+                balance: Some(Money::from_fen(300))
+            }
+        );
+        assert_eq!(
+            client.create_order(&request).await.unwrap(),
+            OrderResult::DuplicateOrder {
+                order_id: Some("O-88".to_string())
+            }
+        );
+        let error = client.create_order(&request).await.unwrap_err();
+        assert_eq!(error.business_code(), Some(40001));
+
+        let sent: serde_json::Value = serde_json::from_slice(&transport.requests()[0].body).unwrap();
+        let payload: serde_json::Value = serde_json::from_str(sent["data"].as_str().unwrap()).unwrap();
+        assert_eq!(payload, serde_json::to_value(&request).unwrap());
+    }
+}
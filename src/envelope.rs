@@ -0,0 +1,183 @@
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::crypto::Crypto;
+use crate::ApiClientError;
+
+pub(crate) const ENCRYPTED_HEADER: &str = "HO-ENCRYPTED";
+pub(crate) const TRUNCATED_HEADER: &str = "HO-TRUNCATED";
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ApiResult {
+    data: Value,
+    encrypted: Option<bool>,
+}
+
+/// Extracts the plaintext from a response envelope. `data` is hex ciphertext unless the
+/// `HO-ENCRYPTED` header or, failing that, the envelope's `encrypted` flag says otherwise.
+pub(crate) fn open(crypto: &Crypto, headers: &HeaderMap, body: &[u8]) -> Result<String, ApiClientError> {
+    open_parsed(crypto, headers, &parse(body)?)
+}
+
+pub(crate) fn parse(body: &[u8]) -> Result<ApiResult, ApiClientError> {
+    Ok(serde_json::from_slice(body)?)
+}
+
+/// `open` for an envelope already parsed, so that each key of a rotation reuses it.
+pub(crate) fn open_parsed(
+    crypto: &Crypto,
+    headers: &HeaderMap,
+    api_result: &ApiResult,
+) -> Result<String, ApiClientError> {
+    let encrypted = header_flag(headers).or(api_result.encrypted).unwrap_or(true);
+    match &api_result.data {
+        Value::String(data) if encrypted => crypto.decrypt_hex(data.as_bytes()),
+        Value::String(data) => Ok(data.clone()),
+        data if encrypted => Err(ApiClientError::InvalidEnvelope(format!(
+            "expected hex ciphertext in `data`, found {}",
+            json_type(data)
+        ))),
+        data => Ok(data.to_string()),
+    }
+}
+
+/// `open_parsed` for a binary payload: the decrypted bytes, or those of an unencrypted
+/// `data` string.
+#[cfg(feature = "archive")]
+pub(crate) fn open_parsed_bytes(
+    crypto: &Crypto,
+    headers: &HeaderMap,
+    api_result: &ApiResult,
+) -> Result<Vec<u8>, ApiClientError> {
+    let encrypted = header_flag(headers).or(api_result.encrypted).unwrap_or(true);
+    match &api_result.data {
+        Value::String(data) if encrypted => crypto.decrypt_hex_bytes(data.as_bytes()),
+        Value::String(data) => Ok(data.clone().into_bytes()),
+        data => Err(ApiClientError::InvalidEnvelope(format!(
+            "expected a string in `data`, found {}",
+            json_type(data)
+        ))),
+    }
+}
+
+/// Whether the server cut a list short: a 206 status or `HO-TRUNCATED: true`.
+pub(crate) fn is_truncated(status: StatusCode, headers: &HeaderMap) -> bool {
+    status == StatusCode::PARTIAL_CONTENT || flag(headers, TRUNCATED_HEADER) == Some(true)
+}
+
+fn header_flag(headers: &HeaderMap) -> Option<bool> {
+    flag(headers, ENCRYPTED_HEADER)
+}
+
+fn flag(headers: &HeaderMap, name: &str) -> Option<bool> {
+    let value = headers.get(name)?.to_str().ok()?.trim();
+    match value.to_ascii_lowercase().as_str() {
+        "0" | "false" => Some(false),
+        "1" | "true" => Some(true),
+        _ => None,
+    }
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+    use serde_json::json;
+
+    use super::*;
+
+    const PLAIN: &str = r#"{"name":"Annie"}"#;
+
+    fn crypto() -> Crypto {
+        Crypto::new("0123456789abcdef0123456789abcdef", "fedcba9876543210").unwrap()
+    }
+
+    fn headers(encrypted: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = encrypted {
+            headers.insert(ENCRYPTED_HEADER, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    fn encrypted_body(flag: Option<bool>) -> Vec<u8> {
+        let mut envelope = json!({ "data": crypto().encrypt_hex(PLAIN.as_bytes()) });
+        if let Some(flag) = flag {
+            envelope["encrypted"] = json!(flag);
+        }
+        envelope.to_string().into_bytes()
+    }
+
+    fn plain_body(flag: Option<bool>) -> Vec<u8> {
+        let mut envelope = json!({ "data": PLAIN });
+        if let Some(flag) = flag {
+            envelope["encrypted"] = json!(flag);
+        }
+        envelope.to_string().into_bytes()
+    }
+
+    #[test]
+    fn test_legacy_envelope_is_decrypted() {
+        assert_eq!(open(&crypto(), &headers(None), &encrypted_body(None)).unwrap(), PLAIN);
+    }
+
+    #[test]
+    fn test_body_flag_true_is_decrypted() {
+        assert_eq!(
+            open(&crypto(), &headers(None), &encrypted_body(Some(true))).unwrap(),
+            PLAIN
+        );
+    }
+
+    #[test]
+    fn test_body_flag_false_is_returned_as_is() {
+        assert_eq!(
+            open(&crypto(), &headers(None), &plain_body(Some(false))).unwrap(),
+            PLAIN
+        );
+
+        let object = json!({ "encrypted": false, "data": { "code": 1 } }).to_string();
+        assert_eq!(
+            open(&crypto(), &headers(None), object.as_bytes()).unwrap(),
+            r#"{"code":1}"#
+        );
+    }
+
+    #[test]
+    fn test_header_overrides_body_flag() {
+        assert_eq!(
+            open(&crypto(), &headers(Some("0")), &plain_body(Some(true))).unwrap(),
+            PLAIN
+        );
+        assert_eq!(
+            open(&crypto(), &headers(Some("1")), &encrypted_body(Some(false))).unwrap(),
+            PLAIN
+        );
+    }
+
+    #[test]
+    fn test_header_alone_disables_decryption() {
+        assert_eq!(open(&crypto(), &headers(Some("0")), &plain_body(None)).unwrap(), PLAIN);
+    }
+
+    #[test]
+    fn test_non_string_data_requires_plaintext_mode() {
+        let body = json!({ "data": { "code": 1 } }).to_string();
+        assert!(matches!(
+            open(&crypto(), &headers(None), body.as_bytes()),
+            Err(ApiClientError::InvalidEnvelope(_))
+        ));
+    }
+}
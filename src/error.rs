@@ -0,0 +1,650 @@
+use std::fmt;
+
+/// Errors returned by the client. New variants may be added in minor releases; the `is_*`
+/// helpers below classify them without matching on every variant.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ApiClientError {
+    #[cfg(feature = "client")]
+    ReqwestError(reqwest::Error),
+    /// The peer closed or reset the connection before answering. `connection` tells whether
+    /// a stale pooled connection may be to blame.
+    #[cfg(feature = "client")]
+    ConnectionClosed {
+        connection: crate::transport::ConnectionReuse,
+        source: reqwest::Error,
+    },
+    SerdeJsonError(serde_json::Error),
+    AesError(block_modes::BlockModeError),
+    /// A response failed to decrypt with the primary key and again with
+    /// `ApiClientConfig::secondary_key`.
+    BothKeysFailed {
+        primary: Box<ApiClientError>,
+        secondary: Box<ApiClientError>,
+    },
+    Utf8Error(std::string::FromUtf8Error),
+    /// Decrypted bytes are not valid text in `charset`; `offset` is the first bad byte.
+    InvalidCharset {
+        charset: crate::charset::Charset,
+        offset: usize,
+    },
+    HexError(hex::FromHexError),
+    InvalidHex(crate::crypto::HexPayloadError),
+    /// The ciphertext decoded to `len` bytes, which is not a whole number of 16-byte AES
+    /// blocks: it was cut short, or is not ciphertext at all.
+    InvalidCiphertextLength {
+        len: usize,
+    },
+    InvalidConfig(String),
+    TruncatedStream(usize),
+    InvalidQuery(String),
+    /// The request uri cannot be sent as given; nothing was signed or sent.
+    InvalidUri {
+        uri: String,
+        reason: String,
+    },
+    /// A client-side request check failed; nothing was sent.
+    RequestValidation {
+        field: String,
+        constraint: String,
+        got: String,
+    },
+    InvalidEnvelope(String),
+    /// The serialized request body is larger than `ApiClientConfig::max_request_size`;
+    /// nothing was sent.
+    RequestTooLarge {
+        limit: usize,
+    },
+    /// The request body is a JSON string whose text is itself a JSON `kind` ("object" or
+    /// "array"), as from `json!(serde_json::to_string(&body)?)`; nothing was sent. `preview`
+    /// is the start of the string. See `ApiClientConfig::detect_double_encoding`.
+    SuspectedDoubleEncoding {
+        kind: String,
+        preview: String,
+    },
+    /// The plaintext is valid JSON but does not match the expected type at `path`.
+    InvalidPayload {
+        path: String,
+        message: String,
+    },
+    #[cfg(feature = "client")]
+    HttpStatus {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    /// The server answered `status` with a redirect to `location` that the client did not
+    /// follow, for `reason`.
+    #[cfg(feature = "client")]
+    RedirectRefused {
+        status: reqwest::StatusCode,
+        location: String,
+        reason: crate::transport::RedirectRefusal,
+    },
+    /// The request's path is down for the scheduled maintenance in `notice`: the gateway
+    /// answered with the notice, or announced it earlier and the request was not sent. Never
+    /// retried; see `ApiClient::active_maintenance`.
+    #[cfg(feature = "client")]
+    Maintenance {
+        notice: crate::maintenance::MaintenanceNotice,
+    },
+    /// The server cut the response to `uri` short and `ApiClientConfig::fail_on_truncated`
+    /// is set. `body` is the decrypted partial response.
+    Truncated {
+        uri: String,
+        body: String,
+    },
+    /// Response processor `stage` (from 0, in the order they were added) rejected the
+    /// response to `uri` for `reason`.
+    ResponseProcessing {
+        uri: String,
+        stage: usize,
+        reason: String,
+    },
+    PaginationLoop {
+        cursor: String,
+        repeats: usize,
+    },
+    /// Every entry of a batch response failed; see `BatchResult::into_result`.
+    BatchFailed {
+        failed: Vec<crate::payload::BatchItemError>,
+    },
+    #[cfg(feature = "client")]
+    AttemptTimeout {
+        attempts: u32,
+    },
+    /// The request waited `waited` for a concurrency permit, more than
+    /// `ApiClientConfig::max_queue_wait`, and was dropped unsent.
+    #[cfg(feature = "client")]
+    QueueTimeout {
+        waited: std::time::Duration,
+    },
+    /// `ApiClientConfig::nonce_guard` remembers `nonce` as issued already: the request,
+    /// or the random source behind nonces, repeats. Nothing was sent.
+    #[cfg(feature = "client")]
+    NonceReuse {
+        nonce: String,
+    },
+    /// The request's priority is held back by `ApiClientConfig::quota_reserve`: the gateway
+    /// last reported `remaining` requests of quota, below `reserve`, and the request was not
+    /// sent.
+    #[cfg(feature = "client")]
+    QuotaReserved {
+        remaining: u64,
+        reserve: u64,
+    },
+    #[cfg(feature = "client")]
+    OverallTimeout {
+        attempts: u32,
+        elapsed: std::time::Duration,
+    },
+    #[cfg(feature = "client")]
+    JobFailed {
+        job_id: String,
+        state: crate::jobs::JobState,
+        detail: Option<String>,
+    },
+    #[cfg(feature = "client")]
+    JobTimeout {
+        job_id: String,
+        elapsed: std::time::Duration,
+    },
+    /// The server rejected the signature (business code 40102) and
+    /// `ApiClientConfig::triage_signature_failures` is set. `components` are the lengths
+    /// of the parts signed here, `timestamp` the one sent; `body_sha1` is the SHA-1 of the
+    /// signed body when `reason` is `BodyHash`. `body` is the response body.
+    #[cfg(feature = "client")]
+    SignatureRejected {
+        status: reqwest::StatusCode,
+        reason: crate::triage::SignatureRejectionReason,
+        components: crate::triage::SignComponents,
+        timestamp: i64,
+        body_sha1: Option<String>,
+        body: String,
+    },
+    /// `Paginator::export_csv` could not write to its writer.
+    #[cfg(feature = "client")]
+    ExportWrite(std::io::Error),
+    /// A `download_archive` archive could not be read: as a whole when `entry` is `None`,
+    /// otherwise the entry of that name, at record `line` (from 1) when one record failed.
+    #[cfg(feature = "client")]
+    InvalidArchive {
+        entry: Option<String>,
+        line: Option<usize>,
+        reason: String,
+    },
+    /// A chunked upload could not go on: the file could not be read or differs from the one
+    /// the session started with, or the server kept acknowledging a wrong checksum. `chunk`
+    /// is the index of the chunk concerned.
+    #[cfg(feature = "client")]
+    UploadFailed {
+        upload_id: String,
+        chunk: Option<u64>,
+        reason: String,
+    },
+    /// Step `step` (from 1) of a `Chain` failed with `source`, while sending `uri` or while
+    /// extracting values from its response. The `is_*` helpers, `status` and
+    /// `business_code` answer for `source`.
+    #[cfg(feature = "client")]
+    ChainStepFailed {
+        step: usize,
+        uri: String,
+        source: Box<ApiClientError>,
+    },
+}
+
+impl ApiClientError {
+    /// Whether sending the same request again may succeed: transport failures, timeouts and
+    /// 5xx responses. The client's own retries and host failover use exactly this test.
+    ///
+    /// Never true for an error for which `is_auth_error` or `is_invalid_request` is true, and
+    /// false for variants it does not know.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            #[cfg(feature = "client")]
+            ApiClientError::ChainStepFailed { source, .. } => source.is_retryable(),
+            #[cfg(feature = "client")]
+            ApiClientError::ReqwestError(e) => !e.is_builder(),
+            #[cfg(feature = "client")]
+            ApiClientError::ConnectionClosed { .. }
+            | ApiClientError::AttemptTimeout { .. }
+            | ApiClientError::OverallTimeout { .. } => true,
+            #[cfg(feature = "client")]
+            ApiClientError::HttpStatus { status, .. } => status.is_server_error(),
+            _ => false,
+        }
+    }
+
+    /// A 401 or 403 response: the credentials or signature were rejected.
+    pub fn is_auth_error(&self) -> bool {
+        match self {
+            #[cfg(feature = "client")]
+            ApiClientError::ChainStepFailed { source, .. } => source.is_auth_error(),
+            #[cfg(feature = "client")]
+            ApiClientError::HttpStatus { status, .. } | ApiClientError::SignatureRejected { status, .. } => {
+                matches!(status.as_u16(), 401 | 403)
+            }
+            _ => false,
+        }
+    }
+
+    /// A 5xx response.
+    pub fn is_server_error(&self) -> bool {
+        match self {
+            #[cfg(feature = "client")]
+            ApiClientError::ChainStepFailed { source, .. } => source.is_server_error(),
+            #[cfg(feature = "client")]
+            ApiClientError::HttpStatus { status, .. } => status.is_server_error(),
+            _ => false,
+        }
+    }
+
+    /// The request itself is wrong and will fail the same way every time: it failed a
+    /// client-side check, or the server answered with a 4xx other than 401, 403 and 429.
+    pub fn is_invalid_request(&self) -> bool {
+        match self {
+            #[cfg(feature = "client")]
+            ApiClientError::ChainStepFailed { source, .. } => source.is_invalid_request(),
+            ApiClientError::InvalidQuery(_)
+            | ApiClientError::InvalidUri { .. }
+            | ApiClientError::RequestValidation { .. }
+            | ApiClientError::RequestTooLarge { .. }
+            | ApiClientError::SuspectedDoubleEncoding { .. } => true,
+            #[cfg(feature = "client")]
+            ApiClientError::HttpStatus { status, .. } => {
+                status.is_client_error() && !matches!(status.as_u16(), 401 | 403 | 429)
+            }
+            _ => false,
+        }
+    }
+
+    /// The HTTP status of a non-2xx response.
+    #[cfg(feature = "client")]
+    pub fn status(&self) -> Option<reqwest::StatusCode> {
+        match self {
+            ApiClientError::HttpStatus { status, .. }
+            | ApiClientError::RedirectRefused { status, .. }
+            | ApiClientError::SignatureRejected { status, .. } => Some(*status),
+            ApiClientError::ChainStepFailed { source, .. } => source.status(),
+            _ => None,
+        }
+    }
+
+    /// The `code` field of an error response body such as `{"code": 40102, "msg": "..."}`.
+    pub fn business_code(&self) -> Option<i64> {
+        match self {
+            #[cfg(feature = "client")]
+            ApiClientError::ChainStepFailed { source, .. } => source.business_code(),
+            #[cfg(feature = "client")]
+            ApiClientError::HttpStatus { body, .. } | ApiClientError::SignatureRejected { body, .. } => {
+                serde_json::from_str::<serde_json::Value>(body)
+                    .ok()?
+                    .get("code")?
+                    .as_i64()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The `message` of `to_wire`. It includes the message of a wrapped library error;
+/// `source` gives what lies under that, and the source of a `ChainStepFailed`.
+impl fmt::Display for ApiClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&crate::wire::describe(self).1)
+    }
+}
+
+impl std::error::Error for ApiClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "client")]
+            ApiClientError::ReqwestError(e) | ApiClientError::ConnectionClosed { source: e, .. } => e.source(),
+            #[cfg(feature = "client")]
+            ApiClientError::ExportWrite(e) => e.source(),
+            #[cfg(feature = "client")]
+            ApiClientError::ChainStepFailed { source, .. } => Some(&**source),
+            ApiClientError::SerdeJsonError(e) => e.source(),
+            ApiClientError::Utf8Error(e) => e.source(),
+            ApiClientError::HexError(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+impl From<reqwest::Error> for ApiClientError {
+    fn from(err: reqwest::Error) -> Self {
+        ApiClientError::ReqwestError(err)
+    }
+}
+
+impl From<serde_json::Error> for ApiClientError {
+    fn from(err: serde_json::Error) -> Self {
+        ApiClientError::SerdeJsonError(err)
+    }
+}
+
+impl From<block_modes::BlockModeError> for ApiClientError {
+    fn from(err: block_modes::BlockModeError) -> Self {
+        ApiClientError::AesError(err)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for ApiClientError {
+    fn from(err: std::string::FromUtf8Error) -> Self {
+        ApiClientError::Utf8Error(err)
+    }
+}
+
+impl From<hex::FromHexError> for ApiClientError {
+    fn from(err: hex::FromHexError) -> Self {
+        ApiClientError::HexError(err)
+    }
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::jobs::JobState;
+    use crate::transport::{ConnectionReuse, RedirectRefusal};
+
+    fn http(status: u16, body: &str) -> ApiClientError {
+        ApiClientError::HttpStatus {
+            status: StatusCode::from_u16(status).unwrap(),
+            body: body.to_string(),
+        }
+    }
+
+    /// (error, retryable, auth, server, invalid request)
+    async fn samples() -> Vec<(ApiClientError, bool, bool, bool, bool)> {
+        let refused = reqwest::get("http://127.0.0.1:1/").await.unwrap_err();
+        let builder = reqwest::Client::new().get("not a url").build().unwrap_err();
+        vec![
+            (ApiClientError::ReqwestError(refused), true, false, false, false),
+            (ApiClientError::ReqwestError(builder), false, false, false, false),
+            (
+                ApiClientError::ExportWrite(std::io::Error::other("disk full")),
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::ConnectionClosed {
+                    connection: ConnectionReuse::Fresh,
+                    source: crate::testing::connection_reset_error().await,
+                },
+                true,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::AttemptTimeout { attempts: 3 },
+                true,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::OverallTimeout {
+                    attempts: 2,
+                    elapsed: Duration::from_secs(6),
+                },
+                true,
+                false,
+                false,
+                false,
+            ),
+            (http(503, ""), true, false, true, false),
+            (http(500, "{}"), true, false, true, false),
+            (http(401, ""), false, true, false, false),
+            (http(403, ""), false, true, false, false),
+            (http(429, ""), false, false, false, false),
+            (
+                ApiClientError::RedirectRefused {
+                    status: StatusCode::TEMPORARY_REDIRECT,
+                    location: "https://other.example.test/".to_string(),
+                    reason: RedirectRefusal::CrossHost,
+                },
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::Maintenance {
+                    notice: serde_json::from_str(r#"{"start":"2024-06-01 02:00:00","end":"2024-06-01 04:00:00"}"#)
+                        .unwrap(),
+                },
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::NonceReuse {
+                    nonce: "3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a13".to_string(),
+                },
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::QuotaReserved {
+                    remaining: 40,
+                    reserve: 100,
+                },
+                false,
+                false,
+                false,
+                false,
+            ),
+            (http(400, ""), false, false, false, true),
+            (http(404, ""), false, false, false, true),
+            (
+                ApiClientError::InvalidQuery("bad".to_string()),
+                false,
+                false,
+                false,
+                true,
+            ),
+            (
+                ApiClientError::InvalidUri {
+                    uri: "/v1/a\r\nb".to_string(),
+                    reason: "control character".to_string(),
+                },
+                false,
+                false,
+                false,
+                true,
+            ),
+            (
+                ApiClientError::RequestValidation {
+                    field: "page_size".to_string(),
+                    constraint: "at most 200".to_string(),
+                    got: "500".to_string(),
+                },
+                false,
+                false,
+                false,
+                true,
+            ),
+            (
+                ApiClientError::SuspectedDoubleEncoding {
+                    kind: "object".to_string(),
+                    preview: r#"{"sku":7}"#.to_string(),
+                },
+                false,
+                false,
+                false,
+                true,
+            ),
+            (
+                ApiClientError::InvalidConfig("bad".to_string()),
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::InvalidEnvelope("bad".to_string()),
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::AesError(block_modes::BlockModeError),
+                false,
+                false,
+                false,
+                false,
+            ),
+            (ApiClientError::TruncatedStream(3), false, false, false, false),
+            (
+                ApiClientError::Truncated {
+                    uri: "/v1/x".to_string(),
+                    body: "{}".to_string(),
+                },
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::ResponseProcessing {
+                    uri: "/v1/x".to_string(),
+                    stage: 0,
+                    reason: "bad".to_string(),
+                },
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::BatchFailed { failed: Vec::new() },
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::InvalidCiphertextLength { len: 31 },
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::JobFailed {
+                    job_id: "j".to_string(),
+                    state: JobState::Failed,
+                    detail: None,
+                },
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::JobTimeout {
+                    job_id: "j".to_string(),
+                    elapsed: Duration::from_secs(600),
+                },
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::SignatureRejected {
+                    status: StatusCode::UNAUTHORIZED,
+                    reason: crate::triage::SignatureRejectionReason::Secret,
+                    components: Default::default(),
+                    timestamp: 0,
+                    body_sha1: None,
+                    body: r#"{"code":40102}"#.to_string(),
+                },
+                false,
+                true,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::UploadFailed {
+                    upload_id: "u".to_string(),
+                    chunk: Some(2),
+                    reason: "cannot read the chunk".to_string(),
+                },
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::InvalidArchive {
+                    entry: Some("2024-07.ndjson".to_string()),
+                    line: None,
+                    reason: "corrupt deflate stream".to_string(),
+                },
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                ApiClientError::ChainStepFailed {
+                    step: 2,
+                    uri: "/v1/b".to_string(),
+                    source: Box::new(http(503, "")),
+                },
+                true,
+                false,
+                true,
+                false,
+            ),
+            (
+                ApiClientError::ChainStepFailed {
+                    step: 1,
+                    uri: "/v1/a".to_string(),
+                    source: Box::new(http(403, "")),
+                },
+                false,
+                true,
+                false,
+                false,
+            ),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_classification_per_variant() {
+        for (err, retryable, auth, server, invalid) in samples().await {
+            assert_eq!(err.is_retryable(), retryable, "is_retryable {:?}", err);
+            assert_eq!(err.is_auth_error(), auth, "is_auth_error {:?}", err);
+            assert_eq!(err.is_server_error(), server, "is_server_error {:?}", err);
+            assert_eq!(err.is_invalid_request(), invalid, "is_invalid_request {:?}", err);
+            if err.is_retryable() {
+                assert!(!err.is_auth_error() && !err.is_invalid_request(), "{:?}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_status_and_business_code() {
+        let err = http(401, r#"{"code":40102,"msg":"signature mismatch"}"#);
+        assert_eq!(err.status(), Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(err.business_code(), Some(40102));
+
+        assert_eq!(http(502, "<html>bad gateway</html>").business_code(), None);
+        assert_eq!(http(400, r#"{"code":"E1"}"#).business_code(), None);
+        assert_eq!(ApiClientError::TruncatedStream(1).status(), None);
+    }
+}
@@ -0,0 +1,429 @@
+//! Flattening list endpoints into CSV for analytics exports. Items are read page by page and
+//! each becomes one or more RFC 4180 records as soon as it arrives, so an export never holds
+//! more than a page in memory. Enabled by the `export` feature.
+//!
+//! ```no_run
+//! # async fn run(client: &ho_api_rust_sdk::ApiClient) -> Result<(), ho_api_rust_sdk::ApiClientError> {
+//! use std::io::BufWriter;
+//!
+//! use ho_api_rust_sdk::export::{ColumnSpec, ColumnType};
+//! use reqwest::Method;
+//!
+//! let columns = [
+//!     ColumnSpec::new("id", "id").kind(ColumnType::Integer).required(),
+//!     ColumnSpec::new("name", "name"),
+//!     ColumnSpec::new("price", "price.amount").kind(ColumnType::Float).or("0"),
+//!     ColumnSpec::new("tag", "tags").explode(),
+//! ];
+//! let file = BufWriter::new(std::fs::File::create("skins.csv").unwrap());
+//! let summary = client
+//!     .export_csv(Method::GET, "/v1/lol/skin/list", None, &columns, file)
+//!     .await?;
+//! println!("{} items, {} rows", summary.items, summary.rows);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::{self, Write};
+
+use futures_util::TryStreamExt;
+use reqwest::Method;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::chain::{lookup, parse_path, Segment};
+use crate::pagination::Paginator;
+use crate::{ApiClient, ApiClientError};
+
+/// How a column's value is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    /// Strings as they are, numbers and booleans as JSON writes them, objects and arrays as
+    /// JSON text.
+    Text,
+    /// Whole numbers, from numbers without a fraction or numeric strings.
+    Integer,
+    /// Numbers or numeric strings.
+    Float,
+    /// `true`/`false`, from booleans, those strings in any case, or `0` and `1`.
+    Bool,
+    /// The value as JSON text, strings included with their quotes.
+    Json,
+}
+
+impl ColumnType {
+    fn name(&self) -> &'static str {
+        match self {
+            ColumnType::Text => "text",
+            ColumnType::Integer => "an integer",
+            ColumnType::Float => "a float",
+            ColumnType::Bool => "a bool",
+            ColumnType::Json => "JSON",
+        }
+    }
+}
+
+/// What a column holds when its path is absent or null.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Missing {
+    Empty,
+    Default(String),
+    /// Fails the export with `InvalidPayload`.
+    Fail,
+}
+
+/// How a column whose value is an array is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrayMode {
+    /// The array as JSON text in one cell.
+    Stringify,
+    /// One record per element, the other columns repeated. With several exploded columns
+    /// the item has as many records as its longest array; shorter arrays leave their cells
+    /// empty. An empty array counts as missing.
+    Explode,
+}
+
+/// One CSV column: its header and the path of its value in each item, in the syntax of
+/// `Chain::extract` (`name`, `price.amount`, `tags[0]`, `$` for the item itself).
+#[derive(Debug, Clone)]
+pub struct ColumnSpec {
+    pub header: String,
+    pub path: String,
+    pub kind: ColumnType,
+    pub missing: Missing,
+    pub arrays: ArrayMode,
+}
+
+impl ColumnSpec {
+    /// A `Text` column, empty when missing, arrays stringified.
+    pub fn new(header: &str, path: &str) -> Self {
+        Self {
+            header: header.to_string(),
+            path: path.to_string(),
+            kind: ColumnType::Text,
+            missing: Missing::Empty,
+            arrays: ArrayMode::Stringify,
+        }
+    }
+
+    pub fn kind(mut self, kind: ColumnType) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Writes `default` when the value is missing.
+    pub fn or(mut self, default: &str) -> Self {
+        self.missing = Missing::Default(default.to_string());
+        self
+    }
+
+    /// Fails the export when the value is missing.
+    pub fn required(mut self) -> Self {
+        self.missing = Missing::Fail;
+        self
+    }
+
+    pub fn explode(mut self) -> Self {
+        self.arrays = ArrayMode::Explode;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct ExportSummary {
+    /// Items read from the list.
+    pub items: u64,
+    /// Records written, not counting the header.
+    pub rows: u64,
+}
+
+impl ApiClient {
+    /// Exports every item of the list at `uri` as CSV into `writer`, with `paginate`'s
+    /// defaults. Use `Paginator::export_csv` for other page sizes or cursor lists.
+    pub async fn export_csv(
+        &self,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+        columns: &[ColumnSpec],
+        writer: impl Write,
+    ) -> Result<ExportSummary, ApiClientError> {
+        let mut paginator = self.paginate(method, uri);
+        if let Some(body) = body {
+            paginator = paginator.body(body);
+        }
+        paginator.export_csv(columns, writer).await
+    }
+}
+
+impl Paginator<'_> {
+    /// Writes a header record, then the records of each item as its page arrives. Fails
+    /// with `InvalidPayload` for an invalid path, a value that does not convert to its
+    /// column's type or a missing required value, and with `ExportWrite` when `writer`
+    /// does; records already written stay written.
+    pub async fn export_csv(
+        self,
+        columns: &[ColumnSpec],
+        mut writer: impl Write,
+    ) -> Result<ExportSummary, ApiClientError> {
+        let paths = columns
+            .iter()
+            .map(|column| parse_path(&column.path))
+            .collect::<Result<Vec<_>, _>>()?;
+        let headers: Vec<&str> = columns.iter().map(|column| column.header.as_str()).collect();
+        write_record(&mut writer, &headers).map_err(ApiClientError::ExportWrite)?;
+
+        let mut summary = ExportSummary::default();
+        let mut items = std::pin::pin!(self.items::<Value>());
+        while let Some(item) = items.try_next().await? {
+            let cells = columns
+                .iter()
+                .zip(&paths)
+                .map(|(column, path)| cells(column, path, &item, summary.items))
+                .collect::<Result<Vec<_>, _>>()?;
+            let records = cells.iter().map(Cells::len).max().unwrap_or(1);
+            for record in 0..records {
+                let fields: Vec<&str> = cells.iter().map(|cells| cells.get(record)).collect();
+                write_record(&mut writer, &fields).map_err(ApiClientError::ExportWrite)?;
+            }
+            summary.items += 1;
+            summary.rows += records as u64;
+        }
+        writer.flush().map_err(ApiClientError::ExportWrite)?;
+        Ok(summary)
+    }
+}
+
+enum Cells {
+    /// The same cell in every record of the item.
+    One(String),
+    Exploded(Vec<String>),
+}
+
+impl Cells {
+    fn len(&self) -> usize {
+        match self {
+            Cells::One(_) => 1,
+            Cells::Exploded(cells) => cells.len(),
+        }
+    }
+
+    fn get(&self, record: usize) -> &str {
+        match self {
+            Cells::One(cell) => cell,
+            Cells::Exploded(cells) => cells.get(record).map_or("", String::as_str),
+        }
+    }
+}
+
+fn cells(column: &ColumnSpec, path: &[Segment<'_>], item: &Value, index: u64) -> Result<Cells, ApiClientError> {
+    let invalid = |message: String| ApiClientError::InvalidPayload {
+        path: column.path.clone(),
+        message: format!("item {}, column `{}`: {}", index, column.header, message),
+    };
+    let value = lookup(item, path).filter(|value| !value.is_null());
+    let value = match (value, column.arrays) {
+        (Some(Value::Array(elements)), ArrayMode::Explode) if !elements.is_empty() => {
+            let cells = elements
+                .iter()
+                .map(|element| convert(column.kind, element).ok_or_else(|| cannot_read(column.kind, element)))
+                .collect::<Result<_, _>>()
+                .map_err(invalid)?;
+            return Ok(Cells::Exploded(cells));
+        }
+        (Some(Value::Array(_)), ArrayMode::Explode) => None,
+        (value, _) => value,
+    };
+    let cell = match (value, &column.missing) {
+        (Some(value), _) => convert(column.kind, value).ok_or_else(|| invalid(cannot_read(column.kind, value)))?,
+        (None, Missing::Empty) => String::new(),
+        (None, Missing::Default(default)) => default.clone(),
+        (None, Missing::Fail) => return Err(invalid("no value for a required column".to_string())),
+    };
+    Ok(Cells::One(cell))
+}
+
+fn cannot_read(kind: ColumnType, value: &Value) -> String {
+    format!("cannot read {} as {}", value, kind.name())
+}
+
+fn convert(kind: ColumnType, value: &Value) -> Option<String> {
+    match (kind, value) {
+        (ColumnType::Json, value) => Some(value.to_string()),
+        (ColumnType::Text, Value::String(text)) => Some(text.clone()),
+        (ColumnType::Text, value) => Some(value.to_string()),
+        (ColumnType::Integer, Value::Number(number)) => match (number.as_i64(), number.as_u64(), number.as_f64()) {
+            (Some(n), _, _) => Some(n.to_string()),
+            (_, Some(n), _) => Some(n.to_string()),
+            (_, _, Some(f)) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => Some((f as i64).to_string()),
+            _ => None,
+        },
+        (ColumnType::Integer, Value::String(text)) => {
+            let text = text.trim();
+            let parsed = text.parse::<i64>().map(|n| n.to_string());
+            parsed.or_else(|_| text.parse::<u64>().map(|n| n.to_string())).ok()
+        }
+        (ColumnType::Float, Value::Number(number)) => Some(number.to_string()),
+        (ColumnType::Float, Value::String(text)) => {
+            let parsed: f64 = text.trim().parse().ok()?;
+            parsed.is_finite().then(|| parsed.to_string())
+        }
+        (ColumnType::Bool, Value::Bool(flag)) => Some(flag.to_string()),
+        (ColumnType::Bool, Value::String(text)) => match text.trim().to_ascii_lowercase().as_str() {
+            flag @ ("true" | "false") => Some(flag.to_string()),
+            _ => None,
+        },
+        (ColumnType::Bool, Value::Number(number)) => match number.as_u64() {
+            Some(0) => Some("false".to_string()),
+            Some(1) => Some("true".to_string()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// One record, fields quoted when they hold a comma, quote, CR or LF, ended with CRLF.
+fn write_record(writer: &mut impl Write, fields: &[&str]) -> io::Result<()> {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        if field.contains([',', '"', '\r', '\n']) {
+            writer.write_all(b"\"")?;
+            writer.write_all(field.replace('"', "\"\"").as_bytes())?;
+            writer.write_all(b"\"")?;
+        } else {
+            writer.write_all(field.as_bytes())?;
+        }
+    }
+    writer.write_all(b"\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport};
+
+    fn page(list: Value) -> MockResponse {
+        MockResponse::encrypted(json!({ "list": list, "total": 3 }).to_string())
+    }
+
+    async fn export(columns: &[ColumnSpec], item: Value) -> Result<String, ApiClientError> {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/list", page(json!([item])));
+        let client = mock_client(&transport, |_| {});
+        let mut csv = Vec::new();
+        client
+            .export_csv(Method::GET, "/v1/list", None, columns, &mut csv)
+            .await?;
+        Ok(String::from_utf8(csv).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_fields_are_quoted_per_rfc_4180() {
+        let mut csv = Vec::new();
+        let fields = ["暗星，光辉", "\"源计划\" 亚索", "第一行\n第二行", "plain", "a\rb", ""];
+        write_record(&mut csv, &fields).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "暗星，光辉,\"\"\"源计划\"\" 亚索\",\"第一行\n第二行\",plain,\"a\rb\",\r\n"
+        );
+
+        let commas = export(&[ColumnSpec::new("name", "name")], json!({ "name": "星守, 金克丝" }))
+            .await
+            .unwrap();
+        assert_eq!(commas, "name\r\n\"星守, 金克丝\"\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_types_missing_values_and_arrays() {
+        let columns = [
+            ColumnSpec::new("id", "id").kind(ColumnType::Integer).required(),
+            ColumnSpec::new("price", "price.amount").kind(ColumnType::Float).or("0"),
+            ColumnSpec::new("on_sale", "on_sale").kind(ColumnType::Bool),
+            ColumnSpec::new("chromas", "chromas"),
+            ColumnSpec::new("tag", "tags").explode(),
+            ColumnSpec::new("first_tag", "tags[0]").kind(ColumnType::Json),
+        ];
+        let item = json!({
+            "id": "266",
+            "price": null,
+            "on_sale": 1,
+            "chromas": [{ "id": 1 }],
+            "tags": ["战士", "坦克"],
+        });
+        assert_eq!(
+            export(&columns, item).await.unwrap(),
+            "id,price,on_sale,chromas,tag,first_tag\r\n\
+             266,0,true,\"[{\"\"id\"\":1}]\",战士,\"\"\"战士\"\"\"\r\n\
+             266,0,true,\"[{\"\"id\"\":1}]\",坦克,\"\"\"战士\"\"\"\r\n"
+        );
+
+        let no_tags = export(&columns[4..5], json!({ "tags": [] })).await.unwrap();
+        assert_eq!(no_tags, "tag\r\n\r\n");
+
+        for (item, expected) in [
+            (
+                json!({ "price": { "amount": 9.5 } }),
+                "item 0, column `id`: no value for a required column",
+            ),
+            (
+                json!({ "id": 1.5 }),
+                "item 0, column `id`: cannot read 1.5 as an integer",
+            ),
+            (
+                json!({ "id": 1, "on_sale": "maybe" }),
+                "item 0, column `on_sale`: cannot read \"maybe\" as a bool",
+            ),
+        ] {
+            match export(&columns[..3], item).await {
+                Err(ApiClientError::InvalidPayload { message, .. }) => assert_eq!(message, expected),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert!(matches!(
+            export(&[ColumnSpec::new("x", "a[")], json!({})).await,
+            Err(ApiClientError::InvalidPayload { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pages_are_written_as_they_arrive() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::GET,
+                "/v1/lol/skin/list?page=1&page_size=2",
+                page(json!([
+                    { "id": 1, "name": "源计划：\"火\"，亚索" },
+                    { "id": 2, "name": "星之守护者\n金克丝" },
+                ])),
+            )
+            .on(
+                Method::GET,
+                "/v1/lol/skin/list?page=2&page_size=2",
+                page(json!([{ "id": 3, "name": "灵魂莲华 永恩" }])),
+            );
+        let client = mock_client(&transport, |_| {});
+        let columns = [
+            ColumnSpec::new("id", "id").kind(ColumnType::Integer),
+            ColumnSpec::new("name", "name"),
+        ];
+
+        let mut csv = Vec::new();
+        let summary = client
+            .paginate(Method::GET, "/v1/lol/skin/list")
+            .page_size(2)
+            .export_csv(&columns, &mut csv)
+            .await
+            .unwrap();
+        assert_eq!(summary, ExportSummary { items: 3, rows: 3 });
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "id,name\r\n1,\"源计划：\"\"火\"\"，亚索\"\r\n2,\"星之守护者\n金克丝\"\r\n3,灵魂莲华 永恩\r\n"
+        );
+        assert_eq!(transport.requests().len(), 2);
+    }
+}
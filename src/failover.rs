@@ -0,0 +1,250 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::time::TimeSource;
+use crate::transport::Route;
+
+/// Tuning for host selection when `fallback_base_urls` is non-empty.
+#[derive(Debug, Clone)]
+pub struct FailoverConfig {
+    /// Time after which a recorded failure counts half as much.
+    pub half_life: Duration,
+    /// How much lower another host's failure rate must be before the client moves off the
+    /// host it is currently using.
+    pub hysteresis: f64,
+}
+
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            half_life: Duration::from_secs(60),
+            hysteresis: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HostScore {
+    pub base_url: String,
+    /// Whether this entry connects to `TransportConfig::fallback_addrs` instead of resolving
+    /// the hostname.
+    pub via_fallback_addrs: bool,
+    /// Exponentially decayed failure rate in `[0, 1)`; lower is healthier.
+    pub failure_rate: f64,
+    pub active: bool,
+}
+
+/// Weight of the implicit, never-decaying success that keeps a host with little or stale
+/// history from being scored as fully broken.
+const PRIOR_WEIGHT: f64 = 1.0;
+
+#[derive(Default, Clone, Copy)]
+struct HostHealth {
+    failures: f64,
+    observations: f64,
+    updated: Option<Instant>,
+}
+
+impl HostHealth {
+    fn decay(&mut self, now: Instant, half_life: Duration) {
+        if let Some(updated) = self.updated {
+            let elapsed = now.saturating_duration_since(updated).as_secs_f64();
+            let factor = 0.5f64.powf(elapsed / half_life.as_secs_f64().max(f64::MIN_POSITIVE));
+            self.failures *= factor;
+            self.observations *= factor;
+        }
+        self.updated = Some(now);
+    }
+
+    fn failure_rate(&self, now: Instant, half_life: Duration) -> f64 {
+        let mut health = *self;
+        health.decay(now, half_life);
+        health.failures / (health.observations + PRIOR_WEIGHT)
+    }
+}
+
+/// One way of reaching the API: a base URL and how to find its address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Endpoint {
+    pub(crate) base_url: String,
+    pub(crate) route: Route,
+}
+
+impl Endpoint {
+    pub(crate) fn dns(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+            route: Route::Dns,
+        }
+    }
+}
+
+struct SelectorState {
+    hosts: Vec<Endpoint>,
+    health: Vec<HostHealth>,
+    active: usize,
+}
+
+pub(crate) struct HostSelector {
+    config: FailoverConfig,
+    time: Arc<dyn TimeSource>,
+    state: Mutex<SelectorState>,
+}
+
+impl HostSelector {
+    pub(crate) fn new(hosts: Vec<Endpoint>, config: FailoverConfig, time: Arc<dyn TimeSource>) -> Self {
+        let health = hosts.iter().map(|_| HostHealth::default()).collect();
+        Self {
+            config,
+            time,
+            state: Mutex::new(SelectorState {
+                hosts,
+                health,
+                active: 0,
+            }),
+        }
+    }
+
+    pub(crate) fn endpoint(&self, index: usize) -> Endpoint {
+        self.state.lock().unwrap().hosts[index].clone()
+    }
+
+    /// Appends `endpoint` with a clean record, unless it is already a host. Hosts are never
+    /// removed, so indices handed out by `order` stay valid.
+    pub(crate) fn add(&self, endpoint: Endpoint) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.hosts.contains(&endpoint) {
+            return false;
+        }
+        state.hosts.push(endpoint);
+        state.health.push(HostHealth::default());
+        true
+    }
+
+    /// Host indices in the order they should be tried for the next request. The active
+    /// host leads unless another one beats it by more than the hysteresis margin.
+    pub(crate) fn order(&self) -> Vec<usize> {
+        let now = self.time.now();
+        let mut state = self.state.lock().unwrap();
+        let rates: Vec<f64> = state
+            .health
+            .iter()
+            .map(|h| h.failure_rate(now, self.config.half_life))
+            .collect();
+
+        let mut order: Vec<usize> = (0..state.hosts.len()).collect();
+        order.sort_by(|&a, &b| rates[a].total_cmp(&rates[b]).then(a.cmp(&b)));
+        if rates[state.active] - rates[order[0]] > self.config.hysteresis {
+            state.active = order[0];
+        }
+        let active = state.active;
+        order.retain(|&i| i != active);
+        order.insert(0, active);
+        order
+    }
+
+    pub(crate) fn record(&self, index: usize, success: bool) {
+        let now = self.time.now();
+        let mut state = self.state.lock().unwrap();
+        let health = &mut state.health[index];
+        health.decay(now, self.config.half_life);
+        health.observations += 1.0;
+        if !success {
+            health.failures += 1.0;
+        }
+    }
+
+    pub(crate) fn scores(&self) -> Vec<HostScore> {
+        let now = self.time.now();
+        let state = self.state.lock().unwrap();
+        state
+            .hosts
+            .iter()
+            .zip(&state.health)
+            .enumerate()
+            .map(|(i, (endpoint, health))| HostScore {
+                base_url: endpoint.base_url.clone(),
+                via_fallback_addrs: endpoint.route == Route::FallbackAddrs,
+                failure_rate: health.failure_rate(now, self.config.half_life),
+                active: i == state.active,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::SimulatedTime;
+
+    fn selector(clock: &SimulatedTime) -> HostSelector {
+        HostSelector::new(
+            vec![Endpoint::dns("https://a"), Endpoint::dns("https://b")],
+            FailoverConfig {
+                half_life: Duration::from_secs(10),
+                hysteresis: 0.1,
+            },
+            Arc::new(clock.clone()),
+        )
+    }
+
+    #[test]
+    fn test_failed_host_recovers_after_decay() {
+        let clock = SimulatedTime::new();
+        let hosts = selector(&clock);
+
+        hosts.record(0, false);
+        assert_eq!(hosts.order(), vec![1, 0]);
+
+        clock.advance(Duration::from_secs(100));
+        assert!(hosts.scores()[0].failure_rate < 0.01);
+
+        hosts.record(1, false);
+        assert_eq!(hosts.order(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_flaky_host_stays_avoided() {
+        let clock = SimulatedTime::new();
+        let hosts = selector(&clock);
+
+        for i in 0..60 {
+            hosts.record(0, i % 3 != 0);
+            hosts.record(1, true);
+            clock.advance(Duration::from_secs(1));
+            assert_eq!(hosts.order()[0], 1, "iteration {}", i);
+        }
+        let scores = hosts.scores();
+        assert!(scores[0].failure_rate > 0.25);
+        assert!(scores[1].active);
+    }
+
+    #[test]
+    fn test_small_differences_do_not_flap() {
+        let clock = SimulatedTime::new();
+        let hosts = selector(&clock);
+
+        for _ in 0..20 {
+            hosts.record(0, true);
+        }
+        hosts.record(0, false);
+        assert_eq!(hosts.order(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_added_hosts_start_clean_and_are_not_duplicated() {
+        let clock = SimulatedTime::new();
+        let hosts = selector(&clock);
+        hosts.record(0, false);
+        hosts.record(1, false);
+
+        assert!(hosts.add(Endpoint::dns("https://c")));
+        assert!(!hosts.add(Endpoint::dns("https://a")));
+        assert_eq!(hosts.endpoint(2), Endpoint::dns("https://c"));
+        assert_eq!(hosts.order(), vec![2, 0, 1]);
+        assert_eq!(hosts.scores()[2].failure_rate, 0.0);
+    }
+}
@@ -0,0 +1,163 @@
+//! A process-wide client for small tools and examples, so one `ApiClient` need not be passed
+//! through every function. Enabled by the `global-client` feature.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), ho_api_rust_sdk::ApiClientError> {
+//! use ho_api_rust_sdk::{global, ApiClientConfig};
+//! use serde_json::json;
+//!
+//! global::init(ApiClientConfig {
+//!     app_id: "your app id".to_string(),
+//!     app_secret: "your app secret (32 bytes long)!".to_string(),
+//!     iv: "your app iv (16)".to_string(),
+//!     ..Default::default()
+//! })?;
+//! let skins = global::get("/v1/lol/skin?id=1").await?;
+//! global::post("/v1/order", json!({ "sku": 7 })).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::OnceLock;
+
+use reqwest::Method;
+use serde_json::Value;
+
+use crate::{ApiClient, ApiClientConfig, ApiClientError};
+
+static GLOBAL: Slot = Slot::new();
+
+struct Slot(OnceLock<ApiClient>);
+
+impl Slot {
+    const fn new() -> Self {
+        Self(OnceLock::new())
+    }
+
+    fn set(&self, client: ApiClient) -> Result<(), ApiClientError> {
+        self.0.set(client).map_err(|_| {
+            ApiClientError::InvalidConfig(
+                "the global client is already initialized; call global::init only once".to_string(),
+            )
+        })
+    }
+
+    fn get(&self) -> Option<&ApiClient> {
+        self.0.get()
+    }
+
+    #[track_caller]
+    fn client(&self) -> &ApiClient {
+        match self.get() {
+            Some(client) => client,
+            None => panic!(
+                "the global client is not initialized: call ho_api_rust_sdk::global::init(config) \
+                 at startup, before anything uses global::client, get or post"
+            ),
+        }
+    }
+}
+
+/// Builds the global client from `config`. Fails with `InvalidConfig` when `config` is
+/// invalid or a global client is already set; concurrent first calls set it exactly once.
+pub fn init(config: ApiClientConfig) -> Result<(), ApiClientError> {
+    init_with(ApiClient::new(config)?)
+}
+
+/// Sets an already built client, e.g. one on a custom transport, as the global client.
+pub fn init_with(client: ApiClient) -> Result<(), ApiClientError> {
+    GLOBAL.set(client)
+}
+
+/// Whether `init` has succeeded.
+pub fn is_initialized() -> bool {
+    GLOBAL.get().is_some()
+}
+
+/// The global client.
+///
+/// # Panics
+///
+/// When `init` has not been called yet, with a message saying so.
+#[track_caller]
+pub fn client() -> &'static ApiClient {
+    GLOBAL.client()
+}
+
+/// `client().send(Method::GET, uri, None)`.
+pub async fn get(uri: &str) -> Result<String, ApiClientError> {
+    client().send(Method::GET, uri, None).await
+}
+
+/// `client().send(Method::POST, uri, Some(body))`.
+pub async fn post(uri: &str, body: Value) -> Result<String, ApiClientError> {
+    client().send(Method::POST, uri, Some(body)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport};
+
+    fn leaked() -> &'static Slot {
+        Box::leak(Box::new(Slot::new()))
+    }
+
+    #[test]
+    #[should_panic(expected = "call ho_api_rust_sdk::global::init(config) at startup")]
+    fn test_client_before_init_says_how_to_fix_it() {
+        leaked().client();
+    }
+
+    #[test]
+    fn test_second_init_is_an_error() {
+        let slot = leaked();
+        let transport = MockTransport::new();
+        slot.set(mock_client(&transport, |_| {})).unwrap();
+        match slot.set(mock_client(&transport, |_| {})) {
+            Err(ApiClientError::InvalidConfig(message)) => assert!(message.contains("already initialized")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_first_use_sets_one_client() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/skin", MockResponse::encrypted(r#"{"id":1}"#));
+        transport.on(
+            Method::POST,
+            "/v1/order",
+            MockResponse::encrypted(r#"{"order_id":"A1"}"#),
+        );
+
+        let results: Vec<Result<(), ApiClientError>> = std::thread::scope(|scope| {
+            let racers: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| init_with(mock_client(&transport, |_| {}))))
+                .collect();
+            racers.into_iter().map(|racer| racer.join().unwrap()).collect()
+        });
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        assert!(is_initialized());
+
+        let requests = (0..16).map(|i| {
+            tokio::spawn(async move {
+                match i % 2 {
+                    0 => get("/v1/skin").await,
+                    _ => post("/v1/order", json!({ "sku": i })).await,
+                }
+            })
+        });
+        for (i, response) in futures_util::future::join_all(requests).await.into_iter().enumerate() {
+            let expected = if i % 2 == 0 {
+                r#"{"id":1}"#
+            } else {
+                r#"{"order_id":"A1"}"#
+            };
+            assert_eq!(response.unwrap().unwrap(), expected);
+        }
+        assert_eq!(transport.requests().len(), 16);
+        assert!(std::ptr::eq(client(), client()));
+    }
+}
@@ -0,0 +1,356 @@
+//! Asynchronous server jobs: submit, poll `/v1/job/{id}/status` until the job settles, then
+//! fetch `/v1/job/{id}/result`.
+
+use std::time::Duration;
+
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::chain::percent_encode;
+use crate::{ApiClient, ApiClientError};
+
+/// Polling schedule for `ApiClient::run_job`.
+#[derive(Debug, Clone)]
+pub struct PollConfig {
+    /// Wait before the first status poll.
+    pub interval: Duration,
+    /// Factor applied to the wait after every poll that finds the job unfinished.
+    pub backoff: f64,
+    pub max_interval: Duration,
+    /// Total time, from submission, after which polling gives up with `JobTimeout`.
+    pub max_duration: Duration,
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            backoff: 1.5,
+            max_interval: Duration::from_secs(30),
+            max_duration: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Pending,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+    Expired,
+    /// A status this SDK does not know. Treated as still in progress.
+    Unknown(String),
+}
+
+impl JobState {
+    pub(crate) fn parse(status: &str) -> Self {
+        match status.to_ascii_lowercase().as_str() {
+            "queued" => JobState::Queued,
+            "pending" => JobState::Pending,
+            "running" => JobState::Running,
+            "done" => JobState::Done,
+            "failed" => JobState::Failed,
+            "cancelled" | "canceled" => JobState::Cancelled,
+            "expired" => JobState::Expired,
+            _ => JobState::Unknown(status.to_string()),
+        }
+    }
+
+    /// The status as the server spells it; unknown states keep their original text.
+    pub fn as_str(&self) -> &str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Pending => "pending",
+            JobState::Running => "running",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+            JobState::Expired => "expired",
+            JobState::Unknown(status) => status,
+        }
+    }
+
+    /// `failed`, `cancelled` and `expired` end the job without a result.
+    pub fn is_failure(&self) -> bool {
+        matches!(self, JobState::Failed | JobState::Cancelled | JobState::Expired)
+    }
+}
+
+/// One status poll, passed to the progress callback whenever the state changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobStatus {
+    pub job_id: String,
+    pub state: JobState,
+    /// Completion in `[0, 1]` when the server reports it.
+    pub progress: Option<f64>,
+    /// The server's error detail for failed jobs.
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Submitted {
+    job_id: Value,
+}
+
+#[derive(Deserialize)]
+struct StatusBody {
+    status: String,
+    #[serde(default)]
+    progress: Option<f64>,
+    #[serde(default)]
+    error: Option<Value>,
+}
+
+impl ApiClient {
+    /// Submits a job with `POST submit_uri` and returns its decrypted result once it is done.
+    pub async fn run_job(
+        &self,
+        submit_uri: &str,
+        body: Option<Value>,
+        poll: &PollConfig,
+    ) -> Result<String, ApiClientError> {
+        self.run_job_with_progress(submit_uri, body, poll, |_| {}).await
+    }
+
+    /// Like `run_job`, calling `on_status` with the first status and every change after it.
+    pub async fn run_job_with_progress(
+        &self,
+        submit_uri: &str,
+        body: Option<Value>,
+        poll: &PollConfig,
+        mut on_status: impl FnMut(&JobStatus),
+    ) -> Result<String, ApiClientError> {
+        let time = &*self.config.time;
+        let started = time.now();
+        let submitted: Submitted = self.send_as(Method::POST, submit_uri, body).await?;
+        let job_id = match submitted.job_id {
+            Value::String(id) if !id.is_empty() => id,
+            Value::Number(id) => id.to_string(),
+            other => {
+                return Err(ApiClientError::InvalidEnvelope(format!(
+                    "job submission returned no usable job_id: {}",
+                    other
+                )))
+            }
+        };
+
+        let mut interval = poll.interval;
+        let mut last_state = None;
+        loop {
+            let remaining = poll.max_duration.saturating_sub(time.elapsed(started));
+            if remaining.is_zero() {
+                return Err(ApiClientError::JobTimeout {
+                    job_id,
+                    elapsed: time.elapsed(started),
+                });
+            }
+            time.sleep(interval.min(remaining)).await;
+
+            let status = self.job_status(&job_id).await?;
+            if last_state.as_ref() != Some(&status.state) {
+                on_status(&status);
+                last_state = Some(status.state.clone());
+            }
+            if status.state == JobState::Done {
+                return self
+                    .send(
+                        Method::GET,
+                        &format!("/v1/job/{}/result", percent_encode(&job_id)),
+                        None,
+                    )
+                    .await;
+            }
+            if status.state.is_failure() {
+                return Err(ApiClientError::JobFailed {
+                    job_id,
+                    state: status.state,
+                    detail: status.error,
+                });
+            }
+            // Clamped in seconds first: `Duration::mul_f64` panics once the product is infinite
+            // or overflows. `max` maps a NaN backoff to 1, and a NaN product fails the comparison.
+            let next = interval.as_secs_f64() * poll.backoff.max(1.0);
+            interval = match next < poll.max_interval.as_secs_f64() {
+                true => Duration::from_secs_f64(next),
+                false => poll.max_interval,
+            };
+        }
+    }
+
+    async fn job_status(&self, job_id: &str) -> Result<JobStatus, ApiClientError> {
+        let body: StatusBody = self
+            .send_as(Method::GET, &format!("/v1/job/{}/status", percent_encode(job_id)), None)
+            .await?;
+        Ok(JobStatus {
+            job_id: job_id.to_string(),
+            state: JobState::parse(&body.status),
+            progress: body.progress,
+            error: body.error.map(|error| match error {
+                Value::String(message) => message,
+                other => other.to_string(),
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport};
+
+    fn status(body: Value) -> MockResponse {
+        MockResponse::encrypted(body.to_string())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pending_until_done_then_fetches_result() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::POST, "/v1/report/export", status(json!({ "job_id": "j-1" })))
+            .on(
+                Method::GET,
+                "/v1/job/j-1/status",
+                status(json!({ "status": "pending" })),
+            )
+            .on(
+                Method::GET,
+                "/v1/job/j-1/status",
+                status(json!({ "status": "pending", "progress": 0.5 })),
+            )
+            .on(Method::GET, "/v1/job/j-1/status", status(json!({ "status": "done" })))
+            .on(
+                Method::GET,
+                "/v1/job/j-1/result",
+                MockResponse::encrypted(r#"{"rows":3}"#),
+            );
+        let client = mock_client(&transport, |_| {});
+        let poll = PollConfig {
+            interval: Duration::from_secs(1),
+            backoff: 2.0,
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let mut seen = Vec::new();
+        let result = client
+            .run_job_with_progress("/v1/report/export", Some(json!({ "month": 6 })), &poll, |s| {
+                seen.push(s.state.clone())
+            })
+            .await
+            .unwrap();
+        assert_eq!(result, r#"{"rows":3}"#);
+        assert_eq!(seen, vec![JobState::Pending, JobState::Done]);
+        assert_eq!(started.elapsed(), Duration::from_secs(1 + 2 + 4));
+
+        let urls: Vec<String> = transport.requests().into_iter().map(|r| r.url).collect();
+        assert_eq!(urls.len(), 5);
+        assert!(urls[4].ends_with("/v1/job/j-1/result"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_job_carries_error_detail() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::POST, "/v1/report/export", status(json!({ "job_id": 42 })))
+            .on(
+                Method::GET,
+                "/v1/job/42/status",
+                status(json!({ "status": "failed", "error": "quota exceeded" })),
+            );
+        let client = mock_client(&transport, |_| {});
+
+        match client.run_job("/v1/report/export", None, &PollConfig::default()).await {
+            Err(ApiClientError::JobFailed { job_id, state, detail }) => {
+                assert_eq!(job_id, "42");
+                assert_eq!(state, JobState::Failed);
+                assert_eq!(detail.as_deref(), Some("quota exceeded"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gives_up_after_max_duration() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::POST, "/v1/slow", status(json!({ "job_id": "j-2" })))
+            .on(
+                Method::GET,
+                "/v1/job/j-2/status",
+                status(json!({ "status": "running" })),
+            );
+        let client = mock_client(&transport, |_| {});
+        let poll = PollConfig {
+            max_duration: Duration::from_secs(10),
+            ..Default::default()
+        };
+
+        match client.run_job("/v1/slow", None, &poll).await {
+            Err(ApiClientError::JobTimeout { job_id, elapsed }) => {
+                assert_eq!(job_id, "j-2");
+                assert_eq!(elapsed, Duration::from_secs(10));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_huge_backoff_clamps_to_max_interval() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::POST, "/v1/slow", status(json!({ "job_id": "j-3" })))
+            .on(
+                Method::GET,
+                "/v1/job/j-3/status",
+                status(json!({ "status": "running" })),
+            )
+            .on(
+                Method::GET,
+                "/v1/job/j-3/status",
+                status(json!({ "status": "running" })),
+            )
+            .on(Method::GET, "/v1/job/j-3/status", status(json!({ "status": "done" })))
+            .on(Method::GET, "/v1/job/j-3/result", MockResponse::encrypted("{}"));
+        let client = mock_client(&transport, |_| {});
+        let poll = PollConfig {
+            interval: Duration::from_secs(1),
+            backoff: f64::MAX,
+            max_interval: Duration::from_secs(5),
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        client.run_job("/v1/slow", None, &poll).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_secs(1 + 5 + 5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_job_id_is_encoded_as_one_path_segment() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::POST, "/v1/report/export", status(json!({ "job_id": "a/b c" })))
+            .on(
+                Method::GET,
+                "/v1/job/a%2Fb%20c/status",
+                status(json!({ "status": "done" })),
+            )
+            .on(Method::GET, "/v1/job/a%2Fb%20c/result", MockResponse::encrypted("{}"));
+        let client = mock_client(&transport, |_| {});
+
+        client
+            .run_job("/v1/report/export", None, &PollConfig::default())
+            .await
+            .unwrap();
+        let urls: Vec<String> = transport.requests().into_iter().map(|r| r.url).collect();
+        assert!(urls[1].ends_with("/v1/job/a%2Fb%20c/status"), "{}", urls[1]);
+        assert!(urls[2].ends_with("/v1/job/a%2Fb%20c/result"), "{}", urls[2]);
+    }
+}
@@ -0,0 +1,293 @@
+//! Background pings that keep a pooled connection from sitting idle long enough for the
+//! gateway to close it. Enabled by `ApiClientConfig::keepalive_interval`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use reqwest::Method;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::body::PreparedBody;
+use crate::client::build_request;
+use crate::failover::Endpoint;
+use crate::nonce::NonceGuard;
+use crate::request::{AuthMode, RequestOptions};
+use crate::stats::{ErrorClass, StatsCollector};
+use crate::time::TimeSource;
+use crate::transport::Transport;
+use crate::{ApiClientConfig, ApiClientError};
+
+#[derive(Debug, Clone, Default)]
+pub struct KeepaliveConfig {
+    /// Pinged under `content`, e.g. `/v1/public/status`. `None` pings `base_url` itself.
+    pub path: Option<String>,
+    /// `AuthMode::None` for health endpoints the gateway exempts from signing.
+    pub auth: AuthMode,
+    /// Counts pings in `ApiClient::stats()`. Off by default, so the numbers describe
+    /// the caller's own traffic.
+    pub record_stats: bool,
+}
+
+/// When the client last had a request on the wire, and how many it has there now.
+pub(crate) struct Activity {
+    in_flight: AtomicUsize,
+    last: Mutex<Instant>,
+    time: Arc<dyn TimeSource>,
+}
+
+impl Activity {
+    pub(crate) fn new(time: Arc<dyn TimeSource>) -> Arc<Self> {
+        Arc::new(Self {
+            in_flight: AtomicUsize::new(0),
+            last: Mutex::new(time.now()),
+            time,
+        })
+    }
+
+    /// Marks a request as in flight until the guard is dropped.
+    pub(crate) fn begin(self: &Arc<Self>) -> ActivityGuard {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.touch();
+        ActivityGuard(self.clone())
+    }
+
+    fn touch(&self) {
+        *self.last.lock().unwrap() = self.time.now();
+    }
+
+    /// When the last request finished; `None` while one is in flight.
+    fn idle_since(&self) -> Option<Instant> {
+        let last = *self.last.lock().unwrap();
+        (self.in_flight.load(Ordering::SeqCst) == 0).then_some(last)
+    }
+}
+
+pub(crate) struct ActivityGuard(Arc<Activity>);
+
+impl Drop for ActivityGuard {
+    fn drop(&mut self) {
+        self.0.touch();
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The ping task; aborted when dropped along with the client.
+pub(crate) struct Keepalive {
+    task: JoinHandle<()>,
+}
+
+impl Keepalive {
+    /// Starts pinging whenever nothing has been sent for `interval`. Needs a tokio runtime.
+    pub(crate) fn spawn(
+        interval: Duration,
+        config: &ApiClientConfig,
+        transport: Arc<dyn Transport>,
+        activity: Arc<Activity>,
+        stats: Arc<StatsCollector>,
+        nonces: Option<Arc<NonceGuard>>,
+    ) -> Result<Self, ApiClientError> {
+        let runtime = tokio::runtime::Handle::try_current().map_err(|_| {
+            ApiClientError::InvalidConfig("keepalive_interval needs a tokio runtime to run its pings".to_string())
+        })?;
+        if interval.is_zero() {
+            return Err(ApiClientError::InvalidConfig(
+                "keepalive_interval must be greater than zero".to_string(),
+            ));
+        }
+        let pinger = Pinger {
+            config: config.clone(),
+            interval,
+            transport,
+            activity,
+            stats,
+            nonces,
+        };
+        Ok(Self {
+            task: runtime.spawn(pinger.run()),
+        })
+    }
+
+    pub(crate) fn stop(&self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for Keepalive {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+struct Pinger {
+    config: ApiClientConfig,
+    interval: Duration,
+    transport: Arc<dyn Transport>,
+    activity: Arc<Activity>,
+    stats: Arc<StatsCollector>,
+    nonces: Option<Arc<NonceGuard>>,
+}
+
+impl Pinger {
+    async fn run(self) {
+        let time = &*self.config.time;
+        loop {
+            let wake = match self.activity.idle_since() {
+                Some(last) => last + self.interval,
+                None => time.now() + self.interval,
+            };
+            time.sleep(wake.saturating_duration_since(time.now())).await;
+            if self
+                .activity
+                .idle_since()
+                .is_some_and(|last| time.elapsed(last) >= self.interval)
+            {
+                self.ping().await;
+            }
+        }
+    }
+
+    async fn ping(&self) {
+        let keepalive = &self.config.keepalive;
+        let (uri, content_path) = match &keepalive.path {
+            Some(path) => (path.as_str(), None),
+            None => ("/", Some(String::new())),
+        };
+        let options = RequestOptions {
+            content_path,
+            auth: keepalive.auth,
+            ..Default::default()
+        };
+        let time = &*self.config.time;
+        let started = time.now();
+        let _active = self.activity.begin();
+        let result = async {
+            let body = PreparedBody::new(None, None)?;
+            let endpoint = Endpoint::dns(&self.config.base_url);
+            let mut request = build_request(
+                &self.config,
+                self.nonces.as_deref(),
+                &endpoint,
+                Method::HEAD,
+                uri,
+                &body,
+                &options,
+            )?;
+            request.body = Bytes::new();
+            request.timeout = Some(self.interval);
+            self.transport.execute(request).await
+        }
+        .await;
+        if let Err(e) = &result {
+            tracing::debug!(error = ?e, "keepalive ping failed");
+        }
+        if keepalive.record_stats {
+            self.stats
+                .record_request(time.elapsed(started), 0, 0, result.as_ref().err().map(ErrorClass::of));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport};
+
+    fn pings(transport: &MockTransport) -> usize {
+        let requests = transport.requests();
+        requests.iter().filter(|r| r.method == Method::HEAD).count()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ping_fires_after_idleness_only() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::HEAD, "/", MockResponse::status(StatusCode::OK))
+            .on(Method::GET, "/v1/x", MockResponse::encrypted("{}"));
+        let client = mock_client(&transport, |config| {
+            config.keepalive_interval = Some(Duration::from_secs(50))
+        });
+
+        for _ in 0..6 {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            client.send(Method::GET, "/v1/x", None).await.unwrap();
+        }
+        assert_eq!(pings(&transport), 0, "busy traffic needs no pings");
+
+        tokio::time::sleep(Duration::from_secs(51)).await;
+        assert_eq!(pings(&transport), 1);
+        let ping = transport.requests().pop().unwrap();
+        assert_eq!(ping.url, "https://mock.zelaser.test/");
+        assert!(ping.headers.contains_key("HO-SIGNATURE") && ping.body.is_empty());
+
+        tokio::time::sleep(Duration::from_secs(50)).await;
+        assert_eq!(pings(&transport), 2);
+        assert_eq!(client.stats().total_requests, 6);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_no_ping_while_a_request_is_in_flight() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/slow",
+            MockResponse::encrypted("{}").with_delay(Duration::from_secs(200)),
+        );
+        let client = mock_client(&transport, |config| {
+            config.keepalive_interval = Some(Duration::from_secs(50));
+            config.keepalive = KeepaliveConfig {
+                path: Some("/v1/public/status".to_string()),
+                auth: AuthMode::None,
+                record_stats: true,
+            };
+        });
+
+        client.send(Method::GET, "/v1/slow", None).await.unwrap();
+        assert_eq!(pings(&transport), 0);
+
+        tokio::time::sleep(Duration::from_secs(51)).await;
+        let ping = transport.requests().pop().unwrap();
+        assert_eq!(ping.method, Method::HEAD);
+        assert!(
+            ping.url.ends_with("/server/common/api/v1/public/status"),
+            "{}",
+            ping.url
+        );
+        assert!(!ping.headers.contains_key("HO-SIGNATURE"));
+        assert_eq!(client.stats().total_requests, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pings_stop_on_shutdown_and_drop() {
+        let transport = MockTransport::new();
+        transport.on(Method::HEAD, "/", MockResponse::status(StatusCode::OK));
+        let interval = Some(Duration::from_secs(50));
+
+        let client = mock_client(&transport, |config| config.keepalive_interval = interval);
+        client.shutdown();
+        tokio::time::sleep(Duration::from_secs(120)).await;
+        assert_eq!(pings(&transport), 0);
+
+        let client = mock_client(&transport, |config| config.keepalive_interval = interval);
+        tokio::time::sleep(Duration::from_secs(51)).await;
+        assert_eq!(pings(&transport), 1);
+        drop(client);
+        tokio::time::sleep(Duration::from_secs(120)).await;
+        assert_eq!(pings(&transport), 1);
+    }
+
+    #[test]
+    fn test_needs_a_runtime() {
+        let transport = MockTransport::new();
+        let mut config = crate::testing::test_config();
+        config.keepalive_interval = Some(Duration::from_secs(50));
+        assert!(matches!(
+            crate::ApiClient::with_transport(config, transport),
+            Err(ApiClientError::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,216 @@
+//! The gateway's scheduled maintenance windows. While one is under way the gateway answers
+//! 503 with an `HO-MAINTENANCE` header describing it, such as
+//! `{"start": "2024-06-01 02:00:00", "end": "2024-06-01 04:00:00", "affected_paths": ["/v1/mall"]}`,
+//! times written as in `endpoints`. Such a response to an affected path fails with
+//! `ApiClientError::Maintenance` rather than being retried or failed over, and the client
+//! keeps the notice until it ends: later requests to affected paths fail the same way
+//! without being sent, while other paths are sent as usual.
+
+use std::sync::Mutex;
+
+use chrono::{DateTime, FixedOffset, Utc};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::endpoints::gateway_time;
+use crate::{ApiClient, ApiClientError};
+
+pub(crate) const MAINTENANCE_HEADER: &str = "HO-MAINTENANCE";
+
+/// The parsed `HO-MAINTENANCE` header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaintenanceNotice {
+    #[serde(rename = "start", with = "gateway_time")]
+    pub starts_at: DateTime<FixedOffset>,
+    #[serde(rename = "end", with = "gateway_time")]
+    pub ends_at: DateTime<FixedOffset>,
+    /// Prefixes of the request uris the maintenance takes down, compared without the
+    /// content path or query; empty for the whole gateway.
+    #[serde(rename = "affected_paths", default)]
+    pub affected_prefixes: Vec<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl MaintenanceNotice {
+    /// Whether `at` falls in `[starts_at, ends_at)`.
+    pub fn is_active_at(&self, at: DateTime<Utc>) -> bool {
+        self.starts_at <= at && at < self.ends_at
+    }
+
+    /// Whether the maintenance takes down requests to `uri`.
+    pub fn affects(&self, uri: &str) -> bool {
+        let path = uri.split('?').next().unwrap_or_default();
+        self.affected_prefixes.is_empty() || self.affected_prefixes.iter().any(|prefix| path.starts_with(&**prefix))
+    }
+
+    /// Whether a request to `uri` at `at` runs into the maintenance.
+    fn blocks(&self, uri: &str, at: DateTime<Utc>) -> bool {
+        self.is_active_at(at) && self.affects(uri)
+    }
+}
+
+/// The notice in `headers`, if any. A malformed one is logged and ignored.
+fn notice(headers: &HeaderMap) -> Option<MaintenanceNotice> {
+    let value = headers.get(MAINTENANCE_HEADER)?;
+    let parsed = value
+        .to_str()
+        .map_err(|e| e.to_string())
+        .and_then(|text| serde_json::from_str(text).map_err(|e| e.to_string()));
+    match parsed {
+        Ok(notice) => Some(notice),
+        Err(error) => {
+            tracing::warn!(error = %error, "ignoring a malformed HO-MAINTENANCE header");
+            None
+        }
+    }
+}
+
+/// The latest notice, until it ends.
+#[derive(Default)]
+pub(crate) struct Maintenance {
+    latest: Mutex<Option<MaintenanceNotice>>,
+}
+
+impl Maintenance {
+    fn record(&self, notice: MaintenanceNotice) {
+        *self.latest.lock().unwrap() = Some(notice);
+    }
+
+    fn active(&self, now: DateTime<Utc>) -> Option<MaintenanceNotice> {
+        let mut latest = self.latest.lock().unwrap();
+        if latest.as_ref().is_some_and(|notice| notice.ends_at <= now) {
+            *latest = None;
+        }
+        latest.clone()
+    }
+
+    /// Fails a request to `uri` that the maintenance of the latest notice takes down at `now`.
+    pub(crate) fn admit(&self, uri: &str, now: DateTime<Utc>) -> Result<(), ApiClientError> {
+        match self.active(now) {
+            Some(notice) if notice.blocks(uri, now) => Err(ApiClientError::Maintenance { notice }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ApiClient {
+    /// The latest maintenance notice from the gateway, until its end time passes.
+    pub fn active_maintenance(&self) -> Option<MaintenanceNotice> {
+        self.maintenance.active(self.config.time.now_utc())
+    }
+
+    /// Keeps the notice of a failed response to `uri`, and gives the error to fail with
+    /// when the maintenance it announces takes `uri` down now.
+    pub(crate) fn maintenance_error(&self, uri: &str, headers: &HeaderMap) -> Option<ApiClientError> {
+        let notice = notice(headers)?;
+        self.maintenance.record(notice.clone());
+        if !notice.blocks(uri, self.config.time.now_utc()) {
+            return None;
+        }
+        tracing::warn!(uri, ends_at = %notice.ends_at, "the gateway is under maintenance");
+        Some(ApiClientError::Maintenance { notice })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use reqwest::{Method, StatusCode};
+
+    use super::*;
+    use crate::retry::RetryConfig;
+    use crate::testing::{mock_client, MockResponse, MockTransport, SimulatedTime};
+
+    /// 07:30 to 09:00 in Shanghai; `SimulatedTime` starts at 08:00 there.
+    fn mall_maintenance() -> MockResponse {
+        MockResponse::status(StatusCode::SERVICE_UNAVAILABLE).with_header(
+            "ho-maintenance",
+            r#"{"start":"2024-06-01 07:30:00","end":"2024-06-01 09:00:00","affected_paths":["/v1/mall"],"message":"mall upgrade"}"#,
+        )
+    }
+
+    fn client_on(transport: &Arc<MockTransport>, time: &SimulatedTime) -> ApiClient {
+        let time = time.clone();
+        mock_client(transport, move |config| {
+            config.time = Arc::new(time);
+            config.retry = RetryConfig {
+                max_retries: 3,
+                initial_backoff: Duration::from_millis(10),
+                ..RetryConfig::default()
+            };
+        })
+    }
+
+    #[tokio::test]
+    async fn test_affected_requests_fail_fast_with_the_notice() {
+        let time = SimulatedTime::new();
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/mall/items", mall_maintenance());
+        let client = client_on(&transport, &time);
+
+        let err = client
+            .send(Method::GET, "/v1/mall/items?page=1", None)
+            .await
+            .unwrap_err();
+        let ApiClientError::Maintenance { notice } = &err else {
+            panic!("{:?}", err);
+        };
+        assert_eq!(notice.ends_at.to_rfc3339(), "2024-06-01T09:00:00+08:00");
+        assert_eq!(notice.message.as_deref(), Some("mall upgrade"));
+        assert!(!err.is_retryable());
+        assert_eq!(transport.requests().len(), 1);
+        assert_eq!(client.active_maintenance().as_ref(), Some(notice));
+
+        // Known to be down: not sent at all.
+        let again = client.send(Method::POST, "/v1/mall/order", None).await.unwrap_err();
+        assert!(matches!(again, ApiClientError::Maintenance { .. }));
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unaffected_requests_proceed_during_the_window() {
+        let time = SimulatedTime::new();
+        let transport = MockTransport::new();
+        transport
+            .on(Method::GET, "/v1/mall/items", mall_maintenance())
+            .on(Method::GET, "/v1/news", mall_maintenance())
+            .on(Method::GET, "/v1/news", MockResponse::encrypted("[]"))
+            .on(Method::GET, "/v1/lol/champion/rotation", MockResponse::encrypted("[]"));
+        let client = client_on(&transport, &time);
+        assert!(client.send(Method::GET, "/v1/mall/items", None).await.is_err());
+
+        let rotation = client.send(Method::GET, "/v1/lol/champion/rotation", None).await;
+        assert_eq!(rotation.unwrap(), "[]");
+        // A 503 outside the affected paths is retried like any other.
+        let news = time.drive(client.send(Method::GET, "/v1/news", None)).await;
+        assert_eq!(news.unwrap(), "[]");
+        assert_eq!(transport.requests().len(), 4);
+        assert!(client.active_maintenance().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_notice_expires_at_its_end() {
+        let time = SimulatedTime::new();
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/mall/items", mall_maintenance()).on(
+            Method::GET,
+            "/v1/mall/items",
+            MockResponse::encrypted("[]"),
+        );
+        let client = client_on(&transport, &time);
+        assert!(client.send(Method::GET, "/v1/mall/items", None).await.is_err());
+
+        time.advance(Duration::from_secs(59 * 60));
+        assert!(client.active_maintenance().is_some());
+        assert!(client.send(Method::GET, "/v1/mall/items", None).await.is_err());
+        assert_eq!(transport.requests().len(), 1);
+
+        time.advance(Duration::from_secs(60));
+        assert_eq!(client.active_maintenance(), None);
+        assert_eq!(client.send(Method::GET, "/v1/mall/items", None).await.unwrap(), "[]");
+        assert_eq!(transport.requests().len(), 2);
+    }
+}
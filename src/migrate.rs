@@ -0,0 +1,414 @@
+//! `ApiClientConfig::migrate`, every check a configuration written as a struct literal must
+//! pass before it takes traffic, run at once so a rollout can log all of them:
+//!
+//! ```
+//! use ho_api_rust_sdk::ApiClientConfig;
+//!
+//! let config = ApiClientConfig {
+//!     app_id: "your app id".to_string(),
+//!     app_secret: "your app secret (32 bytes long)!".to_string(),
+//!     iv: "0000000000000000".to_string(),
+//!     base_url: "https://server.zelaser.com/".to_string(),
+//!     content: "/server/common/api".to_string(),
+//!     ..Default::default()
+//! };
+//! let validated = config.migrate().unwrap();
+//! let codes: Vec<_> = validated.warnings().iter().map(|issue| issue.code).collect();
+//! assert_eq!(codes, ["iv_repeats_one_byte", "base_url_trailing_slash"]);
+//! ```
+//!
+//! Errors are what `ApiClient::new` would fail on; warnings are what it builds a client
+//! from but likely should not. With `strict_self_check` set, warnings fail the migration too.
+//! `ApiClient::new` migrates an `ApiClientConfig` itself and logs the warnings.
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::bulkhead::Bulkheads;
+use crate::crypto::{IV_LEN, SECRET_LEN};
+use crate::request::check_api_version;
+use crate::self_check::{self, SelfCheckWarning};
+use crate::{ApiClientConfig, ApiClientError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueSeverity {
+    /// The client cannot be built from the configuration.
+    Error,
+    /// The client can be built, but likely misbehaves.
+    Warning,
+}
+
+/// One finding of `ApiClientConfig::migrate`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConfigIssue {
+    /// Stable and machine-readable, such as `secret_length`.
+    pub code: &'static str,
+    /// The `ApiClientConfig` field at fault.
+    pub field: &'static str,
+    pub severity: IssueSeverity,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn error(code: &'static str, field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            field,
+            severity: IssueSeverity::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning(code: &'static str, field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            field,
+            severity: IssueSeverity::Warning,
+            message: message.into(),
+        }
+    }
+}
+
+/// An `ApiClientConfig` that passed `migrate`, with the warnings it passed with. Knobs the
+/// struct literal left to `..Default::default()` keep their defaults.
+#[derive(Clone)]
+pub struct ValidatedConfig {
+    config: ApiClientConfig,
+    warnings: Vec<ConfigIssue>,
+}
+
+/// Shows the warnings alone, keeping the key material out of logs.
+impl fmt::Debug for ValidatedConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValidatedConfig")
+            .field("warnings", &self.warnings)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ValidatedConfig {
+    pub fn config(&self) -> &ApiClientConfig {
+        &self.config
+    }
+
+    pub fn warnings(&self) -> &[ConfigIssue] {
+        &self.warnings
+    }
+
+    pub fn into_inner(self) -> ApiClientConfig {
+        self.config
+    }
+}
+
+/// What `ApiClient::new` accepts: a `ValidatedConfig`, or an `ApiClientConfig` it migrates,
+/// logging the warnings and failing with `InvalidConfig` on the errors.
+pub trait IntoValidatedConfig {
+    fn into_validated(self) -> Result<ValidatedConfig, ApiClientError>;
+}
+
+impl IntoValidatedConfig for ValidatedConfig {
+    fn into_validated(self) -> Result<ValidatedConfig, ApiClientError> {
+        Ok(self)
+    }
+}
+
+impl IntoValidatedConfig for ApiClientConfig {
+    fn into_validated(self) -> Result<ValidatedConfig, ApiClientError> {
+        match self.migrate() {
+            Ok(validated) => {
+                for issue in validated.warnings() {
+                    tracing::warn!(code = issue.code, field = issue.field, "{}", issue.message);
+                }
+                Ok(validated)
+            }
+            Err(issues) => {
+                let listed: Vec<String> = issues
+                    .iter()
+                    .map(|issue| format!("{} ({})", issue.code, issue.message))
+                    .collect();
+                Err(ApiClientError::InvalidConfig(format!(
+                    "configuration failed to migrate: {}",
+                    listed.join("; ")
+                )))
+            }
+        }
+    }
+}
+
+impl ApiClientConfig {
+    /// Runs every check at once: errors and warnings, in field order, as `ConfigIssue`s.
+    /// Fails with all of them when any is an error, or when any is found at all with
+    /// `strict_self_check` set.
+    pub fn migrate(self) -> Result<ValidatedConfig, Vec<ConfigIssue>> {
+        let issues = issues(&self);
+        let fails = |issue: &ConfigIssue| self.strict_self_check || issue.severity == IssueSeverity::Error;
+        if issues.iter().any(fails) {
+            return Err(issues);
+        }
+        Ok(ValidatedConfig {
+            config: self,
+            warnings: issues,
+        })
+    }
+}
+
+fn issues(config: &ApiClientConfig) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    if config.app_id.is_empty() {
+        issues.push(ConfigIssue::warning(
+            "app_id_empty",
+            "app_id",
+            "app_id is empty, so the server cannot tell whose requests these are",
+        ));
+    }
+    if config.app_secret.len() != SECRET_LEN {
+        issues.push(ConfigIssue::error(
+            "secret_length",
+            "app_secret",
+            format!(
+                "app_secret is {} bytes; it must be {}",
+                config.app_secret.len(),
+                SECRET_LEN
+            ),
+        ));
+    }
+    if config.iv.len() != IV_LEN {
+        issues.push(ConfigIssue::error(
+            "iv_length",
+            "iv",
+            format!("iv is {} bytes; it must be {}", config.iv.len(), IV_LEN),
+        ));
+    } else if config.iv.bytes().all(|b| b == config.iv.as_bytes()[0]) {
+        issues.push(ConfigIssue::warning(
+            "iv_repeats_one_byte",
+            "iv",
+            format!("iv is {:?}, one byte repeated; it looks like a placeholder", config.iv),
+        ));
+    }
+    for warning in self_check::check_config(config).warnings {
+        let field = match warning {
+            SelfCheckWarning::SecretLooksHex | SelfCheckWarning::SecretAndIvSwapped => "app_secret",
+            SelfCheckWarning::IvLooksHex | SelfCheckWarning::IvIsSecretPrefix => "iv",
+            SelfCheckWarning::SecondaryKeyIsPrimary => "secondary_key",
+        };
+        issues.push(ConfigIssue::warning(warning.code(), field, warning.message()));
+    }
+    if !config.base_url.starts_with("https://") && !config.base_url.starts_with("http://") {
+        issues.push(ConfigIssue::warning(
+            "base_url_not_http",
+            "base_url",
+            format!("base_url {:?} is not an http or https url", config.base_url),
+        ));
+    }
+    if config.base_url.ends_with('/') {
+        issues.push(ConfigIssue::warning(
+            "base_url_trailing_slash",
+            "base_url",
+            "base_url ends with `/`, so request urls have `//` after the host",
+        ));
+    }
+    if !config.content.is_empty() && !config.content.starts_with('/') {
+        issues.push(ConfigIssue::warning(
+            "content_not_rooted",
+            "content",
+            format!(
+                "content {:?} does not start with `/`, so it runs into the host",
+                config.content
+            ),
+        ));
+    }
+    if config.content.ends_with('/') {
+        issues.push(ConfigIssue::warning(
+            "content_trailing_slash",
+            "content",
+            "content ends with `/`, so request urls have `//` before the uri",
+        ));
+    }
+    for url in &config.fallback_base_urls {
+        if url == &config.base_url {
+            issues.push(ConfigIssue::warning(
+                "fallback_repeats_base_url",
+                "fallback_base_urls",
+                format!("fallback_base_urls repeats base_url {:?}", url),
+            ));
+        } else if url.ends_with('/') {
+            issues.push(ConfigIssue::warning(
+                "fallback_base_url_trailing_slash",
+                "fallback_base_urls",
+                format!("fallback base url {:?} ends with `/`", url),
+            ));
+        }
+    }
+    if config.max_concurrent_requests == Some(0) {
+        issues.push(ConfigIssue::error(
+            "max_concurrent_requests_zero",
+            "max_concurrent_requests",
+            "max_concurrent_requests must be at least 1",
+        ));
+    }
+    if let Err(ApiClientError::InvalidConfig(message)) = Bulkheads::new(
+        &config.pools,
+        &config.pool_routes,
+        config.priority_promotion,
+        &config.time,
+    ) {
+        issues.push(ConfigIssue::error("pools_invalid", "pools", message));
+    }
+    if config.retry.max_retries > 0 && config.attempt_timeout.is_none() && config.overall_timeout.is_none() {
+        issues.push(ConfigIssue::warning(
+            "retries_without_timeout",
+            "retry",
+            format!(
+                "retry.max_retries is {} with neither attempt_timeout nor overall_timeout; \
+                 set one so a request's total time is a choice rather than the transport's",
+                config.retry.max_retries
+            ),
+        ));
+    }
+    if let Some(version) = &config.api_version {
+        if let Err(constraint) = check_api_version(version) {
+            issues.push(ConfigIssue::error(
+                "api_version_format",
+                "api_version",
+                format!("api_version must be {}", constraint),
+            ));
+        }
+    }
+    if let Some(key) = &config.secondary_key {
+        if key.app_secret.len() != SECRET_LEN || key.iv.len() != IV_LEN {
+            issues.push(ConfigIssue::error(
+                "secondary_key_length",
+                "secondary_key",
+                "secondary_key has an invalid secret or iv length",
+            ));
+        }
+    }
+    if config.acceptable_statuses.as_ref().is_some_and(Vec::is_empty) {
+        issues.push(ConfigIssue::error(
+            "acceptable_statuses_empty",
+            "acceptable_statuses",
+            "acceptable_statuses must list at least one status",
+        ));
+    }
+    if let Some(Err(err)) = config.redaction.as_ref().map(|redaction| redaction.check()) {
+        let message = match err {
+            ApiClientError::InvalidConfig(message) => message,
+            other => other.to_string(),
+        };
+        issues.push(ConfigIssue::error("redaction_invalid", "redaction", message));
+    }
+    if config.nonce_guard == Some(0) {
+        issues.push(ConfigIssue::error(
+            "nonce_guard_zero",
+            "nonce_guard",
+            "nonce_guard must remember at least 1 nonce",
+        ));
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_config, MockTransport};
+    use crate::ApiClient;
+
+    fn legacy(app_secret: &str, iv: &str, base_url: &str) -> ApiClientConfig {
+        ApiClientConfig {
+            app_id: "your app id".to_string(),
+            app_secret: app_secret.to_string(),
+            iv: iv.to_string(),
+            base_url: base_url.to_string(),
+            content: "/server/common/api".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_valid_legacy_config_migrates_cleanly() {
+        let config = legacy(
+            "your app secret (32 bytes long)!",
+            "your app iv (16)",
+            "https://server.zelaser.com",
+        );
+        let validated = config.clone().migrate().unwrap();
+        assert!(validated.warnings().is_empty());
+        let migrated = validated.config();
+        assert_eq!(
+            (&migrated.app_id, &migrated.base_url),
+            (&config.app_id, &config.base_url)
+        );
+        assert!(migrated.validate_requests && migrated.follow_redirects);
+
+        assert!(ApiClient::new(config).is_ok());
+        assert!(ApiClient::new(validated).is_ok());
+    }
+
+    #[test]
+    fn test_every_issue_is_reported_at_once() {
+        let config = legacy(
+            "your app secret (32 bytes long)",
+            "0000000000000000",
+            "https://server.zelaser.com/",
+        );
+
+        let issues = config.clone().migrate().unwrap_err();
+        let found: Vec<_> = issues
+            .iter()
+            .map(|issue| (issue.code, issue.field, issue.severity))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("secret_length", "app_secret", IssueSeverity::Error),
+                ("iv_repeats_one_byte", "iv", IssueSeverity::Warning),
+                ("base_url_trailing_slash", "base_url", IssueSeverity::Warning),
+            ]
+        );
+        assert_eq!(issues[0].message, "app_secret is 31 bytes; it must be 32");
+        assert_eq!(
+            serde_json::to_value(&issues[2]).unwrap(),
+            serde_json::json!({
+                "code": "base_url_trailing_slash",
+                "field": "base_url",
+                "severity": "warning",
+                "message": "base_url ends with `/`, so request urls have `//` after the host",
+            })
+        );
+
+        match ApiClient::new(config) {
+            Err(ApiClientError::InvalidConfig(message)) => assert!(
+                message.starts_with("configuration failed to migrate: secret_length (app_secret is 31 bytes"),
+                "{}",
+                message
+            ),
+            Err(e) => panic!("unexpected {:?}", e),
+            Ok(_) => panic!("migration passed"),
+        }
+    }
+
+    #[test]
+    fn test_strict_mode_rejects_warnings() {
+        let mut config = test_config();
+        config.base_url.push('/');
+        config.retry.max_retries = 2;
+        let validated = config.clone().migrate().unwrap();
+        let codes: Vec<_> = validated.warnings().iter().map(|issue| issue.code).collect();
+        assert_eq!(codes, ["base_url_trailing_slash", "retries_without_timeout"]);
+        assert!(ApiClient::with_transport(validated.into_inner(), MockTransport::new()).is_ok());
+
+        config.strict_self_check = true;
+        let issues = config.clone().migrate().unwrap_err();
+        assert!(issues.iter().all(|issue| issue.severity == IssueSeverity::Warning));
+        assert_eq!(issues.len(), 2);
+        assert!(matches!(ApiClient::new(config), Err(ApiClientError::InvalidConfig(_))));
+
+        let strict = ApiClientConfig {
+            strict_self_check: true,
+            ..test_config()
+        };
+        assert!(strict.migrate().is_ok());
+    }
+}
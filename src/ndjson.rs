@@ -0,0 +1,335 @@
+use std::collections::VecDeque;
+#[cfg(feature = "gzip")]
+use std::io::Write;
+
+use bytes::Bytes;
+#[cfg(feature = "gzip")]
+use flate2::write::GzDecoder;
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+#[cfg(feature = "gzip")]
+use reqwest::header::{HeaderMap, CONTENT_ENCODING};
+use reqwest::header::{HeaderValue, ACCEPT_ENCODING};
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::body::{check_double_encoding, PreparedBody};
+use crate::client::is_acceptable;
+use crate::priority::{Priority, PriorityPermit};
+use crate::request::{check_uri, RequestOptions};
+use crate::stats::ErrorClass;
+use crate::{ApiClient, ApiClientError};
+
+/// Splits a byte stream into lines, carrying partial lines across chunk boundaries.
+#[derive(Default)]
+pub(crate) struct LineSplitter {
+    partial: Vec<u8>,
+}
+
+impl LineSplitter {
+    /// Feeds one chunk and returns every line completed by it. Blank lines are skipped
+    /// and a trailing `\r` is stripped.
+    pub(crate) fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        let mut lines = Vec::new();
+        let mut rest = chunk;
+        while let Some(pos) = rest.iter().position(|&b| b == b'\n') {
+            self.partial.extend_from_slice(&rest[..pos]);
+            let mut line = std::mem::take(&mut self.partial);
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if !line.iter().all(u8::is_ascii_whitespace) {
+                lines.push(line);
+            }
+            rest = &rest[pos + 1..];
+        }
+        self.partial.extend_from_slice(rest);
+        lines
+    }
+
+    /// Signals end of input. A non-blank unterminated line is reported as an error
+    /// carrying its length, since the sender is expected to newline-terminate records.
+    pub(crate) fn finish(&mut self) -> Result<(), ApiClientError> {
+        let partial = std::mem::take(&mut self.partial);
+        if partial.iter().all(u8::is_ascii_whitespace) {
+            Ok(())
+        } else {
+            Err(ApiClientError::TruncatedStream(partial.len()))
+        }
+    }
+}
+
+type Body = BoxStream<'static, Result<Bytes, ApiClientError>>;
+
+/// `body` decompressed as it arrives, for a `Content-Encoding: gzip` response.
+#[cfg(feature = "gzip")]
+fn gunzip(body: Body) -> Body {
+    let corrupt = |e: std::io::Error| ApiClientError::InvalidEnvelope(format!("the gzip stream is corrupt: {}", e));
+    stream::unfold(Some((body, GzDecoder::new(Vec::new()))), move |state| async move {
+        let (mut body, mut decoder) = state?;
+        loop {
+            match body.next().await {
+                Some(Ok(chunk)) => {
+                    if let Err(e) = decoder.write_all(&chunk) {
+                        return Some((Err(corrupt(e)), None));
+                    }
+                    let out = std::mem::take(decoder.get_mut());
+                    if !out.is_empty() {
+                        return Some((Ok(Bytes::from(out)), Some((body, decoder))));
+                    }
+                }
+                Some(Err(e)) => return Some((Err(e), None)),
+                None => {
+                    return match decoder.finish() {
+                        Ok(rest) if rest.is_empty() => None,
+                        Ok(rest) => Some((Ok(Bytes::from(rest)), None)),
+                        Err(e) => Some((Err(corrupt(e)), None)),
+                    }
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+#[cfg(feature = "gzip")]
+fn is_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| matches!(value.trim().to_ascii_lowercase().as_str(), "gzip" | "x-gzip"))
+}
+
+enum Source {
+    Pending(Method, String, Option<Value>),
+    // The permit rides along with the body so the concurrency slot is held until EOF.
+    Open(Body, Option<PriorityPermit>),
+    Done,
+}
+
+struct StreamState<'a> {
+    client: &'a ApiClient,
+    source: Source,
+    splitter: LineSplitter,
+    lines: VecDeque<Vec<u8>>,
+}
+
+impl<'a> StreamState<'a> {
+    async fn next_line(&mut self) -> Option<Result<Vec<u8>, ApiClientError>> {
+        loop {
+            if let Some(line) = self.lines.pop_front() {
+                return Some(Ok(line));
+            }
+            match std::mem::replace(&mut self.source, Source::Done) {
+                Source::Pending(method, uri, body) => match self.client.open_stream(method, &uri, body).await {
+                    Ok((body, permit)) => self.source = Source::Open(body, permit),
+                    Err(e) => return Some(Err(e)),
+                },
+                Source::Open(mut body, permit) => match body.next().await {
+                    Some(Ok(chunk)) => {
+                        self.lines.extend(self.splitter.push(&chunk));
+                        self.source = Source::Open(body, permit);
+                    }
+                    None => return self.splitter.finish().err().map(Err),
+                    Some(Err(e)) => return Some(Err(e)),
+                },
+                Source::Done => return None,
+            }
+        }
+    }
+}
+
+impl ApiClient {
+    /// Streams a newline-delimited response where every line is an independent hex
+    /// ciphertext. Items are decrypted and deserialized as their line completes; the
+    /// stream ends after the first error. The request accepts gzip, and a gzip-encoded
+    /// response is decompressed chunk by chunk.
+    pub fn send_ndjson_stream<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        uri: &str,
+        body_option: Option<Value>,
+    ) -> impl Stream<Item = Result<T, ApiClientError>> + '_ {
+        let state = StreamState {
+            client: self,
+            source: Source::Pending(method, uri.to_string(), body_option),
+            splitter: LineSplitter::default(),
+            lines: VecDeque::new(),
+        };
+        stream::unfold(Some(state), |state| async move {
+            let mut state = state?;
+            let item = match state.next_line().await? {
+                Ok(line) => state
+                    .client
+                    .decrypt_with_fallback(|crypto| crypto.decrypt_hex(&line))
+                    .and_then(|(plaintext, _)| Ok(serde_json::from_str(&plaintext)?)),
+                Err(e) => Err(e),
+            };
+            let next = if item.is_ok() { Some(state) } else { None };
+            Some((item, next))
+        })
+    }
+
+    async fn open_stream(
+        &self,
+        method: Method,
+        uri: &str,
+        body_option: Option<Value>,
+    ) -> Result<(Body, Option<PriorityPermit>), ApiClientError> {
+        let uri = &*check_uri(uri)?;
+        let permit = self.acquire_permit(uri, Priority::Normal, None).await?.permit;
+        let started = self.config.time.now();
+        let host = self.hosts.order()[0];
+        let mut sent = 0;
+        let result = async {
+            if let (Some(body), true) = (&body_option, self.config.detect_double_encoding) {
+                check_double_encoding(body)?;
+            }
+            let body = PreparedBody::new(body_option.as_ref(), self.config.max_request_size)?;
+            let mut options = RequestOptions::default();
+            if cfg!(feature = "gzip") {
+                options
+                    .headers
+                    .insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+            }
+            let request = self.signed_request(&self.hosts.endpoint(host), method, uri, &body, &options)?;
+            sent = request.body.len() as u64;
+            let active = self.activity.begin();
+            let response = self.transport.execute_streaming(request).await?;
+            self.drift
+                .observe_headers(&response.headers, self.config.time.now_utc());
+            if !is_acceptable(&self.config, response.status) {
+                let body: Vec<Bytes> = response.body.filter_map(|chunk| async { chunk.ok() }).collect().await;
+                return Err(self.redacted(ApiClientError::HttpStatus {
+                    status: response.status,
+                    body: String::from_utf8_lossy(&body.concat()).into_owned(),
+                }));
+            }
+            #[cfg(feature = "gzip")]
+            let body = match is_gzip(&response.headers) {
+                true => gunzip(response.body),
+                false => response.body,
+            };
+            #[cfg(not(feature = "gzip"))]
+            let body = response.body;
+            // The stream counts as traffic for keepalive until it is dropped.
+            Ok(body
+                .map(move |chunk| {
+                    let _ = &active;
+                    chunk
+                })
+                .boxed())
+        }
+        .await;
+        self.hosts.record(host, !matches!(&result, Err(e) if e.is_retryable()));
+        self.stats.record_request(
+            self.config.time.elapsed(started),
+            sent,
+            0,
+            result.as_ref().err().map(ErrorClass::of),
+        );
+        result.map(|body| (body, permit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_split_across_chunks() {
+        let mut splitter = LineSplitter::default();
+        assert!(splitter.push(b"ab").is_empty());
+        assert_eq!(splitter.push(b"cd\nef"), vec![b"abcd".to_vec()]);
+        assert_eq!(splitter.push(b"\r\n\n  \ngh\n"), vec![b"ef".to_vec(), b"gh".to_vec()]);
+        assert!(splitter.finish().is_ok());
+    }
+
+    #[test]
+    fn test_empty_input_finishes_cleanly() {
+        let mut splitter = LineSplitter::default();
+        assert!(splitter.push(b"").is_empty());
+        assert!(splitter.finish().is_ok());
+    }
+
+    #[test]
+    fn test_partial_trailing_line_is_an_error() {
+        let mut splitter = LineSplitter::default();
+        assert_eq!(splitter.push(b"0a0b\n0c"), vec![b"0a0b".to_vec()]);
+        assert!(matches!(splitter.finish(), Err(ApiClientError::TruncatedStream(2))));
+    }
+
+    #[cfg(feature = "gzip")]
+    mod gzip {
+        use std::net::Ipv4Addr;
+
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        use super::*;
+        use crate::testing::{test_config, test_crypto};
+
+        /// Answers one request with `body` gzip-encoded and sent in chunks of `chunk` bytes,
+        /// and returns the request head.
+        async fn serve_gzip_chunked(listener: TcpListener, body: &[u8], chunk: usize) -> String {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body).unwrap();
+            let gzip = encoder.finish().unwrap();
+
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !received.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                assert!(n > 0, "connection closed before the request head");
+                received.extend_from_slice(&buf[..n]);
+            }
+            let head =
+                "HTTP/1.1 200 OK\r\ncontent-encoding: gzip\r\ntransfer-encoding: chunked\r\nconnection: close\r\n\r\n";
+            socket.write_all(head.as_bytes()).await.unwrap();
+            for part in gzip.chunks(chunk) {
+                socket
+                    .write_all(format!("{:x}\r\n", part.len()).as_bytes())
+                    .await
+                    .unwrap();
+                socket.write_all(part).await.unwrap();
+                socket.write_all(b"\r\n").await.unwrap();
+                socket.flush().await.unwrap();
+            }
+            socket.write_all(b"0\r\n\r\n").await.unwrap();
+            String::from_utf8_lossy(&received).into_owned()
+        }
+
+        #[tokio::test]
+        async fn test_gzip_chunked_stream_is_decompressed() {
+            let crypto = test_crypto();
+            let lines: String = (1..=50)
+                .map(|id| format!("{}\n", crypto.encrypt_hex(format!(r#"{{"id":{}}}"#, id).as_bytes())))
+                .collect();
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let mut config = test_config();
+            config.base_url = format!("http://{}", listener.local_addr().unwrap());
+            let server = tokio::spawn(async move { serve_gzip_chunked(listener, lines.as_bytes(), 64).await });
+            let client = ApiClient::new(config).unwrap();
+
+            let items: Vec<Value> = client
+                .send_ndjson_stream(Method::GET, "/v1/matches", None)
+                .map(Result::unwrap)
+                .collect()
+                .await;
+            assert_eq!(items.len(), 50);
+            assert_eq!((&items[0]["id"], &items[49]["id"]), (&Value::from(1), &Value::from(50)));
+            let head = server.await.unwrap().to_ascii_lowercase();
+            assert!(head.contains("accept-encoding: gzip"), "{}", head);
+        }
+
+        #[tokio::test]
+        async fn test_corrupt_gzip_ends_the_stream_with_an_error() {
+            let body: Body = stream::iter([Ok(Bytes::from_static(b"\x1f\x8b\x08\x00 not gzip"))]).boxed();
+            let chunks: Vec<_> = gunzip(body).collect().await;
+            assert!(matches!(chunks.last(), Some(Err(ApiClientError::InvalidEnvelope(_)))));
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! `HO-NONCE` generation. Every source produces a random UUID v4 string, so the server sees
+//! the same format whichever feature picked it: `uuid-nonce` uses the `uuid` crate,
+//! `fastrand-nonce` uses `fastrand`, and with neither the nonce is drawn from the randomly
+//! keyed SipHash behind `std::collections::hash_map::RandomState`.
+//!
+//! With `ApiClientConfig::nonce_guard` set, every nonce the client signs with also passes
+//! a `NonceGuard`, which fails one it has issued recently: the mark of a signed request sent
+//! again, or of a random source that repeats, as in a forked process.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashSet, VecDeque};
+use std::hash::BuildHasher;
+use std::sync::Mutex;
+
+use crate::ApiClientError;
+
+#[cfg(feature = "uuid-nonce")]
+pub(crate) fn generate() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+#[cfg(all(feature = "fastrand-nonce", not(feature = "uuid-nonce")))]
+pub(crate) fn generate() -> String {
+    format_v4(fastrand::u128(..))
+}
+
+#[cfg(not(any(feature = "uuid-nonce", feature = "fastrand-nonce")))]
+pub(crate) fn generate() -> String {
+    use std::hash::Hasher;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut halves = [0u64; 2];
+    for (i, half) in halves.iter_mut().enumerate() {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(count);
+        hasher.write_u128(now);
+        hasher.write_usize(i);
+        *half = hasher.finish();
+    }
+    format_v4(((halves[0] as u128) << 64) | halves[1] as u128)
+}
+
+/// Formats 128 random bits as a hyphenated UUID, with the version and variant bits set.
+#[cfg_attr(feature = "uuid-nonce", allow(dead_code))]
+fn format_v4(random: u128) -> String {
+    let bits = (random & !(0xf << 76) & !(0x3 << 62)) | (0x4 << 76) | (0x2 << 62);
+    let hex = format!("{:032x}", bits);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// The last `capacity` nonces issued, as 64-bit fingerprints under one short lock: a check
+/// is a hash and two set operations, and memory stays at `capacity` entries.
+pub(crate) struct NonceGuard {
+    hasher: RandomState,
+    recent: Mutex<Recent>,
+}
+
+struct Recent {
+    capacity: usize,
+    order: VecDeque<u64>,
+    seen: HashSet<u64>,
+}
+
+impl NonceGuard {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            hasher: RandomState::new(),
+            recent: Mutex::new(Recent {
+                capacity,
+                order: VecDeque::with_capacity(capacity),
+                seen: HashSet::with_capacity(capacity),
+            }),
+        }
+    }
+
+    /// Records `nonce`, or fails with `NonceReuse` if it is among the recent ones. The
+    /// oldest is forgotten once `capacity` are kept.
+    pub(crate) fn issue(&self, nonce: &str) -> Result<(), ApiClientError> {
+        let fingerprint = self.hasher.hash_one(nonce);
+        let mut recent = self.recent.lock().unwrap();
+        if !recent.seen.insert(fingerprint) {
+            drop(recent);
+            tracing::error!(nonce, "a nonce was about to be signed with twice");
+            return Err(ApiClientError::NonceReuse {
+                nonce: nonce.to_string(),
+            });
+        }
+        if recent.order.len() == recent.capacity {
+            let oldest = recent.order.pop_front().expect("a full guard holds nonces");
+            recent.seen.remove(&oldest);
+        }
+        recent.order.push_back(fingerprint);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_v4(nonce: &str) {
+        let groups: Vec<&str> = nonce.split('-').collect();
+        let lengths: Vec<usize> = groups.iter().map(|g| g.len()).collect();
+        assert_eq!(lengths, vec![8, 4, 4, 4, 12], "{}", nonce);
+        assert!(nonce.chars().all(|c| c == '-' || c.is_ascii_hexdigit()));
+        assert!(groups[2].starts_with('4'), "{}", nonce);
+        assert!(
+            matches!(groups[3].as_bytes()[0], b'8' | b'9' | b'a' | b'b'),
+            "{}",
+            nonce
+        );
+    }
+
+    #[test]
+    fn test_generated_nonces_are_distinct_uuid_v4() {
+        let first = generate();
+        let second = generate();
+        assert_v4(&first);
+        assert_v4(&second);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_guard_fails_recent_nonces_and_forgets_old_ones() {
+        let guard = NonceGuard::new(3);
+        for nonce in ["a", "b", "c"] {
+            guard.issue(nonce).unwrap();
+        }
+        assert!(matches!(
+            guard.issue("a"),
+            Err(ApiClientError::NonceReuse { nonce }) if nonce == "a"
+        ));
+
+        // "d" pushes out "a", then "a" pushes out "b".
+        guard.issue("d").unwrap();
+        guard.issue("a").unwrap();
+        guard.issue("b").unwrap();
+        assert!(guard.issue("d").is_err());
+        assert!(guard.issue("c").is_ok());
+    }
+
+    #[test]
+    fn test_format_sets_version_and_variant() {
+        assert_eq!(format_v4(0), "00000000-0000-4000-8000-000000000000");
+        assert_eq!(format_v4(u128::MAX), "ffffffff-ffff-4fff-bfff-ffffffffffff");
+    }
+}
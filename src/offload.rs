@@ -0,0 +1,150 @@
+//! CPU-heavy response work moved off the async worker threads. Parsing, decrypting and
+//! deserializing a multi-megabyte payload takes milliseconds, during which every other task
+//! on the same worker waits; payloads of at least `ApiClientConfig::offload_threshold` bytes
+//! are handled on tokio's blocking pool instead. Smaller ones stay on the calling task, where
+//! they cost less than the hand-off.
+
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use tokio::time::Instant;
+
+use crate::{ApiClient, ApiClientError};
+
+/// The 256 KiB default of `ApiClientConfig::offload_threshold`.
+pub const DEFAULT_OFFLOAD_THRESHOLD: usize = 256 * 1024;
+
+/// Runs `work` on the blocking pool. A blocking task cannot be stopped, so dropping the
+/// returned future detaches it; tokio drops its output as soon as it finishes.
+pub(crate) async fn run<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(work).await {
+        Ok(output) => output,
+        Err(e) => match e.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            Err(_) => unreachable!("blocking tasks are only cancelled by a runtime shutting down under them"),
+        },
+    }
+}
+
+impl ApiClient {
+    /// Whether a payload of `len` bytes goes to the blocking pool.
+    pub(crate) fn offloads(&self, len: usize) -> bool {
+        self.config.offload_threshold.is_some_and(|threshold| len >= threshold)
+    }
+
+    /// `plaintext` deserialized as `T`, on the blocking pool when it is large.
+    pub(crate) async fn deserialize<T: DeserializeOwned + Send + 'static>(
+        &self,
+        plaintext: String,
+    ) -> Result<T, ApiClientError> {
+        Ok(self.deserialize_timed(plaintext).await?.0)
+    }
+
+    /// As `deserialize`, with the time `serde_json` took, which is also added to `Stats`.
+    pub(crate) async fn deserialize_timed<T: DeserializeOwned + Send + 'static>(
+        &self,
+        plaintext: String,
+    ) -> Result<(T, Duration), ApiClientError> {
+        let offloaded = self.offloads(plaintext.len());
+        let deserialize = move || {
+            let started = Instant::now();
+            let value = serde_json::from_str(&plaintext);
+            (value, started.elapsed())
+        };
+        let (value, elapsed) = match offloaded {
+            true => run(deserialize).await,
+            false => deserialize(),
+        };
+        self.stats.record_deserialize(elapsed);
+        Ok((value?, elapsed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::time::Duration;
+
+    use reqwest::Method;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport};
+
+    #[derive(Debug, Deserialize)]
+    struct Skin {
+        id: u64,
+        name: String,
+    }
+
+    /// A catalog of `count` skins, about 60 bytes each before encryption.
+    fn catalog(count: u64) -> String {
+        let skins: Vec<_> = (0..count)
+            .map(|id| json!({ "id": id, "name": format!("skin number {:08}", id) }))
+            .collect();
+        json!(skins).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_large_payloads_round_trip_through_the_blocking_pool() {
+        let plaintext = catalog(40_000);
+        assert!(plaintext.len() > 4 * DEFAULT_OFFLOAD_THRESHOLD);
+        let transport = MockTransport::new();
+        transport
+            .on(Method::GET, "/v1/skins", MockResponse::encrypted(&plaintext))
+            .on(Method::GET, "/v1/skin", MockResponse::encrypted(catalog(1)));
+        let client = mock_client(&transport, |_| {});
+
+        let large = client.send_detailed(Method::GET, "/v1/skins", None).await.unwrap();
+        assert!(large.meta.timings.offloaded);
+        assert!(large.meta.timings.decrypt.is_some());
+        assert_eq!(large.body, plaintext);
+        let skins: Vec<Skin> = client.send_as(Method::GET, "/v1/skins", None).await.unwrap();
+        assert_eq!(skins.len(), 40_000);
+        assert_eq!(
+            (skins[39_999].id, &*skins[39_999].name),
+            (39_999, "skin number 00039999")
+        );
+
+        let small = client.send_detailed(Method::GET, "/v1/skin", None).await.unwrap();
+        assert!(!small.meta.timings.offloaded);
+        let inline = mock_client(&transport, |config| config.offload_threshold = None);
+        let large = inline.send_detailed(Method::GET, "/v1/skins", None).await.unwrap();
+        assert!(!large.meta.timings.offloaded);
+        assert_eq!(large.body, plaintext);
+    }
+
+    /// Sets its flag when dropped.
+    struct Output(Arc<AtomicBool>);
+
+    impl Drop for Output {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_work_drops_its_output() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let (release, wait) = mpsc::channel::<()>();
+        let output = Output(dropped.clone());
+        let work = run(move || {
+            wait.recv().unwrap();
+            output
+        });
+        // Gives up while the work is blocked.
+        assert!(tokio::time::timeout(Duration::from_millis(20), work).await.is_err());
+        assert!(!dropped.load(Ordering::SeqCst));
+
+        release.send(()).unwrap();
+        for _ in 0..200 {
+            if dropped.load(Ordering::SeqCst) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("the output of the abandoned work was never dropped");
+    }
+}
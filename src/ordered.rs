@@ -0,0 +1,285 @@
+//! Results of concurrent requests in the order they were issued. Created with
+//! `ApiClient::ordered_scope`:
+//!
+//! ```no_run
+//! # async fn run(client: &ho_api_rust_sdk::ApiClient) {
+//! use futures_util::{future, StreamExt};
+//! use reqwest::Method;
+//!
+//! let scope = client.ordered_scope(16);
+//! let issue = async {
+//!     let sends = (1..=5).map(|id| scope.send(Method::GET, &format!("/v1/item/{}", id), None));
+//!     future::join_all(sends).await;
+//!     scope.close();
+//! };
+//! let apply = scope.completions().for_each(|completion| async move {
+//!     println!("{} {:?}", completion.sequence, completion.result);
+//! });
+//! future::join(issue, apply).await;
+//! # }
+//! ```
+//!
+//! Each `send` takes the next sequence number, from 1, when it is called, and its result,
+//! an error included, is yielded by `completions` in that place. A request starts only
+//! while fewer than `capacity` sequence numbers separate it from the next one to be
+//! yielded, so at most that many results wait in the buffer and a consumer that falls
+//! behind holds the senders back. A `send` future dropped before it completes gives up its
+//! place: the request is abandoned and `completions` skips its sequence number.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+use futures_util::stream::{self, Stream};
+use reqwest::Method;
+use serde_json::Value;
+use tokio::sync::Notify;
+
+use crate::{ApiClient, ApiClientError};
+
+/// The result of one request sent through an `OrderedScope`.
+#[derive(Debug)]
+pub struct Completion {
+    pub sequence: u64,
+    pub result: Result<String, ApiClientError>,
+}
+
+pub struct OrderedScope<'a> {
+    client: &'a ApiClient,
+    capacity: u64,
+    state: Mutex<State>,
+    /// Woken when a result arrives, one is yielded, or the scope closes.
+    progress: Notify,
+}
+
+struct State {
+    issued: u64,
+    /// The sequence number `completions` yields next.
+    next: u64,
+    /// Finished requests not yielded yet; `None` for abandoned ones.
+    finished: BTreeMap<u64, Option<Result<String, ApiClientError>>>,
+    closed: bool,
+}
+
+impl ApiClient {
+    /// A scope whose results are yielded in issue order, with at most `capacity` of them
+    /// (at least 1) in flight or buffered at once.
+    pub fn ordered_scope(&self, capacity: usize) -> OrderedScope<'_> {
+        OrderedScope {
+            client: self,
+            capacity: capacity.max(1) as u64,
+            state: Mutex::new(State {
+                issued: 0,
+                next: 1,
+                finished: BTreeMap::new(),
+                closed: false,
+            }),
+            progress: Notify::new(),
+        }
+    }
+}
+
+impl<'a> OrderedScope<'a> {
+    /// Sends the request once its place in the order is within `capacity` of the next one
+    /// to be yielded. The sequence number is taken on the call, and the future resolves to
+    /// it once the result is buffered for `completions`.
+    ///
+    /// # Panics
+    ///
+    /// If the scope has been closed.
+    pub fn send(&self, method: Method, uri: &str, body: Option<Value>) -> impl Future<Output = u64> + '_ {
+        let sequence = {
+            let mut state = self.state.lock().unwrap();
+            assert!(!state.closed, "`send` on a closed ordered scope");
+            state.issued += 1;
+            state.issued
+        };
+        let uri = uri.to_string();
+        // Built before the future, so that its place is given up even if it is never polled.
+        let mut slot = Slot {
+            scope: self,
+            sequence,
+            result: None,
+        };
+        async move {
+            self.wait_until(|state| sequence < state.next + self.capacity).await;
+            slot.fill(self.client.send(method, &uri, body).await);
+            sequence
+        }
+    }
+
+    /// No more requests will be sent: `completions` ends after the last issued one.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.progress.notify_waiters();
+    }
+
+    /// The results in issue order. Ends once the scope is closed and every issued request has
+    /// been yielded or abandoned. Meant to be consumed once.
+    pub fn completions(&self) -> impl Stream<Item = Completion> + '_ {
+        stream::unfold(self, |scope| async move {
+            let mut ready = None;
+            scope
+                .wait_until(|state| {
+                    while let Some(result) = state.finished.remove(&state.next) {
+                        state.next += 1;
+                        if let Some(result) = result {
+                            ready = Some(Some((state.next - 1, result)));
+                            return true;
+                        }
+                    }
+                    if state.closed && state.next > state.issued {
+                        ready = Some(None);
+                    }
+                    ready.is_some()
+                })
+                .await;
+            // The next place moved forward: requests waiting for the window may go.
+            scope.progress.notify_waiters();
+            let (sequence, result) = ready.flatten()?;
+            Some((Completion { sequence, result }, scope))
+        })
+    }
+
+    /// Waits until `ready` holds over the state, checking it again on every change.
+    async fn wait_until(&self, mut ready: impl FnMut(&mut State) -> bool) {
+        loop {
+            let notified = self.progress.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if ready(&mut self.state.lock().unwrap()) {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Hands the result of one request to the scope when dropped, or marks its place abandoned
+/// if the request did not finish.
+struct Slot<'s, 'a> {
+    scope: &'s OrderedScope<'a>,
+    sequence: u64,
+    result: Option<Result<String, ApiClientError>>,
+}
+
+impl Slot<'_, '_> {
+    fn fill(&mut self, result: Result<String, ApiClientError>) {
+        self.result = Some(result);
+    }
+}
+
+impl Drop for Slot<'_, '_> {
+    fn drop(&mut self) {
+        let result = self.result.take();
+        self.scope.state.lock().unwrap().finished.insert(self.sequence, result);
+        self.scope.progress.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::{future, StreamExt};
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_completions_follow_the_issue_order() {
+        let transport = MockTransport::new();
+        for (id, delay) in [(1, 400), (2, 100), (3, 500), (4, 200), (5, 300)] {
+            let response = match id {
+                4 => MockResponse::status(StatusCode::BAD_GATEWAY),
+                _ => MockResponse::encrypted(format!(r#"{{"id":{}}}"#, id)),
+            };
+            transport.on(
+                Method::GET,
+                &format!("/v1/item/{}", id),
+                response.with_delay(Duration::from_millis(delay)),
+            );
+        }
+        let client = mock_client(&transport, |_| {});
+        let scope = client.ordered_scope(8);
+
+        let issue = async {
+            let sends = (1..=5).map(|id| scope.send(Method::GET, &format!("/v1/item/{}", id), None));
+            let finished = future::join_all(sends).await;
+            scope.close();
+            finished
+        };
+        let (finished, completions) = future::join(issue, scope.completions().collect::<Vec<_>>()).await;
+        assert_eq!(finished, [1, 2, 3, 4, 5]);
+        let sequences: Vec<u64> = completions.iter().map(|completion| completion.sequence).collect();
+        assert_eq!(sequences, [1, 2, 3, 4, 5]);
+        for completion in &completions {
+            match completion.sequence {
+                4 => assert_eq!(
+                    completion.result.as_ref().unwrap_err().status(),
+                    Some(StatusCode::BAD_GATEWAY)
+                ),
+                id => assert_eq!(completion.result.as_ref().unwrap(), &format!(r#"{{"id":{}}}"#, id)),
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_slow_consumer_holds_back_later_requests() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/item",
+            MockResponse::encrypted("{}").with_delay(Duration::from_millis(10)),
+        );
+        let client = mock_client(&transport, |_| {});
+        let scope = client.ordered_scope(2);
+
+        let issue = async {
+            future::join_all((0..4).map(|_| scope.send(Method::GET, "/v1/item", None))).await;
+            scope.close();
+        };
+        let consume = async {
+            let mut completions = std::pin::pin!(scope.completions());
+            let mut sent_before = Vec::new();
+            while let Some(completion) = completions.next().await {
+                assert!(completion.result.is_ok());
+                sent_before.push(transport.requests().len());
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            sent_before
+        };
+        let ((), sent_before) = future::join(issue, consume).await;
+        // With two places, a request waits until the one two ahead of it has been yielded.
+        assert_eq!(sent_before, [2, 3, 4, 4]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_abandoned_requests_are_skipped() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::GET,
+                "/v1/slow",
+                MockResponse::encrypted("{}").with_delay(Duration::from_secs(60)),
+            )
+            .on(Method::GET, "/v1/fast", MockResponse::encrypted("{}"));
+        let client = mock_client(&transport, |_| {});
+        let scope = client.ordered_scope(4);
+
+        let slow = scope.send(Method::GET, "/v1/slow", None);
+        let fast = scope.send(Method::GET, "/v1/fast", None);
+        let abandoned = tokio::time::timeout(Duration::from_secs(1), future::join(slow, fast)).await;
+        assert!(abandoned.is_err());
+        let last = scope.send(Method::GET, "/v1/fast", None).await;
+        scope.close();
+
+        let sequences: Vec<u64> = scope
+            .completions()
+            .map(|completion| completion.sequence)
+            .collect()
+            .await;
+        assert_eq!(sequences, [2, last]);
+    }
+}
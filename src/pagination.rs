@@ -0,0 +1,1123 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_util::future;
+use futures_util::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::payload::{self, BatchResult};
+use crate::{ApiClient, ApiClientError};
+
+#[derive(Debug, Clone)]
+enum Mode {
+    Pages {
+        page_param: String,
+        size_param: String,
+        first_page: u64,
+    },
+    Cursor {
+        cursor_param: String,
+        next_cursor_path: String,
+        max_repeats: usize,
+    },
+}
+
+/// Sizes each page by how long the previous one took. Set with `Paginator::adaptive`.
+///
+/// After a page that took longer than `target_latency` the page size is multiplied by
+/// `decrease`; after one that would still have fit the budget at `increase` times the
+/// size, by `increase`. Sizes stay within `min_page_size..=max_page_size`. A page that
+/// times out (`ApiClientError::AttemptTimeout` or `OverallTimeout`) is requested again
+/// right away with the decreased size, until the minimum itself times out.
+///
+/// In page-number mode a new size must also divide the items fetched so far, so the next
+/// page starts where the last one ended; a change that cannot land there is put off.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptivePageSize {
+    pub target_latency: Duration,
+    pub min_page_size: u64,
+    pub max_page_size: u64,
+    pub increase: f64,
+    pub decrease: f64,
+}
+
+impl AdaptivePageSize {
+    /// Sizes between 10 and 200, growing by half and halving.
+    pub fn new(target_latency: Duration) -> Self {
+        Self {
+            target_latency,
+            min_page_size: 10,
+            max_page_size: 200,
+            increase: 1.5,
+            decrease: 0.5,
+        }
+    }
+
+    pub fn bounds(mut self, min_page_size: u64, max_page_size: u64) -> Self {
+        self.min_page_size = min_page_size.max(1);
+        self.max_page_size = max_page_size.max(self.min_page_size);
+        self
+    }
+
+    /// The size to request after a page of `page_size` items that took `elapsed`.
+    fn next(&self, page_size: u64, elapsed: Duration) -> u64 {
+        if elapsed > self.target_latency {
+            self.decreased(page_size)
+        } else if elapsed.mul_f64(self.increase.max(1.0)) <= self.target_latency {
+            self.clamp((page_size as f64 * self.increase).ceil() as u64)
+        } else {
+            self.clamp(page_size)
+        }
+    }
+
+    fn decreased(&self, page_size: u64) -> u64 {
+        self.clamp((page_size as f64 * self.decrease.clamp(0.0, 1.0)).floor() as u64)
+    }
+
+    fn clamp(&self, page_size: u64) -> u64 {
+        page_size.clamp(self.min_page_size, self.max_page_size.max(self.min_page_size))
+    }
+}
+
+/// A page as `Paginator::page_stream` yields it.
+#[derive(Debug, Clone)]
+pub struct FetchedPage<T> {
+    pub items: Vec<T>,
+    /// The `page_size` the page was requested with.
+    pub page_size: u64,
+    /// The round trip of the request, as in `ResponseMeta::elapsed`.
+    pub elapsed: Duration,
+    pub truncated: bool,
+}
+
+/// Drives a list endpoint page by page and yields its items. Created with
+/// `ApiClient::paginate`; defaults to `page`/`page_size` numbering starting at 1.
+///
+/// A page the server cut short (status 206 or `HO-TRUNCATED: true`) does not end the
+/// listing: its items are yielded and the rest is requested with a page size the server
+/// managed to return, whatever `ApiClientConfig::fail_on_truncated` says.
+#[derive(Clone)]
+pub struct Paginator<'a> {
+    client: &'a ApiClient,
+    method: Method,
+    uri: String,
+    body: Option<Value>,
+    page_size: u64,
+    limit: Option<usize>,
+    prefetch: Option<usize>,
+    adaptive: Option<AdaptivePageSize>,
+    mode: Mode,
+}
+
+struct Page<T> {
+    field: &'static str,
+    items: Vec<T>,
+    total: Option<u64>,
+    next_cursor: Option<String>,
+    truncated: bool,
+    page_size: u64,
+    elapsed: Duration,
+}
+
+enum Position {
+    Page(u64),
+    Cursor(Option<String>),
+}
+
+struct State<'a> {
+    paginator: Paginator<'a>,
+    position: Position,
+    fetched: u64,
+    repeats: usize,
+    done: bool,
+}
+
+impl ApiClient {
+    pub fn paginate(&self, method: Method, uri: &str) -> Paginator<'_> {
+        Paginator {
+            client: self,
+            method,
+            uri: uri.to_string(),
+            body: None,
+            page_size: self.recommendations.max_page_size().map_or(20, |max| max.min(20)),
+            limit: None,
+            prefetch: None,
+            adaptive: None,
+            mode: Mode::Pages {
+                page_param: "page".to_string(),
+                size_param: "page_size".to_string(),
+                first_page: 1,
+            },
+        }
+    }
+}
+
+impl<'a> Paginator<'a> {
+    pub fn body(mut self, body: Value) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    pub fn page_size(mut self, page_size: u64) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Stops after yielding `limit` items, without fetching further pages.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Fetches up to `pages` pages ahead of the consumer and buffers them. In page-number
+    /// mode the requests run concurrently, so up to `pages - 1` requests may go past the last
+    /// page; in cursor mode each request waits for the previous cursor. Items keep their
+    /// order and an error is yielded where its page's items would have been. Dropping the
+    /// stream cancels whatever is still in flight.
+    pub fn prefetch(mut self, pages: usize) -> Self {
+        self.prefetch = Some(pages.max(1));
+        self
+    }
+
+    /// Tunes the page size to `tuning`'s latency budget, starting from `page_size` (clamped
+    /// to its bounds). Pages are then fetched one after another, `prefetch` only buffering
+    /// them ahead of the consumer.
+    pub fn adaptive(mut self, tuning: AdaptivePageSize) -> Self {
+        self.adaptive = Some(tuning);
+        self
+    }
+
+    /// Page-number mode with custom parameter names and first page number.
+    pub fn pages(mut self, page_param: &str, size_param: &str, first_page: u64) -> Self {
+        self.mode = Mode::Pages {
+            page_param: page_param.to_string(),
+            size_param: size_param.to_string(),
+            first_page,
+        };
+        self
+    }
+
+    /// Cursor mode: the cursor found at `next_cursor_path` (dot-separated, e.g.
+    /// `"next_cursor"` or `"meta.next"`) is sent back as `cursor_param` until it is null,
+    /// empty or absent. `page_size` is still sent as `page_size`.
+    pub fn cursor(mut self, cursor_param: &str, next_cursor_path: &str) -> Self {
+        self.mode = Mode::Cursor {
+            cursor_param: cursor_param.to_string(),
+            next_cursor_path: next_cursor_path.to_string(),
+            max_repeats: 2,
+        };
+        self
+    }
+
+    /// How many consecutive responses may return the cursor that was just sent before the
+    /// stream fails with `ApiClientError::PaginationLoop`. Only meaningful in cursor mode.
+    pub fn max_cursor_repeats(mut self, repeats: usize) -> Self {
+        if let Mode::Cursor { max_repeats, .. } = &mut self.mode {
+            *max_repeats = repeats.max(1);
+        }
+        self
+    }
+
+    pub fn items<T: DeserializeOwned + Send + 'a>(self) -> impl Stream<Item = Result<T, ApiClientError>> + 'a {
+        let limit = self.limit.unwrap_or(usize::MAX);
+        let items = match (self.prefetch, &self.mode) {
+            (None, _) => self
+                .sequential_pages::<T>()
+                .map_ok(|page| stream::iter(page.items.into_iter().map(Ok)))
+                .try_flatten()
+                .boxed(),
+            (Some(pages), Mode::Pages { .. }) if self.adaptive.is_none() => Prefetch::new(
+                self.concurrent_pages::<T>(pages).map_ok(|page| page.items).boxed(),
+                pages,
+            )
+            .boxed(),
+            (Some(pages), _) => {
+                Prefetch::new(self.sequential_pages::<T>().map_ok(|page| page.items).boxed(), pages).boxed()
+            }
+        };
+        items.take(limit)
+    }
+
+    /// Yields each page of a batch endpoint as a `BatchResult`, so failed entries do not end
+    /// the stream. Honors `prefetch`; `limit` counts items and does not apply here.
+    pub fn batches<T: DeserializeOwned + Send + 'a>(
+        self,
+    ) -> impl Stream<Item = Result<BatchResult<T>, ApiClientError>> + 'a {
+        fn split<T: DeserializeOwned>(page: Page<Value>) -> Result<Vec<BatchResult<T>>, ApiClientError> {
+            Ok(vec![BatchResult::from_entries(page.field, page.items)?])
+        }
+        match (self.prefetch, &self.mode) {
+            (None, _) => self
+                .sequential_pages::<Value>()
+                .and_then(|page| future::ready(BatchResult::from_entries(page.field, page.items)))
+                .boxed(),
+            (Some(pages), Mode::Pages { .. }) if self.adaptive.is_none() => {
+                let batches = self.concurrent_pages::<Value>(pages).map(|page| page.and_then(split));
+                Prefetch::new(batches.boxed(), pages).boxed()
+            }
+            (Some(pages), _) => {
+                let batches = self.sequential_pages::<Value>().map(|page| page.and_then(split));
+                Prefetch::new(batches.boxed(), pages).boxed()
+            }
+        }
+    }
+
+    /// Yields whole pages with the size each was requested with and its round trip, one
+    /// after another; `prefetch` and `limit` do not apply.
+    pub fn page_stream<T: DeserializeOwned + Send + 'a>(
+        self,
+    ) -> impl Stream<Item = Result<FetchedPage<T>, ApiClientError>> + 'a {
+        self.sequential_pages::<T>().map_ok(|page| FetchedPage {
+            items: page.items,
+            page_size: page.page_size,
+            elapsed: page.elapsed,
+            truncated: page.truncated,
+        })
+    }
+
+    /// Pages fetched one after another, each request waiting for the previous response.
+    fn sequential_pages<T: DeserializeOwned + Send + 'a>(mut self) -> BoxStream<'a, Result<Page<T>, ApiClientError>> {
+        if let Some(tuning) = &self.adaptive {
+            self.page_size = tuning.clamp(self.page_size);
+        }
+        let position = match &self.mode {
+            Mode::Pages { first_page, .. } => Position::Page(*first_page),
+            Mode::Cursor { .. } => Position::Cursor(None),
+        };
+        let state = State {
+            paginator: self,
+            position,
+            fetched: 0,
+            repeats: 0,
+            done: false,
+        };
+        stream::try_unfold(state, |mut state| async move {
+            if state.done {
+                return Ok::<_, ApiClientError>(None);
+            }
+            let page = state.fetch::<T>().await?;
+            state.advance(&page)?;
+            if let Some(tuning) = &state.paginator.adaptive {
+                if !state.done && !page.truncated {
+                    let target = tuning.next(page.page_size, page.elapsed);
+                    state.resize(target, Some(page.elapsed));
+                }
+            }
+            Ok(Some((page, state)))
+        })
+        .boxed()
+    }
+
+    /// Page-number mode only: up to `in_flight` consecutive pages requested at once, yielded
+    /// in order until the last page or the first error.
+    fn concurrent_pages<T: DeserializeOwned + Send + 'a>(
+        self,
+        in_flight: usize,
+    ) -> BoxStream<'a, Result<Page<T>, ApiClientError>> {
+        self.concurrent_pages_from(in_flight, 0)
+    }
+
+    /// `concurrent_pages` resuming after `fetched` items. A truncated page drops the pages
+    /// requested after it and resumes with the narrowed page size.
+    fn concurrent_pages_from<T: DeserializeOwned + Send + 'a>(
+        self,
+        in_flight: usize,
+        mut fetched: u64,
+    ) -> BoxStream<'a, Result<Page<T>, ApiClientError>> {
+        let first_page = match &self.mode {
+            Mode::Pages { first_page, .. } => *first_page + fetched / self.page_size,
+            Mode::Cursor { .. } => unreachable!("concurrent pages need page-number mode"),
+        };
+        let paginator = Arc::new(self);
+        let fetches = stream::iter(first_page..).map({
+            let paginator = paginator.clone();
+            move |number| {
+                let paginator = paginator.clone();
+                async move { paginator.fetch::<T>(&Position::Page(number)).await }
+            }
+        });
+        let resume = Arc::new(std::sync::Mutex::new(None));
+        let pages = fetches.buffered(in_flight).scan(false, {
+            let paginator = paginator.clone();
+            let resume = resume.clone();
+            move |done, result| {
+                if *done {
+                    return future::ready(None);
+                }
+                let result = result.inspect(|page| {
+                    fetched += page.items.len() as u64;
+                    *done = paginator.is_last_page(page, fetched);
+                    if !*done && page.truncated {
+                        *done = true;
+                        *resume.lock().unwrap() = Some((fetched, page.items.len() as u64));
+                    }
+                });
+                *done |= result.is_err();
+                future::ready(Some(result))
+            }
+        });
+        let rest = stream::once(async move {
+            match resume.lock().unwrap().take() {
+                Some((fetched, returned)) => {
+                    let mut narrowed = (*paginator).clone();
+                    narrowed.page_size = narrowed_page_size(fetched, returned);
+                    narrowed.concurrent_pages_from(in_flight, fetched)
+                }
+                None => stream::empty().boxed(),
+            }
+        })
+        .flatten();
+        pages.chain(rest).boxed()
+    }
+
+    async fn fetch<T: DeserializeOwned>(&self, position: &Position) -> Result<Page<T>, ApiClientError> {
+        let page_size = self.page_size.to_string();
+        let mut params: Vec<(&str, String)> = Vec::new();
+        match (&self.mode, position) {
+            (
+                Mode::Pages {
+                    page_param, size_param, ..
+                },
+                Position::Page(page),
+            ) => {
+                params.push((page_param, page.to_string()));
+                params.push((size_param, page_size));
+            }
+            (Mode::Cursor { cursor_param, .. }, Position::Cursor(cursor)) => {
+                if let Some(cursor) = cursor {
+                    params.push((cursor_param, cursor.clone()));
+                }
+                params.push(("page_size", page_size));
+            }
+            _ => unreachable!("position always matches the mode"),
+        }
+
+        let mut request = self.client.request(self.method.clone(), &self.uri).query(&params);
+        if let Some(body) = &self.body {
+            request = request.body(body.clone());
+        }
+        let response = request.fail_on_truncated(false).send_detailed().await?;
+        let mut page = self.parse_page(&response.body)?;
+        page.truncated = response.meta.truncated;
+        page.page_size = self.page_size;
+        page.elapsed = response.meta.elapsed;
+        Ok(page)
+    }
+
+    fn parse_page<T: DeserializeOwned>(&self, plaintext: &str) -> Result<Page<T>, ApiClientError> {
+        let mut value: Value = serde_json::from_str(plaintext)?;
+        let next_cursor = match &self.mode {
+            Mode::Cursor { next_cursor_path, .. } => lookup(&value, next_cursor_path).and_then(cursor_string),
+            Mode::Pages { .. } => None,
+        };
+        let list = payload::take_list(&mut value)?;
+        Ok(Page {
+            field: list.field,
+            items: list.items,
+            total: list.total,
+            next_cursor,
+            truncated: false,
+            page_size: self.page_size,
+            elapsed: Duration::ZERO,
+        })
+    }
+}
+
+impl Paginator<'_> {
+    /// Page-number mode: whether `page`, after which `fetched` items have been seen, ends
+    /// the listing.
+    fn is_last_page<T>(&self, page: &Page<T>, fetched: u64) -> bool {
+        let short_page = (page.items.len() as u64) < self.page_size && !page.truncated;
+        let reached_total = page.total.is_some_and(|total| fetched >= total);
+        page.items.is_empty() || short_page || reached_total
+    }
+}
+
+/// Flattens a stream of pages into items while polling it ahead of the consumer, buffering
+/// up to `capacity` pages.
+struct Prefetch<'a, T> {
+    pages: BoxStream<'a, Result<Vec<T>, ApiClientError>>,
+    buffer: VecDeque<Result<Vec<T>, ApiClientError>>,
+    current: std::vec::IntoIter<T>,
+    capacity: usize,
+    exhausted: bool,
+}
+
+impl<'a, T> Prefetch<'a, T> {
+    fn new(pages: BoxStream<'a, Result<Vec<T>, ApiClientError>>, capacity: usize) -> Self {
+        Self {
+            pages,
+            buffer: VecDeque::new(),
+            current: Vec::new().into_iter(),
+            capacity,
+            exhausted: false,
+        }
+    }
+}
+
+// Items are only ever moved out, never pinned in place.
+impl<T> Unpin for Prefetch<'_, T> {}
+
+impl<T> Stream for Prefetch<'_, T> {
+    type Item = Result<T, ApiClientError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        while !this.exhausted && this.buffer.len() < this.capacity {
+            match this.pages.poll_next_unpin(cx) {
+                Poll::Ready(Some(page)) => this.buffer.push_back(page),
+                Poll::Ready(None) => this.exhausted = true,
+                Poll::Pending => break,
+            }
+        }
+        loop {
+            if let Some(item) = this.current.next() {
+                return Poll::Ready(Some(Ok(item)));
+            }
+            match this.buffer.pop_front() {
+                Some(Ok(items)) => this.current = items.into_iter(),
+                Some(Err(e)) => {
+                    this.buffer.clear();
+                    this.exhausted = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+                None if this.exhausted => return Poll::Ready(None),
+                None => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<'a> State<'a> {
+    /// The page at the current position. With adaptive sizing a timeout shrinks the page
+    /// size and requests the page again.
+    async fn fetch<T: DeserializeOwned>(&mut self) -> Result<Page<T>, ApiClientError> {
+        loop {
+            let error = match self.paginator.fetch::<T>(&self.position).await {
+                Err(error @ (ApiClientError::AttemptTimeout { .. } | ApiClientError::OverallTimeout { .. })) => error,
+                result => return result,
+            };
+            let target = match &self.paginator.adaptive {
+                Some(tuning) => tuning.decreased(self.paginator.page_size),
+                None => return Err(error),
+            };
+            if !self.resize(target, None) {
+                return Err(error);
+            }
+        }
+    }
+
+    /// Moves the page size towards `target`, as far as the current position allows, after a
+    /// page that took `elapsed` or timed out. Whether it changed.
+    fn resize(&mut self, target: u64, elapsed: Option<Duration>) -> bool {
+        let Some(tuning) = &self.paginator.adaptive else {
+            return false;
+        };
+        let previous = self.paginator.page_size;
+        let page_size = match &mut self.position {
+            Position::Page(number) => {
+                let page_size = narrowed_page_size(self.fetched, target);
+                if page_size < tuning.min_page_size {
+                    return false;
+                }
+                if let Mode::Pages { first_page, .. } = &self.paginator.mode {
+                    *number = first_page + self.fetched / page_size;
+                }
+                page_size
+            }
+            Position::Cursor(_) => target,
+        };
+        if page_size == previous {
+            return false;
+        }
+        self.paginator.page_size = page_size;
+        tracing::debug!(
+            uri = self.paginator.uri.as_str(),
+            page_size,
+            previous,
+            elapsed_ms = elapsed.map(|elapsed| elapsed.as_millis() as u64),
+            timed_out = elapsed.is_none(),
+            target_ms = tuning.target_latency.as_millis() as u64,
+            "adjusted the page size"
+        );
+        true
+    }
+
+    fn advance<T>(&mut self, page: &Page<T>) -> Result<(), ApiClientError> {
+        self.fetched += page.items.len() as u64;
+        match &mut self.position {
+            Position::Page(number) => {
+                self.done = self.paginator.is_last_page(page, self.fetched);
+                if page.truncated && !self.done {
+                    self.paginator.page_size = narrowed_page_size(self.fetched, page.items.len() as u64);
+                    if let Mode::Pages { first_page, .. } = &self.paginator.mode {
+                        *number = first_page + self.fetched / self.paginator.page_size;
+                    }
+                } else {
+                    *number += 1;
+                }
+            }
+            Position::Cursor(current) => {
+                let max_repeats = match &self.paginator.mode {
+                    Mode::Cursor { max_repeats, .. } => *max_repeats,
+                    Mode::Pages { .. } => unreachable!("position always matches the mode"),
+                };
+                if page.truncated && !page.items.is_empty() {
+                    self.paginator.page_size = page.items.len() as u64;
+                }
+                match &page.next_cursor {
+                    None => self.done = true,
+                    Some(next) if current.as_ref() == Some(next) => {
+                        self.repeats += 1;
+                        if self.repeats >= max_repeats {
+                            return Err(ApiClientError::PaginationLoop {
+                                cursor: next.clone(),
+                                repeats: self.repeats,
+                            });
+                        }
+                    }
+                    Some(next) => {
+                        self.repeats = 0;
+                        *current = Some(next.clone());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The largest page size up to `returned` whose page boundaries fall on `offset`, so the
+/// next page starts right after the items already fetched.
+fn narrowed_page_size(offset: u64, returned: u64) -> u64 {
+    (1..=returned.max(1))
+        .rev()
+        .find(|size| offset.is_multiple_of(*size))
+        .unwrap_or(1)
+}
+
+fn lookup<'v>(value: &'v Value, path: &str) -> Option<&'v Value> {
+    path.split('.')
+        .filter(|segment| !segment.is_empty())
+        .try_fold(value, |value, segment| value.get(segment))
+}
+
+fn cursor_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) if !s.is_empty() => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::StatusCode;
+    use serde_json::json;
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::testing::{mock_client, Fixture, MockResponse, MockTransport};
+
+    fn page(list: Value, extra: Value) -> MockResponse {
+        let mut body = json!({ "list": list });
+        if let Value::Object(extra) = extra {
+            body.as_object_mut().unwrap().extend(extra);
+        }
+        MockResponse::encrypted(body.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_page_numbers_until_total() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::GET, "/v1/list", page(json!([1, 2]), json!({ "total": 5 })))
+            .on(Method::GET, "/v1/list", page(json!([3, 4]), json!({ "total": 5 })))
+            .on(Method::GET, "/v1/list", page(json!([5]), json!({ "total": 5 })));
+        let client = mock_client(&transport, |_| {});
+
+        let items: Vec<u64> = client
+            .paginate(Method::GET, "/v1/list?region=cn")
+            .page_size(2)
+            .items::<u64>()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(items, vec![1, 2, 3, 4, 5]);
+
+        let urls: Vec<String> = transport.requests().into_iter().map(|r| r.url).collect();
+        assert_eq!(urls.len(), 3);
+        assert!(urls[0].ends_with("/v1/list?region=cn&page=1&page_size=2"));
+        assert!(urls[2].ends_with("/v1/list?region=cn&page=3&page_size=2"));
+    }
+
+    #[tokio::test]
+    async fn test_cursor_until_absent() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::GET,
+                "/v1/feed",
+                page(json!(["a", "b"]), json!({ "next_cursor": "c1" })),
+            )
+            .on(
+                Method::GET,
+                "/v1/feed",
+                page(json!(["c"]), json!({ "next_cursor": "c2" })),
+            )
+            .on(
+                Method::GET,
+                "/v1/feed",
+                page(json!(["d"]), json!({ "next_cursor": null })),
+            );
+        let client = mock_client(&transport, |_| {});
+
+        let items: Vec<String> = client
+            .paginate(Method::GET, "/v1/feed")
+            .cursor("cursor", "next_cursor")
+            .items::<String>()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(items, vec!["a", "b", "c", "d"]);
+
+        let urls: Vec<String> = transport.requests().into_iter().map(|r| r.url).collect();
+        assert!(urls[0].ends_with("/v1/feed?page_size=20"));
+        assert!(urls[1].ends_with("/v1/feed?cursor=c1&page_size=20"));
+        assert!(urls[2].ends_with("/v1/feed?cursor=c2&page_size=20"));
+    }
+
+    #[tokio::test]
+    async fn test_item_limit_stops_fetching() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::GET,
+                "/v1/feed",
+                page(json!([1, 2]), json!({ "meta": { "next": "x" } })),
+            )
+            .on(
+                Method::GET,
+                "/v1/feed",
+                page(json!([3, 4]), json!({ "meta": { "next": "y" } })),
+            )
+            .on(
+                Method::GET,
+                "/v1/feed",
+                page(json!([5, 6]), json!({ "meta": { "next": "z" } })),
+            );
+        let client = mock_client(&transport, |_| {});
+
+        let items: Vec<u64> = client
+            .paginate(Method::GET, "/v1/feed")
+            .cursor("cursor", "meta.next")
+            .limit(3)
+            .items::<u64>()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(items, vec![1, 2, 3]);
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_cursor_aborts() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/feed",
+            page(json!([1]), json!({ "next_cursor": "same" })),
+        );
+        let client = mock_client(&transport, |_| {});
+
+        let result: Result<Vec<u64>, _> = client
+            .paginate(Method::GET, "/v1/feed")
+            .cursor("cursor", "next_cursor")
+            .max_cursor_repeats(3)
+            .items::<u64>()
+            .try_collect()
+            .await;
+        match result {
+            Err(ApiClientError::PaginationLoop { cursor, repeats }) => {
+                assert_eq!(cursor, "same");
+                assert_eq!(repeats, 3);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(transport.requests().len(), 4);
+    }
+
+    fn numbered_pages(transport: &MockTransport, pages: &[Value], delay: Duration) {
+        for (i, list) in pages.iter().enumerate() {
+            let path = format!("/v1/list?page={}&page_size=2", i + 1);
+            transport.on(Method::GET, &path, page(list.clone(), json!({})).with_delay(delay));
+        }
+    }
+
+    /// Collects the stream with a pause after every item, reducing errors to their status.
+    async fn consume_slowly(
+        stream: impl Stream<Item = Result<u64, ApiClientError>>,
+    ) -> Vec<Result<u64, Option<StatusCode>>> {
+        let mut stream = std::pin::pin!(stream);
+        let mut seen = Vec::new();
+        while let Some(item) = stream.next().await {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            seen.push(item.map_err(|e| match e {
+                ApiClientError::HttpStatus { status, .. } => Some(status),
+                _ => None,
+            }));
+        }
+        seen
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_prefetch_overlaps_fetching_with_consuming() {
+        let pages = [json!([1, 2]), json!([3, 4]), json!([5, 6]), json!([7])];
+        let delay = Duration::from_millis(200);
+
+        let transport = MockTransport::new();
+        numbered_pages(&transport, &pages, delay);
+        let client = mock_client(&transport, |_| {});
+        let started = Instant::now();
+        let plain = consume_slowly(client.paginate(Method::GET, "/v1/list").page_size(2).items::<u64>()).await;
+        let plain_elapsed = started.elapsed();
+
+        let transport = MockTransport::new();
+        numbered_pages(&transport, &pages, delay);
+        let client = mock_client(&transport, |_| {});
+        let started = Instant::now();
+        let prefetched = consume_slowly(
+            client
+                .paginate(Method::GET, "/v1/list")
+                .page_size(2)
+                .prefetch(2)
+                .items::<u64>(),
+        )
+        .await;
+        let prefetched_elapsed = started.elapsed();
+
+        let expected: Vec<Result<u64, Option<StatusCode>>> = (1..=7).map(Ok).collect();
+        assert_eq!(plain, expected);
+        assert_eq!(prefetched, expected);
+        assert_eq!(plain_elapsed, Duration::from_millis(4 * 200 + 7 * 100));
+        assert!(
+            prefetched_elapsed < plain_elapsed,
+            "{:?} vs {:?}",
+            prefetched_elapsed,
+            plain_elapsed
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_prefetched_error_arrives_in_order() {
+        let transport = MockTransport::new();
+        numbered_pages(&transport, &[json!([1, 2]), json!([3, 4])], Duration::from_millis(50));
+        transport
+            .on(
+                Method::GET,
+                "/v1/list?page=3&page_size=2",
+                MockResponse::raw(StatusCode::BAD_GATEWAY, "upstream down"),
+            )
+            .on(
+                Method::GET,
+                "/v1/list?page=4&page_size=2",
+                page(json!([7, 8]), json!({})),
+            );
+        let client = mock_client(&transport, |_| {});
+
+        let seen = consume_slowly(
+            client
+                .paginate(Method::GET, "/v1/list")
+                .page_size(2)
+                .prefetch(3)
+                .items::<u64>(),
+        )
+        .await;
+        assert_eq!(
+            seen,
+            vec![Ok(1), Ok(2), Ok(3), Ok(4), Err(Some(StatusCode::BAD_GATEWAY))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_in_cursor_mode() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::GET,
+                "/v1/feed",
+                page(json!([1, 2]), json!({ "next_cursor": "c1" })),
+            )
+            .on(
+                Method::GET,
+                "/v1/feed",
+                page(json!([3]), json!({ "next_cursor": null })),
+            );
+        let client = mock_client(&transport, |_| {});
+
+        let items: Vec<u64> = client
+            .paginate(Method::GET, "/v1/feed")
+            .cursor("cursor", "next_cursor")
+            .prefetch(4)
+            .items::<u64>()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(items, vec![1, 2, 3]);
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    fn truncated_page(list: Value) -> MockResponse {
+        MockResponse::raw(
+            StatusCode::PARTIAL_CONTENT,
+            crate::testing::encrypted_envelope(&json!({ "list": list }).to_string()),
+        )
+    }
+
+    #[test]
+    fn test_narrowed_page_size_lands_on_the_offset() {
+        assert_eq!(narrowed_page_size(3, 3), 3);
+        assert_eq!(narrowed_page_size(6, 4), 3);
+        assert_eq!(narrowed_page_size(12, 8), 6);
+        assert_eq!(narrowed_page_size(7, 3), 1);
+    }
+
+    #[tokio::test]
+    async fn test_truncated_pages_narrow_the_page_size() {
+        for prefetch in [None, Some(3)] {
+            let transport = MockTransport::new();
+            transport
+                .on(
+                    Method::GET,
+                    "/v1/list?page=1&page_size=4",
+                    page(json!([1, 2, 3, 4]), json!({})),
+                )
+                .on(
+                    Method::GET,
+                    "/v1/list?page=2&page_size=4",
+                    truncated_page(json!([5, 6])),
+                )
+                .on(
+                    Method::GET,
+                    "/v1/list?page=3&page_size=4",
+                    page(json!([9, 10]), json!({})),
+                )
+                .on(
+                    Method::GET,
+                    "/v1/list?page=4&page_size=2",
+                    page(json!([7, 8]), json!({})),
+                )
+                .on(
+                    Method::GET,
+                    "/v1/list?page=5&page_size=2",
+                    page(json!([9, 10]), json!({})),
+                )
+                .on(Method::GET, "/v1/list?page=6&page_size=2", page(json!([11]), json!({})));
+            let client = mock_client(&transport, |config| config.fail_on_truncated = true);
+
+            let mut paginator = client.paginate(Method::GET, "/v1/list").page_size(4);
+            if let Some(pages) = prefetch {
+                paginator = paginator.prefetch(pages);
+            }
+            let items: Vec<u64> = paginator.items::<u64>().try_collect().await.unwrap();
+            assert_eq!(items, (1..=11).collect::<Vec<_>>(), "{:?}", prefetch);
+
+            let urls: Vec<String> = transport.requests().into_iter().map(|r| r.url).collect();
+            assert!(urls.iter().any(|url| url.ends_with("page=4&page_size=2")));
+            if prefetch.is_none() {
+                assert!(urls.last().unwrap().ends_with("page=6&page_size=2"), "{:?}", urls);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_truncated_cursor_page_narrows_the_next_request() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::GET,
+                "/v1/feed",
+                MockResponse::encrypted(json!({ "list": [1], "next_cursor": "c1" }).to_string())
+                    .with_header("ho-truncated", "true"),
+            )
+            .on(
+                Method::GET,
+                "/v1/feed",
+                page(json!([2]), json!({ "next_cursor": null })),
+            );
+        let client = mock_client(&transport, |_| {});
+
+        let items: Vec<u64> = client
+            .paginate(Method::GET, "/v1/feed")
+            .cursor("cursor", "next_cursor")
+            .page_size(5)
+            .items::<u64>()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(items, vec![1, 2]);
+        assert!(transport.requests()[1].url.ends_with("/v1/feed?cursor=c1&page_size=1"));
+    }
+
+    /// A cursor-paged feed of `0..total` that takes 10ms plus 2ms per item a page.
+    struct SlowFeed {
+        total: u64,
+        sizes: std::sync::Mutex<Vec<u64>>,
+    }
+
+    impl crate::transport::Transport for SlowFeed {
+        fn execute(
+            &self,
+            request: crate::transport::TransportRequest,
+        ) -> futures_util::future::BoxFuture<'_, Result<crate::transport::TransportResponse, ApiClientError>> {
+            Box::pin(async move {
+                let url = reqwest::Url::parse(&request.url).unwrap();
+                let param = |name: &str| {
+                    url.query_pairs()
+                        .find(|(key, _)| key == name)
+                        .map(|(_, value)| value.parse::<u64>().unwrap())
+                };
+                let (start, page_size) = (param("cursor").unwrap_or(0), param("page_size").unwrap());
+                self.sizes.lock().unwrap().push(page_size);
+                tokio::time::sleep(Duration::from_millis(10 + 2 * page_size)).await;
+                let end = (start + page_size).min(self.total);
+                let next = (end < self.total).then_some(end);
+                let body = json!({ "list": (start..end).collect::<Vec<_>>(), "next_cursor": next });
+                Ok(crate::transport::TransportResponse {
+                    status: StatusCode::OK,
+                    headers: reqwest::header::HeaderMap::new(),
+                    body: crate::testing::encrypted_envelope(&body.to_string()).into(),
+                    timings: Default::default(),
+                })
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_adaptive_page_size_settles_near_the_budget() {
+        let feed = Arc::new(SlowFeed {
+            total: 3000,
+            sizes: Default::default(),
+        });
+        let mut config = crate::testing::test_config();
+        config.attempt_timeout = Some(Duration::from_millis(250));
+        let client = ApiClient::with_transport(config, feed.clone()).unwrap();
+        let tuning = AdaptivePageSize::new(Duration::from_millis(200)).bounds(5, 200);
+
+        let pages: Vec<FetchedPage<u64>> = client
+            .paginate(Method::GET, "/v1/skins")
+            .cursor("cursor", "next_cursor")
+            .page_size(20)
+            .adaptive(tuning.clone())
+            .page_stream()
+            .try_collect()
+            .await
+            .unwrap();
+        let items: Vec<u64> = pages.iter().flat_map(|page| page.items.clone()).collect();
+        assert_eq!(items, (0..3000).collect::<Vec<_>>());
+        let sizes: Vec<u64> = pages.iter().map(|page| page.page_size).collect();
+        assert_eq!(&sizes[..4], [20, 30, 45, 68]);
+        // 95 items take exactly the budget; growing by half from here would overshoot it.
+        for page in &pages[6..pages.len() - 1] {
+            assert!((60..=95).contains(&page.page_size), "{:?}", sizes);
+            assert!(page.elapsed <= Duration::from_millis(200), "{:?}", page.elapsed);
+        }
+
+        // Starting far too large, each timeout halves the page before it is asked for again.
+        feed.sizes.lock().unwrap().clear();
+        let first: Vec<FetchedPage<u64>> = client
+            .paginate(Method::GET, "/v1/skins")
+            .cursor("cursor", "next_cursor")
+            .page_size(200)
+            .adaptive(tuning)
+            .page_stream()
+            .take(1)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(*feed.sizes.lock().unwrap(), [200, 100]);
+        assert_eq!((first[0].page_size, first[0].items.len()), (100, 100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_adaptive_page_numbers_stay_on_the_offset() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::GET,
+                "/v1/list?page=1&page_size=10",
+                page(json!((0..10).collect::<Vec<_>>()), json!({})).with_delay(Duration::from_millis(10)),
+            )
+            .on(
+                Method::GET,
+                "/v1/list?page=2&page_size=10",
+                page(json!((10..20).collect::<Vec<_>>()), json!({})).with_delay(Duration::from_millis(10)),
+            )
+            .on(
+                Method::GET,
+                "/v1/list?page=2&page_size=20",
+                page(json!((20..25).collect::<Vec<_>>()), json!({})),
+            );
+        let client = mock_client(&transport, |_| {});
+        let tuning = AdaptivePageSize {
+            increase: 2.0,
+            ..AdaptivePageSize::new(Duration::from_millis(100))
+        }
+        .bounds(1, 40);
+
+        let items: Vec<u64> = client
+            .paginate(Method::GET, "/v1/list")
+            .page_size(10)
+            .adaptive(tuning)
+            .prefetch(2)
+            .items::<u64>()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(items, (0..25).collect::<Vec<_>>());
+        let urls: Vec<String> = transport.requests().into_iter().map(|r| r.url).collect();
+        // After 10 items a page of 20 cannot start on the offset; after 20 it can.
+        assert!(urls[1].ends_with("page=2&page_size=10"), "{:?}", urls);
+        assert!(urls[2].ends_with("page=2&page_size=20"), "{:?}", urls);
+    }
+
+    #[tokio::test]
+    async fn test_batch_pages_keep_failed_entries() {
+        let fixture = |name: &str| Fixture::load(format!("{}/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap();
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::POST,
+                "/v1/lol/champion/batch?page=1&page_size=4",
+                fixture("champion_batch_mixed.json").response(),
+            )
+            .on(
+                Method::POST,
+                "/v1/lol/champion/batch?page=2&page_size=4",
+                fixture("champion_batch_failed.json").response(),
+            );
+        let client = mock_client(&transport, |_| {});
+
+        for prefetch in [None, Some(2)] {
+            let mut paginator = client
+                .paginate(Method::POST, "/v1/lol/champion/batch")
+                .body(json!({ "ids": [1, 266, 22, 86, 266, 999] }))
+                .page_size(4);
+            if let Some(pages) = prefetch {
+                paginator = paginator.prefetch(pages);
+            }
+            let batches: Vec<BatchResult<Value>> = paginator.batches().try_collect().await.unwrap();
+            assert_eq!(batches.len(), 2, "{:?}", prefetch);
+            assert_eq!(batches[0].succeeded[0]["name"], "安妮");
+            assert_eq!(batches[0].failed.len(), 2);
+            assert!(batches[1].succeeded.is_empty() && batches[1].failed.len() == 2);
+        }
+    }
+}
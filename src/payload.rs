@@ -0,0 +1,313 @@
+//! The structure most endpoints share inside the decrypted plaintext: `{"list": [...],
+//! "total": n}` for collections (`records` on older endpoints) and `{"item": {...}}` for
+//! single objects. Batch endpoints use the list shape with failed entries mixed in, such as
+//! `{"id": 266, "error": "not_found"}`.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::ApiClientError;
+
+const LIST_FIELDS: [&str; 2] = ["list", "records"];
+
+/// The items of a list payload and its `total`, which defaults to the number of items when
+/// the server leaves it out.
+pub fn parse_list<T: DeserializeOwned>(plaintext: &str) -> Result<(Vec<T>, u64), ApiClientError> {
+    let mut value: Value = serde_json::from_str(plaintext)?;
+    let list = take_list(&mut value)?;
+    let total = list.total.unwrap_or(list.items.len() as u64);
+    Ok((list.items, total))
+}
+
+/// The entries of a batch payload, split into successes and per-entry failures.
+pub fn parse_batch<T: DeserializeOwned>(plaintext: &str) -> Result<BatchResult<T>, ApiClientError> {
+    let mut value: Value = serde_json::from_str(plaintext)?;
+    let list = take_list::<Value>(&mut value)?;
+    BatchResult::from_entries(list.field, list.items)
+}
+
+/// A batch entry the server could not produce.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchItemError {
+    /// The entry's `id` as text, if it has one.
+    pub id: Option<String>,
+    /// Position of the entry in the request.
+    pub index: usize,
+    /// The `error` field, or its `code` when it is an object.
+    pub code: String,
+    pub message: Option<String>,
+}
+
+/// A batch payload split into the entries that succeeded and those that failed, each in
+/// request order: by the entries' `index` field where the server sends one, in response
+/// order otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchResult<T> {
+    pub succeeded: Vec<T>,
+    pub failed: Vec<BatchItemError>,
+}
+
+impl<T> BatchResult<T> {
+    /// Whether no entry failed.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// `BatchFailed` when every entry failed; otherwise the batch as it is, partial or not.
+    pub fn into_result(self) -> Result<Self, ApiClientError> {
+        match self.succeeded.is_empty() && !self.failed.is_empty() {
+            true => Err(ApiClientError::BatchFailed { failed: self.failed }),
+            false => Ok(self),
+        }
+    }
+}
+
+impl<T: DeserializeOwned> BatchResult<T> {
+    /// Splits the entries of the list under `field`. An entry fails when it has a non-null
+    /// `error`; a successful entry that does not match `T` fails the whole batch, as it would
+    /// in `parse_list`.
+    pub(crate) fn from_entries(field: &str, entries: Vec<Value>) -> Result<Self, ApiClientError> {
+        let mut entries: Vec<(usize, usize, Value)> = entries
+            .into_iter()
+            .enumerate()
+            .map(|(position, entry)| {
+                let index = entry.get("index").and_then(Value::as_u64);
+                (index.map_or(position, |index| index as usize), position, entry)
+            })
+            .collect();
+        entries.sort_by_key(|(index, _, _)| *index);
+
+        let mut batch = BatchResult {
+            succeeded: Vec::new(),
+            failed: Vec::new(),
+        };
+        for (index, position, entry) in entries {
+            match entry.get("error").filter(|error| !error.is_null()) {
+                Some(error) => batch.failed.push(item_error(index, &entry, error)),
+                None => batch
+                    .succeeded
+                    .push(deserialize_at(&format!("{}[{}]", field, position), entry)?),
+            }
+        }
+        Ok(batch)
+    }
+}
+
+fn item_error(index: usize, entry: &Value, error: &Value) -> BatchItemError {
+    let text = |value: Option<&Value>| match value? {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => None,
+        other => Some(other.to_string()),
+    };
+    let (code, message) = match error {
+        Value::Object(_) => (
+            text(error.get("code")),
+            text(error.get("message")).or_else(|| text(error.get("msg"))),
+        ),
+        _ => (text(Some(error)), None),
+    };
+    BatchItemError {
+        id: text(entry.get("id")),
+        index,
+        code: code.unwrap_or_default(),
+        message: message
+            .or_else(|| text(entry.get("message")))
+            .or_else(|| text(entry.get("msg"))),
+    }
+}
+
+/// The object under `item`.
+pub fn parse_item<T: DeserializeOwned>(plaintext: &str) -> Result<T, ApiClientError> {
+    let mut value: Value = serde_json::from_str(plaintext)?;
+    match value.get_mut("item").map(Value::take) {
+        Some(item) => deserialize_at("item", item),
+        None => Err(ApiClientError::InvalidEnvelope(
+            "payload has no `item` field".to_string(),
+        )),
+    }
+}
+
+pub(crate) struct List<T> {
+    /// Which of `list` and `records` held the items.
+    pub(crate) field: &'static str,
+    pub(crate) items: Vec<T>,
+    pub(crate) total: Option<u64>,
+}
+
+/// Takes the list out of `value`, leaving the rest of it (cursors, metadata) in place.
+pub(crate) fn take_list<T: DeserializeOwned>(value: &mut Value) -> Result<List<T>, ApiClientError> {
+    let total = value.get("total").and_then(Value::as_u64);
+    for field in LIST_FIELDS {
+        if let Some(list) = value.get_mut(field).map(Value::take) {
+            return Ok(List {
+                field,
+                items: deserialize_at(field, list)?,
+                total,
+            });
+        }
+    }
+    Err(ApiClientError::InvalidEnvelope(
+        "payload has no `list` or `records` field".to_string(),
+    ))
+}
+
+/// Deserializes `value`, reporting failures with their path from the payload root.
+fn deserialize_at<T: DeserializeOwned>(field: &str, value: Value) -> Result<T, ApiClientError> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let inner = e.path().to_string();
+        let path = match inner.as_str() {
+            "." => field.to_string(),
+            path if path.starts_with('[') => format!("{}{}", field, path),
+            path => format!("{}.{}", field, path),
+        };
+        ApiClientError::InvalidPayload {
+            path,
+            message: e.into_inner().to_string(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Skin {
+        id: u64,
+        name: String,
+    }
+
+    const LIST: &str = r#"{"list":[{"id":1,"name":"安妮"},{"id":2,"name":"提莫"}],"total":40}"#;
+    const RECORDS: &str = r#"{"records":[{"id":3,"name":"盖伦"}]}"#;
+    const ITEM: &str = r#"{"item":{"id":1,"name":"安妮"}}"#;
+
+    fn fixture(name: &str) -> String {
+        std::fs::read_to_string(format!("{}/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    fn skin(id: u64, name: &str) -> Skin {
+        Skin {
+            id,
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_list_with_total() {
+        let (skins, total) = parse_list::<Skin>(LIST).unwrap();
+        assert_eq!(skins.len(), 2);
+        assert_eq!(skins[1].name, "提莫");
+        assert_eq!(total, 40);
+    }
+
+    #[test]
+    fn test_legacy_records_without_total() {
+        let (skins, total) = parse_list::<Skin>(RECORDS).unwrap();
+        assert_eq!(
+            skins,
+            vec![Skin {
+                id: 3,
+                name: "盖伦".to_string()
+            }]
+        );
+        assert_eq!(total, 1);
+    }
+
+    #[test]
+    fn test_item() {
+        let skin: Skin = parse_item(ITEM).unwrap();
+        assert_eq!(skin.id, 1);
+        assert!(matches!(
+            parse_item::<Skin>(LIST),
+            Err(ApiClientError::InvalidEnvelope(_))
+        ));
+    }
+
+    #[test]
+    fn test_malformed_items_report_their_path() {
+        let payload = r#"{"list":[{"id":1,"name":"安妮"},{"id":"two","name":"提莫"}]}"#;
+        match parse_list::<Skin>(payload) {
+            Err(ApiClientError::InvalidPayload { path, message }) => {
+                assert_eq!(path, "list[1].id");
+                assert!(message.contains("invalid type"), "{}", message);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match parse_item::<Skin>(r#"{"item":{"id":1}}"#) {
+            Err(ApiClientError::InvalidPayload { path, message }) => {
+                assert_eq!(path, "item");
+                assert!(message.contains("missing field `name`"), "{}", message);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match parse_list::<Skin>(r#"{"list":{"id":1}}"#) {
+            Err(ApiClientError::InvalidPayload { path, .. }) => assert_eq!(path, "list"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_batch_all_succeeded() {
+        let batch = parse_batch::<Skin>(&fixture("champion_batch_ok.json")).unwrap();
+        assert!(batch.is_complete());
+        assert_eq!(batch.succeeded.iter().map(|s| s.id).collect::<Vec<_>>(), [1, 17, 22]);
+        assert_eq!(batch.into_result().unwrap().succeeded.len(), 3);
+    }
+
+    #[test]
+    fn test_batch_mixed_keeps_request_order() {
+        let batch = parse_batch::<Skin>(&fixture("champion_batch_mixed.json")).unwrap();
+        assert_eq!(batch.succeeded, vec![skin(1, "安妮"), skin(22, "艾希")]);
+        assert_eq!(
+            batch.failed,
+            vec![
+                BatchItemError {
+                    id: Some("266".to_string()),
+                    index: 1,
+                    code: "not_found".to_string(),
+                    message: None,
+                },
+                BatchItemError {
+                    id: Some("86".to_string()),
+                    index: 3,
+                    code: "40301".to_string(),
+                    message: Some("region locked".to_string()),
+                },
+            ]
+        );
+        assert!(!batch.is_complete());
+        assert_eq!(batch.into_result().unwrap().succeeded.len(), 2);
+    }
+
+    #[test]
+    fn test_batch_all_failed() {
+        let batch = parse_batch::<Skin>(&fixture("champion_batch_failed.json")).unwrap();
+        assert!(batch.succeeded.is_empty());
+        assert_eq!(batch.failed[1].message.as_deref(), Some("no such champion"));
+        match batch.into_result() {
+            Err(ApiClientError::BatchFailed { failed }) => {
+                assert_eq!(failed.iter().map(|f| f.index).collect::<Vec<_>>(), [0, 1])
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let empty = parse_batch::<Skin>(r#"{"list":[]}"#).unwrap();
+        assert!(empty.into_result().is_ok());
+    }
+
+    #[test]
+    fn test_malformed_batch_success_fails_the_batch() {
+        match parse_batch::<Skin>(r#"{"records":[{"id":1,"error":null},{"id":2,"name":"提莫"}]}"#) {
+            Err(ApiClientError::InvalidPayload { path, message }) => {
+                assert_eq!(path, "records[0]");
+                assert!(message.contains("missing field `name`"), "{}", message);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
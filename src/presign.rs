@@ -0,0 +1,249 @@
+//! Requests signed ahead of time for a third party to send, such as a browser downloading
+//! one asset, without handing it the app secret.
+//!
+//! A presigned request is signed as described in `crate::signing`, except that the
+//! timestamp slot of the sign string holds the time the signature expires, in decimal
+//! milliseconds, and is sent as `HO-EXPIRES` instead of `HO-TIMESTAMP`; see
+//! `SignMode::Presigned`. The gateway accepts
+//! it until then, for at most `MAX_PRESIGN_EXPIRY` after it was signed. The request has no
+//! body, so nothing is added for it.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::Method;
+use serde::Serialize;
+
+use crate::deprecation::VERSION_HEADER;
+use crate::nonce;
+use crate::request::check_uri;
+use crate::signing::{self, SignInputs, SignMode, SignableBody};
+use crate::{ApiClient, ApiClientError};
+
+/// The longest `expires_in` the gateway accepts.
+pub const MAX_PRESIGN_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Everything needed to send one request: send `method` to `url` with `headers`, before
+/// `expires_at`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PresignedRequest {
+    #[serde(serialize_with = "method_name")]
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    #[serde(serialize_with = "rfc3339")]
+    pub expires_at: DateTime<Utc>,
+}
+
+fn method_name<S: serde::Serializer>(method: &Method, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(method.as_str())
+}
+
+fn rfc3339<S: serde::Serializer>(at: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+}
+
+impl ApiClient {
+    /// Signs a bodiless request to `uri` on the primary host, valid for `expires_in`, which
+    /// must be between one second and `MAX_PRESIGN_EXPIRY`.
+    pub fn presign(&self, method: Method, uri: &str, expires_in: Duration) -> Result<PresignedRequest, ApiClientError> {
+        self.presign_at(method, uri, expires_in, self.config.time.now_utc(), nonce::generate())
+    }
+
+    /// `presign` as of `now`, with `nonce`.
+    fn presign_at(
+        &self,
+        method: Method,
+        uri: &str,
+        expires_in: Duration,
+        now: DateTime<Utc>,
+        nonce: String,
+    ) -> Result<PresignedRequest, ApiClientError> {
+        if !(Duration::from_secs(1)..=MAX_PRESIGN_EXPIRY).contains(&expires_in) {
+            return Err(ApiClientError::RequestValidation {
+                field: "expires_in".to_string(),
+                constraint: format!("between 1s and {}s", MAX_PRESIGN_EXPIRY.as_secs()),
+                got: format!("{:?}", expires_in),
+            });
+        }
+        let uri = &*check_uri(uri)?;
+        if let Some(nonces) = &self.nonces {
+            nonces.issue(&nonce)?;
+        }
+        let config = &self.config;
+        let expires_at = now + chrono::Duration::milliseconds(expires_in.as_millis() as i64);
+        let expires = expires_at.timestamp_millis();
+        let inputs = SignInputs {
+            app_id: &config.app_id,
+            app_secret: &config.app_secret,
+            nonce: &nonce,
+            timestamp: expires,
+            content_path: &config.content,
+            uri,
+            body: SignableBody::Empty,
+        };
+        let signature = signing::signature(SignMode::for_presign(config.sign_content_path), &inputs)
+            .as_str()
+            .to_string();
+
+        let mut headers = vec![
+            ("HO-APP-ID".to_string(), config.app_id.clone()),
+            ("HO-NONCE".to_string(), nonce),
+            ("HO-EXPIRES".to_string(), expires.to_string()),
+            ("HO-SIGNATURE".to_string(), signature),
+        ];
+        if let Some(version) = &config.api_version {
+            headers.push((VERSION_HEADER.to_string(), version.clone()));
+        }
+        Ok(PresignedRequest {
+            method,
+            url: format!("{}{}{}", config.base_url, config.content, uri),
+            headers,
+            expires_at,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{test_config, MockTransport, TEST_APP_ID, TEST_APP_SECRET, TEST_BASE_URL, TEST_CONTENT};
+    use crate::ApiClientConfig;
+
+    const NONCE: &str = "3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a13";
+
+    fn client(configure: impl FnOnce(&mut ApiClientConfig)) -> ApiClient {
+        let mut config = test_config();
+        configure(&mut config);
+        ApiClient::with_transport(config, MockTransport::new()).unwrap()
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(1717171717171).unwrap()
+    }
+
+    #[test]
+    fn test_signature_covers_the_expiry() {
+        let client = client(|_| {});
+        let presigned = client
+            .presign_at(
+                Method::GET,
+                "/v1/asset/download?id=7",
+                Duration::from_secs(600),
+                now(),
+                NONCE.to_string(),
+            )
+            .unwrap();
+        let expires = 1717171717171 + 600_000;
+        assert_eq!(
+            presigned.url,
+            format!("{}{}/v1/asset/download?id=7", TEST_BASE_URL, TEST_CONTENT)
+        );
+        assert_eq!(presigned.expires_at.timestamp_millis(), expires);
+        // sha1("test-app-id" + nonce + "1717172317171" + uri + secret)
+        let expected = "cddcf7902d352195c89ca300f26dd711675507aa";
+        assert_eq!(
+            signing::sign(
+                TEST_APP_ID,
+                TEST_APP_SECRET,
+                NONCE,
+                expires,
+                "/v1/asset/download?id=7",
+                SignableBody::Empty
+            ),
+            expected
+        );
+        assert_eq!(
+            serde_json::to_value(&presigned).unwrap(),
+            json!({
+                "method": "GET",
+                "url": presigned.url,
+                "headers": [
+                    ["HO-APP-ID", TEST_APP_ID],
+                    ["HO-NONCE", NONCE],
+                    ["HO-EXPIRES", expires.to_string()],
+                    ["HO-SIGNATURE", expected],
+                ],
+                "expires_at": "2024-05-31T16:18:37.171Z",
+            })
+        );
+        assert!(!format!("{:?}", presigned).contains(TEST_APP_SECRET));
+    }
+
+    #[test]
+    fn test_content_path_and_version_follow_the_config() {
+        let client = client(|config| {
+            config.sign_content_path = true;
+            config.api_version = Some("2024-06-01".to_string());
+        });
+        let presigned = client
+            .presign_at(
+                Method::GET,
+                "/v1/asset",
+                Duration::from_secs(60),
+                now(),
+                NONCE.to_string(),
+            )
+            .unwrap();
+        let signed = format!("{}/v1/asset", TEST_CONTENT);
+        let expected = signing::sign(
+            TEST_APP_ID,
+            TEST_APP_SECRET,
+            NONCE,
+            1717171777171,
+            &signed,
+            SignableBody::Empty,
+        );
+        assert!(presigned.headers.contains(&("HO-SIGNATURE".to_string(), expected)));
+        assert_eq!(
+            presigned.headers.last(),
+            Some(&("HO-VERSION".to_string(), "2024-06-01".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_expiry_outside_the_window_is_refused() {
+        let client = client(|_| {});
+        for expires_in in [
+            Duration::ZERO,
+            Duration::from_millis(999),
+            MAX_PRESIGN_EXPIRY + Duration::from_secs(1),
+        ] {
+            match client.presign(Method::GET, "/v1/asset", expires_in) {
+                Err(ApiClientError::RequestValidation { field, .. }) => assert_eq!(field, "expires_in"),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert!(client.presign(Method::GET, "/v1/asset", MAX_PRESIGN_EXPIRY).is_ok());
+    }
+
+    #[test]
+    fn test_reused_nonce_is_refused_only_when_guarded() {
+        let presign = |client: &ApiClient| {
+            client.presign_at(
+                Method::GET,
+                "/v1/asset",
+                Duration::from_secs(60),
+                now(),
+                NONCE.to_string(),
+            )
+        };
+        let unguarded = client(|_| {});
+        assert!(presign(&unguarded).is_ok());
+        assert!(presign(&unguarded).is_ok());
+
+        let guarded = client(|config| config.nonce_guard = Some(8));
+        assert!(presign(&guarded).is_ok());
+        assert!(matches!(
+            presign(&guarded),
+            Err(ApiClientError::NonceReuse { nonce }) if nonce == NONCE
+        ));
+        // Generated nonces pass the same guard.
+        assert!(guarded
+            .presign(Method::GET, "/v1/asset", Duration::from_secs(60))
+            .is_ok());
+    }
+}
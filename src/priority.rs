@@ -0,0 +1,294 @@
+//! Request priorities and the limiter that admits by them. While every permit of
+//! `max_concurrent_requests` or of a pool is taken, each released permit goes to the oldest
+//! waiter of the highest class. A waiter counts one class higher for every
+//! `ApiClientConfig::priority_promotion` it has waited, so low-priority traffic is
+//! delayed but never starved.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+use crate::time::TimeSource;
+
+/// See `RequestBuilder::priority`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// User-facing lookups.
+    High,
+    #[default]
+    Normal,
+    /// Backfills and other bulk traffic.
+    Low,
+}
+
+impl Priority {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+
+    fn rank(self) -> usize {
+        self as usize
+    }
+}
+
+struct Waiter {
+    since: Instant,
+    wake: oneshot::Sender<PriorityPermit>,
+}
+
+struct State {
+    size: usize,
+    available: usize,
+    /// Permits in use beyond a lowered size, retired instead of released.
+    owed: usize,
+    /// One FIFO queue per priority, by rank.
+    queues: [VecDeque<Waiter>; 3],
+}
+
+pub(crate) struct PriorityLimiter {
+    promote_after: Option<Duration>,
+    time: Arc<dyn TimeSource>,
+    state: Mutex<State>,
+}
+
+/// Returns its permit to the limiter, or hands it to the next waiter, when dropped.
+pub(crate) struct PriorityPermit {
+    limiter: Option<Arc<PriorityLimiter>>,
+}
+
+impl Drop for PriorityPermit {
+    fn drop(&mut self) {
+        if let Some(limiter) = self.limiter.take() {
+            limiter.release();
+        }
+    }
+}
+
+impl PriorityLimiter {
+    pub(crate) fn new(size: usize, promote_after: Option<Duration>, time: Arc<dyn TimeSource>) -> Arc<Self> {
+        Arc::new(Self {
+            promote_after: promote_after.filter(|after| !after.is_zero()),
+            time,
+            state: Mutex::new(State {
+                size,
+                available: size,
+                owed: 0,
+                queues: Default::default(),
+            }),
+        })
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.state.lock().unwrap().size
+    }
+
+    pub(crate) fn available(&self) -> usize {
+        self.state.lock().unwrap().available
+    }
+
+    /// Waits for a permit. Dropping the future gives up the place in the queue.
+    pub(crate) async fn acquire(self: &Arc<Self>, priority: Priority) -> PriorityPermit {
+        let woken = {
+            let mut state = self.state.lock().unwrap();
+            // A free permit means nobody is queued: releases hand permits to waiters directly.
+            if state.available > 0 {
+                state.available -= 1;
+                return PriorityPermit {
+                    limiter: Some(self.clone()),
+                };
+            }
+            let (wake, woken) = oneshot::channel();
+            state.queues[priority.rank()].push_back(Waiter {
+                since: self.time.now(),
+                wake,
+            });
+            woken
+        };
+        woken.await.expect("queued waiters are always woken with a permit")
+    }
+
+    /// Changes the number of permits. Permits in use beyond a lowered size are retired as
+    /// they come back; added permits go to waiters first.
+    pub(crate) fn resize(self: &Arc<Self>, size: usize) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if size < state.size {
+                let cut = state.size - size;
+                let unused = cut.min(state.available);
+                state.available -= unused;
+                state.owed += cut - unused;
+            } else {
+                let added = size - state.size;
+                let repaid = added.min(state.owed);
+                state.owed -= repaid;
+                state.available += added - repaid;
+            }
+            state.size = size;
+        }
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.available == 0 || state.queues.iter().all(VecDeque::is_empty) {
+                    return;
+                }
+                state.available -= 1;
+            }
+            self.clone().release();
+        }
+    }
+
+    fn release(self: Arc<Self>) {
+        loop {
+            let Some(waiter) = self.next_waiter() else {
+                return;
+            };
+            let permit = PriorityPermit {
+                limiter: Some(self.clone()),
+            };
+            match waiter.wake.send(permit) {
+                Ok(()) => return,
+                // The waiter gave up in the meantime; the permit goes to the next one.
+                Err(mut permit) => drop(permit.limiter.take()),
+            }
+        }
+    }
+
+    /// Takes the waiter to hand a released permit to, or returns the permit to the pool
+    /// when nobody waits, or retires it when the pool was shrunk.
+    fn next_waiter(&self) -> Option<Waiter> {
+        let mut state = self.state.lock().unwrap();
+        if state.owed > 0 {
+            state.owed -= 1;
+            return None;
+        }
+        let now = self.time.now();
+        for queue in &mut state.queues {
+            queue.retain(|waiter| !waiter.wake.is_closed());
+        }
+        let effective_rank = |rank: usize, since: Instant| match self.promote_after {
+            Some(after) => rank.saturating_sub((now.duration_since(since).as_nanos() / after.as_nanos()) as usize),
+            None => rank,
+        };
+        let next = (0..state.queues.len())
+            .filter_map(|rank| {
+                let front = state.queues[rank].front()?;
+                Some((effective_rank(rank, front.since), front.since, rank))
+            })
+            .min();
+        match next {
+            Some((_, _, rank)) => state.queues[rank].pop_front(),
+            None => {
+                state.available += 1;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::time;
+
+    /// Queues `queued` in order behind a held permit and returns the order they are admitted
+    /// in once it is released.
+    async fn admission_order(limiter: &Arc<PriorityLimiter>, queued: &[(Priority, u64)]) -> Vec<u64> {
+        let held = limiter.acquire(Priority::Normal).await;
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut tasks = Vec::new();
+        for &(priority, id) in queued {
+            let limiter = limiter.clone();
+            let order = order.clone();
+            tasks.push(tokio::spawn(async move {
+                let _permit = limiter.acquire(priority).await;
+                order.lock().unwrap().push(id);
+            }));
+            tokio::task::yield_now().await;
+        }
+        drop(held);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let order = order.lock().unwrap().clone();
+        order
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_higher_classes_go_first_and_fifo_within_one() {
+        let limiter = PriorityLimiter::new(1, None, time::real());
+        let queued = [
+            (Priority::Low, 1),
+            (Priority::Normal, 2),
+            (Priority::Low, 3),
+            (Priority::High, 4),
+            (Priority::High, 5),
+        ];
+        assert_eq!(admission_order(&limiter, &queued).await, [4, 5, 2, 1, 3]);
+        assert_eq!(limiter.available(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_long_waiters_are_promoted() {
+        let limiter = PriorityLimiter::new(1, Some(Duration::from_secs(1)), time::real());
+        let held = limiter.acquire(Priority::Normal).await;
+        let low = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { drop(limiter.acquire(Priority::Low).await) }
+        });
+        tokio::task::yield_now().await;
+        tokio::time::advance(Duration::from_secs(2)).await;
+        // The Low waiter now counts as High and is older than the High newcomer.
+        let high = limiter.acquire(Priority::High);
+        tokio::pin!(high);
+        assert!(futures_util::poll!(&mut high).is_pending());
+        drop(held);
+        let promoted = tokio::time::timeout(Duration::from_secs(1), low).await;
+        promoted.expect("the promoted Low waiter goes first").unwrap();
+        drop(high.await);
+        assert_eq!(limiter.available(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_abandoned_waiters_do_not_keep_their_permit() {
+        let limiter = PriorityLimiter::new(1, None, time::real());
+        let held = limiter.acquire(Priority::Normal).await;
+        let abandoned = tokio::time::timeout(Duration::from_secs(1), limiter.acquire(Priority::High)).await;
+        assert!(abandoned.is_err());
+        drop(held);
+        assert_eq!(limiter.available(), 1);
+        drop(limiter.acquire(Priority::Low).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resizing_retires_permits_in_use_and_wakes_waiters() {
+        let limiter = PriorityLimiter::new(2, None, time::real());
+        let first = limiter.acquire(Priority::Normal).await;
+        let second = limiter.acquire(Priority::Normal).await;
+        limiter.resize(1);
+        drop(first);
+        assert_eq!(limiter.available(), 0);
+        let third = tokio::time::timeout(Duration::from_secs(1), limiter.acquire(Priority::High)).await;
+        assert!(third.is_err(), "the released permit was retired");
+
+        let waiter = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { drop(limiter.acquire(Priority::Low).await) }
+        });
+        tokio::task::yield_now().await;
+        limiter.resize(3);
+        waiter.await.unwrap();
+        drop(second);
+        assert_eq!((limiter.size(), limiter.available()), (3, 3));
+    }
+}
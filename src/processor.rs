@@ -0,0 +1,196 @@
+//! Rewriting decrypted responses before they are returned, for payloads that need fixing up
+//! on the way in: a byte order mark from an old gateway, a misspelled field. Processors are
+//! added with `ApiClient::add_response_processor` and run in the order they were added.
+//!
+//! ```no_run
+//! # fn configure(client: &mut ho_api_rust_sdk::ApiClient) {
+//! use std::collections::BTreeMap;
+//! use std::sync::Arc;
+//!
+//! use ho_api_rust_sdk::processor;
+//!
+//! client.add_response_processor(processor::strip_bom());
+//! client.add_response_processor(processor::rename_fields(BTreeMap::from([(
+//!     "champoin_id".to_string(),
+//!     "champion_id".to_string(),
+//! )])));
+//! client.add_response_processor(Arc::new(|_uri, plaintext| Ok(plaintext.replace("\"N/A\"", "null"))));
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::{ApiClient, ApiClientError};
+
+/// Receives the request uri and the decrypted plaintext, and returns the plaintext to go on
+/// with or a reason to fail the request with `ApiClientError::ResponseProcessing`.
+pub type ResponseProcessor = Arc<dyn Fn(&str, String) -> Result<String, String> + Send + Sync>;
+
+/// Removes a leading byte order mark (U+FEFF).
+pub fn strip_bom() -> ResponseProcessor {
+    Arc::new(|_, plaintext| match plaintext.strip_prefix('\u{feff}') {
+        Some(rest) => Ok(rest.to_string()),
+        None => Ok(plaintext),
+    })
+}
+
+/// Renames object keys at any depth, `from` to `to` for each entry of `renames`. Fails when
+/// the plaintext is not JSON or an object already has the new key. Plaintext without any of
+/// the old keys is passed on untouched.
+pub fn rename_fields(renames: BTreeMap<String, String>) -> ResponseProcessor {
+    Arc::new(move |_, plaintext| {
+        let mut value: Value = serde_json::from_str(&plaintext).map_err(|e| e.to_string())?;
+        match rename_in(&mut value, &renames)? {
+            true => serde_json::to_string(&value).map_err(|e| e.to_string()),
+            false => Ok(plaintext),
+        }
+    })
+}
+
+fn rename_in(value: &mut Value, renames: &BTreeMap<String, String>) -> Result<bool, String> {
+    let mut renamed = false;
+    match value {
+        Value::Object(object) => {
+            for (from, to) in renames {
+                if let Some(field) = object.remove(from) {
+                    if object.contains_key(to) {
+                        return Err(format!("cannot rename `{}`: `{}` is already present", from, to));
+                    }
+                    object.insert(to.clone(), field);
+                    renamed = true;
+                }
+            }
+            for field in object.values_mut() {
+                renamed |= rename_in(field, renames)?;
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                renamed |= rename_in(item, renames)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(renamed)
+}
+
+impl ApiClient {
+    /// Runs `processor` on every decrypted response after the ones already added, before
+    /// `send`, `send_as` and the other request methods return. Bodies returned as received
+    /// with `RequestBuilder::parse_envelope(false)` skip processors unless the request also
+    /// asks for `process_raw_body(true)`. Cached responses are stored processed; NDJSON
+    /// stream lines are not processed.
+    pub fn add_response_processor(&mut self, processor: ResponseProcessor) {
+        self.processors.push(processor);
+    }
+
+    pub(crate) fn process_response(&self, uri: &str, plaintext: String) -> Result<String, ApiClientError> {
+        self.processors
+            .iter()
+            .enumerate()
+            .try_fold(plaintext, |plaintext, (stage, processor)| {
+                processor(uri, plaintext).map_err(|reason| ApiClientError::ResponseProcessing {
+                    uri: uri.to_string(),
+                    stage,
+                    reason,
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use reqwest::Method;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport};
+
+    fn renames(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|&(from, to)| (from.to_string(), to.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_built_ins() {
+        assert_eq!(strip_bom()("/v1/x", "\u{feff}{}".to_string()).unwrap(), "{}");
+        assert_eq!(strip_bom()("/v1/x", "{}".to_string()).unwrap(), "{}");
+
+        let rename = rename_fields(renames(&[("champoin_id", "champion_id")]));
+        let renamed = rename("/v1/x", r#"{"list":[{"champoin_id":1},{"champion_id":2}]}"#.to_string()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&renamed).unwrap(),
+            json!({ "list": [{ "champion_id": 1 }, { "champion_id": 2 }] })
+        );
+        let untouched = r#"{"b": 1.50, "a": 2}"#;
+        assert_eq!(rename("/v1/x", untouched.to_string()).unwrap(), untouched);
+        assert_eq!(
+            rename("/v1/x", r#"{"champoin_id":1,"champion_id":1}"#.to_string()).unwrap_err(),
+            "cannot rename `champoin_id`: `champion_id` is already present"
+        );
+        assert!(rename("/v1/x", "not json".to_string()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_processors_run_in_order_and_errors_name_the_stage() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/skin",
+            MockResponse::encrypted("\u{feff}{\"name\":\"N/A\"}"),
+        );
+        let mut client = mock_client(&transport, |_| {});
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        client.add_response_processor(strip_bom());
+        client.add_response_processor(Arc::new(move |uri, plaintext| {
+            recorded.lock().unwrap().push((uri.to_string(), plaintext.clone()));
+            Ok(plaintext.replace("\"N/A\"", "null"))
+        }));
+
+        let body: Value = client.request(Method::GET, "/v1/skin?id=7").send_as().await.unwrap();
+        assert_eq!(body, json!({ "name": null }));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [("/v1/skin?id=7".to_string(), "{\"name\":\"N/A\"}".to_string())]
+        );
+
+        client.add_response_processor(Arc::new(|_, _| Err("no".to_string())));
+        client.add_response_processor(Arc::new(|_, _| panic!("runs after a failed stage")));
+        match client.send(Method::GET, "/v1/skin", None).await {
+            Err(ApiClientError::ResponseProcessing { uri, stage, reason }) => {
+                assert_eq!((uri.as_str(), stage, reason.as_str()), ("/v1/skin", 2, "no"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(client.stats().errors.serialization, 1);
+    }
+
+    #[tokio::test]
+    async fn test_raw_bodies_are_processed_only_on_request() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/public/status",
+            MockResponse::json(reqwest::StatusCode::OK, &json!({ "statsu": "ok" })),
+        );
+        let mut client = mock_client(&transport, |_| {});
+        client.add_response_processor(rename_fields(renames(&[("statsu", "status")])));
+
+        let raw = client.request(Method::GET, "/v1/public/status").parse_envelope(false);
+        assert_eq!(raw.send().await.unwrap(), r#"{"statsu":"ok"}"#);
+
+        let processed = client
+            .request(Method::GET, "/v1/public/status")
+            .parse_envelope(false)
+            .process_raw_body(true);
+        assert_eq!(processed.send().await.unwrap(), r#"{"status":"ok"}"#);
+    }
+}
@@ -0,0 +1,341 @@
+//! The daily request quota of the app_id, as the gateway reports it on every response in
+//! `HO-QUOTA-LIMIT`, `HO-QUOTA-REMAINING` and `HO-QUOTA-RESET` (Unix milliseconds), and the
+//! reserve that keeps its last requests for user-facing traffic.
+//!
+//! With `ApiClientConfig::quota_reserve` set, requests of the reserve's priorities (`Low` by
+//! default) are held back while the latest reported remaining quota is below the reserve:
+//! rejected with `ApiClientError::QuotaReserved`, or kept waiting until the reported reset
+//! time. Other priorities are sent as usual. A report older than `QuotaReserve::max_age`, or
+//! one whose reset time has passed, holds nothing back.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use reqwest::header::HeaderMap;
+use tokio::time::Instant;
+
+use crate::priority::Priority;
+use crate::stats::ErrorClass;
+use crate::{ApiClient, ApiClientError};
+
+pub(crate) const LIMIT_HEADER: &str = "HO-QUOTA-LIMIT";
+pub(crate) const REMAINING_HEADER: &str = "HO-QUOTA-REMAINING";
+pub(crate) const RESET_HEADER: &str = "HO-QUOTA-RESET";
+
+/// The quota headers of a response; see `ResponseMeta::quota`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaInfo {
+    pub limit: Option<u64>,
+    pub remaining: u64,
+    pub resets_at: Option<DateTime<Utc>>,
+}
+
+impl QuotaInfo {
+    /// The quota reported in `headers`; `None` without a readable `HO-QUOTA-REMAINING`.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let number = |name: &str| headers.get(name)?.to_str().ok()?.trim().parse::<u64>().ok();
+        Some(Self {
+            limit: number(LIMIT_HEADER),
+            remaining: number(REMAINING_HEADER)?,
+            resets_at: number(RESET_HEADER)
+                .and_then(|millis| Utc.timestamp_millis_opt(i64::try_from(millis).ok()?).single()),
+        })
+    }
+}
+
+/// What happens to a request held back by the reserve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReserveAction {
+    /// Fail it with `ApiClientError::QuotaReserved`.
+    Reject,
+    /// Wait until the reported reset time, then send it. Rejected when the gateway reported
+    /// no reset time, or one further away than `QuotaReserve::max_wait` or what is left of
+    /// `ApiClientConfig::overall_timeout`.
+    WaitForReset,
+}
+
+impl ReserveAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReserveAction::Reject => "reject",
+            ReserveAction::WaitForReset => "wait_for_reset",
+        }
+    }
+}
+
+/// See `ApiClientConfig::quota_reserve`.
+#[derive(Debug, Clone)]
+pub struct QuotaReserve {
+    /// Requests of `priorities` are held back while the remaining quota is below this.
+    pub reserve: u64,
+    pub priorities: Vec<Priority>,
+    pub action: ReserveAction,
+    /// How long a reported quota is acted on. Past this, requests go out until a response
+    /// reports it again.
+    pub max_age: Duration,
+    /// Longest wait for the reset under `WaitForReset`; 15 minutes by default, `None` waits
+    /// however far away the reset is. Requests deferred together wake together, then queue
+    /// for concurrency permits as usual.
+    pub max_wait: Option<Duration>,
+}
+
+impl QuotaReserve {
+    /// Rejects `Low` requests while fewer than `reserve` requests remain, going by reports
+    /// up to a minute old.
+    pub fn new(reserve: u64) -> Self {
+        Self {
+            reserve,
+            priorities: vec![Priority::Low],
+            action: ReserveAction::Reject,
+            max_age: Duration::from_secs(60),
+            max_wait: Some(Duration::from_secs(15 * 60)),
+        }
+    }
+}
+
+/// The latest quota reported, with when it arrived.
+#[derive(Default)]
+pub(crate) struct QuotaTracker {
+    latest: Mutex<Option<(QuotaInfo, Instant)>>,
+}
+
+impl QuotaTracker {
+    pub(crate) fn observe(&self, headers: &HeaderMap, now: Instant) -> Option<QuotaInfo> {
+        let info = QuotaInfo::from_headers(headers)?;
+        *self.latest.lock().unwrap() = Some((info, now));
+        Some(info)
+    }
+
+    fn latest(&self) -> Option<QuotaInfo> {
+        self.latest.lock().unwrap().map(|(info, _)| info)
+    }
+
+    /// The latest quota, unless it arrived more than `max_age` before `now`.
+    fn fresh(&self, max_age: Duration, now: Instant) -> Option<QuotaInfo> {
+        self.latest
+            .lock()
+            .unwrap()
+            .filter(|(_, at)| now.saturating_duration_since(*at) <= max_age)
+            .map(|(info, _)| info)
+    }
+}
+
+impl ApiClient {
+    /// The quota the gateway reported last, however long ago.
+    pub fn quota(&self) -> Option<QuotaInfo> {
+        self.quota.latest()
+    }
+
+    /// Holds a request of `priority`, begun at `requested`, back per
+    /// `ApiClientConfig::quota_reserve`: fails it, or returns once the quota has reset.
+    pub(crate) async fn admit_quota(
+        &self,
+        uri: &str,
+        priority: Priority,
+        requested: Instant,
+    ) -> Result<(), ApiClientError> {
+        let Some(reserve) = &self.config.quota_reserve else {
+            return Ok(());
+        };
+        if !reserve.priorities.contains(&priority) {
+            return Ok(());
+        }
+        let time = &*self.config.time;
+        let Some(info) = self.quota.fresh(reserve.max_age, time.now()) else {
+            return Ok(());
+        };
+        if info.remaining >= reserve.reserve {
+            return Ok(());
+        }
+        let until_reset = info
+            .resets_at
+            .map(|resets_at| (resets_at - time.now_utc()).to_std().unwrap_or(Duration::ZERO));
+        let overall_left = self
+            .config
+            .overall_timeout
+            .map(|timeout| timeout.saturating_sub(time.elapsed(requested)));
+        let within = |limit: Option<Duration>, wait: Duration| limit.is_none_or(|limit| wait <= limit);
+        match (reserve.action, until_reset) {
+            (_, Some(Duration::ZERO)) => Ok(()),
+            (ReserveAction::WaitForReset, Some(wait))
+                if within(reserve.max_wait, wait) && within(overall_left, wait) =>
+            {
+                tracing::debug!(uri, remaining = info.remaining, ?wait, "waiting for the quota to reset");
+                self.stats.record_quota_deferred();
+                time.sleep(wait).await;
+                Ok(())
+            }
+            _ => {
+                let err = ApiClientError::QuotaReserved {
+                    remaining: info.remaining,
+                    reserve: reserve.reserve,
+                };
+                self.stats
+                    .record_request(Duration::ZERO, 0, 0, Some(ErrorClass::of(&err)));
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use reqwest::Method;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport, SimulatedTime};
+    use crate::time::TimeSource;
+
+    /// `SimulatedTime` starts at 2024-06-01 00:00 UTC; the quota resets ten minutes later.
+    const RESET_MS: &str = "1717200600000";
+
+    fn reporting(remaining: &str) -> MockResponse {
+        MockResponse::encrypted("{}")
+            .with_header("ho-quota-limit", "1000")
+            .with_header("ho-quota-remaining", remaining)
+            .with_header("ho-quota-reset", RESET_MS)
+    }
+
+    fn client_on(transport: &Arc<MockTransport>, time: &SimulatedTime, reserve: QuotaReserve) -> ApiClient {
+        let time = time.clone();
+        mock_client(transport, move |config| {
+            config.time = Arc::new(time);
+            config.quota_reserve = Some(reserve);
+        })
+    }
+
+    async fn send(client: &ApiClient, priority: Priority) -> Result<String, ApiClientError> {
+        client.request(Method::GET, "/v1/news").priority(priority).send().await
+    }
+
+    #[tokio::test]
+    async fn test_low_priority_is_rejected_below_the_reserve() {
+        let time = SimulatedTime::new();
+        let transport = MockTransport::new();
+        transport
+            .on(Method::GET, "/v1/news", reporting("101"))
+            .on(Method::GET, "/v1/news", reporting("100"))
+            .on(Method::GET, "/v1/news", reporting("99"));
+        let reserve = QuotaReserve {
+            max_age: Duration::from_secs(3600),
+            ..QuotaReserve::new(100)
+        };
+        let client = client_on(&transport, &time, reserve);
+
+        assert!(send(&client, Priority::Low).await.is_ok());
+        assert!(send(&client, Priority::Low).await.is_ok());
+        assert!(send(&client, Priority::Normal).await.is_ok());
+        let quota = client.quota().unwrap();
+        assert_eq!((quota.limit, quota.remaining), (Some(1000), 99));
+        assert_eq!(quota.resets_at.unwrap().to_rfc3339(), "2024-06-01T00:10:00+00:00");
+
+        let err = send(&client, Priority::Low).await.unwrap_err();
+        assert!(matches!(
+            err,
+            ApiClientError::QuotaReserved {
+                remaining: 99,
+                reserve: 100
+            }
+        ));
+        assert!(!err.is_retryable());
+        assert!(send(&client, Priority::High).await.is_ok());
+        assert_eq!(transport.requests().len(), 4);
+        assert_eq!(client.stats().errors.quota_reserved, 1);
+
+        // Past the reported reset the quota is whole again.
+        time.advance(Duration::from_secs(600));
+        assert!(send(&client, Priority::Low).await.is_ok());
+        assert_eq!(transport.requests().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_the_reset_when_configured() {
+        let time = SimulatedTime::new();
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/news", reporting("3"));
+        let reserve = QuotaReserve {
+            action: ReserveAction::WaitForReset,
+            max_age: Duration::from_secs(3600),
+            ..QuotaReserve::new(10)
+        };
+        let client = client_on(&transport, &time, reserve);
+        assert!(send(&client, Priority::Normal).await.is_ok());
+
+        let started = time.now_utc();
+        assert!(time.drive(send(&client, Priority::Low)).await.is_ok());
+        assert_eq!((time.now_utc() - started).num_seconds(), 600);
+        assert_eq!(transport.requests().len(), 2);
+        let stats = client.stats();
+        assert_eq!((stats.quota_deferred, stats.errors.quota_reserved), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_resets_beyond_the_wait_limits_are_rejected_unsent() {
+        let time = SimulatedTime::new();
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/news", reporting("3"));
+        let reserve = QuotaReserve {
+            action: ReserveAction::WaitForReset,
+            max_age: Duration::from_secs(3600),
+            ..QuotaReserve::new(10)
+        };
+        let time_source = time.clone();
+        let client = mock_client(&transport, |config| {
+            config.time = Arc::new(time_source);
+            config.quota_reserve = Some(reserve.clone());
+            config.overall_timeout = Some(Duration::from_secs(60));
+        });
+        assert!(send(&client, Priority::Normal).await.is_ok());
+
+        // The reset is ten minutes away, past the one minute overall limit.
+        let started = time.now_utc();
+        let err = time.drive(send(&client, Priority::Low)).await.unwrap_err();
+        assert!(matches!(
+            err,
+            ApiClientError::QuotaReserved {
+                remaining: 3,
+                reserve: 10
+            }
+        ));
+        assert_eq!(time.now_utc(), started);
+
+        // And past a five minute `max_wait`.
+        let client = client_on(
+            &transport,
+            &time,
+            QuotaReserve {
+                max_wait: Some(Duration::from_secs(300)),
+                ..reserve
+            },
+        );
+        assert!(send(&client, Priority::Normal).await.is_ok());
+        let err = time.drive(send(&client, Priority::Low)).await.unwrap_err();
+        assert!(matches!(err, ApiClientError::QuotaReserved { .. }));
+        assert_eq!(time.now_utc(), started);
+        assert_eq!(transport.requests().len(), 2);
+        assert_eq!(client.stats().quota_deferred, 0);
+    }
+
+    #[tokio::test]
+    async fn test_stale_reports_hold_nothing_back() {
+        let time = SimulatedTime::new();
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/news",
+            MockResponse::encrypted("{}").with_header("ho-quota-remaining", "0"),
+        );
+        let client = client_on(&transport, &time, QuotaReserve::new(10));
+        assert!(send(&client, Priority::High).await.is_ok());
+        // No reset time: rejected until the report is too old to act on.
+        assert!(send(&client, Priority::Low).await.is_err());
+
+        time.advance(Duration::from_secs(61));
+        assert!(send(&client, Priority::Low).await.is_ok());
+        assert_eq!(transport.requests().len(), 2);
+        assert_eq!(client.quota().map(|quota| quota.remaining), Some(0));
+    }
+}
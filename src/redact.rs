@@ -0,0 +1,456 @@
+//! Masking of personal data before it is kept anywhere observable. With
+//! `ApiClientConfig::redaction` set, the bodies errors carry (and so every log line, wire
+//! form and transaction report holding one) and the exchanges of
+//! `testing::contract::ContractRecorder` have matching fields replaced by the mask. Error
+//! messages that quote payload values, and the request-body preview of
+//! `SuspectedDoubleEncoding`, have every value masked. Payloads returned to the caller are
+//! never changed.
+//!
+//! A pattern is a field name, such as `phone`, matched at any depth, or a path from the
+//! root in the syntax of `Chain::extract`, such as `$.user.id_card`. Arrays are searched
+//! element by element unless a path names an index. Bodies that are not JSON are kept as
+//! they are.
+
+use serde_json::Value;
+
+use crate::chain::{parse_path, Segment};
+use crate::ApiClientError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedactionConfig {
+    pub patterns: Vec<String>,
+    /// Replaces each matched value, whatever its type. `"***"` by default.
+    pub mask: String,
+}
+
+impl RedactionConfig {
+    pub fn new<I, S>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            patterns: patterns.into_iter().map(Into::into).collect(),
+            mask: "***".to_string(),
+        }
+    }
+
+    pub fn mask(mut self, mask: &str) -> Self {
+        self.mask = mask.to_string();
+        self
+    }
+
+    /// Fails on a path pattern that does not parse.
+    pub(crate) fn check(&self) -> Result<(), ApiClientError> {
+        self.rules().map(|_| ())
+    }
+
+    /// `text` with matching fields masked, or unchanged when it is not JSON.
+    pub fn redact(&self, text: &str) -> String {
+        let Ok(mut value) = serde_json::from_str::<Value>(text) else {
+            return text.to_string();
+        };
+        self.redact_value(&mut value);
+        value.to_string()
+    }
+
+    pub fn redact_value(&self, value: &mut Value) {
+        // Patterns are checked when the client is created.
+        let Ok(rules) = self.rules() else {
+            return;
+        };
+        let paths = (0..rules.len())
+            .filter(|&rule| matches!(rules[rule], Rule::Path(_)))
+            .map(|rule| (rule, 0))
+            .collect::<Vec<_>>();
+        let mask = Value::String(self.mask.clone());
+        walk(value, &rules, &paths, &mask);
+    }
+
+    /// A request body as sent: the payload inside a `{"data": "..."}` envelope is redacted
+    /// in place, anything else as a whole.
+    pub fn redact_request_body(&self, body: &str) -> String {
+        match serde_json::from_str::<Value>(body) {
+            Ok(Value::Object(mut envelope)) if envelope.get("data").is_some_and(Value::is_string) => {
+                if let Some(Value::String(payload)) = envelope.get_mut("data") {
+                    *payload = self.redact(payload);
+                }
+                Value::Object(envelope).to_string()
+            }
+            Ok(mut value) => {
+                self.redact_value(&mut value);
+                value.to_string()
+            }
+            Err(_) => body.to_string(),
+        }
+    }
+
+    /// `error` with the body it carries redacted.
+    pub(crate) fn redact_error(&self, error: ApiClientError) -> ApiClientError {
+        match error {
+            ApiClientError::HttpStatus { status, body } => ApiClientError::HttpStatus {
+                status,
+                body: self.redact(&body),
+            },
+            ApiClientError::Truncated { uri, body } => ApiClientError::Truncated {
+                uri,
+                body: self.redact(&body),
+            },
+            ApiClientError::SignatureRejected {
+                status,
+                reason,
+                components,
+                timestamp,
+                body_sha1,
+                body,
+            } => ApiClientError::SignatureRejected {
+                status,
+                reason,
+                components,
+                timestamp,
+                body_sha1,
+                body: self.redact(&body),
+            },
+            ApiClientError::ChainStepFailed { step, uri, source } => ApiClientError::ChainStepFailed {
+                step,
+                uri,
+                source: Box::new(self.redact_error(*source)),
+            },
+            ApiClientError::InvalidPayload { path, message } => ApiClientError::InvalidPayload {
+                path,
+                message: self.redact_message(&message),
+            },
+            ApiClientError::ResponseProcessing { uri, stage, reason } => ApiClientError::ResponseProcessing {
+                uri,
+                stage,
+                reason: self.redact_message(&reason),
+            },
+            // The preview is cut off after a few characters, so it is rarely whole JSON.
+            ApiClientError::SuspectedDoubleEncoding { kind, preview } => ApiClientError::SuspectedDoubleEncoding {
+                kind,
+                preview: match serde_json::from_str::<Value>(&preview) {
+                    Ok(_) => self.redact(&preview),
+                    Err(_) => self.mask.clone(),
+                },
+            },
+            other => other,
+        }
+    }
+
+    /// A message about a payload: redacted as JSON when it is JSON, else with every value
+    /// serde quotes in its messages masked, a `"string"` or an ``integer `1` `` alike.
+    /// Field names, which serde quotes in backticks too, are kept.
+    fn redact_message(&self, message: &str) -> String {
+        if serde_json::from_str::<Value>(message).is_ok() {
+            return self.redact(message);
+        }
+        let mut redacted = String::with_capacity(message.len());
+        let mut rest = message;
+        while let Some(start) = rest.find(['"', '`']) {
+            let (before, quoted) = rest.split_at(start);
+            redacted.push_str(before);
+            let quote = quoted.as_bytes()[0] as char;
+            let Some(len) = quoted_len(&quoted[1..], quote) else {
+                redacted.push_str(quoted);
+                return redacted;
+            };
+            let is_value = quote == '"'
+                || ["integer ", "floating point ", "boolean ", "character "]
+                    .iter()
+                    .any(|kind| before.ends_with(kind));
+            if is_value {
+                redacted.push(quote);
+                redacted.push_str(&self.mask);
+                redacted.push(quote);
+            } else {
+                redacted.push_str(&quoted[..len + 2]);
+            }
+            rest = &quoted[len + 2..];
+        }
+        redacted.push_str(rest);
+        redacted
+    }
+
+    fn rules(&self) -> Result<Vec<Rule>, ApiClientError> {
+        self.patterns
+            .iter()
+            .map(|pattern| match pattern.strip_prefix('$') {
+                Some(_) => {
+                    let segments = parse_path(pattern)?
+                        .into_iter()
+                        .map(|segment| match segment {
+                            Segment::Field(field) => Step::Field(field.to_string()),
+                            Segment::Index(index) => Step::Index(index),
+                        })
+                        .collect::<Vec<_>>();
+                    match segments.is_empty() {
+                        true => Err(ApiClientError::InvalidConfig(format!(
+                            "redaction pattern {:?} names no field",
+                            pattern
+                        ))),
+                        false => Ok(Rule::Path(segments)),
+                    }
+                }
+                None => Ok(Rule::Field(pattern.clone())),
+            })
+            .collect()
+    }
+}
+
+/// The length of `text` up to the `quote` that closes it, skipping escaped characters.
+fn quoted_len(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == quote => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+enum Rule {
+    Field(String),
+    Path(Vec<Step>),
+}
+
+enum Step {
+    Field(String),
+    Index(usize),
+}
+
+/// Masks what `rules` match below `value`. `paths` holds, for each path rule still in
+/// play, how many of its steps lead here.
+fn walk(value: &mut Value, rules: &[Rule], paths: &[(usize, usize)], mask: &Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                let named = rules
+                    .iter()
+                    .any(|rule| matches!(rule, Rule::Field(field) if field == key));
+                let (complete, next) = advance(rules, paths, |step| matches!(step, Step::Field(field) if field == key));
+                if named || complete {
+                    *child = mask.clone();
+                } else {
+                    walk(child, rules, &next, mask);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (i, child) in items.iter_mut().enumerate() {
+                let (complete, mut next) =
+                    advance(rules, paths, |step| matches!(step, Step::Index(index) if *index == i));
+                if complete {
+                    *child = mask.clone();
+                    continue;
+                }
+                // A field step looks into every element.
+                next.extend(paths.iter().copied().filter(
+                    |&(rule, step)| matches!(&rules[rule], Rule::Path(steps) if matches!(steps[step], Step::Field(_))),
+                ));
+                walk(child, rules, &next, mask);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Moves the path rules whose next step `matches` one step on: whether one of them is now
+/// complete, and the others.
+fn advance(rules: &[Rule], paths: &[(usize, usize)], matches: impl Fn(&Step) -> bool) -> (bool, Vec<(usize, usize)>) {
+    let mut complete = false;
+    let mut next = Vec::new();
+    for &(rule, step) in paths {
+        let Rule::Path(steps) = &rules[rule] else {
+            continue;
+        };
+        if matches(&steps[step]) {
+            match step + 1 == steps.len() {
+                true => complete = true,
+                false => next.push((rule, step + 1)),
+            }
+        }
+    }
+    (complete, next)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures_util::future::BoxFuture;
+    use reqwest::{Method, StatusCode};
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::contract::ContractRecorder;
+    use crate::testing::{test_config, MockResponse, MockTransport};
+    use crate::transport::{Transport, TransportRequest, TransportResponse};
+    use crate::ApiClient;
+
+    /// A transport whose every request fails with an error carrying `body`.
+    struct FailingTransport {
+        body: String,
+    }
+
+    impl Transport for FailingTransport {
+        fn execute(&self, _: TransportRequest) -> BoxFuture<'_, Result<TransportResponse, ApiClientError>> {
+            let error = ApiClientError::HttpStatus {
+                status: StatusCode::BAD_GATEWAY,
+                body: self.body.clone(),
+            };
+            Box::pin(async move { Err(error) })
+        }
+    }
+
+    #[test]
+    fn test_names_match_anywhere_and_paths_from_the_root() {
+        let redaction = RedactionConfig::new(["phone", "$.user.id_card", "$.friends[1].name"]).mask("<pii>");
+        let mut payload = json!({
+            "phone": "13800000000",
+            "user": { "id_card": 110101, "name": "A", "contacts": [{ "phone": "1" }, { "phone": null }] },
+            "id_card": "kept at the root",
+            "friends": [{ "name": "B" }, { "name": "C" }],
+            "teams": [[{ "user": { "id_card": "kept, not under $.user" } }]],
+        });
+        redaction.redact_value(&mut payload);
+        assert_eq!(
+            payload,
+            json!({
+                "phone": "<pii>",
+                "user": { "id_card": "<pii>", "name": "A", "contacts": [{ "phone": "<pii>" }, { "phone": "<pii>" }] },
+                "id_card": "kept at the root",
+                "friends": [{ "name": "B" }, { "name": "<pii>" }],
+                "teams": [[{ "user": { "id_card": "kept, not under $.user" } }]],
+            })
+        );
+    }
+
+    #[test]
+    fn test_paths_look_into_arrays_and_other_bodies_pass() {
+        let redaction = RedactionConfig::new(["$.data.users.phone"]);
+        let redacted = redaction.redact(r#"{"data":{"users":[{"phone":"1"},[{"phone":"2"}],{"id":3}]}}"#);
+        assert_eq!(
+            redacted,
+            json!({ "data": { "users": [{ "phone": "***" }, [{ "phone": "***" }], { "id": 3 }] } }).to_string()
+        );
+        assert_eq!(redaction.redact("<html>bad gateway</html>"), "<html>bad gateway</html>");
+        let envelope = json!({ "data": json!({ "phone": "1" }).to_string() }).to_string();
+        let fields = RedactionConfig::new(["phone"]);
+        assert_eq!(
+            fields.redact_request_body(&envelope),
+            json!({ "data": json!({ "phone": "***" }).to_string() }).to_string()
+        );
+        assert!(RedactionConfig::new(["$.user["]).check().is_err());
+        assert!(RedactionConfig::new(["$"]).check().is_err());
+    }
+
+    #[test]
+    fn test_messages_and_previews_lose_their_values() {
+        let redaction = RedactionConfig::new(["phone"]);
+        let serde_message = serde_json::from_str::<Vec<u64>>(r#"["13800000000"]"#)
+            .unwrap_err()
+            .to_string();
+        let invalid = |message: String| ApiClientError::InvalidPayload {
+            path: "list[0].phone".to_string(),
+            message,
+        };
+        let messages = |err| match redaction.redact_error(err) {
+            ApiClientError::InvalidPayload { path, message } => (path, message),
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(
+            messages(invalid(serde_message)),
+            (
+                "list[0].phone".to_string(),
+                r#"invalid type: string "***", expected u64 at line 1 column 14"#.to_string()
+            )
+        );
+        assert_eq!(
+            messages(invalid(
+                r#"invalid value: integer `13800000000`, expected `phone` as "a \"b\" c""#.to_string()
+            ))
+            .1,
+            r#"invalid value: integer `***`, expected `phone` as "***""#
+        );
+
+        match redaction.redact_error(ApiClientError::ResponseProcessing {
+            uri: "/v1/user".to_string(),
+            stage: 1,
+            reason: r#"{"phone":"13800000000","stage":"mask"}"#.to_string(),
+        }) {
+            ApiClientError::ResponseProcessing { uri, stage, reason } => {
+                assert_eq!((uri.as_str(), stage), ("/v1/user", 1));
+                assert_eq!(reason, r#"{"phone":"***","stage":"mask"}"#);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let previews = [r#"{"phone":"1"}"#, r#"{"phone":"13800000"#];
+        let redacted: Vec<String> = previews
+            .into_iter()
+            .map(|preview| {
+                match redaction.redact_error(ApiClientError::SuspectedDoubleEncoding {
+                    kind: "object".to_string(),
+                    preview: preview.to_string(),
+                }) {
+                    ApiClientError::SuspectedDoubleEncoding { preview, .. } => preview,
+                    other => panic!("unexpected {:?}", other),
+                }
+            })
+            .collect();
+        assert_eq!(redacted, [r#"{"phone":"***"}"#, "***"]);
+    }
+
+    #[tokio::test]
+    async fn test_redacted_fields_reach_the_caller_only() {
+        const PHONE: &str = "13800000000";
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::POST,
+                "/v1/user",
+                MockResponse::encrypted(json!({ "name": "A", "contact": { "phone": PHONE } }).to_string()),
+            )
+            .on(
+                Method::GET,
+                "/v1/user/missing",
+                MockResponse::json(StatusCode::NOT_FOUND, &json!({ "code": 40400, "phone": PHONE })),
+            );
+        let mut config = test_config();
+        config.redaction = Some(RedactionConfig::new(["phone"]));
+        let recorder = ContractRecorder::wrap(transport, &config).unwrap();
+        let client = ApiClient::with_transport(config, recorder.clone()).unwrap();
+
+        let user: Value = client
+            .send_as(Method::POST, "/v1/user", Some(json!({ "phone": PHONE })))
+            .await
+            .unwrap();
+        assert_eq!(user["contact"]["phone"], PHONE);
+        let error = client.send(Method::GET, "/v1/user/missing", None).await.unwrap_err();
+        assert_eq!(error.business_code(), Some(40400));
+
+        assert!(!format!("{:?}", error).contains(PHONE));
+        assert!(!serde_json::to_string(&error.to_wire()).unwrap().contains(PHONE));
+        let report = recorder.report().to_string();
+        assert!(!report.contains(PHONE), "{}", report);
+        assert!(report.contains(r#"\"phone\":\"***\""#), "{}", report);
+
+        // Errors from the transport itself are recorded redacted as well.
+        let failing = Arc::new(FailingTransport {
+            body: json!({ "code": 50200, "phone": PHONE }).to_string(),
+        });
+        let mut config = test_config();
+        config.redaction = Some(RedactionConfig::new(["phone"]));
+        config.retry.max_retries = 0;
+        let recorder = ContractRecorder::wrap(failing, &config).unwrap();
+        let client = ApiClient::with_transport(config, recorder.clone()).unwrap();
+        let error = client.send(Method::GET, "/v1/user", None).await.unwrap_err();
+        assert!(!format!("{:?}", error).contains(PHONE));
+        let recorded = recorder.exchanges()[0].error.clone().unwrap();
+        assert!(!recorded.contains(PHONE), "{}", recorded);
+        assert!(recorded.contains(r#"\"phone\":\"***\""#), "{}", recorded);
+    }
+}
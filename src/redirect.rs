@@ -0,0 +1,114 @@
+//! Following 301, 302, 307 and 308 responses. The transport never follows redirects itself:
+//! a request re-sent as is carries the signature of the old uri, which the new location
+//! rejects. The client re-signs each hop instead.
+
+use reqwest::header::{HeaderMap, LOCATION};
+use reqwest::{StatusCode, Url};
+
+pub(crate) fn is_redirect(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 301 | 302 | 307 | 308)
+}
+
+/// Where a redirect points, split the way `build_request` joins a URL.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Hop {
+    /// The absolute target, for errors and logs.
+    pub(crate) url: String,
+    pub(crate) base_url: String,
+    pub(crate) content: String,
+    pub(crate) uri: String,
+    /// The target has another origin: scheme, host or port.
+    pub(crate) cross_host: bool,
+}
+
+/// The target of the `Location` header, resolved against `url`, which was requested as
+/// `base_url + content + uri`. A target under the same prefix keeps it, so the new uri is
+/// signed the way the old one was; any other target becomes its origin plus path and
+/// query, with an empty content path. `None` when there is no usable `Location`.
+pub(crate) fn hop(url: &str, base_url: &str, content: &str, headers: &HeaderMap) -> Option<Hop> {
+    let location = headers.get(LOCATION)?.to_str().ok()?;
+    let current = Url::parse(url).ok()?;
+    let mut target = current.join(location.trim()).ok()?;
+    target.set_fragment(None);
+    let cross_host = target.origin() != current.origin();
+
+    let prefix = format!("{}{}", base_url, content);
+    let (base_url, content, uri) = match target.as_str().strip_prefix(&prefix) {
+        Some(rest) if !cross_host && (rest.is_empty() || rest.starts_with(['/', '?'])) => {
+            (base_url.to_string(), content.to_string(), rest.to_string())
+        }
+        _ => {
+            let uri = match target.query() {
+                Some(query) => format!("{}?{}", target.path(), query),
+                None => target.path().to_string(),
+            };
+            (target.origin().ascii_serialization(), String::new(), uri)
+        }
+    };
+    Some(Hop {
+        url: target.to_string(),
+        base_url,
+        content,
+        uri,
+        cross_host,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    const BASE: &str = "https://gw.example.test";
+    const CONTENT: &str = "/server/common/api";
+
+    fn hop_to(location: &str) -> Option<Hop> {
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, HeaderValue::from_str(location).unwrap());
+        hop(
+            "https://gw.example.test/server/common/api/v1/skin?region=cn",
+            BASE,
+            CONTENT,
+            &headers,
+        )
+    }
+
+    #[test]
+    fn test_targets_under_the_prefix_keep_it() {
+        let hop = hop_to("/server/common/api/v2/skin?region=cn#top").unwrap();
+        assert_eq!(hop.url, "https://gw.example.test/server/common/api/v2/skin?region=cn");
+        assert_eq!((hop.base_url.as_str(), hop.content.as_str()), (BASE, CONTENT));
+        assert_eq!(hop.uri, "/v2/skin?region=cn");
+        assert!(!hop.cross_host);
+
+        assert_eq!(hop_to("skin-v2").unwrap().uri, "/v1/skin-v2");
+    }
+
+    #[test]
+    fn test_other_targets_split_at_the_origin() {
+        let hop = hop_to("/maintenance/v1/skin").unwrap();
+        assert_eq!((hop.base_url.as_str(), hop.content.as_str()), (BASE, ""));
+        assert_eq!(hop.uri, "/maintenance/v1/skin");
+        assert!(!hop.cross_host);
+
+        let hop = hop_to("/server/common/apix").unwrap();
+        assert_eq!(hop.uri, "/server/common/apix");
+
+        for location in [
+            "https://other.example.test/server/common/api/v1/skin",
+            "http://gw.example.test/server/common/api/v1/skin",
+            "https://gw.example.test:8443/v1/skin",
+        ] {
+            let hop = hop_to(location).unwrap();
+            assert!(hop.cross_host, "{}", location);
+            assert_eq!(hop.content, "");
+        }
+    }
+
+    #[test]
+    fn test_no_location_is_no_hop() {
+        assert_eq!(hop("https://gw.example.test/x", BASE, "", &HeaderMap::new()), None);
+        assert_eq!(hop_to("http://[::1"), None);
+    }
+}
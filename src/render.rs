@@ -0,0 +1,282 @@
+//! `ApiClientError::render`, an error written out for a person at a terminal or in a log:
+//!
+//! ```text
+//! error: server answered 401 Unauthorized
+//!   kind:      http_status
+//!   status:    401 Unauthorized
+//!   code:      40102
+//!   cause:     the server answered 401 Unauthorized — the signature or app_id was rejected; ...
+//!   next:      check app_id and app_secret, and that the local clock is in sync
+//! ```
+//!
+//! The cause is `tools::explain_error`; the next step follows from the same classification
+//! as `is_retryable` and the other `is_*` helpers.
+
+use std::error::Error;
+
+use serde_json::Value;
+
+use crate::ApiClientError;
+
+/// See `ApiClientError::render`. Non-exhaustive, as the `cli` feature adds a variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RenderStyle {
+    /// One line: the kind, the message with its underlying errors, and the status, business
+    /// code and retryability when they apply.
+    Compact,
+    /// One line per fact: what failed, the endpoint, status and business code, the underlying
+    /// errors, the likely cause and what to do next.
+    Verbose,
+    /// `Verbose` with ANSI colors.
+    #[cfg(feature = "cli")]
+    AnsiVerbose,
+}
+
+/// The escape sequences a style writes around the parts of `Verbose`.
+struct Palette {
+    error: &'static str,
+    label: &'static str,
+    next: &'static str,
+    reset: &'static str,
+}
+
+const PLAIN: Palette = Palette {
+    error: "",
+    label: "",
+    next: "",
+    reset: "",
+};
+
+#[cfg(feature = "cli")]
+const ANSI: Palette = Palette {
+    error: "\x1b[1;31m",
+    label: "\x1b[2m",
+    next: "\x1b[32m",
+    reset: "\x1b[0m",
+};
+
+impl ApiClientError {
+    pub fn render(&self, style: RenderStyle) -> String {
+        match style {
+            RenderStyle::Compact => compact(self),
+            RenderStyle::Verbose => verbose(self, &PLAIN),
+            #[cfg(feature = "cli")]
+            RenderStyle::AnsiVerbose => verbose(self, &ANSI),
+        }
+    }
+}
+
+fn compact(err: &ApiClientError) -> String {
+    let mut text = format!("{}: {}", kind(err), one_line(&err.to_string()));
+    for cause in causes(err) {
+        text.push_str(": ");
+        text.push_str(&one_line(&cause));
+    }
+    let mut facts = Vec::new();
+    #[cfg(feature = "client")]
+    if let Some(status) = err.status() {
+        facts.push(format!("http {}", status.as_u16()));
+    }
+    if let Some(code) = err.business_code() {
+        facts.push(format!("code {}", code));
+    }
+    if err.is_retryable() {
+        facts.push("retryable".to_string());
+    }
+    if !facts.is_empty() {
+        text.push_str(&format!(" ({})", facts.join(", ")));
+    }
+    text
+}
+
+fn verbose(err: &ApiClientError, palette: &Palette) -> String {
+    let mut lines = vec![format!("{}error:{} {}", palette.error, palette.reset, err)];
+    let mut fact = |label: &str, value: String| {
+        lines.push(format!(
+            "  {}{:<10}{} {}",
+            palette.label,
+            format!("{}:", label),
+            palette.reset,
+            value
+        ));
+    };
+    fact("kind", kind(err));
+    if let Some(endpoint) = endpoint(err) {
+        fact("endpoint", endpoint.to_string());
+    }
+    #[cfg(feature = "client")]
+    if let Some(status) = err.status() {
+        fact("status", status.to_string());
+    }
+    if let Some(code) = err.business_code() {
+        fact("code", code.to_string());
+    }
+    for cause in causes(err) {
+        fact("caused by", one_line(&cause));
+    }
+    #[cfg(feature = "client")]
+    fact("cause", crate::tools::explain_error(err));
+    fact("next", format!("{}{}{}", palette.next, next_step(err), palette.reset));
+    lines.join("\n")
+}
+
+/// The wire kind, such as `http_status`.
+fn kind(err: &ApiClientError) -> String {
+    match serde_json::to_value(err.to_wire().kind) {
+        Ok(Value::String(kind)) => kind,
+        _ => "unknown".to_string(),
+    }
+}
+
+/// The messages of the errors under `err`, outermost first.
+fn causes(err: &ApiClientError) -> Vec<String> {
+    let mut causes = Vec::new();
+    let mut next = err.source();
+    while let Some(cause) = next {
+        causes.push(cause.to_string());
+        next = cause.source();
+    }
+    causes
+}
+
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The request uri the error names, when it names one.
+fn endpoint(err: &ApiClientError) -> Option<&str> {
+    match err {
+        ApiClientError::InvalidUri { uri, .. }
+        | ApiClientError::Truncated { uri, .. }
+        | ApiClientError::ResponseProcessing { uri, .. } => Some(uri),
+        #[cfg(feature = "client")]
+        ApiClientError::ChainStepFailed { uri, .. } => Some(uri),
+        _ => None,
+    }
+}
+
+fn next_step(err: &ApiClientError) -> &'static str {
+    use ApiClientError as E;
+    match err {
+        #[cfg(feature = "client")]
+        E::ChainStepFailed { source, .. } => next_step(source),
+        #[cfg(feature = "client")]
+        E::Maintenance { .. } => "wait for the maintenance window to end; `ApiClient::active_maintenance` says when",
+        #[cfg(feature = "client")]
+        E::NonceReuse { .. } => {
+            "build the request anew rather than re-sending a signed one, and check the nonce source"
+        }
+        #[cfg(feature = "client")]
+        E::QuotaReserved { .. } => "wait for the daily quota to reset, or send the request at a higher priority",
+        #[cfg(feature = "client")]
+        E::QueueTimeout { .. } => "raise `max_concurrent_requests` or `max_queue_wait`, or send fewer requests at once",
+        #[cfg(feature = "client")]
+        E::SignatureRejected { .. } => "compare the sign string with `tools::verify_signature`, and check the clock",
+        E::InvalidConfig(_) => "fix the configuration named above",
+        E::AesError(_)
+        | E::BothKeysFailed { .. }
+        | E::Utf8Error(_)
+        | E::InvalidCharset { .. }
+        | E::HexError(_)
+        | E::InvalidHex(_)
+        | E::InvalidCiphertextLength { .. } => {
+            "check app_secret and iv against the app's credentials; `tools::decrypt_envelope` tries a copied body"
+        }
+        _ if err.is_retryable() => "try again later; the client already retried as far as `RetryConfig` allows",
+        _ if err.is_auth_error() => "check app_id and app_secret, and that the local clock is in sync",
+        _ if err.is_invalid_request() => "fix the request: it fails the same way every time it is sent",
+        #[cfg(feature = "client")]
+        E::HttpStatus { status, .. } if status.as_u16() == 429 => "slow down: the app is being rate limited",
+        _ => "report it with the JSON of `to_wire` attached",
+    }
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::StatusCode;
+
+    use super::*;
+
+    fn samples() -> Vec<ApiClientError> {
+        vec![
+            ApiClientError::HttpStatus {
+                status: StatusCode::UNAUTHORIZED,
+                body: r#"{"code":40102}"#.to_string(),
+            },
+            ApiClientError::OverallTimeout {
+                attempts: 3,
+                elapsed: Duration::from_secs(10),
+            },
+            ApiClientError::ChainStepFailed {
+                step: 2,
+                uri: "/v1/lol/champion/rotation".to_string(),
+                source: Box::new(ApiClientError::SerdeJsonError(
+                    serde_json::from_str::<Value>("{\"a\":").unwrap_err(),
+                )),
+            },
+            ApiClientError::QuotaReserved {
+                remaining: 40,
+                reserve: 100,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_compact_is_one_line() {
+        let rendered: Vec<String> = samples().iter().map(|err| err.render(RenderStyle::Compact)).collect();
+        assert_eq!(
+            rendered,
+            [
+                "http_status: server answered 401 Unauthorized (http 401, code 40102)",
+                "overall_timeout: request timed out after 10000 ms and 3 attempts (retryable)",
+                "chain_step_failed: step 2 (/v1/lol/champion/rotation) failed: EOF while parsing a value at line 1 \
+                 column 5",
+                "quota_reserved: held back with 40 requests of quota left, below the reserve of 100",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verbose_explains_and_suggests() {
+        let rendered: Vec<String> = samples().iter().map(|err| err.render(RenderStyle::Verbose)).collect();
+        assert_eq!(
+            rendered[0],
+            "error: server answered 401 Unauthorized
+  kind:      http_status
+  status:    401 Unauthorized
+  code:      40102
+  cause:     the server answered 401 Unauthorized — the signature or app_id was rejected; check the clock and the secret (business code 40102)
+  next:      check app_id and app_secret, and that the local clock is in sync"
+        );
+        assert_eq!(
+            rendered[2],
+            "error: step 2 (/v1/lol/champion/rotation) failed
+  kind:      chain_step_failed
+  endpoint:  /v1/lol/champion/rotation
+  caused by: EOF while parsing a value at line 1 column 5
+  cause:     step 2 (/v1/lol/champion/rotation) failed: the body ends before the JSON does (EOF while parsing a value at line 1 column 5) — it was likely truncated when copied
+  next:      report it with the JSON of `to_wire` attached"
+        );
+        assert_eq!(
+            rendered[3].lines().last(),
+            Some("  next:      wait for the daily quota to reset, or send the request at a higher priority")
+        );
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_ansi_verbose_colors_the_plain_text() {
+        let err = &samples()[1];
+        let colored = err.render(RenderStyle::AnsiVerbose);
+        assert!(colored.starts_with("\x1b[1;31merror:\x1b[0m request timed out after 10000 ms"));
+        let stripped = colored
+            .replace("\x1b[1;31m", "")
+            .replace("\x1b[2m", "")
+            .replace("\x1b[32m", "")
+            .replace("\x1b[0m", "");
+        assert_eq!(stripped, err.render(RenderStyle::Verbose));
+    }
+}
@@ -0,0 +1,807 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::charset::Charset;
+use crate::priority::Priority;
+use crate::response::{ApiResponse, ResponseMeta};
+use crate::retry::RetryConfig;
+use crate::scoped::ScopedKey;
+use crate::stats::ErrorClass;
+use crate::{ApiClient, ApiClientError};
+
+/// Per-request options on top of `ApiClient::send`. Created with `ApiClient::request`.
+pub struct RequestBuilder<'a> {
+    client: &'a ApiClient,
+    method: Method,
+    uri: String,
+    body: Option<Value>,
+    options: RequestOptions,
+    error: Option<ApiClientError>,
+}
+
+/// How a request authenticates itself to the gateway.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AuthMode {
+    /// `HO-APP-ID`, `HO-NONCE`, `HO-TIMESTAMP` and `HO-SIGNATURE`, as for every request
+    /// by default.
+    #[default]
+    Signed,
+    /// No `HO-*` headers at all, not even `HO-VERSION`, for endpoints the gateway exempts
+    /// from signing and rejects signed requests to.
+    None,
+}
+
+/// Settings that override the client configuration for a single request.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RequestOptions {
+    pub(crate) content_path: Option<String>,
+    pub(crate) attempt_timeout: Option<Duration>,
+    pub(crate) api_version: Option<String>,
+    pub(crate) response_charset: Option<Charset>,
+    pub(crate) auth: AuthMode,
+    /// Return the response body as received rather than opening the envelope.
+    pub(crate) raw_body: bool,
+    /// Run the response processors on a raw body too.
+    pub(crate) process_raw_body: bool,
+    pub(crate) fail_on_truncated: Option<bool>,
+    pub(crate) allow_string_body: bool,
+    pub(crate) priority: Priority,
+    /// The bootstrap fetch itself, which must not wait for bootstrapping.
+    pub(crate) bootstrap: bool,
+    pub(crate) retry: Option<RetryConfig>,
+    pub(crate) idempotency_key: Option<String>,
+    /// From `ScopedClient::credentials`.
+    pub(crate) credentials: Option<Arc<ScopedKey>>,
+    /// From `ScopedClient::headers`.
+    pub(crate) headers: HeaderMap,
+}
+
+impl RequestOptions {
+    /// What else besides the uri and body tells responses apart in the cache: a raw body,
+    /// the `HO-VERSION` sent, a charset other than UTF-8, and the scoped credentials and
+    /// headers. Empty for a plain, unversioned UTF-8 request outside a scope.
+    pub(crate) fn cache_scope(&self, api_version: Option<&str>, charset: Charset) -> String {
+        let mut scope = String::new();
+        if self.raw_body {
+            scope.push_str(if self.process_raw_body {
+                "\nraw:processed"
+            } else {
+                "\nraw"
+            });
+        }
+        if let Some(version) = api_version {
+            scope.push_str("\nversion:");
+            scope.push_str(version);
+        }
+        if charset != Charset::Utf8 {
+            scope.push_str("\ncharset:");
+            scope.push_str(&charset.to_string());
+        }
+        if let Some(credentials) = &self.credentials {
+            scope.push_str("\napp_id:");
+            scope.push_str(&credentials.app_id);
+        }
+        for (name, value) in &self.headers {
+            scope.push('\n');
+            scope.push_str(name.as_str());
+            scope.push(':');
+            scope.push_str(&String::from_utf8_lossy(value.as_bytes()));
+        }
+        scope
+    }
+}
+
+impl ApiClient {
+    pub fn request(&self, method: Method, uri: &str) -> RequestBuilder<'_> {
+        self.request_with(method, uri, RequestOptions::default())
+    }
+
+    pub(crate) fn request_with(&self, method: Method, uri: &str, options: RequestOptions) -> RequestBuilder<'_> {
+        RequestBuilder {
+            client: self,
+            method,
+            uri: uri.to_string(),
+            body: None,
+            options,
+            error: None,
+        }
+    }
+}
+
+impl<'a> RequestBuilder<'a> {
+    pub fn body(mut self, body: Value) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    /// Sends this request under `content_path` instead of the configured `content` prefix.
+    /// Whether the prefix is signed follows `ApiClientConfig::sign_content_path`.
+    pub fn content_path(mut self, content_path: &str) -> Self {
+        self.options.content_path = Some(content_path.to_string());
+        self
+    }
+
+    /// Overrides `ApiClientConfig::attempt_timeout` for this request.
+    pub fn timeout(mut self, attempt_timeout: Duration) -> Self {
+        self.options.attempt_timeout = Some(attempt_timeout);
+        self
+    }
+
+    /// Decodes this response as `charset` instead of `ApiClientConfig::response_charset`.
+    pub fn response_charset(mut self, charset: Charset) -> Self {
+        self.options.response_charset = Some(charset);
+        self
+    }
+
+    /// Sends this request signed or, with `AuthMode::None`, without any `HO-*` header.
+    pub fn auth(mut self, auth: AuthMode) -> Self {
+        self.options.auth = auth;
+        self
+    }
+
+    /// Whether to open the `{"data": ...}` envelope of the response, as by default. With
+    /// `false` the body is returned as received, decoded as `response_charset`; for
+    /// unsigned endpoints that answer plain JSON.
+    pub fn parse_envelope(mut self, parse: bool) -> Self {
+        self.options.raw_body = !parse;
+        self
+    }
+
+    /// Runs `ApiClient::add_response_processor` processors on the body even when it is
+    /// returned as received with `parse_envelope(false)`; by default they skip it.
+    pub fn process_raw_body(mut self, process: bool) -> Self {
+        self.options.process_raw_body = process;
+        self
+    }
+
+    /// Where this request queues when the concurrency limit is reached: ahead of every
+    /// lower priority waiter, behind earlier waiters of its own. `Normal` by default.
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.options.priority = priority;
+        self
+    }
+
+    /// Sends a string body that holds JSON text as it is, for endpoints that really take
+    /// one; see `ApiClientConfig::detect_double_encoding`.
+    pub fn allow_string_body(mut self) -> Self {
+        self.options.allow_string_body = true;
+        self
+    }
+
+    /// Overrides `ApiClientConfig::fail_on_truncated` for this request.
+    pub fn fail_on_truncated(mut self, fail: bool) -> Self {
+        self.options.fail_on_truncated = Some(fail);
+        self
+    }
+
+    /// Sends this request with `HO-VERSION: version` instead of `ApiClientConfig::api_version`.
+    pub fn api_version(mut self, version: &str) -> Self {
+        match check_api_version(version) {
+            Ok(()) => self.options.api_version = Some(version.to_string()),
+            Err(constraint) => {
+                self.error.get_or_insert(ApiClientError::RequestValidation {
+                    field: "api_version".to_string(),
+                    constraint,
+                    got: version.to_string(),
+                });
+            }
+        }
+        self
+    }
+
+    /// Appends `params` to the uri's query string, after any query already present. `None`
+    /// fields are skipped. The resulting uri is both sent and signed.
+    pub fn query<T: Serialize + ?Sized>(mut self, params: &T) -> Self {
+        match encode_query(params) {
+            Ok(encoded) => self.uri = merge_query(&self.uri, &encoded),
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+        self
+    }
+
+    /// Retries this request by `retry` instead of `ApiClientConfig::retry`.
+    pub fn retry(mut self, retry: RetryConfig) -> Self {
+        self.options.retry = Some(retry);
+        self
+    }
+
+    /// Sends `key` as `HO-IDEMPOTENCY-KEY`, the same on every retry, so the server can drop
+    /// a repeated mutation. Signed requests only.
+    pub fn idempotency_key(mut self, key: &str) -> Self {
+        self.options.idempotency_key = Some(key.to_string());
+        self
+    }
+
+    pub(crate) fn bootstrap_fetch(mut self) -> Self {
+        self.options.bootstrap = true;
+        self
+    }
+
+    pub async fn send(self) -> Result<String, ApiClientError> {
+        Ok(self.send_detailed().await?.body)
+    }
+
+    pub async fn send_detailed(mut self) -> Result<ApiResponse, ApiClientError> {
+        if self.error.is_none() {
+            match check_uri(&self.uri) {
+                Ok(Cow::Borrowed(_)) => {}
+                Ok(Cow::Owned(encoded)) => self.uri = encoded,
+                Err(e) => self.error = Some(e),
+            }
+        }
+        if self.error.is_none() && self.client.config.validate_requests {
+            self.error = self
+                .client
+                .config
+                .validation
+                .validate(&self.uri, self.body.as_ref())
+                .err();
+        }
+        if let Some(e) = self.error {
+            self.client
+                .stats
+                .record_request(Duration::ZERO, 0, 0, Some(ErrorClass::of(&e)));
+            return Err(e);
+        }
+        self.client
+            .dispatch(self.method, &self.uri, self.body, &self.options)
+            .await
+    }
+
+    pub async fn send_as<T: DeserializeOwned + Send + 'static>(self) -> Result<T, ApiClientError> {
+        let client = self.client;
+        let decrypted_str = self.send().await?;
+        client.deserialize(decrypted_str).await
+    }
+
+    /// As `send_as`, with the response metadata; `meta.timings.deserialize` is the time
+    /// spent deserializing the body.
+    pub async fn send_detailed_as<T: DeserializeOwned + Send + 'static>(
+        self,
+    ) -> Result<(T, ResponseMeta), ApiClientError> {
+        let client = self.client;
+        let ApiResponse { body, mut meta } = self.send_detailed().await?;
+        let (value, elapsed) = client.deserialize_timed(body).await?;
+        meta.timings.deserialize = Some(elapsed);
+        Ok((value, meta))
+    }
+}
+
+/// Versions go out as a header value, so they must be non-empty visible ASCII.
+pub(crate) fn check_api_version(version: &str) -> Result<(), String> {
+    if !version.is_empty() && version.bytes().all(|b| b.is_ascii_graphic()) {
+        Ok(())
+    } else {
+        Err("non-empty visible ASCII without spaces".to_string())
+    }
+}
+
+/// Checks a request uri before anything is signed, percent-encoding what reqwest would
+/// otherwise encode after signing, so that the uri signed is the uri sent.
+///
+/// An empty uri or one starting with `/` is accepted, except with control characters (CR
+/// and LF among them), whitespace of any script, a `#` fragment, a backslash, or `.` and
+/// `..` path segments, plain or percent-encoded, which URL parsing would resolve away.
+/// Non-ASCII characters, `"`, `<`, `>` and `` ` ``, `{`, `}` in the path or `'` in the
+/// query are percent-encoded; existing escapes are kept as they are.
+pub(crate) fn check_uri(uri: &str) -> Result<Cow<'_, str>, ApiClientError> {
+    let invalid = |reason: &str| ApiClientError::InvalidUri {
+        uri: uri.to_string(),
+        reason: reason.to_string(),
+    };
+    if !uri.is_empty() && !uri.starts_with('/') {
+        return Err(invalid("does not start with `/`"));
+    }
+    if let Some(c) = uri.chars().find(|c| c.is_control() || c.is_whitespace()) {
+        return Err(invalid(match c.is_control() {
+            true => "contains a control character",
+            false => "contains whitespace",
+        }));
+    }
+    if uri.contains('#') {
+        return Err(invalid("contains a fragment"));
+    }
+    if uri.contains('\\') {
+        return Err(invalid("contains a backslash"));
+    }
+    let (path, query) = match uri.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (uri, None),
+    };
+    let dot = |segment: &str| {
+        let segment = segment.to_ascii_lowercase().replace("%2e", ".");
+        segment == "." || segment == ".."
+    };
+    if path.split('/').any(dot) {
+        return Err(invalid("contains a `.` or `..` segment"));
+    }
+
+    let encoded_in_path = |c: char| !c.is_ascii() || matches!(c, '"' | '<' | '>' | '`' | '{' | '}');
+    let encoded_in_query = |c: char| !c.is_ascii() || matches!(c, '"' | '<' | '>' | '\'');
+    if !path.contains(encoded_in_path) && !query.is_some_and(|query| query.contains(encoded_in_query)) {
+        return Ok(Cow::Borrowed(uri));
+    }
+    let mut encoded = String::with_capacity(uri.len() * 3);
+    percent_encode(&mut encoded, path, encoded_in_path);
+    if let Some(query) = query {
+        encoded.push('?');
+        percent_encode(&mut encoded, query, encoded_in_query);
+    }
+    Ok(Cow::Owned(encoded))
+}
+
+fn percent_encode(out: &mut String, text: &str, encode: impl Fn(char) -> bool) {
+    for c in text.chars() {
+        if encode(c) {
+            let mut utf8 = [0; 4];
+            for byte in c.encode_utf8(&mut utf8).bytes() {
+                out.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            out.push(c);
+        }
+    }
+}
+
+fn encode_query<T: Serialize + ?Sized>(params: &T) -> Result<String, ApiClientError> {
+    serde_urlencoded::to_string(params).map_err(|e| {
+        ApiClientError::InvalidQuery(format!(
+            "query parameters must be a flat struct or map of scalar values ({}); \
+             nested structs and sequences are not supported",
+            e
+        ))
+    })
+}
+
+fn merge_query(uri: &str, encoded: &str) -> String {
+    if encoded.is_empty() {
+        return uri.to_string();
+    }
+    match uri.find('?') {
+        None => format!("{}?{}", uri, encoded),
+        Some(pos) if pos + 1 == uri.len() => format!("{}{}", uri, encoded),
+        Some(_) if uri.ends_with('&') => format!("{}{}", uri, encoded),
+        Some(_) => format!("{}&{}", uri, encoded),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Serialize;
+
+    use super::*;
+    use crate::signing::{sign, SignableBody};
+    use crate::testing::{mock_client, MockResponse, MockTransport, TEST_APP_ID, TEST_APP_SECRET};
+    use crate::types::{ChampionId, Region};
+
+    #[derive(Serialize)]
+    struct SkinQuery {
+        region: Region,
+        champion_id: Option<ChampionId>,
+    }
+
+    #[test]
+    fn test_none_fields_are_skipped() {
+        let query = SkinQuery {
+            region: Region::Cn,
+            champion_id: None,
+        };
+        assert_eq!(encode_query(&query).unwrap(), "region=cn");
+    }
+
+    #[test]
+    fn test_region_and_ids_encode_as_strings() {
+        let query = SkinQuery {
+            region: Region::Euw,
+            champion_id: Some(ChampionId(266)),
+        };
+        assert_eq!(encode_query(&query).unwrap(), "region=euw&champion_id=266");
+    }
+
+    #[test]
+    fn test_merges_with_existing_query() {
+        let query = SkinQuery {
+            region: Region::Cn,
+            champion_id: Some(ChampionId(1)),
+        };
+        let encoded = encode_query(&query).unwrap();
+        assert_eq!(
+            merge_query("/v1/lol/champion/skin?foo=1", &encoded),
+            "/v1/lol/champion/skin?foo=1&region=cn&champion_id=1"
+        );
+        assert_eq!(
+            merge_query("/v1/lol/champion/skin", &encoded),
+            "/v1/lol/champion/skin?region=cn&champion_id=1"
+        );
+        assert_eq!(merge_query("/v1/x?", "a=1"), "/v1/x?a=1");
+        assert_eq!(merge_query("/v1/x?foo=1", ""), "/v1/x?foo=1");
+    }
+
+    #[test]
+    fn test_unsupported_shapes_are_rejected() {
+        #[derive(Serialize)]
+        struct Nested {
+            inner: SkinQuery,
+        }
+        #[derive(Serialize)]
+        struct WithList {
+            ids: Vec<u64>,
+        }
+
+        let nested = Nested {
+            inner: SkinQuery {
+                region: Region::Cn,
+                champion_id: None,
+            },
+        };
+        assert!(matches!(encode_query(&nested), Err(ApiClientError::InvalidQuery(_))));
+        assert!(matches!(
+            encode_query(&WithList { ids: vec![1, 2] }),
+            Err(ApiClientError::InvalidQuery(_))
+        ));
+    }
+
+    fn checked(uri: &str) -> Result<String, String> {
+        match check_uri(uri) {
+            Ok(uri) => Ok(uri.into_owned()),
+            Err(ApiClientError::InvalidUri { reason, .. }) => Err(reason),
+            Err(other) => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_hostile_uris() {
+        let cases = [
+            (
+                "/v1/lol/champion/skin?region=cn&id=1",
+                Ok("/v1/lol/champion/skin?region=cn&id=1"),
+            ),
+            ("", Ok("")),
+            ("/v1/a%20b?q=%0D%0A", Ok("/v1/a%20b?q=%0D%0A")),
+            ("/v1/a/..b/c..?x=..", Ok("/v1/a/..b/c..?x=..")),
+            ("/v1/a?next=/../b", Ok("/v1/a?next=/../b")),
+            (
+                "/v1/英雄/安妮?name=亚索",
+                Ok("/v1/%E8%8B%B1%E9%9B%84/%E5%AE%89%E5%A6%AE?name=%E4%BA%9A%E7%B4%A2"),
+            ),
+            ("/v1/{id}/\"x\"?q={'a'}", Ok("/v1/%7Bid%7D/%22x%22?q={%27a%27}")),
+            ("/v1/a|b^c?q=<x>", Ok("/v1/a|b^c?q=%3Cx%3E")),
+            ("/v1/a\r\nHO-NONCE: x", Err("contains a control character")),
+            ("/v1/a\0b", Err("contains a control character")),
+            ("/v1/a\u{7f}", Err("contains a control character")),
+            ("/v1/a\u{85}", Err("contains a control character")),
+            ("/v1/a b", Err("contains whitespace")),
+            ("/v1/a\u{3000}b", Err("contains whitespace")),
+            ("/v1/a\tb", Err("contains a control character")),
+            ("/v1/a#top", Err("contains a fragment")),
+            ("/v1/a\\b", Err("contains a backslash")),
+            ("/v1/../admin", Err("contains a `.` or `..` segment")),
+            ("/v1/%2E%2e/admin", Err("contains a `.` or `..` segment")),
+            ("/v1/./a", Err("contains a `.` or `..` segment")),
+            ("/v1/a/..", Err("contains a `.` or `..` segment")),
+            ("https://evil.example.com/v1/a", Err("does not start with `/`")),
+            ("v1/a", Err("does not start with `/`")),
+            ("?a=1", Err("does not start with `/`")),
+        ];
+        for (uri, expected) in cases {
+            assert_eq!(
+                checked(uri),
+                expected.map(str::to_string).map_err(str::to_string),
+                "{:?}",
+                uri
+            );
+        }
+    }
+
+    #[test]
+    fn test_accepted_uris_reach_the_wire_as_signed() {
+        // Every character in the path and in the query: either refused, or sent verbatim.
+        let characters = (0..0x80u8)
+            .map(char::from)
+            .chain(['é', '安', '😀', '\u{a0}', '\u{200b}']);
+        for c in characters {
+            let uri = format!("/v1/a{}b/{}?q=x{}y&{}", c, c, c, c);
+            let Ok(checked) = check_uri(&uri) else {
+                continue;
+            };
+            let url = reqwest::Url::parse(&format!("https://api.example.com/server/common/api{}", checked)).unwrap();
+            let sent = format!("{}?{}", url.path(), url.query().unwrap());
+            assert_eq!(sent, format!("/server/common/api{}", checked), "{:?}", c);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_uri_is_rejected_before_signing() {
+        let transport = MockTransport::new();
+        let client = mock_client(&transport, |_| {});
+        let error = client
+            .request(Method::GET, "/v1/skin\r\nHO-TIMESTAMP: 0")
+            .send()
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ApiClientError::InvalidUri { .. }), "{:?}", error);
+        assert!(error.is_invalid_request());
+        assert!(transport.requests().is_empty());
+
+        transport.on(
+            Method::GET,
+            "/v1/hero/%E5%AE%89%E5%A6%AE",
+            MockResponse::encrypted("{}"),
+        );
+        client.send(Method::GET, "/v1/hero/安妮", None).await.unwrap();
+        let request = transport.requests().remove(0);
+        let header = |name: &str| request.headers[name].to_str().unwrap().to_string();
+        let expected = sign(
+            TEST_APP_ID,
+            TEST_APP_SECRET,
+            &header("HO-NONCE"),
+            header("HO-TIMESTAMP").parse().unwrap(),
+            "/v1/hero/%E5%AE%89%E5%A6%AE",
+            SignableBody::Empty,
+        );
+        assert_eq!(header("HO-SIGNATURE"), expected);
+    }
+
+    /// Sends an admin request and checks the signature, returning the requested url.
+    async fn send_admin_request(sign_content_path: bool) -> String {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/admin/users", MockResponse::encrypted("[]"));
+        let client = mock_client(&transport, |config| config.sign_content_path = sign_content_path);
+        client
+            .request(Method::GET, "/v1/admin/users")
+            .content_path("/server/admin/api")
+            .send()
+            .await
+            .unwrap();
+
+        let request = transport.requests().remove(0);
+        let header = |name: &str| request.headers[name].to_str().unwrap().to_string();
+        let timestamp: i64 = header("HO-TIMESTAMP").parse().unwrap();
+        let signed_uri = match sign_content_path {
+            true => "/server/admin/api/v1/admin/users",
+            false => "/v1/admin/users",
+        };
+        let expected = sign(
+            TEST_APP_ID,
+            TEST_APP_SECRET,
+            &header("HO-NONCE"),
+            timestamp,
+            signed_uri,
+            SignableBody::Empty,
+        );
+        assert_eq!(header("HO-SIGNATURE"), expected);
+        request.url
+    }
+
+    #[tokio::test]
+    async fn test_content_path_override_replaces_prefix_only() {
+        let expected = "https://mock.zelaser.test/server/admin/api/v1/admin/users";
+        assert_eq!(send_admin_request(false).await, expected);
+        assert_eq!(send_admin_request(true).await, expected);
+    }
+
+    #[tokio::test]
+    async fn test_default_content_path_is_unchanged() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/users", MockResponse::encrypted("[]"));
+        let client = mock_client(&transport, |_| {});
+        client.request(Method::GET, "/v1/users").send().await.unwrap();
+        assert_eq!(
+            transport.requests()[0].url,
+            "https://mock.zelaser.test/server/common/api/v1/users"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_request_is_rejected_before_sending() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/lol/champion/skin", MockResponse::encrypted("[]"));
+        let client = mock_client(&transport, |_| {});
+
+        let result = client
+            .request(Method::GET, "/v1/lol/champion/skin?region=cn")
+            .query(&[("page_size", 500)])
+            .send()
+            .await;
+        assert!(matches!(
+            result,
+            Err(ApiClientError::RequestValidation { ref field, .. }) if field == "page_size"
+        ));
+        assert!(transport.requests().is_empty());
+        assert_eq!(client.stats().errors.invalid_request, 1);
+
+        client
+            .request(Method::GET, "/v1/lol/champion/skin?region=cn")
+            .query(&[("page_size", 200)])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_validation_can_be_turned_off() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/lol/champion/skin", MockResponse::encrypted("[]"));
+        let client = mock_client(&transport, |config| config.validate_requests = false);
+
+        client
+            .send(Method::GET, "/v1/lol/champion/skin?page_size=500", None)
+            .await
+            .unwrap();
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    fn version_sent(transport: &MockTransport) -> Vec<Option<String>> {
+        transport
+            .requests()
+            .iter()
+            .map(|r| r.headers.get("HO-VERSION").map(|v| v.to_str().unwrap().to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_api_version_header() {
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/lol/champion/skin", MockResponse::encrypted("[]"));
+        let uri = "/v1/lol/champion/skin?region=cn";
+
+        let unversioned = mock_client(&transport, |_| {});
+        let response = unversioned.send_detailed(Method::GET, uri, None).await.unwrap();
+        assert_eq!(response.meta.api_version, None);
+
+        let versioned = mock_client(&transport, |config| config.api_version = Some("2024-06-01".to_string()));
+        let response = versioned.send_detailed(Method::GET, uri, None).await.unwrap();
+        assert_eq!(response.meta.api_version.as_deref(), Some("2024-06-01"));
+        let response = versioned
+            .request(Method::GET, uri)
+            .api_version("2025-01-15")
+            .send_detailed()
+            .await
+            .unwrap();
+        assert_eq!(response.meta.api_version.as_deref(), Some("2025-01-15"));
+
+        assert_eq!(
+            version_sent(&transport),
+            vec![None, Some("2024-06-01".to_string()), Some("2025-01-15".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_api_version_is_rejected() {
+        let transport = MockTransport::new();
+        let config = crate::ApiClientConfig {
+            api_version: Some("2024-06-01\r\nX-Injected: 1".to_string()),
+            ..crate::testing::test_config()
+        };
+        assert!(matches!(
+            ApiClient::with_transport(config, transport.clone()),
+            Err(ApiClientError::InvalidConfig(_))
+        ));
+
+        let client = mock_client(&transport, |_| {});
+        match client
+            .request(Method::GET, "/v1/lol/champion/skin?region=cn")
+            .api_version("2024 06 01")
+            .send()
+            .await
+        {
+            Err(ApiClientError::RequestValidation { field, .. }) => assert_eq!(field, "api_version"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(transport.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deprecation_header_reaches_the_metadata() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/lol/legacy/rank",
+            MockResponse::encrypted("[]").with_header("ho-deprecation", "2023-01-01 is retired on 2025-01-01"),
+        );
+        let client = mock_client(&transport, |config| config.api_version = Some("2023-01-01".to_string()));
+
+        let response = client
+            .send_detailed(Method::GET, "/v1/lol/legacy/rank", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            response.meta.deprecation.as_deref(),
+            Some("2023-01-01 is retired on 2025-01-01")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_requests_carry_no_ho_headers() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::GET, "/v1/lol/champion/skin", MockResponse::encrypted("[]"))
+            .on(
+                Method::GET,
+                "/v1/public/status",
+                MockResponse::raw(reqwest::StatusCode::OK, r#"{"status":"up"}"#),
+            );
+        let client = mock_client(&transport, |config| config.api_version = Some("2024-06-01".to_string()));
+
+        client
+            .send(Method::GET, "/v1/lol/champion/skin?region=cn", None)
+            .await
+            .unwrap();
+        let status = client
+            .request(Method::GET, "/v1/public/status")
+            .auth(AuthMode::None)
+            .parse_envelope(false)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(status, r#"{"status":"up"}"#);
+
+        let ho_headers = |i: usize| -> Vec<String> {
+            let requests = transport.requests();
+            let mut names: Vec<String> = requests[i]
+                .headers
+                .keys()
+                .map(|name| name.as_str().to_string())
+                .filter(|name| name.starts_with("ho-"))
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            ho_headers(0),
+            ["ho-app-id", "ho-nonce", "ho-signature", "ho-timestamp", "ho-version"]
+        );
+        assert!(ho_headers(1).is_empty(), "{:?}", ho_headers(1));
+        assert_eq!(
+            transport.requests()[1].url,
+            "https://mock.zelaser.test/server/common/api/v1/public/status"
+        );
+    }
+
+    #[cfg(feature = "charset")]
+    #[tokio::test]
+    async fn test_gbk_fixture_round_trips() {
+        let gbk = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/champions_gbk.json")).unwrap();
+        assert!(String::from_utf8(gbk.clone()).is_err());
+        let envelope = serde_json::json!({ "data": crate::testing::test_crypto().encrypt_hex(&gbk) });
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/lol/legacy/champion",
+            MockResponse::json(reqwest::StatusCode::OK, &envelope),
+        );
+        let uri = "/v1/lol/legacy/champion";
+
+        let utf8 = mock_client(&transport, |_| {});
+        assert!(matches!(
+            utf8.send(Method::GET, uri, None).await,
+            Err(ApiClientError::InvalidCharset {
+                charset: Charset::Utf8,
+                ..
+            })
+        ));
+        let plaintext = utf8
+            .request(Method::GET, uri)
+            .response_charset(Charset::Gbk)
+            .send()
+            .await
+            .unwrap();
+        assert!(plaintext.contains("黑暗之女 安妮"), "{}", plaintext);
+
+        let auto = mock_client(&transport, |config| config.response_charset = Charset::Auto);
+        let (champions, total) =
+            crate::payload::parse_list::<Value>(&auto.send(Method::GET, uri, None).await.unwrap()).unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(champions[2]["name"], "德玛西亚之力 盖伦");
+    }
+}
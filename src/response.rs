@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+
+use crate::priority::Priority;
+use crate::quota::QuotaInfo;
+
+/// Plaintext body plus what the client observed while producing it.
+#[derive(Debug, Clone)]
+pub struct ApiResponse {
+    pub body: String,
+    pub meta: ResponseMeta,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ResponseMeta {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// Base URL of the host that produced the response.
+    pub base_url: String,
+    /// Time spent waiting for a concurrency permit before the request was dispatched.
+    pub queue_time: Duration,
+    /// The `ApiClientConfig::pools` entry the request ran in, if any.
+    pub pool: Option<String>,
+    /// The `RequestBuilder::priority` the request queued with.
+    pub priority: Priority,
+    /// Total time from dispatch to decrypted body, excluding `queue_time`.
+    pub elapsed: Duration,
+    /// Whether the body came from `ApiClientConfig::cache` rather than the network. Cached
+    /// responses carry status 200 and no headers.
+    pub from_cache: bool,
+    /// The body is a cached one past its ttl, served because the request failed; see
+    /// `CacheConfig::max_stale`. Implies `from_cache`.
+    pub stale: bool,
+    /// The `HO-VERSION` the request was sent with, for quoting in support tickets.
+    pub api_version: Option<String>,
+    /// The server's `HO-DEPRECATION` warning, when it sent one.
+    pub deprecation: Option<String>,
+    /// Which key decrypted the body. `Secondary` means the server has moved to (or is still
+    /// on) the other key of a rotation.
+    pub key: DecryptionKey,
+    /// The server cut the response short: status 206 or `HO-TRUNCATED: true`. The body is
+    /// a valid envelope holding only the first items of the list.
+    pub truncated: bool,
+    /// Where the time of the attempt that produced the response went.
+    pub timings: Timings,
+    /// The app_id's quota as `HO-QUOTA-*` reported it, when the response carried them.
+    pub quota: Option<QuotaInfo>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecryptionKey {
+    #[default]
+    Primary,
+    /// `ApiClientConfig::secondary_key`.
+    Secondary,
+}
+
+/// Phases of one attempt. `None` means the phase was not measured, not that it took no time:
+/// reqwest reports neither DNS, connect nor TLS times, so they are known only on a reused
+/// connection, where they are zero. Transports other than `ReqwestTransport` fill in what
+/// they can.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    pub dns: Option<Duration>,
+    pub connect: Option<Duration>,
+    pub tls: Option<Duration>,
+    /// From sending the request to receiving the response headers.
+    pub time_to_first_byte: Option<Duration>,
+    /// From the response headers to the last byte of the body.
+    pub body_read: Option<Duration>,
+    /// Reading the `{"data": ...}` envelope JSON.
+    pub parse: Option<Duration>,
+    /// Decrypting and decoding `data`, with both keys when the first fails.
+    pub decrypt: Option<Duration>,
+    /// Deserializing the plaintext as the caller's type. Only `RequestBuilder::send_detailed_as`
+    /// deserializes and returns timings; `send_detailed` leaves it `None`.
+    pub deserialize: Option<Duration>,
+    /// The request went out on a pooled connection rather than a new one.
+    pub connection_reused: bool,
+    /// `parse` and `decrypt` ran on the blocking pool; see `ApiClientConfig::offload_threshold`.
+    pub offloaded: bool,
+}
+
+impl Timings {
+    /// The timings of a request sent on a pooled connection, which had no connect phases.
+    pub fn reused() -> Self {
+        Self {
+            dns: Some(Duration::ZERO),
+            connect: Some(Duration::ZERO),
+            tls: Some(Duration::ZERO),
+            connection_reused: true,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+/// How often a request that failed on every host is sent again. Each retry goes through the
+/// configured hosts in order of health once more.
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// Rounds over the hosts after the first one. `0` disables retries.
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Factor applied to the backoff after every retry.
+    pub multiplier: f64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Wait before the `retry`th (1-based) retry.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(retry.saturating_sub(1) as i32);
+        self.initial_backoff.mul_f64(factor).min(self.max_backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_to_the_cap() {
+        let retry = RetryConfig {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let waits: Vec<Duration> = (1..=5).map(|n| retry.backoff(n)).collect();
+        assert_eq!(waits, [1, 2, 4, 5, 5].map(Duration::from_secs).to_vec());
+    }
+}
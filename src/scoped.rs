@@ -0,0 +1,301 @@
+//! Handles on a client with some settings overridden for one code path, such as a shorter
+//! timeout or another tenant's credentials. A `ScopedClient` borrows its client, so requests
+//! through it share the connections, cache, concurrency limits, failover scores and stats;
+//! making one costs a few clones and never touches the client itself.
+//!
+//! A setting made on a `RequestBuilder` from `ScopedClient::request` wins over the scope's,
+//! and the scope's over `ApiClientConfig`.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::crypto::Crypto;
+use crate::request::{RequestBuilder, RequestOptions};
+use crate::response::ApiResponse;
+use crate::retry::RetryConfig;
+use crate::{ApiClient, ApiClientError};
+
+/// Another app's credentials: requests are signed with `app_id` and `app_secret`, and
+/// responses decrypted with `app_secret` and `iv`.
+#[derive(Clone)]
+pub struct Credentials {
+    pub app_id: String,
+    pub app_secret: String,
+    pub iv: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("app_id", &self.app_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// `Credentials` ready for use, shared by the requests of a scope.
+pub(crate) struct ScopedKey {
+    pub(crate) app_id: String,
+    pub(crate) app_secret: String,
+    pub(crate) crypto: Crypto,
+}
+
+impl fmt::Debug for ScopedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScopedKey")
+            .field("app_id", &self.app_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// See the module documentation. Created with `ApiClient::scoped`.
+#[derive(Clone)]
+pub struct ScopedClient<'a> {
+    client: &'a ApiClient,
+    options: RequestOptions,
+}
+
+impl ApiClient {
+    /// A handle on this client whose overrides apply to every request made through it.
+    pub fn scoped(&self) -> ScopedClient<'_> {
+        ScopedClient {
+            client: self,
+            options: RequestOptions::default(),
+        }
+    }
+}
+
+impl<'a> ScopedClient<'a> {
+    /// Overrides `ApiClientConfig::attempt_timeout`.
+    pub fn timeout(mut self, attempt_timeout: Duration) -> Self {
+        self.options.attempt_timeout = Some(attempt_timeout);
+        self
+    }
+
+    /// Signs requests and decrypts responses with `credentials` instead of the client's own
+    /// key; `secondary_key` is not tried. Responses are cached apart from other scopes'.
+    /// Fails if the secret or iv has the wrong length.
+    pub fn credentials(mut self, credentials: Credentials) -> Result<Self, ApiClientError> {
+        let config = &self.client.config;
+        let crypto = Crypto::new(&credentials.app_secret, &credentials.iv)
+            .map_err(|_| ApiClientError::InvalidConfig("credentials have an invalid secret or iv length".to_string()))?
+            .strict_hex(config.strict_hex)
+            .charset(config.response_charset);
+        self.options.credentials = Some(Arc::new(ScopedKey {
+            app_id: credentials.app_id,
+            app_secret: credentials.app_secret,
+            crypto,
+        }));
+        Ok(self)
+    }
+
+    /// Adds `headers` to every request, replacing earlier ones of the same name. Headers the
+    /// client sets itself, such as `HO-SIGNATURE`, replace these. Responses are cached apart
+    /// from other scopes'.
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        self.options.headers.extend(headers);
+        self
+    }
+
+    /// Retries by `retry` instead of `ApiClientConfig::retry`.
+    pub fn retry_policy(mut self, retry: RetryConfig) -> Self {
+        self.options.retry = Some(retry);
+        self
+    }
+
+    /// A request starting from the scope's overrides.
+    pub fn request(&self, method: Method, uri: &str) -> RequestBuilder<'a> {
+        self.client.request_with(method, uri, self.options.clone())
+    }
+
+    pub async fn send(&self, method: Method, uri: &str, body_option: Option<Value>) -> Result<String, ApiClientError> {
+        Ok(self.send_detailed(method, uri, body_option).await?.body)
+    }
+
+    pub async fn send_detailed(
+        &self,
+        method: Method,
+        uri: &str,
+        body_option: Option<Value>,
+    ) -> Result<ApiResponse, ApiClientError> {
+        let mut request = self.request(method, uri);
+        if let Some(body) = body_option {
+            request = request.body(body);
+        }
+        request.send_detailed().await
+    }
+
+    pub async fn send_as<T: DeserializeOwned + Send + 'static>(
+        &self,
+        method: Method,
+        uri: &str,
+        body_option: Option<Value>,
+    ) -> Result<T, ApiClientError> {
+        let decrypted_str = self.send(method, uri, body_option).await?;
+        self.client.deserialize(decrypted_str).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::cache::CacheConfig;
+    use crate::signing::{self, SignableBody};
+    use crate::testing::{mock_client, MockResponse, MockTransport, TEST_APP_ID, TEST_APP_SECRET};
+    use crate::transport::TransportRequest;
+
+    fn tenant(id: &str) -> Credentials {
+        Credentials {
+            app_id: format!("tenant-{}", id),
+            app_secret: format!("tenant-{}-secret-0123456789abcdef", id),
+            iv: "abcdef0123456789".to_string(),
+        }
+    }
+
+    /// 200 with `plaintext` encrypted for `credentials`.
+    fn encrypted_for(credentials: &Credentials, plaintext: &str) -> MockResponse {
+        let crypto = Crypto::new(&credentials.app_secret, &credentials.iv).unwrap();
+        MockResponse::json(
+            StatusCode::OK,
+            &json!({ "data": crypto.encrypt_hex(plaintext.as_bytes()) }),
+        )
+    }
+
+    /// Whether `request` carries a valid signature by `app_id` and `app_secret`.
+    fn signed_by(request: &TransportRequest, app_id: &str, app_secret: &str) -> bool {
+        let header = |name| request.headers[name].to_str().unwrap();
+        let uri = request.url.split_once("/server/common/api").unwrap().1;
+        header("HO-APP-ID") == app_id
+            && header("HO-SIGNATURE")
+                == signing::sign(
+                    app_id,
+                    app_secret,
+                    header("HO-NONCE"),
+                    header("HO-TIMESTAMP").parse().unwrap(),
+                    uri,
+                    SignableBody::Empty,
+                )
+    }
+
+    #[tokio::test]
+    async fn test_scopes_sign_and_decrypt_with_their_own_credentials() {
+        let (a, b) = (tenant("a"), tenant("b"));
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::GET,
+                "/v1/profile?tenant=a",
+                encrypted_for(&a, r#"{"tenant":"a"}"#),
+            )
+            .on(
+                Method::GET,
+                "/v1/profile?tenant=b",
+                encrypted_for(&b, r#"{"tenant":"b"}"#),
+            )
+            .on(
+                Method::GET,
+                "/v1/profile",
+                MockResponse::encrypted(r#"{"tenant":null}"#),
+            );
+        let client = mock_client(&transport, |config| {
+            config.cache = Some(CacheConfig::memory(Duration::from_secs(60)))
+        });
+        let scope_a = client.scoped().credentials(a.clone()).unwrap();
+        let scope_b = client.scoped().credentials(b.clone()).unwrap();
+
+        assert_eq!(
+            scope_a.send(Method::GET, "/v1/profile?tenant=a", None).await.unwrap(),
+            r#"{"tenant":"a"}"#
+        );
+        assert_eq!(
+            scope_b.send(Method::GET, "/v1/profile?tenant=b", None).await.unwrap(),
+            r#"{"tenant":"b"}"#
+        );
+        assert_eq!(
+            client.send(Method::GET, "/v1/profile", None).await.unwrap(),
+            r#"{"tenant":null}"#
+        );
+        let requests = transport.requests();
+        assert!(signed_by(&requests[0], &a.app_id, &a.app_secret));
+        assert!(signed_by(&requests[1], &b.app_id, &b.app_secret));
+        assert!(signed_by(&requests[2], TEST_APP_ID, TEST_APP_SECRET));
+        assert_ne!(requests[0].headers["HO-SIGNATURE"], requests[1].headers["HO-SIGNATURE"]);
+        assert_eq!(client.config.app_id, TEST_APP_ID);
+
+        // The client's key cannot open tenant a's response, and a's cache entry is its own.
+        assert!(client.send(Method::GET, "/v1/profile?tenant=a", None).await.is_err());
+        assert!(
+            scope_a
+                .send_detailed(Method::GET, "/v1/profile?tenant=a", None)
+                .await
+                .unwrap()
+                .meta
+                .from_cache
+        );
+        assert_eq!(transport.requests().len(), 4);
+
+        let invalid = Credentials {
+            iv: "short".to_string(),
+            ..a
+        };
+        assert!(matches!(
+            client.scoped().credentials(invalid),
+            Err(ApiClientError::InvalidConfig(_))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_settings_win_over_the_scope() {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::GET,
+            "/v1/slow",
+            MockResponse::encrypted("{}").with_delay(Duration::from_secs(5)),
+        );
+        transport
+            .on(Method::GET, "/v1/flaky", MockResponse::status(StatusCode::BAD_GATEWAY))
+            .on(Method::GET, "/v1/flaky", MockResponse::encrypted("{}"));
+        let client = mock_client(&transport, |_| {});
+        let mut headers = HeaderMap::new();
+        headers.insert("x-tenant", HeaderValue::from_static("a"));
+        headers.insert("ho-signature", HeaderValue::from_static("forged"));
+        let scope = client
+            .scoped()
+            .timeout(Duration::from_secs(2))
+            .headers(headers)
+            .retry_policy(RetryConfig {
+                max_retries: 1,
+                ..RetryConfig::default()
+            });
+
+        assert!(matches!(
+            scope.send(Method::GET, "/v1/slow", None).await,
+            Err(ApiClientError::AttemptTimeout { .. })
+        ));
+        let patient = scope.request(Method::GET, "/v1/slow").timeout(Duration::from_secs(10));
+        assert!(patient.send().await.is_ok());
+        assert!(client.send(Method::GET, "/v1/slow", None).await.is_ok());
+
+        let no_retry = scope.request(Method::GET, "/v1/flaky").retry(RetryConfig::default());
+        assert!(no_retry.send().await.is_err());
+        assert!(scope.send(Method::GET, "/v1/flaky", None).await.is_ok());
+
+        // Two attempts at the slow endpoint by the scope's retry policy, one by the request's.
+        let requests = transport.requests();
+        let tenants: Vec<_> = requests.iter().map(|request| request.headers.get("x-tenant")).collect();
+        let a = Some(&HeaderValue::from_static("a"));
+        assert_eq!(tenants, [a, a, a, None, a, a]);
+        assert!(requests
+            .iter()
+            .all(|request| signed_by(request, TEST_APP_ID, TEST_APP_SECRET)));
+    }
+}
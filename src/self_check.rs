@@ -0,0 +1,249 @@
+//! Offline checks of the key material, for deploy tooling to run before a client takes
+//! traffic. A swapped secret and IV, or a key pasted in its hex form, otherwise only shows up
+//! as decryption errors once requests start failing.
+//!
+//! ```no_run
+//! # fn check(client: &ho_api_rust_sdk::ApiClient) -> Result<(), ho_api_rust_sdk::ApiClientError> {
+//! let report = client.self_check()?;
+//! println!("{}", serde_json::to_string(&report).unwrap()); // {"warnings":["iv_is_secret_prefix"]}
+//! # Ok(())
+//! # }
+//! ```
+
+use serde::Serialize;
+
+use crate::conformance::{SIGNATURE_VECTORS, VECTOR_APP_ID, VECTOR_APP_SECRET, VECTOR_NONCE, VECTOR_TIMESTAMP};
+use crate::crypto::{Crypto, IV_LEN, SECRET_LEN};
+use crate::signing::sign;
+use crate::{ApiClient, ApiClientConfig, ApiClientError};
+
+const PROBE: &str = r#"{"self_check":"ok"}"#;
+
+/// A suspicious shape of the configured key material. Serialized as its `code`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfCheckWarning {
+    /// `app_secret` is 64 hex digits: the hex form of a 32-byte key rather than the key.
+    SecretLooksHex,
+    /// `iv` is 32 hex digits: the hex form of a 16-byte IV rather than the IV.
+    IvLooksHex,
+    /// `app_secret` is 16 bytes and `iv` 32: the two are swapped.
+    SecretAndIvSwapped,
+    /// `iv` is the first 16 bytes of `app_secret`.
+    IvIsSecretPrefix,
+    /// `secondary_key` repeats the primary secret and IV, so it cannot help in a rotation.
+    SecondaryKeyIsPrimary,
+}
+
+impl SelfCheckWarning {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SelfCheckWarning::SecretLooksHex => "secret_looks_hex",
+            SelfCheckWarning::IvLooksHex => "iv_looks_hex",
+            SelfCheckWarning::SecretAndIvSwapped => "secret_and_iv_swapped",
+            SelfCheckWarning::IvIsSecretPrefix => "iv_is_secret_prefix",
+            SelfCheckWarning::SecondaryKeyIsPrimary => "secondary_key_is_primary",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            SelfCheckWarning::SecretLooksHex => "app_secret is 64 hex digits; pass the 32 bytes they encode",
+            SelfCheckWarning::IvLooksHex => "iv is 32 hex digits; pass the 16 bytes they encode",
+            SelfCheckWarning::SecretAndIvSwapped => "app_secret is 16 bytes and iv 32; they look swapped",
+            SelfCheckWarning::IvIsSecretPrefix => "iv is the first 16 bytes of app_secret",
+            SelfCheckWarning::SecondaryKeyIsPrimary => "secondary_key is the same as the primary key",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SelfCheckReport {
+    pub warnings: Vec<SelfCheckWarning>,
+}
+
+impl SelfCheckReport {
+    pub fn is_clean(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    /// `InvalidConfig` naming every warning, or the report if there are none.
+    pub fn into_result(self) -> Result<Self, ApiClientError> {
+        if self.is_clean() {
+            return Ok(self);
+        }
+        let listed: Vec<String> = self
+            .warnings
+            .iter()
+            .map(|warning| format!("{} ({})", warning.code(), warning.message()))
+            .collect();
+        Err(ApiClientError::InvalidConfig(format!(
+            "self check failed: {}",
+            listed.join("; ")
+        )))
+    }
+}
+
+/// The shape checks alone, for configurations that `ApiClient::new` would reject outright,
+/// such as a hex-encoded secret.
+pub fn check_config(config: &ApiClientConfig) -> SelfCheckReport {
+    let is_hex = |text: &str| text.bytes().all(|b| b.is_ascii_hexdigit());
+    let secret = config.app_secret.as_str();
+    let iv = config.iv.as_str();
+    let mut warnings = Vec::new();
+    if secret.len() == 2 * SECRET_LEN && is_hex(secret) {
+        warnings.push(SelfCheckWarning::SecretLooksHex);
+    }
+    if iv.len() == 2 * IV_LEN && is_hex(iv) && secret.len() != IV_LEN {
+        warnings.push(SelfCheckWarning::IvLooksHex);
+    }
+    if secret.len() == IV_LEN && iv.len() == SECRET_LEN {
+        warnings.push(SelfCheckWarning::SecretAndIvSwapped);
+    }
+    if iv.len() == IV_LEN && secret.as_bytes().starts_with(iv.as_bytes()) {
+        warnings.push(SelfCheckWarning::IvIsSecretPrefix);
+    }
+    if let Some(secondary) = &config.secondary_key {
+        if secondary.app_secret == secret && secondary.iv == iv {
+            warnings.push(SelfCheckWarning::SecondaryKeyIsPrimary);
+        }
+    }
+    SelfCheckReport { warnings }
+}
+
+impl ApiClient {
+    /// Checks the client without touching the network: a known plaintext survives a round
+    /// trip through each configured key, the signature code reproduces the conformance
+    /// vectors, and the key material has no suspicious shape. Round-trip and signature
+    /// failures are errors; shapes are the report's warnings. Runs inside `new` when
+    /// `ApiClientConfig::strict_self_check` is set.
+    pub fn self_check(&self) -> Result<SelfCheckReport, ApiClientError> {
+        let keys = [Some(&self.crypto), self.secondary_crypto.as_ref()];
+        for (name, crypto) in ["app_secret", "secondary_key"].into_iter().zip(keys) {
+            if let Some(crypto) = crypto {
+                round_trip(name, crypto)?;
+            }
+        }
+        for vector in SIGNATURE_VECTORS {
+            let got = sign(
+                VECTOR_APP_ID,
+                VECTOR_APP_SECRET,
+                VECTOR_NONCE,
+                VECTOR_TIMESTAMP,
+                vector.uri,
+                vector.body,
+            );
+            if got != vector.expected {
+                return Err(ApiClientError::InvalidConfig(format!(
+                    "self check failed: signature vector `{}` gave {}, expected {}",
+                    vector.name, got, vector.expected
+                )));
+            }
+        }
+        Ok(check_config(&self.config))
+    }
+}
+
+fn round_trip(name: &str, crypto: &Crypto) -> Result<(), ApiClientError> {
+    let ciphertext = crypto.encrypt_hex(PROBE.as_bytes());
+    match crypto.decrypt_hex(ciphertext.as_bytes()) {
+        Ok(plaintext) if plaintext == PROBE => Ok(()),
+        Ok(plaintext) => Err(ApiClientError::InvalidConfig(format!(
+            "self check failed: {} decrypts its own ciphertext to {:?}",
+            name, plaintext
+        ))),
+        Err(e) => Err(ApiClientError::InvalidConfig(format!(
+            "self check failed: {} cannot decrypt its own ciphertext: {:?}",
+            name, e
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_config, MockTransport};
+    use crate::SecondaryKey;
+
+    fn with_keys(app_secret: &str, iv: &str) -> ApiClientConfig {
+        ApiClientConfig {
+            app_secret: app_secret.to_string(),
+            iv: iv.to_string(),
+            ..test_config()
+        }
+    }
+
+    #[test]
+    fn test_sound_config_has_no_warnings() {
+        let client = ApiClient::with_transport(test_config(), MockTransport::new()).unwrap();
+        let report = client.self_check().unwrap();
+        assert!(report.is_clean());
+        assert_eq!(serde_json::to_string(&report).unwrap(), r#"{"warnings":[]}"#);
+    }
+
+    #[test]
+    fn test_shapes_are_reported_by_code() {
+        let hex_secret = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let hex_iv = "00112233445566778899aabbccddeeff";
+        let report = check_config(&with_keys(hex_secret, hex_iv));
+        assert_eq!(
+            report.warnings,
+            [SelfCheckWarning::SecretLooksHex, SelfCheckWarning::IvLooksHex]
+        );
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"warnings":["secret_looks_hex","iv_looks_hex"]}"#
+        );
+
+        let swapped = check_config(&with_keys("fedcba9876543210", "0123456789abcdef0123456789abcdef"));
+        assert_eq!(swapped.warnings, [SelfCheckWarning::SecretAndIvSwapped]);
+
+        let mut config = with_keys("0123456789abcdef0123456789abcdef", "0123456789abcdef");
+        config.secondary_key = Some(SecondaryKey {
+            app_secret: config.app_secret.clone(),
+            iv: config.iv.clone(),
+        });
+        let client = ApiClient::with_transport(config, MockTransport::new()).unwrap();
+        assert_eq!(
+            client.self_check().unwrap().warnings,
+            [
+                SelfCheckWarning::IvIsSecretPrefix,
+                SelfCheckWarning::SecondaryKeyIsPrimary
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strict_self_check_turns_warnings_into_errors() {
+        let mut config = with_keys("0123456789abcdef0123456789abcdef", "0123456789abcdef");
+        config.strict_self_check = true;
+        match ApiClient::with_transport(config, MockTransport::new()) {
+            Err(ApiClientError::InvalidConfig(message)) => assert_eq!(
+                message,
+                "self check failed: iv_is_secret_prefix (iv is the first 16 bytes of app_secret)"
+            ),
+            Err(e) => panic!("unexpected {:?}", e),
+            Ok(_) => panic!("strict self check passed"),
+        }
+
+        let mut config = with_keys("fedcba9876543210", "0123456789abcdef0123456789abcdef");
+        config.strict_self_check = true;
+        match ApiClient::with_transport(config, MockTransport::new()) {
+            Err(ApiClientError::InvalidConfig(message)) => {
+                assert!(
+                    message.starts_with("self check failed: secret_and_iv_swapped"),
+                    "{}",
+                    message
+                )
+            }
+            Err(e) => panic!("unexpected {:?}", e),
+            Ok(_) => panic!("strict self check passed"),
+        }
+
+        let config = ApiClientConfig {
+            strict_self_check: true,
+            ..test_config()
+        };
+        assert!(ApiClient::with_transport(config, MockTransport::new()).is_ok());
+    }
+}
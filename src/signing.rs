@@ -0,0 +1,178 @@
+//! The `HO-SIGNATURE` computation.
+//!
+//! The signature is the lowercase hex SHA-1 of the concatenation, with no separators, of:
+//!
+//! 1. `app_id`
+//! 2. the nonce sent as `HO-NONCE`
+//! 3. the timestamp sent as `HO-TIMESTAMP`, in decimal milliseconds
+//! 4. the request uri exactly as appended to the content path, query string included. The
+//!    content path itself is not signed unless `ApiClientConfig::sign_content_path` is set,
+//!    in which case it is prepended here
+//! 5. the body component, see [`SignableBody`]
+//! 6. `app_secret`
+//!
+//! [`spec`] holds this, and the presigned variant, as the component lists everything is
+//! signed from; [`describe`] prints one.
+
+#[cfg(feature = "client")]
+use std::borrow::Cow;
+
+use sha1::{Digest, Sha1};
+
+pub mod spec;
+
+pub use spec::{describe, SignInputs, SignMode};
+
+#[derive(Debug, Clone, Copy)]
+pub enum SignableBody<'a> {
+    /// JSON or other text bodies: the UTF-8 bytes of the text, as serialized for the wire.
+    Text(&'a str),
+    /// Binary bodies: the 40-character lowercase hex SHA-1 of the bytes, so the sign input
+    /// stays printable and independent of the payload size.
+    Bytes(&'a [u8]),
+    /// No body: nothing is added. Signs identically to `Text("")`.
+    Empty,
+}
+
+pub fn sign(app_id: &str, app_secret: &str, nonce: &str, timestamp: i64, uri: &str, body: SignableBody<'_>) -> String {
+    let inputs = SignInputs {
+        app_id,
+        app_secret,
+        nonce,
+        timestamp,
+        content_path: "",
+        uri,
+        body,
+    };
+    signature(SignMode::Request, &inputs).as_str().to_string()
+}
+
+/// A signature in lowercase hex, held inline.
+pub(crate) struct Signature([u8; 40]);
+
+impl Signature {
+    pub(crate) fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("hex digits are ASCII")
+    }
+}
+
+/// `sign` for any mode, without allocating.
+pub(crate) fn signature(mode: SignMode, inputs: &SignInputs<'_>) -> Signature {
+    let mut hasher = Sha1::default();
+    spec::assemble(mode, inputs, |piece| hasher.update(piece));
+    hex_digest(&hasher.finalize())
+}
+
+fn hex_digest(digest: &[u8]) -> Signature {
+    let mut hex = [0u8; 40];
+    hex::encode_to_slice(digest, &mut hex).expect("a SHA-1 digest is 20 bytes");
+    Signature(hex)
+}
+
+/// What `body` contributes to the sign string.
+#[cfg(feature = "client")]
+pub(crate) fn body_component(body: SignableBody<'_>) -> Cow<'_, str> {
+    match body {
+        SignableBody::Text(text) => Cow::Borrowed(text),
+        SignableBody::Bytes(bytes) => Cow::Owned(hex_digest(&Sha1::digest(bytes)).as_str().to_string()),
+        SignableBody::Empty => Cow::Borrowed(""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APP_ID: &str = "test-app-id";
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+    const NONCE: &str = "3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a13";
+    const TIMESTAMP: i64 = 1717171717171;
+    const URI: &str = "/v1/lol/champion/skin?region=cn";
+
+    #[test]
+    fn test_text_body_known_answer() {
+        let body = SignableBody::Text(r#"{"key":"value"}"#);
+        assert_eq!(
+            sign(APP_ID, SECRET, NONCE, TIMESTAMP, URI, body),
+            "1ae2672d3d67e434efe485b3cfb4f0510e7457c2"
+        );
+    }
+
+    #[test]
+    fn test_utf8_text_body_known_answer() {
+        let body = SignableBody::Text(r#"{"name":"安妮"}"#);
+        assert_eq!(
+            sign(APP_ID, SECRET, NONCE, TIMESTAMP, URI, body),
+            "7f403d37ce6f1cedaef01b0f64d9b22133930796"
+        );
+    }
+
+    #[test]
+    fn test_empty_body_known_answer() {
+        let expected = "132c2b49f73d079ac4b6ebd6e0b52751e5e97c79";
+        assert_eq!(
+            sign(APP_ID, SECRET, NONCE, TIMESTAMP, URI, SignableBody::Empty),
+            expected
+        );
+        assert_eq!(
+            sign(APP_ID, SECRET, NONCE, TIMESTAMP, URI, SignableBody::Text("")),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_content_path_and_uri_sign_as_concatenated() {
+        let body = SignableBody::Text(r#"{"key":"value"}"#);
+        for timestamp in [TIMESTAMP, 0, -1, i64::MIN] {
+            let inputs = SignInputs {
+                app_id: APP_ID,
+                app_secret: SECRET,
+                nonce: NONCE,
+                timestamp,
+                content_path: "/v1/lol",
+                uri: "/champion/skin?region=cn",
+                body,
+            };
+            assert_eq!(
+                signature(SignMode::RequestWithContentPath, &inputs).as_str(),
+                sign(
+                    APP_ID,
+                    SECRET,
+                    NONCE,
+                    timestamp,
+                    "/v1/lol/champion/skin?region=cn",
+                    body
+                )
+            );
+        }
+        let inputs = SignInputs {
+            app_id: APP_ID,
+            app_secret: SECRET,
+            nonce: NONCE,
+            timestamp: TIMESTAMP,
+            content_path: "/ignored",
+            uri: URI,
+            body,
+        };
+        assert_eq!(
+            signature(SignMode::Request, &inputs).as_str(),
+            "1ae2672d3d67e434efe485b3cfb4f0510e7457c2"
+        );
+    }
+
+    #[test]
+    fn test_bytes_body_known_answer() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(
+            sign(
+                APP_ID,
+                SECRET,
+                NONCE,
+                TIMESTAMP,
+                "/v1/asset/upload/chunk",
+                SignableBody::Bytes(&bytes)
+            ),
+            "1664a6425e4f8c21917e4baa730d2c7c4db61f51"
+        );
+    }
+}
@@ -0,0 +1,280 @@
+//! The sign string, as data. The server recomputes `HO-SIGNATURE` from the same components,
+//! so their order and form are a wire contract: every mode is listed here once, and both
+//! the signature and `sign_string` are assembled from the list by `assemble`. The golden
+//! tests below pin the assembled string for each mode; a change to one is a change to the
+//! protocol.
+
+use std::io::{Cursor, Write};
+
+use sha1::{Digest, Sha1};
+
+use super::{hex_digest, SignableBody};
+
+/// One part of the sign string. The parts are concatenated with no separators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Component {
+    /// `app_id`, sent as `HO-APP-ID`.
+    AppId,
+    /// The nonce sent as `HO-NONCE`.
+    Nonce,
+    /// The time sent as `HO-TIMESTAMP`, in decimal milliseconds.
+    Timestamp,
+    /// The time the signature expires, sent as `HO-EXPIRES`, in decimal milliseconds.
+    Expires,
+    /// The content path, such as `/server/common/api`.
+    ContentPath,
+    /// The request uri exactly as appended to the content path, query string included.
+    Uri,
+    /// The body as described by `SignableBody`.
+    Body,
+    /// `app_secret`, never sent.
+    AppSecret,
+}
+
+impl Component {
+    /// What the component holds, for `describe`.
+    pub fn description(self) -> &'static str {
+        match self {
+            Component::AppId => "app_id (HO-APP-ID)",
+            Component::Nonce => "nonce (HO-NONCE)",
+            Component::Timestamp => "timestamp in decimal milliseconds (HO-TIMESTAMP)",
+            Component::Expires => "expiry time in decimal milliseconds (HO-EXPIRES)",
+            Component::ContentPath => "content path",
+            Component::Uri => "uri after the content path, query string included",
+            Component::Body => {
+                "body: text as sent, UTF-8; binary as the lowercase hex SHA-1 of its bytes; nothing when there is none"
+            }
+            Component::AppSecret => "app_secret",
+        }
+    }
+}
+
+/// The ways the client signs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SignMode {
+    /// A request, the default.
+    Request,
+    /// A request under `ApiClientConfig::sign_content_path`.
+    RequestWithContentPath,
+    /// `ApiClient::presign`. The request has no body.
+    Presigned,
+    /// `ApiClient::presign` under `sign_content_path`.
+    PresignedWithContentPath,
+}
+
+impl SignMode {
+    pub const ALL: [SignMode; 4] = [
+        SignMode::Request,
+        SignMode::RequestWithContentPath,
+        SignMode::Presigned,
+        SignMode::PresignedWithContentPath,
+    ];
+
+    /// The mode of requests under a `sign_content_path` setting.
+    pub fn for_request(sign_content_path: bool) -> Self {
+        match sign_content_path {
+            true => SignMode::RequestWithContentPath,
+            false => SignMode::Request,
+        }
+    }
+
+    /// The mode of `presign` under a `sign_content_path` setting.
+    pub fn for_presign(sign_content_path: bool) -> Self {
+        match sign_content_path {
+            true => SignMode::PresignedWithContentPath,
+            false => SignMode::Presigned,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SignMode::Request => "request",
+            SignMode::RequestWithContentPath => "request_with_content_path",
+            SignMode::Presigned => "presigned",
+            SignMode::PresignedWithContentPath => "presigned_with_content_path",
+        }
+    }
+
+    /// The components of the sign string, in order.
+    pub fn components(self) -> &'static [Component] {
+        use Component::*;
+        match self {
+            SignMode::Request => &[AppId, Nonce, Timestamp, Uri, Body, AppSecret],
+            SignMode::RequestWithContentPath => &[AppId, Nonce, Timestamp, ContentPath, Uri, Body, AppSecret],
+            SignMode::Presigned => &[AppId, Nonce, Expires, Uri, AppSecret],
+            SignMode::PresignedWithContentPath => &[AppId, Nonce, Expires, ContentPath, Uri, AppSecret],
+        }
+    }
+}
+
+/// The values the components are filled from. A mode ignores the ones it has no
+/// component for.
+#[derive(Debug, Clone, Copy)]
+pub struct SignInputs<'a> {
+    pub app_id: &'a str,
+    pub app_secret: &'a str,
+    pub nonce: &'a str,
+    /// `HO-TIMESTAMP`, or `HO-EXPIRES` for the presigned modes.
+    pub timestamp: i64,
+    pub content_path: &'a str,
+    pub uri: &'a str,
+    pub body: SignableBody<'a>,
+}
+
+/// Hands the sign string of `inputs` under `mode` to `sink`, in pieces, in order. Nothing
+/// is allocated: the timestamp and the hash of a binary body are formatted on the stack.
+pub(crate) fn assemble(mode: SignMode, inputs: &SignInputs<'_>, mut sink: impl FnMut(&[u8])) {
+    for component in mode.components() {
+        match component {
+            Component::AppId => sink(inputs.app_id.as_bytes()),
+            Component::Nonce => sink(inputs.nonce.as_bytes()),
+            Component::Timestamp | Component::Expires => {
+                let mut digits = [0u8; 20];
+                let mut cursor = Cursor::new(&mut digits[..]);
+                write!(cursor, "{}", inputs.timestamp).expect("an i64 has at most 20 characters");
+                let len = cursor.position() as usize;
+                sink(&digits[..len]);
+            }
+            Component::ContentPath => sink(inputs.content_path.as_bytes()),
+            Component::Uri => sink(inputs.uri.as_bytes()),
+            Component::Body => match inputs.body {
+                SignableBody::Text(text) => sink(text.as_bytes()),
+                SignableBody::Bytes(bytes) => sink(&hex_digest(&Sha1::digest(bytes)).0),
+                SignableBody::Empty => {}
+            },
+            Component::AppSecret => sink(inputs.app_secret.as_bytes()),
+        }
+    }
+}
+
+/// The string whose SHA-1 is the signature of `inputs` under `mode`, secret included.
+pub fn sign_string(mode: SignMode, inputs: &SignInputs<'_>) -> String {
+    let mut bytes = Vec::new();
+    assemble(mode, inputs, |piece| bytes.extend_from_slice(piece));
+    String::from_utf8(bytes).expect("every component is text")
+}
+
+/// What `mode` signs, one numbered component per line, for comparing against the vendor's
+/// documentation. `signing::describe(SignMode::for_request(config.sign_content_path))`
+/// prints what a client signs.
+pub fn describe(mode: SignMode) -> String {
+    let mut text = format!(
+        "{}: HO-SIGNATURE is the lowercase hex SHA-1 of, concatenated without separators:",
+        mode.as_str()
+    );
+    for (i, component) in mode.components().iter().enumerate() {
+        text.push_str(&format!("\n  {}. {}", i + 1, component.description()));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::signature;
+
+    fn inputs(body: SignableBody<'_>) -> SignInputs<'_> {
+        SignInputs {
+            app_id: "test-app-id",
+            app_secret: "0123456789abcdef0123456789abcdef",
+            nonce: "3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a13",
+            timestamp: 1717171717171,
+            content_path: "/server/common/api",
+            uri: "/v1/lol/champion/skin?region=cn",
+            body,
+        }
+    }
+
+    /// Every mode with every kind of body, one `mode body: sign string` line each.
+    fn golden() -> String {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let bodies = [
+            ("text", SignableBody::Text(r#"{"name":"安妮"}"#)),
+            ("bytes", SignableBody::Bytes(&bytes)),
+            ("empty", SignableBody::Empty),
+        ];
+        let mut lines = Vec::new();
+        for mode in SignMode::ALL {
+            for (name, body) in bodies {
+                lines.push(format!(
+                    "{} {}: {}",
+                    mode.as_str(),
+                    name,
+                    sign_string(mode, &inputs(body))
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+
+    #[test]
+    fn test_sign_strings_of_every_mode() {
+        assert_eq!(
+            golden(),
+            r#"request text: test-app-id3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a131717171717171/v1/lol/champion/skin?region=cn{"name":"安妮"}0123456789abcdef0123456789abcdef
+request bytes: test-app-id3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a131717171717171/v1/lol/champion/skin?region=cn4916d6bdb7f78e6803698cab32d1586ea457dfc80123456789abcdef0123456789abcdef
+request empty: test-app-id3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a131717171717171/v1/lol/champion/skin?region=cn0123456789abcdef0123456789abcdef
+request_with_content_path text: test-app-id3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a131717171717171/server/common/api/v1/lol/champion/skin?region=cn{"name":"安妮"}0123456789abcdef0123456789abcdef
+request_with_content_path bytes: test-app-id3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a131717171717171/server/common/api/v1/lol/champion/skin?region=cn4916d6bdb7f78e6803698cab32d1586ea457dfc80123456789abcdef0123456789abcdef
+request_with_content_path empty: test-app-id3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a131717171717171/server/common/api/v1/lol/champion/skin?region=cn0123456789abcdef0123456789abcdef
+presigned text: test-app-id3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a131717171717171/v1/lol/champion/skin?region=cn0123456789abcdef0123456789abcdef
+presigned bytes: test-app-id3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a131717171717171/v1/lol/champion/skin?region=cn0123456789abcdef0123456789abcdef
+presigned empty: test-app-id3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a131717171717171/v1/lol/champion/skin?region=cn0123456789abcdef0123456789abcdef
+presigned_with_content_path text: test-app-id3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a131717171717171/server/common/api/v1/lol/champion/skin?region=cn0123456789abcdef0123456789abcdef
+presigned_with_content_path bytes: test-app-id3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a131717171717171/server/common/api/v1/lol/champion/skin?region=cn0123456789abcdef0123456789abcdef
+presigned_with_content_path empty: test-app-id3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a131717171717171/server/common/api/v1/lol/champion/skin?region=cn0123456789abcdef0123456789abcdef"#
+        );
+    }
+
+    #[test]
+    fn test_signature_is_the_sha1_of_the_sign_string() {
+        let bytes = [0u8, 1, 2, 0xff];
+        for mode in SignMode::ALL {
+            for body in [
+                SignableBody::Text("{}"),
+                SignableBody::Bytes(&bytes),
+                SignableBody::Empty,
+            ] {
+                let inputs = inputs(body);
+                let expected = hex::encode(Sha1::digest(sign_string(mode, &inputs).as_bytes()));
+                assert_eq!(signature(mode, &inputs).as_str(), expected, "{:?}", mode);
+            }
+        }
+    }
+
+    #[test]
+    fn test_descriptions_of_every_mode() {
+        let descriptions: Vec<String> = SignMode::ALL.into_iter().map(describe).collect();
+        assert_eq!(
+            descriptions.join("\n"),
+            "request: HO-SIGNATURE is the lowercase hex SHA-1 of, concatenated without separators:
+  1. app_id (HO-APP-ID)
+  2. nonce (HO-NONCE)
+  3. timestamp in decimal milliseconds (HO-TIMESTAMP)
+  4. uri after the content path, query string included
+  5. body: text as sent, UTF-8; binary as the lowercase hex SHA-1 of its bytes; nothing when there is none
+  6. app_secret
+request_with_content_path: HO-SIGNATURE is the lowercase hex SHA-1 of, concatenated without separators:
+  1. app_id (HO-APP-ID)
+  2. nonce (HO-NONCE)
+  3. timestamp in decimal milliseconds (HO-TIMESTAMP)
+  4. content path
+  5. uri after the content path, query string included
+  6. body: text as sent, UTF-8; binary as the lowercase hex SHA-1 of its bytes; nothing when there is none
+  7. app_secret
+presigned: HO-SIGNATURE is the lowercase hex SHA-1 of, concatenated without separators:
+  1. app_id (HO-APP-ID)
+  2. nonce (HO-NONCE)
+  3. expiry time in decimal milliseconds (HO-EXPIRES)
+  4. uri after the content path, query string included
+  5. app_secret
+presigned_with_content_path: HO-SIGNATURE is the lowercase hex SHA-1 of, concatenated without separators:
+  1. app_id (HO-APP-ID)
+  2. nonce (HO-NONCE)
+  3. expiry time in decimal milliseconds (HO-EXPIRES)
+  4. content path
+  5. uri after the content path, query string included
+  6. app_secret"
+        );
+    }
+}
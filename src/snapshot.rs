@@ -0,0 +1,465 @@
+//! A flat, serializable record of the settings a client runs with, for logging at startup
+//! and comparing across deployments. Secrets appear only as their length and a short
+//! fingerprint, which changes when the secret does.
+//!
+//! ```no_run
+//! # fn log(config: &ho_api_rust_sdk::ApiClientConfig, deployed: &ho_api_rust_sdk::snapshot::ConfigSnapshot) {
+//! let snapshot = config.snapshot();
+//! println!("{}", serde_json::to_string(&snapshot).unwrap());
+//! for delta in deployed.diff(&snapshot) {
+//!     println!("{}", delta); // retry.max_retries: 0 -> 3
+//! }
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+
+use crate::cache::CacheConfig;
+use crate::deprecation::{DEPRECATION_HEADER, VERSION_HEADER};
+use crate::envelope::{ENCRYPTED_HEADER, TRUNCATED_HEADER};
+use crate::failover::FailoverConfig;
+use crate::keepalive::KeepaliveConfig;
+use crate::request::AuthMode;
+use crate::retry::RetryConfig;
+use crate::transport::TransportConfig;
+use crate::{ApiClientConfig, SecondaryKey};
+
+/// Settings by dotted path, such as `retry.max_retries`. Durations are written as
+/// `"<n>ms"`, or `"<n>ns"` when not a whole number of milliseconds; unset options are null.
+/// Keys under `protocol.` are fixed by this SDK version rather than configured.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ConfigSnapshot {
+    pub settings: BTreeMap<String, Value>,
+}
+
+/// One setting that differs between two snapshots. `None` means the key is missing on that
+/// side, as when comparing with a snapshot from another SDK version.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigDelta {
+    pub key: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+impl fmt::Display for ConfigDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = |value: &Option<Value>| match value {
+            Some(value) => value.to_string(),
+            None => "(absent)".to_string(),
+        };
+        write!(f, "{}: {} -> {}", self.key, side(&self.before), side(&self.after))
+    }
+}
+
+impl ConfigSnapshot {
+    /// The settings that differ from `self` in `other`, by key.
+    pub fn diff(&self, other: &ConfigSnapshot) -> Vec<ConfigDelta> {
+        let keys: std::collections::BTreeSet<&String> = self.settings.keys().chain(other.settings.keys()).collect();
+        keys.into_iter()
+            .filter_map(|key| {
+                let before = self.settings.get(key);
+                let after = other.settings.get(key);
+                (before != after).then(|| ConfigDelta {
+                    key: key.clone(),
+                    before: before.cloned(),
+                    after: after.cloned(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl ApiClientConfig {
+    /// Every setting of this configuration, secrets redacted.
+    pub fn snapshot(&self) -> ConfigSnapshot {
+        // No `..`: a new field does not compile until it is added here.
+        let ApiClientConfig {
+            app_id,
+            app_secret,
+            iv,
+            base_url,
+            content,
+            fallback_base_urls,
+            failover,
+            max_concurrent_requests,
+            max_queue_wait,
+            priority_promotion,
+            apply_bootstrap,
+            bootstrap_refresh,
+            redaction,
+            pools,
+            pool_routes,
+            clock_drift_warn_threshold,
+            strict_hex,
+            sign_content_path,
+            attempt_timeout,
+            overall_timeout,
+            retry,
+            validate_requests,
+            validation,
+            transport,
+            cache,
+            max_request_size,
+            api_version,
+            secondary_key,
+            response_charset,
+            retry_once_on_stale_connection,
+            keepalive_interval,
+            keepalive,
+            reset_connections_after,
+            follow_redirects,
+            max_redirects,
+            allow_cross_host_redirects,
+            strict_self_check,
+            fail_on_truncated,
+            triage_signature_failures,
+            acceptable_statuses,
+            detect_double_encoding,
+            // Not a setting to compare: production configs all run on `RealTime`.
+            time: _,
+            offload_threshold,
+            quota_reserve,
+            nonce_guard,
+        } = self;
+        let FailoverConfig { half_life, hysteresis } = failover;
+        let RetryConfig {
+            max_retries,
+            initial_backoff,
+            max_backoff,
+            multiplier,
+        } = retry;
+        let TransportConfig {
+            timeout,
+            connect_timeout,
+            dns_timeout,
+            fallback_addrs,
+        } = transport;
+        let KeepaliveConfig {
+            path: keepalive_path,
+            auth: keepalive_auth,
+            record_stats: keepalive_record_stats,
+        } = keepalive;
+
+        let mut settings = BTreeMap::new();
+        let mut set = |key: &str, value: Value| {
+            settings.insert(key.to_string(), value);
+        };
+        set("app_id", json!(app_id));
+        set("app_secret", redacted(app_secret));
+        set("iv", redacted(iv));
+        set("base_url", json!(base_url));
+        set("content", json!(content));
+        set("fallback_base_urls", json!(fallback_base_urls));
+        set("failover.half_life", duration(*half_life));
+        set("failover.hysteresis", json!(hysteresis));
+        set("max_concurrent_requests", json!(max_concurrent_requests));
+        set("max_queue_wait", optional_duration(*max_queue_wait));
+        set("priority_promotion", optional_duration(*priority_promotion));
+        set("apply_bootstrap", json!(apply_bootstrap));
+        set("bootstrap_refresh", optional_duration(*bootstrap_refresh));
+        set(
+            "redaction.patterns",
+            json!(redaction.as_ref().map(|redaction| &redaction.patterns)),
+        );
+        set(
+            "redaction.mask",
+            json!(redaction.as_ref().map(|redaction| &redaction.mask)),
+        );
+        set("pools", json!(pools));
+        set("pool_routes", json!(pool_routes));
+        set(
+            "clock_drift_warn_threshold",
+            optional_duration(*clock_drift_warn_threshold),
+        );
+        set("strict_hex", json!(strict_hex));
+        set("sign_content_path", json!(sign_content_path));
+        set("attempt_timeout", optional_duration(*attempt_timeout));
+        set("overall_timeout", optional_duration(*overall_timeout));
+        set("retry.max_retries", json!(max_retries));
+        set("retry.initial_backoff", duration(*initial_backoff));
+        set("retry.max_backoff", duration(*max_backoff));
+        set("retry.multiplier", json!(multiplier));
+        set("validate_requests", json!(validate_requests));
+        set("validation", json!(validation.describe()));
+        set("transport.timeout", duration(*timeout));
+        set("transport.connect_timeout", duration(*connect_timeout));
+        set("transport.dns_timeout", optional_duration(*dns_timeout));
+        set(
+            "transport.fallback_addrs",
+            json!(fallback_addrs.iter().map(ToString::to_string).collect::<Vec<_>>()),
+        );
+        set(
+            "cache.ttl",
+            optional_duration(cache.as_ref().map(|CacheConfig { ttl, .. }| *ttl)),
+        );
+        set(
+            "cache.max_stale",
+            optional_duration(cache.as_ref().and_then(|CacheConfig { max_stale, .. }| *max_stale)),
+        );
+        set("max_request_size", json!(max_request_size));
+        set("api_version", json!(api_version));
+        let (secondary_secret, secondary_iv) = match secondary_key {
+            Some(SecondaryKey { app_secret, iv }) => (redacted(app_secret), redacted(iv)),
+            None => (Value::Null, Value::Null),
+        };
+        set("secondary_key.app_secret", secondary_secret);
+        set("secondary_key.iv", secondary_iv);
+        set("response_charset", json!(response_charset.to_string()));
+        set("retry_once_on_stale_connection", json!(retry_once_on_stale_connection));
+        set("keepalive_interval", optional_duration(*keepalive_interval));
+        set("keepalive.path", json!(keepalive_path));
+        set(
+            "keepalive.auth",
+            json!(match keepalive_auth {
+                AuthMode::Signed => "signed",
+                AuthMode::None => "none",
+            }),
+        );
+        set("keepalive.record_stats", json!(keepalive_record_stats));
+        set(
+            "reset_connections_after.failures",
+            json!(reset_connections_after.map(|reset| reset.failures)),
+        );
+        set(
+            "reset_connections_after.window",
+            optional_duration(reset_connections_after.map(|reset| reset.window)),
+        );
+        set("follow_redirects", json!(follow_redirects));
+        set("max_redirects", json!(max_redirects));
+        set("allow_cross_host_redirects", json!(allow_cross_host_redirects));
+        set("strict_self_check", json!(strict_self_check));
+        set("fail_on_truncated", json!(fail_on_truncated));
+        set("triage_signature_failures", json!(triage_signature_failures));
+        set("detect_double_encoding", json!(detect_double_encoding));
+        set("offload_threshold", json!(offload_threshold));
+        set("nonce_guard", json!(nonce_guard));
+        set(
+            "quota_reserve.reserve",
+            json!(quota_reserve.as_ref().map(|quota| quota.reserve)),
+        );
+        set(
+            "quota_reserve.priorities",
+            json!(quota_reserve.as_ref().map(|quota| &quota.priorities)),
+        );
+        set(
+            "quota_reserve.action",
+            json!(quota_reserve.as_ref().map(|quota| quota.action.as_str())),
+        );
+        set(
+            "quota_reserve.max_age",
+            optional_duration(quota_reserve.as_ref().map(|quota| quota.max_age)),
+        );
+        set(
+            "quota_reserve.max_wait",
+            optional_duration(quota_reserve.as_ref().and_then(|quota| quota.max_wait)),
+        );
+        set(
+            "acceptable_statuses",
+            json!(acceptable_statuses
+                .as_ref()
+                .map(|statuses| statuses.iter().map(StatusCode::as_u16).collect::<Vec<_>>())),
+        );
+
+        set("protocol.encryption", json!("AES-256-CBC, PKCS#7 padding, hex"));
+        set("protocol.signature", json!("SHA-1, lowercase hex"));
+        set("protocol.envelope_field", json!("data"));
+        set(
+            "protocol.headers",
+            json!([
+                "HO-APP-ID",
+                "HO-NONCE",
+                "HO-TIMESTAMP",
+                "HO-SIGNATURE",
+                VERSION_HEADER,
+                ENCRYPTED_HEADER,
+                DEPRECATION_HEADER,
+                TRUNCATED_HEADER
+            ]),
+        );
+        ConfigSnapshot { settings }
+    }
+}
+
+/// Length and the first 8 hex digits of the SHA-1, enough to tell two secrets apart.
+fn redacted(secret: &str) -> Value {
+    let digest = format!("{:x}", Sha1::digest(secret.as_bytes()));
+    json!(format!("<redacted {} bytes, sha1 {}>", secret.len(), &digest[..8]))
+}
+
+fn duration(duration: Duration) -> Value {
+    match duration.subsec_nanos() % 1_000_000 {
+        0 => json!(format!("{}ms", duration.as_millis())),
+        _ => json!(format!("{}ns", duration.as_nanos())),
+    }
+}
+
+fn optional_duration(duration_option: Option<Duration>) -> Value {
+    duration_option.map_or(Value::Null, duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::charset::Charset;
+    use crate::connections::StaleConnectionReset;
+    use crate::quota::QuotaReserve;
+    use crate::redact::RedactionConfig;
+    use crate::testing::{test_config, SimulatedTime};
+    use crate::validation::{Constraint, ValidationRegistry};
+
+    /// A value other than the default for every setting.
+    fn everything_changed() -> ApiClientConfig {
+        let mut validation = ValidationRegistry::new();
+        validation.register("/v1/x", Constraint::MaxPageSize(10));
+        ApiClientConfig {
+            app_id: "app".to_string(),
+            app_secret: "0123456789abcdef0123456789abcdef".to_string(),
+            iv: "fedcba9876543210".to_string(),
+            base_url: "https://a.test".to_string(),
+            content: "/api".to_string(),
+            fallback_base_urls: vec!["https://b.test".to_string()],
+            failover: FailoverConfig {
+                half_life: Duration::from_secs(30),
+                hysteresis: 0.25,
+            },
+            max_concurrent_requests: Some(8),
+            max_queue_wait: Some(Duration::from_millis(250)),
+            priority_promotion: None,
+            apply_bootstrap: true,
+            bootstrap_refresh: Some(Duration::from_secs(600)),
+            redaction: Some(RedactionConfig::new(["phone"])),
+            pools: BTreeMap::from([("reports".to_string(), 4)]),
+            pool_routes: vec![("/v1/report/*".to_string(), "reports".to_string())],
+            clock_drift_warn_threshold: Some(Duration::from_secs(2)),
+            strict_hex: true,
+            sign_content_path: true,
+            attempt_timeout: Some(Duration::from_secs(3)),
+            overall_timeout: Some(Duration::from_secs(10)),
+            retry: RetryConfig {
+                max_retries: 3,
+                initial_backoff: Duration::from_millis(50),
+                max_backoff: Duration::from_secs(2),
+                multiplier: 1.5,
+            },
+            validate_requests: false,
+            validation,
+            transport: TransportConfig {
+                timeout: Duration::from_secs(7),
+                connect_timeout: Duration::from_secs(1),
+                dns_timeout: Some(Duration::from_micros(1500)),
+                fallback_addrs: vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))],
+            },
+            cache: Some(CacheConfig::memory(Duration::from_secs(60)).serve_stale(Duration::from_secs(600))),
+            max_request_size: Some(1 << 20),
+            api_version: Some("2024-06-01".to_string()),
+            secondary_key: Some(SecondaryKey {
+                app_secret: "abcdef0123456789abcdef0123456789".to_string(),
+                iv: "0000000000000000".to_string(),
+            }),
+            #[cfg(feature = "charset")]
+            response_charset: Charset::Auto,
+            // The only charset without the feature.
+            #[cfg(not(feature = "charset"))]
+            response_charset: Charset::Utf8,
+            retry_once_on_stale_connection: false,
+            keepalive_interval: Some(Duration::from_secs(45)),
+            keepalive: KeepaliveConfig {
+                path: Some("/v1/public/status".to_string()),
+                auth: AuthMode::None,
+                record_stats: true,
+            },
+            reset_connections_after: Some(StaleConnectionReset {
+                failures: 5,
+                window: Duration::from_secs(30),
+            }),
+            follow_redirects: false,
+            max_redirects: 1,
+            allow_cross_host_redirects: true,
+            strict_self_check: true,
+            fail_on_truncated: true,
+            triage_signature_failures: true,
+            acceptable_statuses: Some(vec![StatusCode::OK, StatusCode::ACCEPTED]),
+            detect_double_encoding: false,
+            time: Arc::new(SimulatedTime::new()),
+            offload_threshold: None,
+            quota_reserve: Some(QuotaReserve::new(100)),
+            nonce_guard: Some(1024),
+        }
+    }
+
+    #[test]
+    fn test_every_setting_is_in_the_snapshot() {
+        let defaults = ApiClientConfig::default().snapshot();
+        let changed = everything_changed().snapshot();
+        let deltas = defaults.diff(&changed);
+        let differing: Vec<&str> = deltas.iter().map(|d| d.key.as_str()).collect();
+        let configurable: Vec<&str> = changed
+            .settings
+            .keys()
+            .map(String::as_str)
+            .filter(|key| !key.starts_with("protocol."))
+            .filter(|key| cfg!(feature = "charset") || *key != "response_charset")
+            .collect();
+        assert_eq!(differing, configurable);
+
+        assert_eq!(changed.settings["transport.dns_timeout"], "1500000ns");
+        assert_eq!(changed.settings["max_queue_wait"], "250ms");
+        assert_eq!(changed.settings["validation"], json!(["/v1/x: MaxPageSize(10)"]));
+    }
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let config = test_config();
+        let text = serde_json::to_string(&config.snapshot()).unwrap();
+        assert!(
+            !text.contains(&config.app_secret) && !text.contains(&config.iv),
+            "{}",
+            text
+        );
+        assert_eq!(
+            config.snapshot().settings["app_secret"],
+            "<redacted 32 bytes, sha1 b1775a78>"
+        );
+    }
+
+    #[test]
+    fn test_diff_is_readable_and_ordered() {
+        let before = test_config().snapshot();
+        let mut config = test_config();
+        config.retry.max_retries = 3;
+        config.attempt_timeout = Some(Duration::from_millis(1500));
+        config.app_secret = "ffffffffffffffffffffffffffffffff".to_string();
+        let mut after = config.snapshot();
+        after.settings.remove("strict_self_check");
+
+        let lines: Vec<String> = before.diff(&after).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            [
+                format!(
+                    "app_secret: {} -> \"<redacted 32 bytes, sha1 {}>\"",
+                    before.settings["app_secret"],
+                    &format!("{:x}", Sha1::digest(b"ffffffffffffffffffffffffffffffff"))[..8]
+                ),
+                "attempt_timeout: null -> \"1500ms\"".to_string(),
+                "retry.max_retries: 0 -> 3".to_string(),
+                "strict_self_check: false -> (absent)".to_string(),
+            ]
+        );
+        assert!(before.diff(&test_config().snapshot()).is_empty());
+
+        let round_trip: ConfigSnapshot = serde_json::from_str(&serde_json::to_string(&before).unwrap()).unwrap();
+        assert_eq!(round_trip, before);
+    }
+}
@@ -0,0 +1,320 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::bulkhead::PoolStats;
+use crate::cache::CacheUsage;
+use crate::failover::HostScore;
+use crate::ApiClientError;
+
+/// Upper bounds (inclusive, in milliseconds) of the latency histogram buckets.
+/// Samples above the last bound land in an overflow bucket.
+const LATENCY_BOUNDS_MS: [u64; 16] = [
+    1, 2, 5, 10, 20, 50, 100, 200, 300, 500, 1_000, 2_000, 5_000, 10_000, 30_000, 60_000,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ErrorClass {
+    Transport,
+    HttpStatus,
+    Serialization,
+    Decryption,
+    Decoding,
+    Config,
+    InvalidRequest,
+    QueueTimeout,
+    QuotaReserved,
+}
+
+impl ErrorClass {
+    const COUNT: usize = 9;
+
+    pub(crate) fn of(err: &ApiClientError) -> Self {
+        match err {
+            ApiClientError::ReqwestError(_) | ApiClientError::ConnectionClosed { .. } => ErrorClass::Transport,
+            ApiClientError::HttpStatus { .. }
+            | ApiClientError::RedirectRefused { .. }
+            | ApiClientError::Maintenance { .. }
+            | ApiClientError::JobFailed { .. }
+            | ApiClientError::UploadFailed { .. }
+            | ApiClientError::SignatureRejected { .. }
+            | ApiClientError::BatchFailed { .. }
+            | ApiClientError::Truncated { .. } => ErrorClass::HttpStatus,
+            ApiClientError::AttemptTimeout { .. }
+            | ApiClientError::OverallTimeout { .. }
+            | ApiClientError::JobTimeout { .. } => ErrorClass::Transport,
+            ApiClientError::SerdeJsonError(_)
+            | ApiClientError::InvalidEnvelope(_)
+            | ApiClientError::InvalidPayload { .. }
+            | ApiClientError::ResponseProcessing { .. }
+            | ApiClientError::ExportWrite(_)
+            | ApiClientError::PaginationLoop { .. } => ErrorClass::Serialization,
+            ApiClientError::AesError(_) | ApiClientError::BothKeysFailed { .. } => ErrorClass::Decryption,
+            ApiClientError::Utf8Error(_)
+            | ApiClientError::InvalidCharset { .. }
+            | ApiClientError::HexError(_)
+            | ApiClientError::InvalidHex(_)
+            | ApiClientError::InvalidCiphertextLength { .. }
+            | ApiClientError::InvalidArchive { .. }
+            | ApiClientError::TruncatedStream(_) => ErrorClass::Decoding,
+            ApiClientError::InvalidConfig(_) => ErrorClass::Config,
+            ApiClientError::InvalidQuery(_)
+            | ApiClientError::InvalidUri { .. }
+            | ApiClientError::RequestValidation { .. }
+            | ApiClientError::RequestTooLarge { .. }
+            | ApiClientError::SuspectedDoubleEncoding { .. }
+            | ApiClientError::NonceReuse { .. } => ErrorClass::InvalidRequest,
+            ApiClientError::QueueTimeout { .. } => ErrorClass::QueueTimeout,
+            ApiClientError::QuotaReserved { .. } => ErrorClass::QuotaReserved,
+            ApiClientError::ChainStepFailed { source, .. } => ErrorClass::of(source),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ErrorCounts {
+    pub transport: u64,
+    pub http_status: u64,
+    pub serialization: u64,
+    pub decryption: u64,
+    pub decoding: u64,
+    pub config: u64,
+    pub invalid_request: u64,
+    /// Requests dropped after waiting too long for a concurrency permit: a client capacity
+    /// problem rather than an upstream one.
+    pub queue_timeout: u64,
+    /// Requests rejected unsent by `ApiClientConfig::quota_reserve`.
+    pub quota_reserved: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LatencyPercentiles {
+    /// Bucket upper bounds in milliseconds; `None` until a request has completed.
+    pub p50_ms: Option<u64>,
+    pub p95_ms: Option<u64>,
+    pub p99_ms: Option<u64>,
+}
+
+/// Point-in-time view of the counters returned by `ApiClient::stats()`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub total_requests: u64,
+    pub errors: ErrorCounts,
+    pub latency: LatencyPercentiles,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// `None` while the cache has never been consulted.
+    pub cache_hit_ratio: Option<f64>,
+    /// Attempts repeated on a fresh connection because a possibly pooled one was closed
+    /// before the response arrived. Counted whether or not the repeat succeeded, and apart
+    /// from the retries of `RetryConfig`.
+    pub stale_connection_retries: u64,
+    /// Failed GETs answered from expired cache entries, per `CacheConfig::max_stale`. The
+    /// failures are counted in `errors` as well.
+    pub stale_served: u64,
+    /// Requests held back by `ApiClientConfig::quota_reserve` until the quota reset, then
+    /// sent. Rejected ones are in `errors.quota_reserved`.
+    pub quota_deferred: u64,
+    /// Plaintexts deserialized as a caller's type by `send_as` and its variants, failed ones
+    /// included, and the total time that took in microseconds.
+    pub deserializations: u64,
+    pub deserialize_micros: u64,
+    /// What the cache backend holds, when it reports it.
+    pub cache: Option<CacheUsage>,
+    pub hosts: Vec<HostScore>,
+    /// One entry per `ApiClientConfig::pools` entry, by name.
+    pub pools: Vec<PoolStats>,
+}
+
+#[derive(Default)]
+pub(crate) struct StatsCollector {
+    total_requests: AtomicU64,
+    errors: [AtomicU64; ErrorClass::COUNT],
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    stale_connection_retries: AtomicU64,
+    stale_served: AtomicU64,
+    quota_deferred: AtomicU64,
+    deserializations: AtomicU64,
+    deserialize_micros: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BOUNDS_MS.len() + 1],
+}
+
+impl StatsCollector {
+    pub(crate) fn record_request(
+        &self,
+        elapsed: Duration,
+        bytes_sent: u64,
+        bytes_received: u64,
+        error: Option<ErrorClass>,
+    ) {
+        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        if bytes_sent > 0 {
+            self.bytes_sent.fetch_add(bytes_sent, Ordering::Relaxed);
+        }
+        if bytes_received > 0 {
+            self.bytes_received.fetch_add(bytes_received, Ordering::Relaxed);
+        }
+        if let Some(class) = error {
+            self.errors[class as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        let ms = elapsed.as_millis().min(u64::MAX as u128) as u64;
+        let bucket = LATENCY_BOUNDS_MS
+            .iter()
+            .position(|&bound| ms <= bound)
+            .unwrap_or(LATENCY_BOUNDS_MS.len());
+        self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_stale_connection_retry(&self) {
+        self.stale_connection_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_cache_lookup(&self, hit: bool) {
+        let counter = if hit { &self.cache_hits } else { &self.cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_stale_served(&self) {
+        self.stale_served.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_quota_deferred(&self) {
+        self.quota_deferred.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_deserialize(&self, elapsed: Duration) {
+        self.deserializations.fetch_add(1, Ordering::Relaxed);
+        let micros = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        self.deserialize_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> Stats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let buckets: Vec<u64> = self.latency_buckets.iter().map(load).collect();
+        let cache_hits = load(&self.cache_hits);
+        let cache_misses = load(&self.cache_misses);
+        let cache_lookups = cache_hits + cache_misses;
+
+        Stats {
+            total_requests: load(&self.total_requests),
+            errors: ErrorCounts {
+                transport: load(&self.errors[ErrorClass::Transport as usize]),
+                http_status: load(&self.errors[ErrorClass::HttpStatus as usize]),
+                serialization: load(&self.errors[ErrorClass::Serialization as usize]),
+                decryption: load(&self.errors[ErrorClass::Decryption as usize]),
+                decoding: load(&self.errors[ErrorClass::Decoding as usize]),
+                config: load(&self.errors[ErrorClass::Config as usize]),
+                invalid_request: load(&self.errors[ErrorClass::InvalidRequest as usize]),
+                queue_timeout: load(&self.errors[ErrorClass::QueueTimeout as usize]),
+                quota_reserved: load(&self.errors[ErrorClass::QuotaReserved as usize]),
+            },
+            latency: LatencyPercentiles {
+                p50_ms: percentile(&buckets, 0.50),
+                p95_ms: percentile(&buckets, 0.95),
+                p99_ms: percentile(&buckets, 0.99),
+            },
+            bytes_sent: load(&self.bytes_sent),
+            bytes_received: load(&self.bytes_received),
+            cache_hits,
+            cache_misses,
+            cache_hit_ratio: (cache_lookups > 0).then(|| cache_hits as f64 / cache_lookups as f64),
+            stale_connection_retries: load(&self.stale_connection_retries),
+            stale_served: load(&self.stale_served),
+            quota_deferred: load(&self.quota_deferred),
+            deserializations: load(&self.deserializations),
+            deserialize_micros: load(&self.deserialize_micros),
+            cache: None,
+            hosts: Vec::new(),
+            pools: Vec::new(),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        let counters = [
+            &self.total_requests,
+            &self.bytes_sent,
+            &self.bytes_received,
+            &self.cache_hits,
+            &self.cache_misses,
+            &self.stale_connection_retries,
+            &self.stale_served,
+            &self.quota_deferred,
+            &self.deserializations,
+            &self.deserialize_micros,
+        ];
+        for counter in counters.into_iter().chain(&self.errors).chain(&self.latency_buckets) {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Returns the upper bound of the bucket containing the `q` quantile. Samples in the
+/// overflow bucket report the largest finite bound.
+fn percentile(buckets: &[u64], q: f64) -> Option<u64> {
+    let total: u64 = buckets.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let rank = ((total as f64) * q).ceil().max(1.0) as u64;
+    let mut seen = 0;
+    for (i, count) in buckets.iter().enumerate() {
+        seen += count;
+        if seen >= rank {
+            return Some(LATENCY_BOUNDS_MS[i.min(LATENCY_BOUNDS_MS.len() - 1)]);
+        }
+    }
+    LATENCY_BOUNDS_MS.last().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_follow_bucket_bounds() {
+        let stats = StatsCollector::default();
+        for _ in 0..90 {
+            stats.record_request(Duration::from_millis(8), 10, 100, None);
+        }
+        for _ in 0..9 {
+            stats.record_request(Duration::from_millis(150), 10, 100, None);
+        }
+        stats.record_request(Duration::from_secs(90), 10, 0, Some(ErrorClass::Transport));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.total_requests, 100);
+        assert_eq!(snapshot.latency.p50_ms, Some(10));
+        assert_eq!(snapshot.latency.p95_ms, Some(200));
+        assert_eq!(snapshot.latency.p99_ms, Some(200));
+        assert_eq!(snapshot.bytes_sent, 1_000);
+        assert_eq!(snapshot.bytes_received, 9_900);
+        assert_eq!(snapshot.errors.transport, 1);
+        assert_eq!(snapshot.cache_hit_ratio, None);
+    }
+
+    #[test]
+    fn test_reset_clears_everything() {
+        let stats = StatsCollector::default();
+        stats.record_request(Duration::from_millis(3), 1, 2, Some(ErrorClass::Decryption));
+        stats.record_stale_connection_retry();
+        stats.record_deserialize(Duration::from_micros(250));
+        stats.reset();
+        assert_eq!(stats.snapshot(), Stats::default());
+    }
+
+    #[test]
+    fn test_stats_serialize() {
+        let stats = StatsCollector::default();
+        stats.record_request(Duration::from_millis(42), 5, 7, None);
+        let value = serde_json::to_value(stats.snapshot()).unwrap();
+        assert_eq!(value["total_requests"], 1);
+        assert_eq!(value["latency"]["p99_ms"], 50);
+        assert_eq!(value["errors"]["decoding"], 0);
+    }
+}
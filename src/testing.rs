@@ -0,0 +1,249 @@
+//! Test doubles for exercising `ApiClient` without a network. Enabled by the `test-util`
+//! feature.
+
+use std::collections::{HashSet, VecDeque};
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+use crate::crypto::Crypto;
+use crate::response::Timings;
+use crate::time::TimeSource;
+use crate::transport::{Transport, TransportRequest, TransportResponse};
+use crate::{ApiClient, ApiClientConfig, ApiClientError};
+
+pub mod contract;
+mod fake;
+mod fixtures;
+mod simulated;
+
+pub use contract::{ContractRecorder, Exchange};
+pub use fake::{FakeApi, FakeRequest};
+pub use fixtures::{Fixture, FixtureGen};
+pub use simulated::SimulatedTime;
+
+pub const TEST_APP_ID: &str = "test-app-id";
+pub const TEST_APP_SECRET: &str = "0123456789abcdef0123456789abcdef";
+pub const TEST_IV: &str = "fedcba9876543210";
+pub const TEST_BASE_URL: &str = "https://mock.zelaser.test";
+pub const TEST_CONTENT: &str = "/server/common/api";
+
+pub fn test_config() -> ApiClientConfig {
+    ApiClientConfig {
+        app_id: TEST_APP_ID.to_string(),
+        app_secret: TEST_APP_SECRET.to_string(),
+        iv: TEST_IV.to_string(),
+        base_url: TEST_BASE_URL.to_string(),
+        content: TEST_CONTENT.to_string(),
+        ..Default::default()
+    }
+}
+
+pub fn test_crypto() -> Crypto {
+    Crypto::new(TEST_APP_SECRET, TEST_IV).expect("test key and iv have valid lengths")
+}
+
+/// A `{"data": "<hex>"}` envelope encrypted with the test key.
+pub fn encrypted_envelope(plaintext: &str) -> String {
+    json!({ "data": test_crypto().encrypt_hex(plaintext.as_bytes()) }).to_string()
+}
+
+/// Builds a client over `transport` using `test_config()` adjusted by `configure`.
+pub fn mock_client(transport: &Arc<MockTransport>, configure: impl FnOnce(&mut ApiClientConfig)) -> ApiClient {
+    let mut config = test_config();
+    configure(&mut config);
+    ApiClient::with_transport(config, transport.clone()).expect("test config is valid")
+}
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    delay: Duration,
+    connection_reset: bool,
+}
+
+impl MockResponse {
+    /// 200 with an envelope whose `data` is `plaintext` encrypted with the test key.
+    pub fn encrypted(plaintext: impl AsRef<str>) -> Self {
+        Self::raw(StatusCode::OK, encrypted_envelope(plaintext.as_ref()))
+    }
+
+    pub fn json(status: StatusCode, body: &Value) -> Self {
+        Self::raw(status, body.to_string())
+    }
+
+    pub fn raw(status: StatusCode, body: impl Into<Bytes>) -> Self {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            body: body.into(),
+            delay: Duration::ZERO,
+            connection_reset: false,
+        }
+    }
+
+    pub fn status(status: StatusCode) -> Self {
+        Self::raw(status, Bytes::new())
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.insert(
+            HeaderName::from_static(name),
+            HeaderValue::from_str(value).expect("valid header value"),
+        );
+        self
+    }
+
+    /// Fails the request the way a stale pooled connection does: the peer resets the
+    /// connection before answering.
+    pub fn connection_reset() -> Self {
+        Self {
+            connection_reset: true,
+            ..Self::status(StatusCode::OK)
+        }
+    }
+
+    /// Delays the response, measured on the tokio clock so paused-time tests stay instant, or
+    /// on the `MockTransport::with_time` source.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+struct Route {
+    method: Method,
+    path: String,
+    responses: VecDeque<MockResponse>,
+}
+
+/// A scripted `Transport`. Responses registered for the same method and path are served in
+/// order, the last one repeating; unmatched requests get a 404. Connections are simulated
+/// per origin: every request after the first to an origin reuses one, unless it asks for a
+/// fresh connection, the previous one was reset, or the connections were reset since.
+#[derive(Default)]
+pub struct MockTransport {
+    routes: Mutex<Vec<Route>>,
+    requests: Mutex<Vec<TransportRequest>>,
+    connected: Mutex<HashSet<String>>,
+    time: Option<Arc<dyn TimeSource>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// A `MockTransport` whose delays pass on `time`, such as a `SimulatedTime`.
+    pub fn with_time(time: Arc<dyn TimeSource>) -> Arc<Self> {
+        Arc::new(Self {
+            time: Some(time),
+            ..Self::default()
+        })
+    }
+
+    /// Queues `response` for requests whose path ends with `path`. When `path` contains a
+    /// `?` the query string takes part in the match too.
+    pub fn on(&self, method: Method, path: &str, response: MockResponse) -> &Self {
+        let mut routes = self.routes.lock().unwrap();
+        match routes.iter_mut().find(|r| r.method == method && r.path == path) {
+            Some(route) => route.responses.push_back(response),
+            None => routes.push(Route {
+                method,
+                path: path.to_string(),
+                responses: VecDeque::from([response]),
+            }),
+        }
+        self
+    }
+
+    /// Every request received so far, in arrival order.
+    pub fn requests(&self) -> Vec<TransportRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn next_response(&self, request: &TransportRequest) -> MockResponse {
+        let path_and_query = path_and_query(&request.url);
+        let path = path_and_query.split('?').next().unwrap_or_default();
+        let mut routes = self.routes.lock().unwrap();
+        let route = routes.iter_mut().find(|r| {
+            let target = if r.path.contains('?') { path_and_query } else { path };
+            r.method == request.method && target.ends_with(&r.path)
+        });
+        match route {
+            Some(route) if route.responses.len() > 1 => route.responses.pop_front().unwrap(),
+            Some(route) => route.responses[0].clone(),
+            None => MockResponse::raw(
+                StatusCode::NOT_FOUND,
+                format!("no mock route for {} {}", request.method, path_and_query),
+            ),
+        }
+    }
+}
+
+impl Transport for MockTransport {
+    fn execute(&self, request: TransportRequest) -> BoxFuture<'_, Result<TransportResponse, ApiClientError>> {
+        let response = self.next_response(&request);
+        let origin = request.url[..request.url.len() - path_and_query(&request.url).len()].to_string();
+        let reused = !self.connected.lock().unwrap().insert(origin.clone()) && !request.fresh_connection;
+        self.requests.lock().unwrap().push(request);
+        Box::pin(async move {
+            let time = self.time.clone().unwrap_or_else(crate::time::real);
+            let sent = time.now();
+            if !response.delay.is_zero() {
+                time.sleep(response.delay).await;
+            }
+            if response.connection_reset {
+                self.connected.lock().unwrap().remove(&origin);
+                return Err(ApiClientError::ReqwestError(connection_reset_error().await));
+            }
+            let mut timings = match reused {
+                true => Timings::reused(),
+                false => Timings::default(),
+            };
+            timings.time_to_first_byte = Some(time.elapsed(sent));
+            timings.body_read = Some(Duration::ZERO);
+            Ok(TransportResponse {
+                status: response.status,
+                headers: response.headers,
+                body: response.body,
+                timings,
+            })
+        })
+    }
+
+    fn reset_connections(&self) -> Result<(), ApiClientError> {
+        self.connected.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+/// The error reqwest reports when a local server reads a request and drops the connection
+/// without answering. reqwest errors cannot be built by hand, so this provokes a real one.
+pub async fn connection_reset_error() -> reqwest::Error {
+    let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .expect("bind a local port");
+    let url = format!("http://{}/", listener.local_addr().expect("bound address"));
+    let server = async {
+        if let Ok((socket, _)) = listener.accept().await {
+            // Closing with the request unread makes the kernel reset the connection.
+            let _ = socket.readable().await;
+        }
+    };
+    let (_, result) = futures_util::future::join(server, reqwest::Client::new().get(url).send()).await;
+    result.expect_err("the server closes without answering")
+}
+
+fn path_and_query(url: &str) -> &str {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    without_scheme.find('/').map_or("/", |pos| &without_scheme[pos..])
+}
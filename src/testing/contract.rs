@@ -0,0 +1,324 @@
+//! Recording what a client sends, for contract tests against a staging gateway. Unlike a log,
+//! nothing is truncated: bodies, signatures and decrypted responses are kept in full, so the
+//! recorder belongs in tests only. Only the fields of `ApiClientConfig::redaction` are masked,
+//! in request payloads, responses and errors alike.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), ho_api_rust_sdk::ApiClientError> {
+//! use std::sync::Arc;
+//!
+//! use ho_api_rust_sdk::testing::{test_config, ContractRecorder};
+//! use ho_api_rust_sdk::transport::ReqwestTransport;
+//! use ho_api_rust_sdk::{assert_exchange, ApiClient};
+//! use reqwest::Method;
+//! use serde_json::json;
+//!
+//! let config = test_config();
+//! let recorder = ContractRecorder::wrap(Arc::new(ReqwestTransport::new()?), &config)?;
+//! let client = ApiClient::with_transport(config, recorder.clone())?;
+//! client.send(Method::POST, "/v1/order", Some(json!({ "sku": 7 }))).await?;
+//!
+//! assert_exchange!(recorder, 0, method = POST, uri_contains = "/order", body_json_eq = json!({ "sku": 7 }));
+//! std::fs::write("contract-report.json", recorder.report().to_string()).unwrap();
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use futures_util::future::BoxFuture;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::client::signed_uri;
+use crate::crypto::Crypto;
+use crate::envelope;
+use crate::redact::RedactionConfig;
+use crate::transport::{Transport, TransportRequest, TransportResponse};
+use crate::{ApiClientConfig, ApiClientError};
+
+/// One request as it went out and what came back.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Exchange {
+    pub method: String,
+    /// The full URL requested.
+    pub url: String,
+    /// The uri the signature covers.
+    pub uri: String,
+    /// The request body exactly as sent.
+    pub body: String,
+    /// Every `HO-*` request header, by lowercase name.
+    pub headers: BTreeMap<String, String>,
+    /// `None` when the transport failed.
+    pub status: Option<u16>,
+    /// The decrypted response, or the raw body when it is not an envelope the primary key
+    /// opens.
+    pub response: Option<String>,
+    /// The transport error, when there was one.
+    pub error: Option<String>,
+}
+
+impl Exchange {
+    /// The JSON text inside the `{"data": ...}` request envelope, or the body itself when it
+    /// is not one.
+    pub fn payload(&self) -> String {
+        match serde_json::from_str::<Value>(&self.body) {
+            Ok(Value::Object(mut envelope)) => match envelope.remove("data") {
+                Some(Value::String(payload)) => payload,
+                _ => self.body.clone(),
+            },
+            _ => self.body.clone(),
+        }
+    }
+
+    /// The payload, parsed.
+    pub fn body_json(&self) -> Option<Value> {
+        serde_json::from_str(&self.payload()).ok()
+    }
+
+    pub fn response_json(&self) -> Option<Value> {
+        serde_json::from_str(self.response.as_deref()?).ok()
+    }
+}
+
+/// A `Transport` that passes requests to another and records each exchange.
+pub struct ContractRecorder {
+    inner: Arc<dyn Transport>,
+    crypto: Crypto,
+    content: String,
+    sign_content_path: bool,
+    redaction: Option<RedactionConfig>,
+    exchanges: Mutex<Vec<Exchange>>,
+}
+
+impl ContractRecorder {
+    /// Records the requests of a client built from `config` on top of `inner`.
+    pub fn wrap(inner: Arc<dyn Transport>, config: &ApiClientConfig) -> Result<Arc<Self>, ApiClientError> {
+        let crypto = Crypto::new(&config.app_secret, &config.iv)?
+            .strict_hex(config.strict_hex)
+            .charset(config.response_charset);
+        Ok(Arc::new(Self {
+            inner,
+            crypto,
+            content: config.content.clone(),
+            sign_content_path: config.sign_content_path,
+            redaction: config.redaction.clone(),
+            exchanges: Mutex::new(Vec::new()),
+        }))
+    }
+
+    /// Every exchange so far, in the order the requests were sent.
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        self.exchanges.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.exchanges.lock().unwrap().clear();
+    }
+
+    /// `{"exchanges": [...]}`, for writing next to the test results.
+    pub fn report(&self) -> Value {
+        json!({ "exchanges": self.exchanges() })
+    }
+
+    fn uri(&self, url: &str) -> String {
+        let path_and_query = match url.find("://") {
+            Some(scheme_end) => {
+                let rest = &url[scheme_end + 3..];
+                rest.find(['/', '?']).map_or("", |start| &rest[start..])
+            }
+            None => url,
+        };
+        match path_and_query.strip_prefix(self.content.as_str()) {
+            Some(uri) if !self.content.is_empty() => signed_uri(&self.content, uri, self.sign_content_path),
+            _ => path_and_query.to_string(),
+        }
+    }
+}
+
+impl Transport for ContractRecorder {
+    fn execute(&self, request: TransportRequest) -> BoxFuture<'_, Result<TransportResponse, ApiClientError>> {
+        let mut exchange = Exchange {
+            method: request.method.to_string(),
+            url: request.url.clone(),
+            uri: self.uri(&request.url),
+            body: String::from_utf8_lossy(&request.body).into_owned(),
+            headers: request
+                .headers
+                .iter()
+                .filter(|(name, _)| name.as_str().starts_with("ho-"))
+                .map(|(name, value)| {
+                    let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+                    (name.to_string(), value)
+                })
+                .collect(),
+            status: None,
+            response: None,
+            error: None,
+        };
+        Box::pin(async move {
+            let result = match self.inner.execute(request).await {
+                Ok(response) => {
+                    exchange.status = Some(response.status.as_u16());
+                    exchange.response = Some(
+                        envelope::open(&self.crypto, &response.headers, &response.body)
+                            .unwrap_or_else(|_| String::from_utf8_lossy(&response.body).into_owned()),
+                    );
+                    Ok(response)
+                }
+                // Errors cannot be cloned, so the client gets the redacted one too; it would
+                // redact it before returning it anyway.
+                Err(e) => {
+                    let e = match &self.redaction {
+                        Some(redaction) => redaction.redact_error(e),
+                        None => e,
+                    };
+                    exchange.error = Some(format!("{:?}", e));
+                    Err(e)
+                }
+            };
+            if let Some(redaction) = &self.redaction {
+                exchange.body = redaction.redact_request_body(&exchange.body);
+                exchange.response = exchange.response.map(|response| redaction.redact(&response));
+            }
+            self.exchanges.lock().unwrap().push(exchange);
+            result
+        })
+    }
+}
+
+/// Asserts facts about one recorded exchange, by index:
+///
+/// - `method = POST`
+/// - `uri_contains = "/order"`
+/// - `body_contains = "\"sku\""`, in the payload inside the envelope
+/// - `body_json_eq = json!({...})`
+/// - `status = 200`
+/// - `response_json_eq = json!({...})`
+///
+/// A failed check panics with the whole exchange in the message.
+#[macro_export]
+macro_rules! assert_exchange {
+    ($recorder:expr, $index:expr $(, $($checks:tt)*)?) => {{
+        let exchange = $crate::testing::contract::nth(&$recorder, $index);
+        $crate::__assert_exchange_checks!(exchange; $($($checks)*)?);
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __assert_exchange_checks {
+    ($exchange:ident;) => {};
+    ($exchange:ident; method = $method:ident $(, $($rest:tt)*)?) => {
+        $crate::testing::contract::check(&$exchange, "method", $exchange.method == stringify!($method));
+        $crate::__assert_exchange_checks!($exchange; $($($rest)*)?);
+    };
+    ($exchange:ident; uri_contains = $needle:expr $(, $($rest:tt)*)?) => {
+        $crate::testing::contract::check(&$exchange, "uri_contains", $exchange.uri.contains($needle));
+        $crate::__assert_exchange_checks!($exchange; $($($rest)*)?);
+    };
+    ($exchange:ident; body_contains = $needle:expr $(, $($rest:tt)*)?) => {
+        $crate::testing::contract::check(&$exchange, "body_contains", $exchange.payload().contains($needle));
+        $crate::__assert_exchange_checks!($exchange; $($($rest)*)?);
+    };
+    ($exchange:ident; body_json_eq = $expected:expr $(, $($rest:tt)*)?) => {
+        $crate::testing::contract::check(&$exchange, "body_json_eq", $exchange.body_json() == Some($expected));
+        $crate::__assert_exchange_checks!($exchange; $($($rest)*)?);
+    };
+    ($exchange:ident; status = $status:expr $(, $($rest:tt)*)?) => {
+        $crate::testing::contract::check(&$exchange, "status", $exchange.status == Some($status));
+        $crate::__assert_exchange_checks!($exchange; $($($rest)*)?);
+    };
+    ($exchange:ident; response_json_eq = $expected:expr $(, $($rest:tt)*)?) => {
+        let matched = $exchange.response_json() == Some($expected);
+        $crate::testing::contract::check(&$exchange, "response_json_eq", matched);
+        $crate::__assert_exchange_checks!($exchange; $($($rest)*)?);
+    };
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn nth(recorder: &ContractRecorder, index: usize) -> Exchange {
+    let exchanges = recorder.exchanges();
+    match exchanges.get(index) {
+        Some(exchange) => exchange.clone(),
+        None => panic!("no exchange {}: {} recorded", index, exchanges.len()),
+    }
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn check(exchange: &Exchange, name: &str, passed: bool) {
+    if !passed {
+        let shown = serde_json::to_string_pretty(exchange).unwrap_or_default();
+        panic!("assert_exchange: {} failed for {}", name, shown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{Method, StatusCode};
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{test_config, MockResponse, MockTransport};
+    use crate::ApiClient;
+
+    async fn recorded() -> Arc<ContractRecorder> {
+        let transport = MockTransport::new();
+        transport.on(
+            Method::POST,
+            "/v1/order",
+            MockResponse::encrypted(r#"{"order_id":"A1"}"#),
+        );
+        transport.on(Method::GET, "/v1/missing", MockResponse::status(StatusCode::NOT_FOUND));
+        let recorder = ContractRecorder::wrap(transport, &test_config()).unwrap();
+        let client = ApiClient::with_transport(test_config(), recorder.clone()).unwrap();
+        let body = json!({ "sku": 7, "note": "x".repeat(4096) });
+        client.send(Method::POST, "/v1/order", Some(body)).await.unwrap();
+        assert!(client.send(Method::GET, "/v1/missing?page=2", None).await.is_err());
+        recorder
+    }
+
+    #[tokio::test]
+    async fn test_exchanges_are_recorded_in_full() {
+        let recorder = recorded().await;
+        let exchanges = recorder.exchanges();
+        assert_eq!(exchanges.len(), 2);
+
+        let order = &exchanges[0];
+        assert_eq!(order.uri, "/v1/order");
+        assert!(order.body.starts_with(r#"{"data":"{\"note\":"#), "{}", order.body);
+        assert_eq!(order.body_json().unwrap()["note"].as_str().unwrap().len(), 4096);
+        assert_eq!(order.response.as_deref(), Some(r#"{"order_id":"A1"}"#));
+        let names: Vec<&str> = order.headers.keys().map(String::as_str).collect();
+        assert_eq!(names, ["ho-app-id", "ho-nonce", "ho-signature", "ho-timestamp"]);
+
+        assert_eq!(exchanges[1].uri, "/v1/missing?page=2");
+        assert_eq!(exchanges[1].status, Some(404));
+        assert_eq!(recorder.report()["exchanges"][1]["method"], "GET");
+    }
+
+    #[tokio::test]
+    async fn test_assert_exchange_checks_each_fact() {
+        let recorder = recorded().await;
+        crate::assert_exchange!(
+            recorder,
+            0,
+            method = POST,
+            uri_contains = "/order",
+            body_contains = "\"sku\":7",
+            status = 200,
+            response_json_eq = json!({ "order_id": "A1" }),
+        );
+        crate::assert_exchange!(recorder, 1, method = GET, status = 404);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "assert_exchange: body_json_eq failed")]
+    async fn test_assert_exchange_panics_on_mismatch() {
+        let recorder = recorded().await;
+        crate::assert_exchange!(recorder, 0, method = POST, body_json_eq = json!({ "sku": 8 }));
+    }
+}
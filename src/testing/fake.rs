@@ -0,0 +1,167 @@
+//! `FakeApi`, an in-memory `HoApi` answering with plaintext: no signing, encryption or
+//! transport, for testing code written against the trait rather than `ApiClient`.
+//!
+//! ```
+//! use ho_api_rust_sdk::testing::FakeApi;
+//! use ho_api_rust_sdk::{ApiClientError, HoApi};
+//! use reqwest::{Method, StatusCode};
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct Skin {
+//!     name: String,
+//! }
+//!
+//! // Downstream code, generic over the client.
+//! async fn skin_names(api: &impl HoApi, champion: &str) -> Result<Vec<String>, ApiClientError> {
+//!     let uri = format!("/v1/champions/{}/skins", champion);
+//!     let skins: Vec<Skin> = api.send_as(Method::GET, &uri, None).await?;
+//!     Ok(skins.into_iter().map(|skin| skin.name).collect())
+//! }
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let api = FakeApi::new();
+//! api.on(Method::GET, "/v1/champions/1/skins", r#"[{"name":"哥特萝莉 安妮"}]"#)
+//!     .on_status(Method::GET, "/v1/champions/2/skins", StatusCode::NOT_FOUND, "");
+//!
+//! assert_eq!(skin_names(&api, "1").await.unwrap(), ["哥特萝莉 安妮"]);
+//! assert!(skin_names(&api, "2").await.is_err());
+//! assert_eq!(api.requests().len(), 2);
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use reqwest::{Method, StatusCode};
+use serde_json::Value;
+
+use crate::api::HoApi;
+use crate::response::{ApiResponse, ResponseMeta};
+use crate::time::TimeSource;
+use crate::ApiClientError;
+
+#[derive(Clone)]
+enum Reply {
+    Ok(String),
+    Status(StatusCode, String),
+}
+
+struct Route {
+    method: Method,
+    path: String,
+    replies: VecDeque<Reply>,
+}
+
+/// A request `FakeApi` received.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeRequest {
+    pub method: Method,
+    pub uri: String,
+    pub body: Option<Value>,
+}
+
+/// A scripted `HoApi`. Replies are matched and served as `MockTransport` serves responses:
+/// by method and path suffix, in order with the last one repeating; unmatched requests fail
+/// with a 404 `HttpStatus`.
+#[derive(Default)]
+pub struct FakeApi {
+    routes: Mutex<Vec<Route>>,
+    requests: Mutex<Vec<FakeRequest>>,
+    time: Option<Arc<dyn TimeSource>>,
+}
+
+impl FakeApi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A `FakeApi` whose `now_utc` is `time`'s, such as a `SimulatedTime`.
+    pub fn with_time(time: Arc<dyn TimeSource>) -> Self {
+        Self {
+            time: Some(time),
+            ..Self::default()
+        }
+    }
+
+    /// Queues `plaintext` as a successful reply to requests whose path ends with `path`.
+    /// When `path` contains a `?` the query string takes part in the match too.
+    pub fn on(&self, method: Method, path: &str, plaintext: impl Into<String>) -> &Self {
+        self.push(method, path, Reply::Ok(plaintext.into()))
+    }
+
+    /// Queues a failure with `ApiClientError::HttpStatus` of `status` and `body`.
+    pub fn on_status(&self, method: Method, path: &str, status: StatusCode, body: impl Into<String>) -> &Self {
+        self.push(method, path, Reply::Status(status, body.into()))
+    }
+
+    /// Every request received so far, in arrival order.
+    pub fn requests(&self) -> Vec<FakeRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn push(&self, method: Method, path: &str, reply: Reply) -> &Self {
+        let mut routes = self.routes.lock().unwrap();
+        match routes.iter_mut().find(|r| r.method == method && r.path == path) {
+            Some(route) => route.replies.push_back(reply),
+            None => routes.push(Route {
+                method,
+                path: path.to_string(),
+                replies: VecDeque::from([reply]),
+            }),
+        }
+        self
+    }
+
+    fn next_reply(&self, method: &Method, uri: &str) -> Reply {
+        let path = uri.split('?').next().unwrap_or_default();
+        let mut routes = self.routes.lock().unwrap();
+        let route = routes.iter_mut().find(|r| {
+            let target = if r.path.contains('?') { uri } else { path };
+            r.method == method && target.ends_with(&r.path)
+        });
+        match route {
+            Some(route) if route.replies.len() > 1 => route.replies.pop_front().unwrap(),
+            Some(route) => route.replies[0].clone(),
+            None => Reply::Status(StatusCode::NOT_FOUND, format!("no fake route for {} {}", method, uri)),
+        }
+    }
+}
+
+impl HoApi for FakeApi {
+    fn send_detailed<'a>(
+        &'a self,
+        method: Method,
+        uri: &'a str,
+        body: Option<Value>,
+    ) -> BoxFuture<'a, Result<ApiResponse, ApiClientError>> {
+        let reply = self.next_reply(&method, uri);
+        self.requests.lock().unwrap().push(FakeRequest {
+            method,
+            uri: uri.to_string(),
+            body,
+        });
+        Box::pin(async move {
+            match reply {
+                Reply::Ok(body) => Ok(ApiResponse {
+                    body,
+                    meta: ResponseMeta {
+                        status: StatusCode::OK,
+                        ..ResponseMeta::default()
+                    },
+                }),
+                Reply::Status(status, body) => Err(ApiClientError::HttpStatus { status, body }),
+            }
+        })
+    }
+
+    fn now_utc(&self) -> DateTime<Utc> {
+        match &self.time {
+            Some(time) => time.now_utc(),
+            None => Utc::now(),
+        }
+    }
+}
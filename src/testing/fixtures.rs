@@ -0,0 +1,181 @@
+//! Fixture payloads for `MockTransport`: compressed plaintext JSON files checked into the
+//! repository, and large catalogs generated on demand. Both are encrypted with the test key
+//! when served, so the encrypted form never needs to be stored.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use super::{encrypted_envelope, MockResponse};
+
+/// A plaintext JSON payload, served as an encrypted envelope.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixture {
+    plaintext: String,
+}
+
+impl Fixture {
+    pub fn from_plaintext(plaintext: impl Into<String>) -> Self {
+        Self {
+            plaintext: plaintext.into(),
+        }
+    }
+
+    /// Reads a fixture file, decompressing it when the name ends in `.br` (Brotli) or `.gz`
+    /// (gzip). Anything else is read as plain JSON text.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let mut reader: Box<dyn Read> = match path.extension().and_then(|e| e.to_str()) {
+            Some("br") => Box::new(brotli_decompressor::Decompressor::new(file, 4096)),
+            Some("gz") => Box::new(flate2::read::GzDecoder::new(file)),
+            _ => Box::new(file),
+        };
+        let mut plaintext = String::new();
+        reader.read_to_string(&mut plaintext)?;
+        Ok(Self::from_plaintext(plaintext))
+    }
+
+    pub fn plaintext(&self) -> &str {
+        &self.plaintext
+    }
+
+    /// The payload as a `{"data": "<hex>"}` envelope. Encryption with the fixed test key and
+    /// IV is deterministic, so the same fixture always yields the same bytes.
+    pub fn envelope(&self) -> String {
+        encrypted_envelope(&self.plaintext)
+    }
+
+    pub fn response(&self) -> MockResponse {
+        MockResponse::encrypted(&self.plaintext)
+    }
+}
+
+const CHAMPIONS: [(u64, &str); 6] = [
+    (1, "安妮"),
+    (17, "提莫"),
+    (22, "艾希"),
+    (86, "盖伦"),
+    (103, "阿狸"),
+    (157, "亚索"),
+];
+const THEMES: [&str; 5] = ["星之守护者", "源计划", "灵魂莲华", "电玩", "西部魔影"];
+const RARITIES: [&str; 4] = ["common", "epic", "legendary", "ultimate"];
+const PRICES: [u64; 4] = [750, 1350, 1820, 3250];
+
+/// Builds synthetic catalog payloads in the `{"list": [...], "total": n}` shape. Output
+/// depends only on the count and seed.
+#[derive(Debug, Clone)]
+pub struct FixtureGen {
+    count: usize,
+    seed: u64,
+}
+
+impl FixtureGen {
+    /// `count` champion skins with ids, names, prices and rarities.
+    pub fn champion_skins(count: usize) -> Self {
+        Self { count, seed: 0 }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn skins(&self) -> Vec<Value> {
+        let mut state = self.seed;
+        (0..self.count)
+            .map(|i| {
+                let roll = splitmix64(&mut state);
+                let (champion_id, champion) = CHAMPIONS[(roll % CHAMPIONS.len() as u64) as usize];
+                let theme = THEMES[((roll >> 8) % THEMES.len() as u64) as usize];
+                let tier = ((roll >> 16) % RARITIES.len() as u64) as usize;
+                json!({
+                    "id": champion_id * 1000 + i as u64,
+                    "champion_id": champion_id,
+                    "name": format!("{} {}", theme, champion),
+                    "price": PRICES[tier],
+                    "rarity": RARITIES[tier],
+                })
+            })
+            .collect()
+    }
+
+    pub fn build(&self) -> Fixture {
+        let payload = json!({ "list": self.skins(), "total": self.count });
+        Fixture::from_plaintext(payload.to_string())
+    }
+}
+
+/// A small, fixed pseudo-random sequence, so generated fixtures are stable across platforms
+/// and releases.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Method;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::payload::parse_list;
+    use crate::testing::{mock_client, MockTransport};
+
+    #[derive(Debug, Deserialize)]
+    struct Skin {
+        id: u64,
+        name: String,
+        price: u64,
+    }
+
+    fn fixture_path(name: &str) -> String {
+        format!("{}/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn test_brotli_and_gzip_fixtures_match() {
+        let brotli = Fixture::load(fixture_path("champion_skins.json.br")).unwrap();
+        let gzip = Fixture::load(fixture_path("champion_skins.json.gz")).unwrap();
+        assert_eq!(brotli, gzip);
+
+        let (skins, total) = parse_list::<Skin>(brotli.plaintext()).unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(skins[0].id, 1001);
+        assert_eq!(skins[2].name, "绿色军团 提莫");
+    }
+
+    #[tokio::test]
+    async fn test_fixture_is_served_encrypted() {
+        let fixture = Fixture::load(fixture_path("champion_skins.json.br")).unwrap();
+        assert_eq!(fixture.envelope(), fixture.envelope());
+
+        let transport = MockTransport::new();
+        transport.on(Method::GET, "/v1/lol/champion/skin", fixture.response());
+        let client = mock_client(&transport, |_| {});
+        let plaintext = client
+            .send(Method::GET, "/v1/lol/champion/skin?region=cn", None)
+            .await
+            .unwrap();
+        assert_eq!(plaintext, fixture.plaintext());
+    }
+
+    #[test]
+    fn test_generated_catalog_is_deterministic() {
+        let catalog = FixtureGen::champion_skins(20_000).build();
+        assert!(catalog.plaintext().len() > 1_000_000, "{}", catalog.plaintext().len());
+        assert_eq!(catalog, FixtureGen::champion_skins(20_000).build());
+        assert_ne!(catalog, FixtureGen::champion_skins(20_000).seed(7).build());
+
+        let (skins, total) = parse_list::<Skin>(catalog.plaintext()).unwrap();
+        assert_eq!(total, 20_000);
+        let revenue: u64 = skins.iter().map(|s| s.price).sum();
+        assert_eq!(revenue, 36_047_880);
+    }
+}
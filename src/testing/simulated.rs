@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use futures_util::future::BoxFuture;
+use tokio::time::Instant;
+
+use crate::time::TimeSource;
+
+/// Yields to the runtime this many times for tasks woken by a step to run up to their next
+/// wait.
+const SETTLE_YIELDS: usize = 16;
+
+/// A `TimeSource` that only moves when told to. Sleeps end, in deadline order, as `advance`
+/// or `run_for` pass their deadline; `drive` runs a future with time jumping to the next
+/// deadline whenever everything waits. Clones share the same clock.
+#[derive(Clone)]
+pub struct SimulatedTime {
+    shared: Arc<Mutex<Clock>>,
+}
+
+struct Clock {
+    start: Instant,
+    wall_start: DateTime<Utc>,
+    elapsed: Duration,
+    /// Pending sleeps by deadline, then by creation.
+    sleepers: BTreeMap<(Duration, u64), Option<Waker>>,
+    next_id: u64,
+}
+
+impl Default for SimulatedTime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulatedTime {
+    /// Starts at 2024-06-01 00:00:00 UTC.
+    pub fn new() -> Self {
+        Self::starting_at(Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap())
+    }
+
+    pub fn starting_at(wall_start: DateTime<Utc>) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Clock {
+                start: Instant::now(),
+                wall_start,
+                elapsed: Duration::ZERO,
+                sleepers: BTreeMap::new(),
+                next_id: 0,
+            })),
+        }
+    }
+
+    /// How far the clock has moved since it was created.
+    pub fn elapsed_total(&self) -> Duration {
+        self.shared.lock().unwrap().elapsed
+    }
+
+    /// Moves the clock forward and wakes the sleeps it passes, without waiting for them.
+    pub fn advance(&self, by: Duration) {
+        let mut clock = self.shared.lock().unwrap();
+        clock.elapsed += by;
+        clock.wake_due();
+    }
+
+    /// Moves the clock forward by `by` one deadline at a time, letting woken tasks run
+    /// before the next.
+    pub async fn run_for(&self, by: Duration) {
+        let target = self.elapsed_total() + by;
+        loop {
+            settle().await;
+            let mut clock = self.shared.lock().unwrap();
+            match clock.next_deadline().filter(|&deadline| deadline <= target) {
+                Some(deadline) => {
+                    clock.elapsed = deadline;
+                    clock.wake_due();
+                }
+                None => {
+                    clock.elapsed = target;
+                    break;
+                }
+            }
+        }
+        settle().await;
+    }
+
+    /// Runs `future` to completion, moving the clock to the next pending deadline whenever
+    /// it and the tasks it woke are waiting.
+    pub async fn drive<F: Future>(&self, future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        loop {
+            if let Poll::Ready(output) = poll_once(future.as_mut()).await {
+                return output;
+            }
+            settle().await;
+            if let Poll::Ready(output) = poll_once(future.as_mut()).await {
+                return output;
+            }
+            let mut clock = self.shared.lock().unwrap();
+            if let Some(deadline) = clock.next_deadline() {
+                clock.elapsed = clock.elapsed.max(deadline);
+                clock.wake_due();
+            }
+        }
+    }
+}
+
+impl Clock {
+    fn next_deadline(&self) -> Option<Duration> {
+        self.sleepers.keys().next().map(|&(deadline, _)| deadline)
+    }
+
+    fn wake_due(&mut self) {
+        let elapsed = self.elapsed;
+        for ((deadline, _), waker) in self.sleepers.iter_mut() {
+            if *deadline > elapsed {
+                break;
+            }
+            if let Some(waker) = waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl TimeSource for SimulatedTime {
+    fn now(&self) -> Instant {
+        let clock = self.shared.lock().unwrap();
+        clock.start + clock.elapsed
+    }
+
+    fn now_utc(&self) -> DateTime<Utc> {
+        let clock = self.shared.lock().unwrap();
+        clock.wall_start + chrono::Duration::from_std(clock.elapsed).expect("simulated time fits in chrono")
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let mut clock = self.shared.lock().unwrap();
+        let key = (clock.elapsed + duration, clock.next_id);
+        clock.next_id += 1;
+        clock.sleepers.insert(key, None);
+        Box::pin(Sleep {
+            shared: self.shared.clone(),
+            key,
+        })
+    }
+}
+
+struct Sleep {
+    shared: Arc<Mutex<Clock>>,
+    key: (Duration, u64),
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut clock = self.shared.lock().unwrap();
+        if clock.elapsed >= self.key.0 {
+            clock.sleepers.remove(&self.key);
+            return Poll::Ready(());
+        }
+        clock.sleepers.insert(self.key, Some(cx.waker().clone()));
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.shared.lock().unwrap().sleepers.remove(&self.key);
+    }
+}
+
+async fn poll_once<F: Future>(mut future: Pin<&mut F>) -> Poll<F::Output> {
+    std::future::poll_fn(|cx| Poll::Ready(future.as_mut().poll(cx))).await
+}
+
+async fn settle() {
+    for _ in 0..SETTLE_YIELDS {
+        tokio::task::yield_now().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sleeps_end_in_deadline_order() {
+        let time = SimulatedTime::new();
+        let woken = Arc::new(Mutex::new(Vec::new()));
+        for (name, secs) in [("c", 30), ("a", 10), ("b", 20), ("a2", 10)] {
+            let (time, woken) = (time.clone(), woken.clone());
+            tokio::spawn(async move {
+                time.sleep(Duration::from_secs(secs)).await;
+                woken.lock().unwrap().push((name, time.elapsed_total()));
+            });
+        }
+        time.run_for(Duration::from_secs(25)).await;
+        assert_eq!(
+            *woken.lock().unwrap(),
+            [
+                ("a", Duration::from_secs(10)),
+                ("a2", Duration::from_secs(10)),
+                ("b", Duration::from_secs(20))
+            ]
+        );
+        assert_eq!(time.elapsed_total(), Duration::from_secs(25));
+        assert_eq!(time.now_utc().to_rfc3339(), "2024-06-01T00:00:25+00:00");
+
+        let start = time.now();
+        let slept = time.drive(time.sleep(Duration::from_secs(3600))).await;
+        assert_eq!((slept, time.elapsed(start)), ((), Duration::from_secs(3600)));
+        time.run_for(Duration::from_secs(1)).await;
+        assert_eq!(woken.lock().unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,205 @@
+//! Where the client gets the time from. Every timestamp, elapsed time, timeout and backoff
+//! of the client, its failover scores and background tasks goes through the `TimeSource` in
+//! `ApiClientConfig::time`, so a test can swap in `testing::SimulatedTime` and run minutes
+//! of retries, failover and cache expiry without waiting for them. Cache backends take
+//! theirs separately, as in `MemoryCache::time_source`. The default, `RealTime`, follows
+//! tokio's clock, so `tokio::time::pause` applies to it too, and the system clock for wall
+//! time. `ReqwestTransport` measures the network it talks to on the real clock whatever the
+//! source.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures_util::future::{self, BoxFuture, Either};
+use tokio::time::Instant;
+
+pub trait TimeSource: Send + Sync {
+    /// A monotonic instant, for durations.
+    fn now(&self) -> Instant;
+
+    /// The wall-clock time, for signatures and dates.
+    fn now_utc(&self) -> DateTime<Utc>;
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+}
+
+/// tokio's clock and the system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealTime;
+
+impl TimeSource for RealTime {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_utc(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+pub(crate) fn real() -> Arc<dyn TimeSource> {
+    Arc::new(RealTime)
+}
+
+/// `future`'s output, or `None` if `duration` passes on `time` first.
+pub(crate) async fn timeout<F: Future>(time: &dyn TimeSource, duration: Duration, future: F) -> Option<F::Output> {
+    let future = std::pin::pin!(future);
+    match future::select(future, time.sleep(duration)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(((), _)) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+    use std::sync::Mutex;
+
+    use reqwest::{Method, StatusCode};
+
+    use super::*;
+    use crate::cache::{CacheConfig, MemoryCache};
+    use crate::response::Timings;
+    use crate::retry::RetryConfig;
+    use crate::testing::{encrypted_envelope, test_config, SimulatedTime};
+    use crate::transport::{Transport, TransportRequest, TransportResponse};
+    use crate::{ApiClient, ApiClientError};
+
+    const PRIMARY: &str = "https://primary.example.com";
+    const BACKUP: &str = "https://backup.example.com";
+
+    /// Two gateways on simulated time: the primary is down for `primary_down`, hanging until
+    /// the attempt times out, and the backup answers 503 during `backup_down`.
+    struct Outage {
+        time: SimulatedTime,
+        primary_down: Range<Duration>,
+        backup_down: Range<Duration>,
+        sent: Mutex<Vec<(Duration, String)>>,
+    }
+
+    impl Transport for Outage {
+        fn execute(&self, request: TransportRequest) -> BoxFuture<'_, Result<TransportResponse, ApiClientError>> {
+            Box::pin(async move {
+                let at = self.time.elapsed_total();
+                let host = match request.url.starts_with(PRIMARY) {
+                    true => "primary",
+                    false => "backup",
+                };
+                self.sent.lock().unwrap().push((at, host.to_string()));
+                let status = match host {
+                    "primary" if self.primary_down.contains(&at) => {
+                        self.time.sleep(Duration::from_secs(30)).await;
+                        StatusCode::GATEWAY_TIMEOUT
+                    }
+                    "backup" if self.backup_down.contains(&at) => StatusCode::SERVICE_UNAVAILABLE,
+                    _ => StatusCode::OK,
+                };
+                let body = match status {
+                    StatusCode::OK => encrypted_envelope(&format!(r#"{{"host":"{}"}}"#, host)),
+                    _ => String::new(),
+                };
+                Ok(TransportResponse {
+                    status,
+                    headers: Default::default(),
+                    body: body.into(),
+                    timings: Timings::default(),
+                })
+            })
+        }
+    }
+
+    fn minutes(m: f64) -> Duration {
+        Duration::from_secs_f64(m * 60.0)
+    }
+
+    /// Ten minutes of a primary outage, two of which the backup shares, run on simulated time.
+    #[tokio::test]
+    async fn test_outage_and_recovery_on_simulated_time() {
+        let real_start = std::time::Instant::now();
+        let time = SimulatedTime::new();
+        let transport = Arc::new(Outage {
+            time: time.clone(),
+            primary_down: minutes(1.0)..minutes(11.0),
+            backup_down: minutes(1.0)..minutes(3.0),
+            sent: Mutex::new(Vec::new()),
+        });
+        let mut config = test_config();
+        config.time = Arc::new(time.clone());
+        config.base_url = PRIMARY.to_string();
+        config.fallback_base_urls = vec![BACKUP.to_string()];
+        config.attempt_timeout = Some(Duration::from_secs(2));
+        config.retry = RetryConfig {
+            max_retries: 2,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+        };
+        config.cache = Some(CacheConfig {
+            backend: Arc::new(MemoryCache::new().time_source(Arc::new(time.clone()))),
+            ttl: Duration::from_secs(60),
+            max_stale: Some(minutes(5.0)),
+        });
+        let client = ApiClient::with_transport(config, transport.clone()).unwrap();
+        let get = |uri: &'static str| {
+            let client = &client;
+            let time = &time;
+            async move { time.drive(client.send_detailed(Method::GET, uri, None)).await }
+        };
+        let host = |response: &crate::response::ApiResponse| response.body.clone();
+
+        let first = get("/v1/catalog").await.unwrap();
+        assert_eq!(host(&first), r#"{"host":"primary"}"#);
+        time.run_for(Duration::from_secs(30)).await;
+        assert!(get("/v1/catalog").await.unwrap().meta.from_cache);
+
+        // 2m: the cache entry expired a minute ago and both gateways fail. Three passes over
+        // both hosts, 2s timeouts and 1s + 2s of backoff later, the stale entry is served.
+        time.run_for(minutes(1.5)).await;
+        let started = time.elapsed_total();
+        let stale = get("/v1/catalog").await.unwrap();
+        assert!(stale.meta.stale);
+        assert_eq!(time.elapsed_total() - started, Duration::from_secs(9));
+        let sent = transport.sent.lock().unwrap().len();
+        assert_eq!(sent, 1 + 6);
+
+        // 4m: the backup is back. The primary still hangs, so the request moves on to the
+        // backup after its timeout, and later requests go to the backup first.
+        time.run_for(minutes(2.0)).await;
+        let recovered = get("/v1/catalog").await.unwrap();
+        assert_eq!(host(&recovered), r#"{"host":"backup"}"#);
+        assert!(!recovered.meta.stale && !recovered.meta.from_cache);
+        let profile = get("/v1/profile").await.unwrap();
+        assert_eq!(host(&profile), r#"{"host":"backup"}"#);
+        let last = transport.sent.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(last.1, "backup");
+        let active: Vec<bool> = client.stats().hosts.iter().map(|host| host.active).collect();
+        assert_eq!(active, [false, true]);
+
+        // 12m: the primary recovered a minute ago and the failures of both have decayed, but
+        // traffic stays on the backup; the cached catalog expired long ago.
+        time.run_for(minutes(8.0)).await;
+        let refreshed = get("/v1/catalog").await.unwrap();
+        assert!(!refreshed.meta.from_cache);
+        assert_eq!(host(&refreshed), r#"{"host":"backup"}"#);
+        let hosts = client.stats().hosts;
+        assert!(hosts.iter().all(|host| host.failure_rate < 0.01), "{:?}", hosts);
+        assert!(hosts[1].active);
+
+        assert!(time.elapsed_total() >= minutes(12.0));
+        assert!(
+            real_start.elapsed() < Duration::from_secs(5),
+            "{:?}",
+            real_start.elapsed()
+        );
+    }
+}
@@ -0,0 +1,439 @@
+//! Helpers for support tooling: decrypt a response body copied from a ticket, check a
+//! signature, and explain an error in words. Nothing here touches the network.
+//!
+//! ```
+//! use ho_api_rust_sdk::conformance::{ENVELOPE_CIPHERTEXT, VECTOR_APP_SECRET, VECTOR_IV};
+//! use ho_api_rust_sdk::tools;
+//!
+//! let body = format!(r#"{{"data":"{}"}}"#, ENVELOPE_CIPHERTEXT);
+//! match tools::decrypt_envelope(&body, VECTOR_APP_SECRET, VECTOR_IV) {
+//!     Ok(plaintext) => println!("{}", plaintext),
+//!     Err(e) => println!("{}", tools::explain_error(&e)),
+//! }
+//! ```
+
+use std::fmt::Write;
+
+use reqwest::header::HeaderMap;
+
+use crate::charset::Charset;
+use crate::crypto::{Crypto, IV_LEN, SECRET_LEN};
+use crate::envelope;
+use crate::signing::{sign, spec, SignInputs, SignMode, SignableBody};
+use crate::transport::RedirectRefusal;
+use crate::ApiClientError;
+
+const REDACTED_SECRET: &str = "<app_secret>";
+
+/// Decrypts a response body: either the whole `{"data": ...}` envelope as received, or the
+/// bare hex ciphertext from its `data` field. Whitespace and a `0x` prefix are tolerated.
+pub fn decrypt_envelope(envelope_json: &str, secret: &str, iv: &str) -> Result<String, ApiClientError> {
+    check_key_len("app_secret", secret, SECRET_LEN)?;
+    check_key_len("iv", iv, IV_LEN)?;
+    let crypto = Crypto::new(secret, iv)?;
+    let body = envelope_json.trim();
+    match body.starts_with('{') {
+        true => envelope::open(&crypto, &HeaderMap::new(), body.as_bytes()),
+        false => crypto.decrypt_hex(body.as_bytes()),
+    }
+}
+
+fn check_key_len(name: &str, value: &str, expected: usize) -> Result<(), ApiClientError> {
+    match value.len() {
+        len if len == expected => Ok(()),
+        len => Err(ApiClientError::InvalidConfig(format!(
+            "{} is {} bytes; AES-256-CBC needs exactly {}",
+            name, len, expected
+        ))),
+    }
+}
+
+/// The signed parts of a request as they appear in its headers and on the wire.
+#[derive(Debug, Clone, Copy)]
+pub struct SignatureParts<'a> {
+    /// `HO-APP-ID`.
+    pub app_id: &'a str,
+    /// `HO-NONCE`.
+    pub nonce: &'a str,
+    /// `HO-TIMESTAMP`.
+    pub timestamp: i64,
+    /// The uri as signed: after the content path, query string included.
+    pub uri: &'a str,
+    pub body: SignableBody<'a>,
+    /// `HO-SIGNATURE` as received.
+    pub signature: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureCheck {
+    /// The signature `secret` gives for the parts.
+    pub expected: String,
+    /// Whether the received signature equals `expected`, ignoring case and surrounding
+    /// whitespace.
+    pub matched: bool,
+    /// The string that was hashed, with the secret replaced by `<app_secret>`, safe to paste
+    /// into a ticket.
+    pub sign_string: String,
+}
+
+pub fn verify_signature(parts: SignatureParts<'_>, secret: &str) -> SignatureCheck {
+    let expected = sign(
+        parts.app_id,
+        secret,
+        parts.nonce,
+        parts.timestamp,
+        parts.uri,
+        parts.body,
+    );
+    let redacted = SignInputs {
+        app_id: parts.app_id,
+        app_secret: REDACTED_SECRET,
+        nonce: parts.nonce,
+        timestamp: parts.timestamp,
+        content_path: "",
+        uri: parts.uri,
+        body: parts.body,
+    };
+    let sign_string = spec::sign_string(SignMode::Request, &redacted);
+    SignatureCheck {
+        matched: parts.signature.trim().eq_ignore_ascii_case(&expected),
+        expected,
+        sign_string,
+    }
+}
+
+/// A diagnosis of `err` for a person: what went wrong and the usual cause.
+pub fn explain_error(err: &ApiClientError) -> String {
+    use ApiClientError as E;
+    match err {
+        E::InvalidCiphertextLength { len: 0 } => {
+            "the ciphertext is empty — the `data` field was blank or the payload was cut off".to_string()
+        }
+        E::InvalidCiphertextLength { len } => format!(
+            "ciphertext length {} is not a multiple of 16 — the payload was likely truncated",
+            len
+        ),
+        E::InvalidHex(e) if e.error == hex::FromHexError::OddLength => format!(
+            "the hex payload has an odd number of digits ({} characters) — the payload was likely truncated",
+            e.len
+        ),
+        E::InvalidHex(e) => format!(
+            "the payload is not hex ({}) — `data` may be plaintext sent with HO-ENCRYPTED: false, \
+             or mangled by copying",
+            e
+        ),
+        E::HexError(e) => format!("the payload is not valid hex ({})", e),
+        E::AesError(_) => "the ciphertext does not decrypt to valid padding — the app_secret is most likely \
+                           wrong, or the payload belongs to another app"
+            .to_string(),
+        E::BothKeysFailed { primary, secondary } => format!(
+            "neither key decrypts the response. primary key: {}; secondary key: {}",
+            explain_error(primary),
+            explain_error(secondary)
+        ),
+        E::Utf8Error(e) if e.utf8_error().valid_up_to() < IV_LEN => format!(
+            "decryption produced invalid UTF-8 at byte {}, within the first block — the app_secret is right \
+             but the IV is most likely wrong",
+            e.utf8_error().valid_up_to()
+        ),
+        E::Utf8Error(e) => format!(
+            "the plaintext is not UTF-8 from byte {} on — the endpoint may send GBK; try the `charset` feature",
+            e.utf8_error().valid_up_to()
+        ),
+        E::InvalidCharset {
+            charset: Charset::Utf8,
+            offset,
+        } if *offset < IV_LEN => format!(
+            "decryption produced invalid UTF-8 at byte {}, within the first block — the app_secret is right \
+             but the IV is most likely wrong",
+            offset
+        ),
+        E::InvalidCharset {
+            charset: Charset::Utf8,
+            offset,
+        } => format!(
+            "the plaintext is not UTF-8 from byte {} on — the endpoint may send GBK; try the `charset` feature",
+            offset
+        ),
+        #[cfg(feature = "charset")]
+        E::InvalidCharset { charset, offset } => {
+            format!("the plaintext is not valid {} at byte {}", charset, offset)
+        }
+        E::SerdeJsonError(e) if e.is_eof() => format!(
+            "the body ends before the JSON does ({}) — it was likely truncated when copied",
+            e
+        ),
+        E::SerdeJsonError(e) => format!("the body is not the expected JSON ({})", e),
+        E::BatchFailed { failed } => match failed.first() {
+            Some(first) => format!(
+                "all {} items of the batch failed, the first with `{}` — check the requested ids",
+                failed.len(),
+                first.code
+            ),
+            None => "the batch failed without naming an item".to_string(),
+        },
+        E::InvalidEnvelope(message) => format!("the envelope is malformed: {}", message),
+        E::InvalidConfig(message) => format!("the configuration is invalid: {}", message),
+        E::InvalidPayload { path, message } => {
+            format!(
+                "the plaintext does not match the expected type at `{}`: {}",
+                path, message
+            )
+        }
+        E::HttpStatus { status, body } => {
+            let mut text = format!("the server answered {}", status);
+            match status.as_u16() {
+                401 | 403 => text.push_str(" — the signature or app_id was rejected; check the clock and the secret"),
+                429 => text.push_str(" — the app is being rate limited"),
+                500..=599 => text.push_str(" — a server-side failure; retrying may help"),
+                _ => {}
+            }
+            if let Some(code) = err.business_code() {
+                let _ = write!(text, " (business code {})", code);
+            } else if !body.is_empty() {
+                let _ = write!(text, ": {}", body.chars().take(200).collect::<String>());
+            }
+            text
+        }
+        E::RedirectRefused {
+            status,
+            location,
+            reason,
+        } => match reason {
+            RedirectRefusal::CrossHost => format!(
+                "the server redirected ({}) to {}, on another host; the client does not send signed \
+                 requests there unless allow_cross_host_redirects is set",
+                status.as_u16(),
+                location
+            ),
+            RedirectRefusal::TooManyRedirects => format!(
+                "the server kept redirecting ({}), last to {} — likely a redirect loop",
+                status.as_u16(),
+                location
+            ),
+        },
+        E::Maintenance { notice } => {
+            let mut text = format!("the gateway is under scheduled maintenance until {}", notice.ends_at);
+            if let Some(message) = &notice.message {
+                let _ = write!(text, " ({})", message);
+            }
+            text.push_str(" — requests to the affected paths fail without being sent until then");
+            text
+        }
+        E::NonceReuse { nonce } => format!(
+            "nonce {} was signed with before — a signed request is being sent again, or the random source \
+             behind nonces repeats (a process forked after seeding it, for one)",
+            nonce
+        ),
+        E::QuotaReserved { remaining, reserve } => format!(
+            "only {} requests of the daily quota remain, below the reserve of {} kept for higher-priority \
+             requests — this priority is held back until the quota resets",
+            remaining, reserve
+        ),
+        E::SuspectedDoubleEncoding { kind, preview } => format!(
+            "the body is a JSON string holding a JSON {} ({:?}...) — it was likely serialized twice; pass the \
+             value rather than serde_json::to_string of it, or call allow_string_body if the string is intended",
+            kind, preview
+        ),
+        E::ChainStepFailed { step, uri, source } => {
+            format!("step {} ({}) failed: {}", step, uri, explain_error(source))
+        }
+        E::SignatureRejected {
+            reason,
+            components,
+            timestamp,
+            body_sha1,
+            ..
+        } => {
+            let mut text = format!(
+                "the server rejected the signature: {} ({}); signed here: app_id {} bytes, nonce {}, \
+                 timestamp {} ({} bytes), uri {}, body {}",
+                reason.message(),
+                reason.code(),
+                components.app_id,
+                components.nonce,
+                timestamp,
+                components.timestamp,
+                components.uri,
+                components.body
+            );
+            if let Some(sha1) = body_sha1 {
+                let _ = write!(text, "; body sha1 {}", sha1);
+            }
+            text
+        }
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::conformance::{
+        ENVELOPE_CIPHERTEXT, ENVELOPE_PLAINTEXT, SIGNATURE_VECTORS, VECTOR_APP_ID, VECTOR_APP_SECRET, VECTOR_IV,
+        VECTOR_NONCE, VECTOR_TIMESTAMP,
+    };
+
+    fn decrypt(body: &str) -> Result<String, ApiClientError> {
+        decrypt_envelope(body, VECTOR_APP_SECRET, VECTOR_IV)
+    }
+
+    fn explain(body: &str) -> String {
+        explain_error(&decrypt(body).unwrap_err())
+    }
+
+    #[test]
+    fn test_decrypts_envelopes_and_bare_ciphertext() {
+        let envelope = format!("{{\"data\":\"{}\"}}\n", ENVELOPE_CIPHERTEXT);
+        assert_eq!(decrypt(&envelope).unwrap(), ENVELOPE_PLAINTEXT);
+        assert_eq!(decrypt(ENVELOPE_CIPHERTEXT).unwrap(), ENVELOPE_PLAINTEXT);
+        assert_eq!(
+            decrypt(&format!(" 0x{}\r\n", ENVELOPE_CIPHERTEXT)).unwrap(),
+            ENVELOPE_PLAINTEXT
+        );
+        assert_eq!(decrypt(r#"{"data":"plain","encrypted":false}"#).unwrap(), "plain");
+    }
+
+    #[test]
+    fn test_key_lengths_are_reported_in_bytes() {
+        let err = decrypt_envelope(ENVELOPE_CIPHERTEXT, "short", VECTOR_IV).unwrap_err();
+        assert_eq!(
+            explain_error(&err),
+            "the configuration is invalid: app_secret is 5 bytes; AES-256-CBC needs exactly 32"
+        );
+        let err = decrypt_envelope(ENVELOPE_CIPHERTEXT, VECTOR_APP_SECRET, "0123456789abcdef0").unwrap_err();
+        assert!(
+            explain_error(&err).contains("iv is 17 bytes"),
+            "{}",
+            explain_error(&err)
+        );
+    }
+
+    #[test]
+    fn test_truncation_is_diagnosed() {
+        assert_eq!(
+            explain(&ENVELOPE_CIPHERTEXT[..62]),
+            "ciphertext length 31 is not a multiple of 16 — the payload was likely truncated"
+        );
+        assert_eq!(
+            explain(&ENVELOPE_CIPHERTEXT[..61]),
+            "the hex payload has an odd number of digits (61 characters) — the payload was likely truncated"
+        );
+        assert!(explain(r#"{"data":""}"#).starts_with("the ciphertext is empty"));
+        let cut = format!("{{\"data\":\"{}", &ENVELOPE_CIPHERTEXT[..20]);
+        assert!(
+            explain(&cut).contains("likely truncated when copied"),
+            "{}",
+            explain(&cut)
+        );
+    }
+
+    #[test]
+    fn test_wrong_secret_and_wrong_iv() {
+        let other_secret = "ffffffffffffffffffffffffffffffff";
+        let err = decrypt_envelope(ENVELOPE_CIPHERTEXT, other_secret, VECTOR_IV).unwrap_err();
+        assert!(
+            explain_error(&err).contains("app_secret is most likely wrong"),
+            "{:?}",
+            err
+        );
+
+        let err = decrypt_envelope(ENVELOPE_CIPHERTEXT, VECTOR_APP_SECRET, "ffffffffffffffff").unwrap_err();
+        assert!(explain_error(&err).contains("IV is most likely wrong"), "{:?}", err);
+    }
+
+    #[test]
+    fn test_plaintext_data_is_not_hex() {
+        let message = explain(r#"{"data":"{\"name\":\"Annie\"}"}"#);
+        assert!(
+            message.starts_with("the payload is not hex (invalid hex character '{' at position 0"),
+            "{}",
+            message
+        );
+        assert!(message.contains("HO-ENCRYPTED"));
+    }
+
+    #[test]
+    fn test_signature_vectors_verify() {
+        for vector in SIGNATURE_VECTORS {
+            let parts = SignatureParts {
+                app_id: VECTOR_APP_ID,
+                nonce: VECTOR_NONCE,
+                timestamp: VECTOR_TIMESTAMP,
+                uri: vector.uri,
+                body: vector.body,
+                signature: &vector.expected.to_uppercase(),
+            };
+            let check = verify_signature(parts, VECTOR_APP_SECRET);
+            assert!(check.matched, "{}", vector.name);
+            assert_eq!(check.expected, vector.expected);
+            assert!(!check.sign_string.contains(VECTOR_APP_SECRET));
+        }
+    }
+
+    #[test]
+    fn test_mismatch_shows_the_sign_string() {
+        let parts = SignatureParts {
+            app_id: VECTOR_APP_ID,
+            nonce: VECTOR_NONCE,
+            timestamp: VECTOR_TIMESTAMP,
+            uri: "/v1/lol/champion/skin?region=cn",
+            body: SignableBody::Text(r#"{"key":"value"}"#),
+            signature: "0000000000000000000000000000000000000000",
+        };
+        let check = verify_signature(parts, VECTOR_APP_SECRET);
+        assert!(!check.matched);
+        assert_eq!(check.expected, SIGNATURE_VECTORS[0].expected);
+        assert_eq!(
+            check.sign_string,
+            "test-app-id3f2b8c1e-9d4a-4b6e-8f1a-2c5d7e9b0a131717171717171\
+             /v1/lol/champion/skin?region=cn{\"key\":\"value\"}<app_secret>"
+        );
+
+        let binary = verify_signature(
+            SignatureParts {
+                body: SignableBody::Bytes(b"\x00\x01"),
+                ..parts
+            },
+            VECTOR_APP_SECRET,
+        );
+        assert!(binary
+            .sign_string
+            .ends_with("3f29546453678b855931c174a97d6c0894b8f546<app_secret>"));
+    }
+
+    #[test]
+    fn test_explains_other_errors() {
+        let auth = ApiClientError::HttpStatus {
+            status: StatusCode::UNAUTHORIZED,
+            body: r#"{"code":40102}"#.to_string(),
+        };
+        assert_eq!(
+            explain_error(&auth),
+            "the server answered 401 Unauthorized — the signature or app_id was rejected; check the clock and \
+             the secret (business code 40102)"
+        );
+        let both = ApiClientError::BothKeysFailed {
+            primary: Box::new(ApiClientError::AesError(block_modes::BlockModeError)),
+            secondary: Box::new(ApiClientError::InvalidCiphertextLength { len: 0 }),
+        };
+        assert!(explain_error(&both).starts_with("neither key decrypts the response. primary key: the ciphertext"));
+        let chained = ApiClientError::ChainStepFailed {
+            step: 2,
+            uri: "/v1/b".to_string(),
+            source: Box::new(ApiClientError::InvalidEnvelope(
+                "payload has no `item` field".to_string(),
+            )),
+        };
+        assert_eq!(
+            explain_error(&chained),
+            "step 2 (/v1/b) failed: the envelope is malformed: payload has no `item` field"
+        );
+        assert_eq!(
+            explain_error(&ApiClientError::TruncatedStream(3)),
+            "stream ended inside a 3 byte line"
+        );
+    }
+}
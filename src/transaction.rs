@@ -0,0 +1,316 @@
+//! Mutating calls that must succeed together. Created with `ApiClient::transaction`:
+//!
+//! ```no_run
+//! # async fn run(client: &ho_api_rust_sdk::ApiClient) -> Result<(), ho_api_rust_sdk::transaction::TransactionError> {
+//! use ho_api_rust_sdk::transaction::Call;
+//! use reqwest::Method;
+//! use serde_json::json;
+//!
+//! let bodies = client
+//!     .transaction()
+//!     .step(Call::new(Method::POST, "/v1/order", Some(json!({ "sku": 7 }))))
+//!     .compensate(|created| {
+//!         let id = serde_json::from_str::<serde_json::Value>(created).unwrap()["id"].clone();
+//!         Call::new(Method::POST, "/v1/order/cancel", Some(json!({ "id": id })))
+//!     })
+//!     .step(Call::new(Method::POST, "/v1/order/confirm", None))
+//!     .run()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Steps run in order. When one fails, the compensations of the steps before it run in
+//! reverse order, each built from the plaintext its step returned. Every step and
+//! compensation carries its own `HO-IDEMPOTENCY-KEY`, the same on each retry of that call,
+//! so the server can tell a retried call from a new one.
+
+use reqwest::Method;
+use serde_json::Value;
+
+use crate::nonce;
+use crate::retry::RetryConfig;
+use crate::{ApiClient, ApiClientError};
+
+/// A call made by a step or a compensation.
+#[derive(Debug, Clone)]
+pub struct Call {
+    method: Method,
+    uri: String,
+    body: Option<Value>,
+    retry: Option<RetryConfig>,
+}
+
+impl Call {
+    pub fn new(method: Method, uri: &str, body: Option<Value>) -> Self {
+        Self {
+            method,
+            uri: uri.to_string(),
+            body,
+            retry: None,
+        }
+    }
+
+    /// Retries this call by `retry` instead of `ApiClientConfig::retry`.
+    pub fn retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+type Compensation<'a> = Box<dyn FnOnce(&str) -> Call + Send + 'a>;
+
+pub struct Transaction<'a> {
+    client: &'a ApiClient,
+    id: String,
+    steps: Vec<(Call, Option<Compensation<'a>>)>,
+}
+
+/// A compensation that failed; the effect of its step remains.
+#[derive(Debug)]
+pub struct CompensationFailure {
+    /// The step it compensates, from 1.
+    pub step: usize,
+    pub uri: String,
+    pub error: ApiClientError,
+}
+
+/// The step that failed and how the steps before it were rolled back.
+#[derive(Debug)]
+pub struct TransactionError {
+    /// The failed step, from 1.
+    pub step: usize,
+    pub uri: String,
+    pub error: ApiClientError,
+    /// The steps whose compensation ran, in the order it ran, failed ones included.
+    pub compensated: Vec<usize>,
+    pub compensation_failures: Vec<CompensationFailure>,
+}
+
+impl TransactionError {
+    /// Whether every completed step that has a compensation was rolled back.
+    pub fn is_fully_compensated(&self) -> bool {
+        self.compensation_failures.is_empty()
+    }
+}
+
+impl ApiClient {
+    pub fn transaction(&self) -> Transaction<'_> {
+        Transaction {
+            client: self,
+            id: nonce::generate(),
+            steps: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Transaction<'a> {
+    /// The prefix of the idempotency keys of this transaction's calls.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn step(mut self, call: Call) -> Self {
+        self.steps.push((call, None));
+        self
+    }
+
+    /// Sets how to undo the last step added, should a later one fail. `compensation`
+    /// receives the plaintext that step returned.
+    ///
+    /// # Panics
+    ///
+    /// If no step has been added yet.
+    pub fn compensate(mut self, compensation: impl FnOnce(&str) -> Call + Send + 'a) -> Self {
+        let step = self.steps.last_mut().expect("`compensate` follows a `step`");
+        step.1 = Some(Box::new(compensation));
+        self
+    }
+
+    /// Runs the steps and returns the plaintext of each, or, once one fails, compensates
+    /// the completed ones.
+    pub async fn run(self) -> Result<Vec<String>, TransactionError> {
+        let mut completed: Vec<(String, Option<Compensation<'a>>)> = Vec::new();
+        for (i, (call, compensation)) in self.steps.into_iter().enumerate() {
+            let key = format!("{}-{}", self.id, i + 1);
+            let uri = call.uri.clone();
+            match send(self.client, call, &key).await {
+                Ok(body) => completed.push((body, compensation)),
+                Err(error) => {
+                    let mut failure = TransactionError {
+                        step: i + 1,
+                        uri,
+                        error,
+                        compensated: Vec::new(),
+                        compensation_failures: Vec::new(),
+                    };
+                    for (j, (body, compensation)) in completed.into_iter().enumerate().rev() {
+                        let Some(compensation) = compensation else {
+                            continue;
+                        };
+                        let call = compensation(&body);
+                        let uri = call.uri.clone();
+                        let key = format!("{}-{}-compensate", self.id, j + 1);
+                        failure.compensated.push(j + 1);
+                        if let Err(error) = send(self.client, call, &key).await {
+                            tracing::warn!(step = j + 1, uri, error = ?error, "transaction compensation failed");
+                            failure.compensation_failures.push(CompensationFailure {
+                                step: j + 1,
+                                uri,
+                                error,
+                            });
+                        }
+                    }
+                    return Err(failure);
+                }
+            }
+        }
+        Ok(completed.into_iter().map(|(body, _)| body).collect())
+    }
+}
+
+async fn send(client: &ApiClient, call: Call, idempotency_key: &str) -> Result<String, ApiClientError> {
+    let mut request = client.request(call.method, &call.uri).idempotency_key(idempotency_key);
+    if let Some(body) = call.body {
+        request = request.body(body);
+    }
+    if let Some(retry) = call.retry {
+        request = request.retry(retry);
+    }
+    request.send().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport, TEST_BASE_URL, TEST_CONTENT};
+
+    fn order_transaction(client: &ApiClient) -> Transaction<'_> {
+        client
+            .transaction()
+            .step(Call::new(Method::POST, "/v1/order", Some(json!({ "sku": 7 }))))
+            .compensate(|created| {
+                let created: Value = serde_json::from_str(created).unwrap();
+                Call::new(Method::POST, "/v1/order/cancel", Some(json!({ "id": created["id"] })))
+            })
+            .step(Call::new(Method::POST, "/v1/order/items", None))
+            .step(Call::new(Method::POST, "/v1/order/confirm", None))
+    }
+
+    fn sent(transport: &MockTransport) -> Vec<(String, String)> {
+        transport
+            .requests()
+            .into_iter()
+            .map(|request| {
+                let prefix = format!("{}{}", TEST_BASE_URL, TEST_CONTENT);
+                let path = request.url.strip_prefix(&prefix).unwrap().to_string();
+                let key = request.headers["HO-IDEMPOTENCY-KEY"].to_str().unwrap().to_string();
+                (path, key)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_every_step_runs_with_its_own_key() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::POST, "/v1/order", MockResponse::encrypted(r#"{"id":41}"#))
+            .on(Method::POST, "/v1/order/items", MockResponse::encrypted("{}"))
+            .on(
+                Method::POST,
+                "/v1/order/confirm",
+                MockResponse::encrypted(r#"{"ok":true}"#),
+            );
+        let client = mock_client(&transport, |_| {});
+
+        let transaction = order_transaction(&client);
+        let id = transaction.id().to_string();
+        let bodies = transaction.run().await.unwrap();
+        assert_eq!(bodies, [r#"{"id":41}"#, "{}", r#"{"ok":true}"#]);
+        let keys: Vec<String> = sent(&transport).into_iter().map(|(_, key)| key).collect();
+        assert_eq!(keys, [1, 2, 3].map(|step| format!("{}-{}", id, step)));
+    }
+
+    #[tokio::test]
+    async fn test_failed_step_compensates_the_completed_ones_in_reverse() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::POST, "/v1/order", MockResponse::encrypted(r#"{"id":41}"#))
+            .on(
+                Method::POST,
+                "/v1/order/items",
+                MockResponse::status(StatusCode::CONFLICT),
+            )
+            .on(Method::POST, "/v1/order/cancel", MockResponse::encrypted("{}"));
+        let client = mock_client(&transport, |_| {});
+
+        let transaction = order_transaction(&client);
+        let id = transaction.id().to_string();
+        let error = transaction.run().await.unwrap_err();
+        assert_eq!((error.step, error.uri.as_str()), (2, "/v1/order/items"));
+        assert_eq!(error.error.status(), Some(StatusCode::CONFLICT));
+        assert_eq!(error.compensated, [1]);
+        assert!(error.is_fully_compensated());
+
+        let sent = sent(&transport);
+        assert_eq!(sent.len(), 3, "the confirm step is never sent");
+        assert_eq!(
+            sent[2],
+            ("/v1/order/cancel".to_string(), format!("{}-1-compensate", id))
+        );
+        let cancel = json!({ "data": json!({ "id": 41 }).to_string() });
+        assert_eq!(transport.requests()[2].body, cancel.to_string());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_compensations_are_reported_after_their_retries() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::POST, "/v1/a", MockResponse::encrypted("{}"))
+            .on(Method::POST, "/v1/b", MockResponse::encrypted("{}"))
+            .on(Method::POST, "/v1/c", MockResponse::status(StatusCode::BAD_REQUEST))
+            .on(Method::POST, "/v1/a/undo", MockResponse::encrypted("{}"))
+            .on(
+                Method::POST,
+                "/v1/b/undo",
+                MockResponse::status(StatusCode::SERVICE_UNAVAILABLE),
+            );
+        let client = mock_client(&transport, |_| {});
+        let retry_once = RetryConfig {
+            max_retries: 1,
+            initial_backoff: Duration::from_secs(1),
+            ..Default::default()
+        };
+
+        let error = client
+            .transaction()
+            .step(Call::new(Method::POST, "/v1/a", None))
+            .compensate(|_| Call::new(Method::POST, "/v1/a/undo", None))
+            .step(Call::new(Method::POST, "/v1/b", None))
+            .compensate(move |_| Call::new(Method::POST, "/v1/b/undo", None).retry(retry_once))
+            .step(Call::new(Method::POST, "/v1/c", None))
+            .run()
+            .await
+            .unwrap_err();
+        assert_eq!(error.step, 3);
+        assert_eq!(error.compensated, [2, 1]);
+        assert!(!error.is_fully_compensated());
+        match error.compensation_failures.as_slice() {
+            [CompensationFailure { step: 2, uri, error }] => {
+                assert_eq!(uri, "/v1/b/undo");
+                assert_eq!(error.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let paths: Vec<String> = sent(&transport).into_iter().map(|(path, _)| path).collect();
+        assert_eq!(
+            paths,
+            ["/v1/a", "/v1/b", "/v1/c", "/v1/b/undo", "/v1/b/undo", "/v1/a/undo"]
+        );
+    }
+}
@@ -0,0 +1,490 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use hyper_util::client::legacy::connect::HttpInfo;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, ClientBuilder, Method, StatusCode, Url};
+use tokio::time::Instant;
+
+use crate::response::Timings;
+use crate::ApiClientError;
+
+/// Settings for the default `ReqwestTransport`.
+#[derive(Debug, Clone)]
+pub struct TransportConfig {
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    /// Upper bound on resolving a hostname. `None` leaves it to the system resolver.
+    pub dns_timeout: Option<Duration>,
+    /// Addresses of the `base_url` host to connect to when resolving it fails or times out.
+    /// The URL, `Host` header and TLS SNI keep the original hostname.
+    pub fallback_addrs: Vec<IpAddr>,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(100),
+            connect_timeout: Duration::from_secs(100),
+            dns_timeout: None,
+            fallback_addrs: Vec::new(),
+        }
+    }
+}
+
+/// How the transport should find the address of the host named in the URL.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Route {
+    #[default]
+    Dns,
+    /// Skip resolution and connect to `TransportConfig::fallback_addrs`.
+    FallbackAddrs,
+}
+
+/// A fully signed request, ready to put on the wire.
+#[derive(Debug, Clone)]
+pub struct TransportRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    pub route: Route,
+    /// Limit on this request alone, overriding `TransportConfig::timeout` when set.
+    pub timeout: Option<Duration>,
+    /// Open a new connection for this request instead of reusing a pooled one.
+    pub fresh_connection: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransportResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// The network phases; the client adds its own.
+    pub timings: Timings,
+}
+
+/// What the client knows about the connection behind an `ApiClientError::ConnectionClosed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionReuse {
+    /// The request may have gone out on a pooled connection. Seen when
+    /// `retry_once_on_stale_connection` is off.
+    MaybePooled,
+    /// The request was retried on a fresh connection after failing on a possibly pooled one,
+    /// and was closed again, so a stale pool does not explain the failure.
+    Fresh,
+}
+
+impl ConnectionReuse {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionReuse::MaybePooled => "maybe_pooled",
+            ConnectionReuse::Fresh => "fresh",
+        }
+    }
+}
+
+/// Why the client stopped at a redirect instead of following it; see
+/// `ApiClientError::RedirectRefused`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectRefusal {
+    /// The target is on another origin and following it would send signed credentials
+    /// there. `allow_cross_host_redirects` permits it.
+    CrossHost,
+    /// The request had already been redirected `max_redirects` times.
+    TooManyRedirects,
+}
+
+impl RedirectRefusal {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RedirectRefusal::CrossHost => "cross_host",
+            RedirectRefusal::TooManyRedirects => "too_many_redirects",
+        }
+    }
+
+    pub(crate) fn parse(text: &str) -> Option<Self> {
+        [RedirectRefusal::CrossHost, RedirectRefusal::TooManyRedirects]
+            .into_iter()
+            .find(|reason| reason.as_str() == text)
+    }
+}
+
+/// Whether `err` looks like the peer closed or reset the connection before answering, the
+/// way requests fail on a pooled connection that a middle-box has silently dropped.
+pub(crate) fn is_stale_connection(err: &reqwest::Error) -> bool {
+    if err.is_connect() || err.is_timeout() || err.is_builder() || err.is_status() {
+        return false;
+    }
+    let mut source = std::error::Error::source(err);
+    while let Some(cause) = source {
+        if let Some(io) = cause.downcast_ref::<io::Error>() {
+            if matches!(
+                io.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+            ) {
+                return true;
+            }
+        }
+        // hyper's `IncompleteMessage`, which it does not expose as a type.
+        if cause.to_string().contains("connection closed before message completed") {
+            return true;
+        }
+        source = cause.source();
+    }
+    false
+}
+
+pub struct StreamingResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: BoxStream<'static, Result<Bytes, ApiClientError>>,
+}
+
+/// Sends signed requests. `ApiClient` uses `ReqwestTransport` unless another transport is
+/// passed to `ApiClient::with_transport`.
+pub trait Transport: Send + Sync {
+    /// Sends the request and reads the whole response body.
+    fn execute(&self, request: TransportRequest) -> BoxFuture<'_, Result<TransportResponse, ApiClientError>>;
+
+    /// Sends the request and returns the body as it arrives. The default implementation
+    /// buffers through `execute` and yields the body as a single chunk.
+    fn execute_streaming(&self, request: TransportRequest) -> BoxFuture<'_, Result<StreamingResponse, ApiClientError>> {
+        Box::pin(async move {
+            let response = self.execute(request).await?;
+            Ok(StreamingResponse {
+                status: response.status,
+                headers: response.headers,
+                body: stream::once(async move { Ok(response.body) }).boxed(),
+            })
+        })
+    }
+
+    /// Drops pooled connections, so later requests open new ones while requests in flight
+    /// finish on theirs. Transports without a pool keep the default, which does nothing.
+    fn reset_connections(&self) -> Result<(), ApiClientError> {
+        Ok(())
+    }
+}
+
+/// Bound on `ReqwestTransport::connections`, which starts over when full.
+const MAX_TRACKED_CONNECTIONS: usize = 1024;
+
+pub struct ReqwestTransport {
+    config: TransportConfig,
+    /// Replaced whole by `reset_connections`; requests hold a clone of the one they started on.
+    client: Mutex<Client>,
+    /// Clients built on demand, keyed by the hostname whose resolution they pin to
+    /// `fallback_addrs` (`None` for DNS) and whether they keep connections in a pool.
+    clients: Mutex<HashMap<(Option<String>, bool), Client>>,
+    /// Local and remote addresses of the connections seen so far: a response over a pair
+    /// already in here came over a reused connection.
+    connections: Mutex<HashSet<(SocketAddr, SocketAddr)>>,
+}
+
+impl ReqwestTransport {
+    pub fn new() -> Result<Self, ApiClientError> {
+        Self::with_config(TransportConfig::default())
+    }
+
+    pub fn with_config(config: TransportConfig) -> Result<Self, ApiClientError> {
+        let client = dns_builder(&config).build()?;
+        Ok(Self {
+            config,
+            client: Mutex::new(client),
+            clients: Mutex::new(HashMap::new()),
+            connections: Mutex::new(HashSet::new()),
+        })
+    }
+
+    fn client_for(&self, request: &TransportRequest) -> Result<Client, ApiClientError> {
+        let mut pinned_host = None;
+        if request.route == Route::FallbackAddrs && !self.config.fallback_addrs.is_empty() {
+            let url = Url::parse(&request.url)
+                .map_err(|e| ApiClientError::InvalidConfig(format!("invalid request url {}: {}", request.url, e)))?;
+            pinned_host = url.host_str().map(str::to_string);
+        }
+        if pinned_host.is_none() && !request.fresh_connection {
+            return Ok(self.client.lock().unwrap().clone());
+        }
+
+        let key = (pinned_host, request.fresh_connection);
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+        let mut builder = match &key.0 {
+            Some(host) => {
+                // Port 0 makes reqwest use the port from the URL.
+                let addrs: Vec<SocketAddr> = self
+                    .config
+                    .fallback_addrs
+                    .iter()
+                    .map(|ip| SocketAddr::new(*ip, 0))
+                    .collect();
+                builder(&self.config).resolve_to_addrs(host, &addrs)
+            }
+            None => dns_builder(&self.config),
+        };
+        if request.fresh_connection {
+            // A pool of its own that never keeps a connection, so every request connects anew.
+            builder = builder.pool_max_idle_per_host(0);
+        }
+        let client = builder.build()?;
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    /// Whether `response` came over a connection an earlier response did, by its addresses.
+    /// A new connection that happens to get the local port of a closed one counts as reused.
+    fn reused(&self, response: &reqwest::Response) -> bool {
+        let Some(info) = response.extensions().get::<HttpInfo>() else {
+            return false;
+        };
+        let mut connections = self.connections.lock().unwrap();
+        if connections.len() >= MAX_TRACKED_CONNECTIONS {
+            connections.clear();
+        }
+        !connections.insert((info.local_addr(), info.remote_addr()))
+    }
+
+    fn send(&self, request: TransportRequest) -> Result<reqwest::RequestBuilder, ApiClientError> {
+        let client = self.client_for(&request)?;
+        let mut builder = client
+            .request(request.method, &request.url)
+            .headers(request.headers)
+            .body(request.body);
+        if let Some(timeout) = request.timeout {
+            builder = builder.timeout(timeout);
+        }
+        Ok(builder)
+    }
+}
+
+/// Redirects are left to the client, which re-signs the request for the new location.
+fn builder(config: &TransportConfig) -> ClientBuilder {
+    Client::builder()
+        .timeout(config.timeout)
+        .connect_timeout(config.connect_timeout)
+        .redirect(reqwest::redirect::Policy::none())
+}
+
+fn dns_builder(config: &TransportConfig) -> ClientBuilder {
+    match config.dns_timeout {
+        Some(timeout) => builder(config).dns_resolver(Arc::new(TimeoutResolver { timeout })),
+        None => builder(config),
+    }
+}
+
+/// The system resolver with an upper bound on how long a lookup may take.
+struct TimeoutResolver {
+    timeout: Duration,
+}
+
+impl Resolve for TimeoutResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let timeout = self.timeout;
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let lookup = tokio::net::lookup_host(format!("{}:0", host));
+            match tokio::time::timeout(timeout, lookup).await {
+                Ok(Ok(addrs)) => Ok(Box::new(addrs) as Addrs),
+                Ok(Err(e)) => Err(e.into()),
+                Err(_) => Err(format!("resolving {} timed out after {:?}", host, timeout).into()),
+            }
+        })
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn execute(&self, request: TransportRequest) -> BoxFuture<'_, Result<TransportResponse, ApiClientError>> {
+        Box::pin(async move {
+            let fresh_connection = request.fresh_connection;
+            let sent = Instant::now();
+            let response = self.send(request)?.send().await?;
+            let headers_received = Instant::now();
+            let mut timings = match self.reused(&response) && !fresh_connection {
+                true => Timings::reused(),
+                false => Timings::default(),
+            };
+            timings.time_to_first_byte = Some(headers_received - sent);
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = response.bytes().await?;
+            timings.body_read = Some(headers_received.elapsed());
+            Ok(TransportResponse {
+                status,
+                headers,
+                body,
+                timings,
+            })
+        })
+    }
+
+    fn execute_streaming(&self, request: TransportRequest) -> BoxFuture<'_, Result<StreamingResponse, ApiClientError>> {
+        Box::pin(async move {
+            let response = self.send(request)?.send().await?;
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = stream::try_unfold(response, |mut response| async move {
+                Ok(response.chunk().await?.map(|chunk| (chunk, response)))
+            })
+            .boxed();
+            Ok(StreamingResponse { status, headers, body })
+        })
+    }
+
+    fn reset_connections(&self) -> Result<(), ApiClientError> {
+        let client = dns_builder(&self.config).build()?;
+        *self.client.lock().unwrap() = client;
+        self.clients.lock().unwrap().clear();
+        self.connections.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::testing::{encrypted_envelope, test_config};
+    use crate::ApiClient;
+
+    /// Answers one HTTP/1.1 request with `body` and returns the request head it read.
+    async fn serve_once(listener: TcpListener, body: String) -> String {
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        respond_once(listener, response).await
+    }
+
+    /// Answers one HTTP/1.1 request with the raw `response` and returns the request head.
+    async fn respond_once(listener: TcpListener, response: String) -> String {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0u8; 4096];
+        while !received.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = socket.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed before the request head");
+            received.extend_from_slice(&buf[..n]);
+        }
+        socket.write_all(response.as_bytes()).await.unwrap();
+        let head = String::from_utf8_lossy(&received).into_owned();
+        head.split("\r\n\r\n").next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_unresolvable_host_falls_back_to_pinned_addrs() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(serve_once(listener, encrypted_envelope(r#"{"ok":true}"#)));
+
+        let mut config = test_config();
+        config.base_url = format!("http://gateway.invalid:{}", port);
+        config.transport.dns_timeout = Some(Duration::from_secs(2));
+        config.transport.fallback_addrs = vec![IpAddr::V4(Ipv4Addr::LOCALHOST)];
+        let client = ApiClient::new(config).unwrap();
+
+        let response = client.send_detailed(Method::GET, "/v1/ping", None).await.unwrap();
+        assert_eq!(response.body, r#"{"ok":true}"#);
+
+        let head = server.await.unwrap().to_ascii_lowercase();
+        assert!(head.contains(&format!("host: gateway.invalid:{}", port)), "{}", head);
+
+        let hosts = client.stats().hosts;
+        assert_eq!(hosts.len(), 2);
+        assert!(!hosts[0].via_fallback_addrs && hosts[0].failure_rate > 0.0);
+        assert!(hosts[1].via_fallback_addrs && hosts[1].failure_rate == 0.0);
+        assert_eq!(
+            client.hosts.order()[0],
+            1,
+            "later requests should skip the failing resolver"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redirects_are_returned_not_followed() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let url = format!("http://{}/v1/old", listener.local_addr().unwrap());
+        let response = "HTTP/1.1 307 Temporary Redirect\r\nlocation: /v1/new\r\ncontent-length: 0\r\n\r\n";
+        let server = tokio::spawn(respond_once(listener, response.to_string()));
+
+        let transport = ReqwestTransport::new().unwrap();
+        let response = transport
+            .execute(TransportRequest {
+                method: Method::GET,
+                url,
+                headers: HeaderMap::new(),
+                body: Bytes::new(),
+                route: Route::Dns,
+                timeout: None,
+                fresh_connection: false,
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.status, StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(response.headers["location"], "/v1/new");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_second_request_on_a_kept_alive_connection_is_reused() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let url = format!("http://{}/v1/ping", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            for _ in 0..2 {
+                let mut received = Vec::new();
+                while !received.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    assert!(n > 0, "connection closed before the request head");
+                    received.extend_from_slice(&buf[..n]);
+                }
+                socket
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}")
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let transport = ReqwestTransport::new().unwrap();
+        let mut timings = Vec::new();
+        for _ in 0..2 {
+            let request = TransportRequest {
+                method: Method::GET,
+                url: url.clone(),
+                headers: HeaderMap::new(),
+                body: Bytes::new(),
+                route: Route::Dns,
+                timeout: None,
+                fresh_connection: false,
+            };
+            timings.push(transport.execute(request).await.unwrap().timings);
+        }
+        assert!(!timings[0].connection_reused);
+        assert_eq!((timings[0].dns, timings[0].connect, timings[0].tls), (None, None, None));
+        assert!(timings[1].connection_reused);
+        assert_eq!(timings[1].connect, Some(Duration::ZERO));
+        assert!(timings
+            .iter()
+            .all(|t| t.time_to_first_byte.is_some() && t.body_read.is_some()));
+        server.await.unwrap();
+    }
+}
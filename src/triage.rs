@@ -0,0 +1,270 @@
+//! Triage of rejected signatures. In debug mode the gateway answers a signature it rejects
+//! (business code 40102) with the part of the sign string it disagreed on:
+//!
+//! ```json
+//! {"code": 40102, "msg": "signature mismatch", "detail": {"component": "body_hash"}}
+//! ```
+//!
+//! With `ApiClientConfig::triage_signature_failures` set, such a response fails with
+//! `ApiClientError::SignatureRejected`: the server's reason next to the lengths of the parts
+//! this client signed, and the SHA-1 of the signed body when the body is to blame.
+
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+
+use crate::body::PreparedBody;
+use crate::client::signed_uri;
+use crate::request::RequestOptions;
+use crate::signing::body_component;
+use crate::{ApiClientConfig, ApiClientError};
+
+/// The business code of a rejected signature.
+pub const SIGNATURE_REJECTED_CODE: i64 = 40102;
+
+/// The part of the sign string the server found at fault. Serialized as its `code`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SignatureRejectionReason {
+    /// `HO-TIMESTAMP` is outside the server's window: the local clock is off.
+    TimestampWindow,
+    /// The body the server hashed is not the body that was signed.
+    BodyHash,
+    /// `HO-NONCE` is not in a format the server accepts.
+    NonceFormat,
+    /// The nonce was seen before.
+    NonceReused,
+    /// The signed uri differs from the one the server rebuilt, e.g. over `sign_content_path`
+    /// or query encoding.
+    Uri,
+    /// Every part matched, so the secret differs.
+    Secret,
+    /// A component this SDK does not know.
+    Unknown(String),
+    /// The server did not say; its debug mode is likely off.
+    NotReported,
+}
+
+impl SignatureRejectionReason {
+    pub(crate) fn parse(component: &str) -> Self {
+        match component.to_ascii_lowercase().as_str() {
+            "timestamp" | "timestamp_window" => SignatureRejectionReason::TimestampWindow,
+            "body" | "body_hash" => SignatureRejectionReason::BodyHash,
+            "nonce" | "nonce_format" => SignatureRejectionReason::NonceFormat,
+            "nonce_reused" => SignatureRejectionReason::NonceReused,
+            "uri" => SignatureRejectionReason::Uri,
+            "secret" | "signature" => SignatureRejectionReason::Secret,
+            "not_reported" => SignatureRejectionReason::NotReported,
+            _ => SignatureRejectionReason::Unknown(component.to_string()),
+        }
+    }
+
+    /// The reason as the server spells it; unknown reasons keep their original text.
+    pub fn code(&self) -> &str {
+        match self {
+            SignatureRejectionReason::TimestampWindow => "timestamp_window",
+            SignatureRejectionReason::BodyHash => "body_hash",
+            SignatureRejectionReason::NonceFormat => "nonce_format",
+            SignatureRejectionReason::NonceReused => "nonce_reused",
+            SignatureRejectionReason::Uri => "uri",
+            SignatureRejectionReason::Secret => "secret",
+            SignatureRejectionReason::Unknown(component) => component,
+            SignatureRejectionReason::NotReported => "not_reported",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            SignatureRejectionReason::TimestampWindow => {
+                "the timestamp is outside the server's window; check the clock"
+            }
+            SignatureRejectionReason::BodyHash => "the server hashed a different body than the one signed",
+            SignatureRejectionReason::NonceFormat => "the server does not accept the nonce format",
+            SignatureRejectionReason::NonceReused => "the nonce was already used",
+            SignatureRejectionReason::Uri => "the server signed a different uri; check sign_content_path and encoding",
+            SignatureRejectionReason::Secret => "every component matched, so the app_secret differs",
+            SignatureRejectionReason::Unknown(_) => "the server named a component this SDK does not know",
+            SignatureRejectionReason::NotReported => "the server gave no detail; is its debug mode on?",
+        }
+    }
+}
+
+/// Byte lengths of the parts of the sign string of a rejected request, in signing order.
+/// The secret is left out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignComponents {
+    pub app_id: usize,
+    pub nonce: usize,
+    pub timestamp: usize,
+    pub uri: usize,
+    pub body: usize,
+}
+
+/// `SignatureRejected` for a 40102 response to a signed request, `None` for any other.
+/// `headers` are those of the request as sent.
+pub(crate) fn triage(
+    config: &ApiClientConfig,
+    options: &RequestOptions,
+    uri: &str,
+    body: &PreparedBody,
+    headers: &HeaderMap,
+    status: StatusCode,
+    response_body: &str,
+) -> Option<ApiClientError> {
+    let response: Value = serde_json::from_str(response_body).ok()?;
+    if response.get("code")?.as_i64()? != SIGNATURE_REJECTED_CODE {
+        return None;
+    }
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let nonce = header("HO-NONCE")?;
+    let timestamp = header("HO-TIMESTAMP")?;
+    let reason = match response.pointer("/detail/component").and_then(Value::as_str) {
+        Some(component) => SignatureRejectionReason::parse(component),
+        None => SignatureRejectionReason::NotReported,
+    };
+
+    let content = options.content_path.as_deref().unwrap_or(&config.content);
+    let signed_body = body_component(body.signable());
+    let components = SignComponents {
+        app_id: options
+            .credentials
+            .as_ref()
+            .map_or(config.app_id.len(), |credentials| credentials.app_id.len()),
+        nonce: nonce.len(),
+        timestamp: timestamp.len(),
+        uri: signed_uri(content, uri, config.sign_content_path).len(),
+        body: signed_body.len(),
+    };
+    let body_sha1 =
+        (reason == SignatureRejectionReason::BodyHash).then(|| format!("{:x}", Sha1::digest(signed_body.as_bytes())));
+    tracing::warn!(
+        uri,
+        reason = reason.code(),
+        components = ?components,
+        timestamp,
+        body_sha1 = body_sha1.as_deref(),
+        "the server rejected the signature"
+    );
+    Some(ApiClientError::SignatureRejected {
+        status,
+        reason,
+        components,
+        timestamp: timestamp.parse().ok()?,
+        body_sha1,
+        body: response_body.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Method;
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{mock_client, MockResponse, MockTransport, TEST_APP_ID};
+
+    fn rejected(component: Option<&str>) -> MockResponse {
+        let mut body = json!({ "code": SIGNATURE_REJECTED_CODE, "msg": "signature mismatch" });
+        if let Some(component) = component {
+            body["detail"] = json!({ "component": component });
+        }
+        MockResponse::json(StatusCode::UNAUTHORIZED, &body)
+    }
+
+    #[tokio::test]
+    async fn test_body_mismatch_carries_the_signed_body_hash() {
+        let transport = MockTransport::new();
+        transport.on(Method::POST, "/v1/order", rejected(Some("body_hash")));
+        let client = mock_client(&transport, |config| config.triage_signature_failures = true);
+
+        let body = json!({ "sku": 7 });
+        match client.send(Method::POST, "/v1/order?region=cn", Some(body)).await {
+            Err(ApiClientError::SignatureRejected {
+                status,
+                reason,
+                components,
+                timestamp,
+                body_sha1,
+                ..
+            }) => {
+                assert_eq!(status, StatusCode::UNAUTHORIZED);
+                assert_eq!(reason, SignatureRejectionReason::BodyHash);
+                let sent = &transport.requests()[0].headers;
+                let sent_timestamp = sent["HO-TIMESTAMP"].to_str().unwrap();
+                assert_eq!(timestamp.to_string(), sent_timestamp);
+                assert_eq!(
+                    components,
+                    SignComponents {
+                        app_id: TEST_APP_ID.len(),
+                        nonce: 36,
+                        timestamp: sent_timestamp.len(),
+                        uri: "/v1/order?region=cn".len(),
+                        body: r#"{"sku":7}"#.len(),
+                    }
+                );
+                assert_eq!(
+                    body_sha1.as_deref(),
+                    Some(format!("{:x}", Sha1::digest(br#"{"sku":7}"#)).as_str())
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(client.stats().errors.http_status, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reasons_and_non_triaged_responses() {
+        let transport = MockTransport::new();
+        transport
+            .on(Method::GET, "/v1/clock", rejected(Some("timestamp_window")))
+            .on(Method::GET, "/v1/quiet", rejected(None))
+            .on(Method::GET, "/v1/odd", rejected(Some("app_id_case")))
+            .on(
+                Method::GET,
+                "/v1/other",
+                MockResponse::json(StatusCode::UNAUTHORIZED, &json!({ "code": 40101 })),
+            );
+        let client = mock_client(&transport, |config| config.triage_signature_failures = true);
+
+        let reason_of = |uri: &'static str| {
+            let client = &client;
+            async move {
+                match client.send(Method::GET, uri, None).await {
+                    Err(e @ ApiClientError::SignatureRejected { .. }) => {
+                        assert!(e.is_auth_error() && !e.is_retryable());
+                        assert_eq!(e.business_code(), Some(SIGNATURE_REJECTED_CODE));
+                        match e {
+                            ApiClientError::SignatureRejected { reason, body_sha1, .. } => {
+                                assert_eq!(body_sha1, None);
+                                Some(reason)
+                            }
+                            _ => unreachable!(),
+                        }
+                    }
+                    Err(ApiClientError::HttpStatus { .. }) => None,
+                    other => panic!("unexpected {:?}", other),
+                }
+            }
+        };
+        assert_eq!(
+            reason_of("/v1/clock").await,
+            Some(SignatureRejectionReason::TimestampWindow)
+        );
+        assert_eq!(
+            reason_of("/v1/quiet").await,
+            Some(SignatureRejectionReason::NotReported)
+        );
+        assert_eq!(
+            reason_of("/v1/odd").await,
+            Some(SignatureRejectionReason::Unknown("app_id_case".to_string()))
+        );
+        assert_eq!(reason_of("/v1/other").await, None);
+
+        let untriaged = mock_client(&transport, |_| {});
+        assert!(matches!(
+            untriaged.send(Method::GET, "/v1/clock", None).await,
+            Err(ApiClientError::HttpStatus { .. })
+        ));
+    }
+}
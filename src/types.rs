@@ -0,0 +1,341 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+macro_rules! id_newtype {
+    ($($(#[$meta:meta])* $name:ident),* $(,)?) => {
+        $(
+            $(#[$meta])*
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+            pub struct $name(pub u64);
+
+            impl $name {
+                pub fn get(self) -> u64 {
+                    self.0
+                }
+            }
+
+            impl From<u64> for $name {
+                fn from(id: u64) -> Self {
+                    $name(id)
+                }
+            }
+
+            impl From<$name> for u64 {
+                fn from(id: $name) -> Self {
+                    id.0
+                }
+            }
+
+            impl fmt::Display for $name {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    self.0.fmt(f)
+                }
+            }
+
+            impl FromStr for $name {
+                type Err = std::num::ParseIntError;
+
+                fn from_str(s: &str) -> Result<Self, Self::Err> {
+                    s.trim().parse().map($name)
+                }
+            }
+
+            impl Serialize for $name {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.serialize_u64(self.0)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $name {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    deserializer.deserialize_any(IdVisitor(stringify!($name))).map($name)
+                }
+            }
+        )*
+    };
+}
+
+id_newtype!(
+    /// Skin id. Accepts both `123` and `"123"` on the wire, serializes as a number.
+    SkinId,
+    /// Item id. Accepts both `123` and `"123"` on the wire, serializes as a number.
+    ItemId,
+    /// Champion id. Accepts both `123` and `"123"` on the wire, serializes as a number.
+    ChampionId,
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Region {
+    Cn,
+    Tw,
+    Kr,
+    Jp,
+    Na,
+    Euw,
+}
+
+impl Region {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Region::Cn => "cn",
+            Region::Tw => "tw",
+            Region::Kr => "kr",
+            Region::Jp => "jp",
+            Region::Na => "na",
+            Region::Euw => "euw",
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An amount of money in fen, the minor unit of the yuan. There is deliberately no
+/// conversion from floats: build one from fen, or parse a yuan amount written out as text
+/// with `from_yuan_str`. Serializes as the integer number of fen, and accepts only that.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(u64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub const fn from_fen(fen: u64) -> Self {
+        Money(fen)
+    }
+
+    pub const fn fen(self) -> u64 {
+        self.0
+    }
+
+    /// Parses a yuan amount such as `"12"`, `"12.5"` or `"12.50"`: ASCII digits with at
+    /// most two after the point. Signs, exponents, separators, whitespace and a point
+    /// without digits on both sides are refused.
+    pub fn from_yuan_str(text: &str) -> Result<Self, ParseMoneyError> {
+        let error = |reason| ParseMoneyError {
+            input: text.to_string(),
+            reason,
+        };
+        let (yuan, fraction) = match text.split_once('.') {
+            Some((yuan, fraction)) => (yuan, Some(fraction)),
+            None => (text, None),
+        };
+        let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+        if text.starts_with('-') {
+            return Err(error("negative amount"));
+        }
+        if !digits(yuan) || fraction.is_some_and(|fraction| !digits(fraction)) {
+            return Err(error("not a decimal number of yuan"));
+        }
+        let fraction = fraction.unwrap_or_default();
+        if fraction.len() > 2 {
+            return Err(error("more than two decimal places"));
+        }
+        let fen = format!("{}{:0<2}", yuan, fraction)
+            .parse::<u64>()
+            .map_err(|_| error("too large"))?;
+        Ok(Money(fen))
+    }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    pub fn checked_mul(self, times: u64) -> Option<Money> {
+        self.0.checked_mul(times).map(Money)
+    }
+}
+
+/// Yuan with two decimal places, such as `12.50`.
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:02}", self.0 / 100, self.0 % 100)
+    }
+}
+
+/// `Money::from_yuan_str`.
+impl FromStr for Money {
+    type Err = ParseMoneyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Money::from_yuan_str(s)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_u64(FenVisitor).map(Money)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseMoneyError {
+    pub input: String,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ParseMoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid yuan amount {:?}: {}", self.input, self.reason)
+    }
+}
+
+impl std::error::Error for ParseMoneyError {}
+
+struct FenVisitor;
+
+impl<'de> Visitor<'de> for FenVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an amount as a non-negative integer number of fen")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<u64, E> {
+        Ok(v)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<u64, E> {
+        u64::try_from(v).map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+    }
+}
+
+struct IdVisitor(&'static str);
+
+impl<'de> Visitor<'de> for IdVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} as an unsigned integer or a decimal string", self.0)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<u64, E> {
+        Ok(v)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<u64, E> {
+        u64::try_from(v).map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<u64, E> {
+        // Floats above 2^53 have already lost precision, so only accept exact small integers.
+        if v.fract() == 0.0 && v >= 0.0 && v <= (1u64 << 53) as f64 {
+            Ok(v as u64)
+        } else {
+            Err(E::invalid_value(de::Unexpected::Float(v), &self))
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<u64, E> {
+        v.trim()
+            .parse()
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const BIG: u64 = (1u64 << 53) + 1;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Skin {
+        id: SkinId,
+        champion_id: ChampionId,
+    }
+
+    #[test]
+    fn test_id_above_2_pow_53_round_trips_exactly() {
+        let from_number: Skin = serde_json::from_str(&format!(r#"{{"id":{},"champion_id":266}}"#, BIG)).unwrap();
+        let from_string: Skin = serde_json::from_str(&format!(r#"{{"id":"{}","champion_id":"266"}}"#, BIG)).unwrap();
+        assert_eq!(from_number.id, SkinId(BIG));
+        assert_eq!(from_number, from_string);
+
+        let text = serde_json::to_string(&from_number).unwrap();
+        assert_eq!(text, format!(r#"{{"id":{},"champion_id":266}}"#, BIG));
+
+        let value: Value = serde_json::to_value(&from_number).unwrap();
+        let back: Skin = serde_json::from_value(value).unwrap();
+        assert_eq!(back.id.get(), BIG);
+    }
+
+    #[test]
+    fn test_id_rejects_invalid_forms() {
+        assert!(serde_json::from_value::<SkinId>(json!(-1)).is_err());
+        assert!(serde_json::from_value::<SkinId>(json!("abc")).is_err());
+        assert!(serde_json::from_value::<SkinId>(json!(1.5)).is_err());
+        assert!(serde_json::from_value::<SkinId>(json!(9007199254740994.0)).is_err());
+        assert_eq!(serde_json::from_value::<ItemId>(json!(12.0)).unwrap(), ItemId(12));
+    }
+
+    #[test]
+    fn test_region_serializes_lowercase() {
+        assert_eq!(serde_json::to_value(Region::Euw).unwrap(), json!("euw"));
+        assert_eq!(serde_json::from_value::<Region>(json!("cn")).unwrap(), Region::Cn);
+        assert_eq!(Region::Kr.to_string(), "kr");
+    }
+
+    #[test]
+    fn test_yuan_strings_parse_to_exact_fen() {
+        let fen = |text: &str| Money::from_yuan_str(text).map(Money::fen);
+        assert_eq!(fen("12.50"), Ok(1250));
+        assert_eq!(fen("12.5"), Ok(1250));
+        assert_eq!(fen("12"), Ok(1200));
+        assert_eq!(fen("0.01"), Ok(1));
+        assert_eq!(fen("007.10"), Ok(710));
+        assert_eq!(fen("0"), Ok(0));
+        assert_eq!(fen("184467440737095516.15"), Ok(u64::MAX));
+
+        let reason = |text: &str| Money::from_yuan_str(text).unwrap_err().reason;
+        assert_eq!(reason("-1"), "negative amount");
+        assert_eq!(reason("-0.50"), "negative amount");
+        assert_eq!(reason("12.505"), "more than two decimal places");
+        assert_eq!(reason("12.500"), "more than two decimal places");
+        assert_eq!(reason("184467440737095516.16"), "too large");
+        for text in [
+            "", "abc", "12.", ".5", "1.2.3", "+1", "1e3", "1,000", " 12", "12 ", "¥12", "１２", "NaN", "inf", "0x10",
+        ] {
+            assert_eq!(reason(text), "not a decimal number of yuan", "{:?}", text);
+        }
+        assert_eq!(
+            "1.234".parse::<Money>().unwrap_err().to_string(),
+            r#"invalid yuan amount "1.234": more than two decimal places"#
+        );
+    }
+
+    #[test]
+    fn test_money_is_integer_fen_on_the_wire() {
+        let money = Money::from_fen(1250);
+        assert_eq!(money.to_string(), "12.50");
+        assert_eq!(Money::from_fen(7).to_string(), "0.07");
+        assert_eq!(serde_json::to_value(money).unwrap(), json!(1250));
+        assert_eq!(serde_json::from_value::<Money>(json!(1250)).unwrap(), money);
+        for float in [json!(12.5), json!(1250.0), json!(-1), json!("1250")] {
+            assert!(serde_json::from_value::<Money>(float.clone()).is_err(), "{}", float);
+        }
+        assert_eq!(money.checked_mul(3), Some(Money::from_fen(3750)));
+        assert_eq!(Money::from_fen(u64::MAX).checked_add(Money::from_fen(1)), None);
+    }
+
+    #[test]
+    fn test_id_display_and_parse() {
+        let id: ItemId = BIG.to_string().parse().unwrap();
+        assert_eq!(id.to_string(), BIG.to_string());
+        assert_eq!(u64::from(id), BIG);
+    }
+}
@@ -0,0 +1,373 @@
+//! Chunked asset uploads: `POST {prefix}/init` opens a session, every chunk goes to
+//! `POST {prefix}/chunk` as its own signed request, and `POST {prefix}/complete` finishes the
+//! upload with the checksums the server acknowledged. A failed chunk is retried on its own;
+//! an `UploadSession` saved from the progress callback continues the upload after a restart.
+//!
+//! ```no_run
+//! # async fn run(client: &ho_api_rust_sdk::ApiClient) -> Result<(), ho_api_rust_sdk::ApiClientError> {
+//! use ho_api_rust_sdk::upload::UploadSession;
+//!
+//! let file = tokio::fs::File::open("skin.png").await.unwrap();
+//! let len = file.metadata().await.unwrap().len();
+//! let save = |session: &UploadSession| std::fs::write("upload.json", session.to_json()).unwrap();
+//! let asset = client
+//!     .upload_chunked("/v1/asset/upload", file, len, 1 << 20, save)
+//!     .await?;
+//!
+//! // After a restart, with the same file:
+//! let session = UploadSession::from_json(&std::fs::read_to_string("upload.json").unwrap())?;
+//! let file = tokio::fs::File::open("skin.png").await.unwrap();
+//! let asset = client.resume_upload(session, file, save).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::retry::RetryConfig;
+use crate::{ApiClient, ApiClientError};
+
+/// Tries per chunk: the first and 3 retries, 0.5s, 1s and 2s apart.
+const CHUNK_RETRY: RetryConfig = RetryConfig {
+    max_retries: 3,
+    initial_backoff: Duration::from_millis(500),
+    max_backoff: Duration::from_secs(8),
+    multiplier: 2.0,
+};
+
+/// Where an upload stands: enough to continue it in another process. The file itself is not
+/// part of it; `ApiClient::resume_upload` re-reads the uploaded chunks and checks them
+/// against `checksums` before sending the rest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub uri_prefix: String,
+    pub upload_id: String,
+    /// Length of the file in bytes.
+    pub len: u64,
+    pub chunk_size: u64,
+    /// Chunks are sent encrypted with the client's key rather than as plain hex.
+    pub encrypted: bool,
+    /// SHA-1 (hex) of each chunk the server acknowledged, in order.
+    pub checksums: Vec<String>,
+}
+
+impl UploadSession {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("sessions serialize")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ApiClientError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn chunk_count(&self) -> u64 {
+        self.len.div_ceil(self.chunk_size)
+    }
+
+    pub fn uploaded_bytes(&self) -> u64 {
+        (self.checksums.len() as u64 * self.chunk_size).min(self.len)
+    }
+
+    /// Every chunk is acknowledged; only `complete` is left.
+    pub fn is_complete(&self) -> bool {
+        self.checksums.len() as u64 >= self.chunk_count()
+    }
+
+    fn chunk_len(&self, index: u64) -> usize {
+        (self.len - index * self.chunk_size).min(self.chunk_size) as usize
+    }
+
+    fn failed(&self, chunk: Option<u64>, reason: String) -> ApiClientError {
+        ApiClientError::UploadFailed {
+            upload_id: self.upload_id.clone(),
+            chunk,
+            reason,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Initialized {
+    upload_id: Value,
+}
+
+#[derive(Deserialize)]
+struct ChunkAck {
+    sha1: String,
+}
+
+impl ApiClient {
+    /// Uploads the `len` bytes of `reader` in chunks of `chunk_size` bytes and returns the
+    /// decrypted response of `complete`. `progress` sees the session after every
+    /// acknowledged chunk; saving it lets `resume_upload` continue after a restart.
+    pub async fn upload_chunked(
+        &self,
+        uri_prefix: &str,
+        reader: impl AsyncRead + Unpin,
+        len: u64,
+        chunk_size: u64,
+        progress: impl FnMut(&UploadSession),
+    ) -> Result<String, ApiClientError> {
+        let session = self.start_upload(uri_prefix, len, chunk_size, false).await?;
+        self.resume_upload(session, reader, progress).await
+    }
+
+    /// Opens an upload session with `POST {uri_prefix}/init` without sending any data.
+    /// With `encrypted`, chunks are encrypted with the client's key the way response
+    /// envelopes are.
+    pub async fn start_upload(
+        &self,
+        uri_prefix: &str,
+        len: u64,
+        chunk_size: u64,
+        encrypted: bool,
+    ) -> Result<UploadSession, ApiClientError> {
+        if chunk_size == 0 {
+            return Err(ApiClientError::InvalidConfig(
+                "chunk_size must be at least 1 byte".to_string(),
+            ));
+        }
+        let body = json!({ "size": len, "chunk_size": chunk_size, "encrypted": encrypted });
+        let initialized: Initialized = self
+            .send_as(Method::POST, &format!("{}/init", uri_prefix), Some(body))
+            .await?;
+        let upload_id = match initialized.upload_id {
+            Value::String(id) if !id.is_empty() => id,
+            Value::Number(id) => id.to_string(),
+            other => {
+                return Err(ApiClientError::InvalidEnvelope(format!(
+                    "upload init returned no usable upload_id: {}",
+                    other
+                )))
+            }
+        };
+        Ok(UploadSession {
+            uri_prefix: uri_prefix.to_string(),
+            upload_id,
+            len,
+            chunk_size,
+            encrypted,
+            checksums: Vec::new(),
+        })
+    }
+
+    /// Sends the chunks `session` has not had acknowledged, reading `reader` from the start
+    /// of the file, then completes the upload. Fails with `UploadFailed` when an already
+    /// uploaded chunk reads differently than it did, or `reader` ends early.
+    pub async fn resume_upload(
+        &self,
+        mut session: UploadSession,
+        mut reader: impl AsyncRead + Unpin,
+        mut progress: impl FnMut(&UploadSession),
+    ) -> Result<String, ApiClientError> {
+        let mut buf = vec![0; session.chunk_size.min(session.len) as usize];
+        for index in 0..session.chunk_count() {
+            let chunk = &mut buf[..session.chunk_len(index)];
+            if let Err(e) = reader.read_exact(chunk).await {
+                return Err(session.failed(Some(index), format!("cannot read the chunk: {}", e)));
+            }
+            let checksum = hex::encode(Sha1::digest(&*chunk));
+            match session.checksums.get(index as usize) {
+                Some(acknowledged) if *acknowledged == checksum => continue,
+                Some(_) => {
+                    let reason = "the file differs from the one the session uploaded".to_string();
+                    return Err(session.failed(Some(index), reason));
+                }
+                None => {}
+            }
+            self.upload_chunk(&session, index, chunk, &checksum).await?;
+            session.checksums.push(checksum);
+            progress(&session);
+        }
+
+        let body = json!({
+            "upload_id": session.upload_id,
+            "size": session.len,
+            "checksums": session.checksums,
+        });
+        self.send(Method::POST, &format!("{}/complete", session.uri_prefix), Some(body))
+            .await
+    }
+
+    /// Sends one chunk, retrying it alone when the attempt fails with a retryable error or
+    /// the server acknowledges a different checksum.
+    async fn upload_chunk(
+        &self,
+        session: &UploadSession,
+        index: u64,
+        chunk: &[u8],
+        checksum: &str,
+    ) -> Result<(), ApiClientError> {
+        let data = match session.encrypted {
+            true => self.crypto.encrypt_hex(chunk),
+            false => hex::encode(chunk),
+        };
+        let body = json!({
+            "upload_id": session.upload_id,
+            "index": index,
+            "offset": index * session.chunk_size,
+            "encrypted": session.encrypted,
+            "data": data,
+        });
+        let uri = format!("{}/chunk", session.uri_prefix);
+        let mut retry = 0;
+        loop {
+            let error = match self.send_as::<ChunkAck>(Method::POST, &uri, Some(body.clone())).await {
+                Ok(ack) if ack.sha1.eq_ignore_ascii_case(checksum) => return Ok(()),
+                Ok(ack) => session.failed(
+                    Some(index),
+                    format!("the server acknowledged sha1 {}, expected {}", ack.sha1, checksum),
+                ),
+                Err(e) if e.is_retryable() => e,
+                Err(e) => return Err(e),
+            };
+            if retry >= CHUNK_RETRY.max_retries {
+                return Err(error);
+            }
+            retry += 1;
+            tracing::debug!(upload_id = %session.upload_id, index, retry, error = ?error, "retrying upload chunk");
+            self.config.time.sleep(CHUNK_RETRY.backoff(retry)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::testing::{mock_client, test_crypto, MockResponse, MockTransport};
+
+    const FILE: &[u8] = b"0123456789abcdefghij";
+
+    fn ack(chunk: &[u8]) -> MockResponse {
+        MockResponse::encrypted(json!({ "sha1": hex::encode(Sha1::digest(chunk)) }).to_string())
+    }
+
+    fn sent(transport: &MockTransport) -> Vec<Value> {
+        transport
+            .requests()
+            .into_iter()
+            .map(|request| {
+                let envelope: Value = serde_json::from_slice(&request.body).unwrap();
+                serde_json::from_str(envelope["data"].as_str().unwrap()).unwrap()
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_chunk_is_retried_alone() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::POST,
+                "/v1/asset/upload/init",
+                MockResponse::encrypted(r#"{"upload_id":"u-1"}"#),
+            )
+            .on(Method::POST, "/v1/asset/upload/chunk", ack(&FILE[..8]))
+            .on(
+                Method::POST,
+                "/v1/asset/upload/chunk",
+                MockResponse::status(StatusCode::SERVICE_UNAVAILABLE),
+            )
+            .on(Method::POST, "/v1/asset/upload/chunk", ack(&FILE[8..16]))
+            .on(Method::POST, "/v1/asset/upload/chunk", ack(&FILE[16..]))
+            .on(
+                Method::POST,
+                "/v1/asset/upload/complete",
+                MockResponse::encrypted(r#"{"asset_id":"a-9"}"#),
+            );
+        let client = mock_client(&transport, |_| {});
+
+        let started = Instant::now();
+        let mut uploaded = Vec::new();
+        let asset = client
+            .upload_chunked("/v1/asset/upload", FILE, FILE.len() as u64, 8, |session| {
+                uploaded.push(session.uploaded_bytes())
+            })
+            .await
+            .unwrap();
+        assert_eq!(asset, r#"{"asset_id":"a-9"}"#);
+        assert_eq!(uploaded, [8, 16, 20]);
+        assert_eq!(started.elapsed(), Duration::from_millis(500));
+
+        let bodies = sent(&transport);
+        assert_eq!(bodies[0], json!({ "size": 20, "chunk_size": 8, "encrypted": false }));
+        let indices: Vec<&Value> = bodies[1..5].iter().map(|body| &body["index"]).collect();
+        assert_eq!(indices, [0, 1, 1, 2]);
+        assert_eq!(bodies[4]["offset"], 16);
+        assert_eq!(bodies[4]["data"], hex::encode(&FILE[16..]));
+        let checksums: Vec<String> = [&FILE[..8], &FILE[8..16], &FILE[16..]]
+            .iter()
+            .map(|chunk| hex::encode(Sha1::digest(chunk)))
+            .collect();
+        assert_eq!(
+            bodies[5],
+            json!({ "upload_id": "u-1", "size": 20, "checksums": checksums })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_resumes_after_a_restart() {
+        let transport = MockTransport::new();
+        transport
+            .on(
+                Method::POST,
+                "/v1/asset/upload/init",
+                MockResponse::encrypted(r#"{"upload_id":7}"#),
+            )
+            .on(Method::POST, "/v1/asset/upload/chunk", ack(&FILE[..8]))
+            .on(
+                Method::POST,
+                "/v1/asset/upload/chunk",
+                MockResponse::status(StatusCode::BAD_REQUEST),
+            );
+        let client = mock_client(&transport, |_| {});
+
+        let session = client.start_upload("/v1/asset/upload", 20, 8, true).await.unwrap();
+        let mut saved = String::new();
+        let interrupted = client
+            .resume_upload(session, FILE, |session| saved = session.to_json())
+            .await;
+        assert!(matches!(interrupted, Err(ApiClientError::HttpStatus { .. })));
+        let session = UploadSession::from_json(&saved).unwrap();
+        assert_eq!((session.upload_id.as_str(), session.uploaded_bytes()), ("7", 8));
+        let chunk = &sent(&transport)[1];
+        assert_eq!(
+            test_crypto()
+                .decrypt_hex(chunk["data"].as_str().unwrap().as_bytes())
+                .unwrap(),
+            "01234567"
+        );
+
+        let other_file = b"x123456789abcdefghij";
+        match client.resume_upload(session.clone(), &other_file[..], |_| {}).await {
+            Err(ApiClientError::UploadFailed { upload_id, chunk, .. }) => {
+                assert_eq!((upload_id.as_str(), chunk), ("7", Some(0)));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let transport = MockTransport::new();
+        transport
+            .on(Method::POST, "/v1/asset/upload/chunk", ack(&FILE[8..16]))
+            .on(Method::POST, "/v1/asset/upload/chunk", ack(&FILE[16..]))
+            .on(
+                Method::POST,
+                "/v1/asset/upload/complete",
+                MockResponse::encrypted(r#"{"asset_id":"a-7"}"#),
+            );
+        let restarted = mock_client(&transport, |_| {});
+        let asset = restarted.resume_upload(session, FILE, |_| {}).await.unwrap();
+        assert_eq!(asset, r#"{"asset_id":"a-7"}"#);
+        let indices: Vec<Value> = sent(&transport)[..2].iter().map(|body| body["index"].clone()).collect();
+        assert_eq!(indices, [1, 2]);
+    }
+}
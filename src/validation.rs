@@ -0,0 +1,194 @@
+//! Client-side checks for requests the server is known to reject, run before signing so they
+//! fail without a round trip.
+
+use serde_json::Value;
+
+use crate::types::Region;
+use crate::ApiClientError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+    /// `page_size` may not exceed this.
+    MaxPageSize(u64),
+    /// The list in `field`, a body array or a comma-separated query value, may not be longer.
+    MaxBatchLen { field: String, max: usize },
+    /// `region` must be one of these.
+    AllowedRegions(Vec<Region>),
+    /// The parameter must be present in the query string or the body.
+    Required(String),
+}
+
+/// Constraints by endpoint path. A path ending in `*` matches every path with that prefix,
+/// so `"*"` applies to all requests.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationRegistry {
+    entries: Vec<(String, Constraint)>,
+}
+
+impl ValidationRegistry {
+    /// A registry with no constraints.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The constraints this SDK knows the server enforces.
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+        registry
+            .register("*", Constraint::MaxPageSize(200))
+            .register("/v1/lol/champion/skin", Constraint::Required("region".to_string()));
+        registry
+    }
+
+    pub fn register(&mut self, path: &str, constraint: Constraint) -> &mut Self {
+        self.entries.push((path.to_string(), constraint));
+        self
+    }
+
+    /// Each registered constraint as `"<path>: <constraint>"`, in registration order.
+    pub(crate) fn describe(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|(path, constraint)| format!("{}: {:?}", path, constraint))
+            .collect()
+    }
+
+    pub(crate) fn validate(&self, uri: &str, body: Option<&Value>) -> Result<(), ApiClientError> {
+        let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+        let query: Vec<(String, String)> = serde_urlencoded::from_str(query).unwrap_or_default();
+        let params = Params { query, body };
+        self.entries
+            .iter()
+            .filter(|(pattern, _)| matches_path(pattern, path))
+            .try_for_each(|(_, constraint)| check(constraint, &params))
+    }
+}
+
+struct Params<'a> {
+    query: Vec<(String, String)>,
+    body: Option<&'a Value>,
+}
+
+impl Params<'_> {
+    /// The parameter as text, from the query string first and then the top level of the body.
+    fn get(&self, name: &str) -> Option<String> {
+        let from_query = self.query.iter().find(|(key, _)| key == name).map(|(_, v)| v.clone());
+        from_query.or_else(|| match self.body?.get(name)? {
+            Value::String(s) => Some(s.clone()),
+            Value::Null => None,
+            other => Some(other.to_string()),
+        })
+    }
+
+    fn list_len(&self, name: &str) -> Option<usize> {
+        if let Some(Value::Array(items)) = self.body.and_then(|body| body.get(name)) {
+            return Some(items.len());
+        }
+        let value = self.query.iter().find(|(key, _)| key == name)?.1.as_str();
+        Some(value.split(',').filter(|item| !item.is_empty()).count())
+    }
+}
+
+pub(crate) fn matches_path(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => path.starts_with(prefix),
+        None => path == pattern,
+    }
+}
+
+fn check(constraint: &Constraint, params: &Params<'_>) -> Result<(), ApiClientError> {
+    let violation = |field: &str, constraint: String, got: String| {
+        Err(ApiClientError::RequestValidation {
+            field: field.to_string(),
+            constraint,
+            got,
+        })
+    };
+    match constraint {
+        Constraint::MaxPageSize(max) => match params.get("page_size") {
+            Some(got) if got.parse::<u64>().is_ok_and(|size| size > *max) => {
+                violation("page_size", format!("at most {}", max), got)
+            }
+            _ => Ok(()),
+        },
+        Constraint::MaxBatchLen { field, max } => match params.list_len(field) {
+            Some(len) if len > *max => violation(field, format!("at most {} entries", max), len.to_string()),
+            _ => Ok(()),
+        },
+        Constraint::AllowedRegions(regions) => match params.get("region") {
+            Some(got) if !regions.iter().any(|r| r.as_str() == got) => {
+                let allowed: Vec<&str> = regions.iter().map(|r| r.as_str()).collect();
+                violation("region", format!("one of {}", allowed.join(", ")), got)
+            }
+            _ => Ok(()),
+        },
+        Constraint::Required(field) => match params.get(field) {
+            Some(_) => Ok(()),
+            None => violation(field, "required".to_string(), "nothing".to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn violation(result: Result<(), ApiClientError>) -> (String, String, String) {
+        match result {
+            Err(ApiClientError::RequestValidation { field, constraint, got }) => (field, constraint, got),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_builtin_constraints() {
+        let registry = ValidationRegistry::builtin();
+        assert!(registry
+            .validate("/v1/lol/champion/skin?region=cn&page_size=200", None)
+            .is_ok());
+        assert_eq!(
+            violation(registry.validate("/v1/lol/champion/skin?region=cn&page_size=500", None)),
+            ("page_size".to_string(), "at most 200".to_string(), "500".to_string())
+        );
+        assert_eq!(
+            violation(registry.validate("/v1/lol/champion/skin", Some(&json!({ "key": "value" })))),
+            ("region".to_string(), "required".to_string(), "nothing".to_string())
+        );
+        assert!(registry
+            .validate("/v1/lol/champion/skin", Some(&json!({ "region": "cn" })))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_custom_batch_and_region_constraints() {
+        let mut registry = ValidationRegistry::new();
+        registry
+            .register(
+                "/v1/lol/item/*",
+                Constraint::MaxBatchLen {
+                    field: "ids".to_string(),
+                    max: 50,
+                },
+            )
+            .register(
+                "/v1/lol/item/*",
+                Constraint::AllowedRegions(vec![Region::Cn, Region::Tw]),
+            );
+
+        let ids: Vec<u64> = (0..51).collect();
+        assert_eq!(
+            violation(registry.validate("/v1/lol/item/batch", Some(&json!({ "ids": ids })))),
+            ("ids".to_string(), "at most 50 entries".to_string(), "51".to_string())
+        );
+        assert!(registry
+            .validate("/v1/lol/item/batch?ids=1,2,3&region=tw", None)
+            .is_ok());
+        assert_eq!(
+            violation(registry.validate("/v1/lol/item/batch?region=kr", None)).1,
+            "one of cn, tw"
+        );
+        assert!(registry.validate("/v1/lol/other?region=kr", None).is_ok());
+    }
+}
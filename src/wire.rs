@@ -0,0 +1,856 @@
+//! A stable JSON form of `ApiClientError` for passing failures on to other services. The
+//! schema is versioned by `ErrorWire::version`: fields and kinds may be added within a
+//! version, but none are renamed or removed.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::charset::Charset;
+use crate::ApiClientError;
+
+pub const WIRE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Transport,
+    ConnectionClosed,
+    AttemptTimeout,
+    OverallTimeout,
+    QueueTimeout,
+    QuotaReserved,
+    NonceReuse,
+    HttpStatus,
+    RedirectRefused,
+    Maintenance,
+    Serialization,
+    Decryption,
+    BothKeysFailed,
+    Utf8,
+    Charset,
+    Hex,
+    CiphertextLength,
+    Config,
+    TruncatedStream,
+    InvalidQuery,
+    InvalidUri,
+    RequestValidation,
+    RequestTooLarge,
+    SuspectedDoubleEncoding,
+    InvalidEnvelope,
+    InvalidPayload,
+    Truncated,
+    ResponseProcessing,
+    PaginationLoop,
+    BatchFailed,
+    JobFailed,
+    UploadFailed,
+    InvalidArchive,
+    ExportWrite,
+    SignatureRejected,
+    JobTimeout,
+    ChainStepFailed,
+    /// A kind from a newer schema, or an error this version cannot name.
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorWire {
+    pub version: u32,
+    pub kind: ErrorKind,
+    pub retryable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub business_code: Option<i64>,
+    pub message: String,
+    /// Kind-specific fields, e.g. `attempts` for timeouts or `path` for payload errors.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub detail: BTreeMap<String, Value>,
+}
+
+impl ApiClientError {
+    pub fn to_wire(&self) -> ErrorWire {
+        let (kind, message, detail) = describe(self);
+        #[cfg(feature = "client")]
+        let http_status = self.status().map(|status| status.as_u16());
+        #[cfg(not(feature = "client"))]
+        let http_status = None;
+        ErrorWire {
+            version: WIRE_VERSION,
+            kind,
+            retryable: self.is_retryable(),
+            http_status,
+            business_code: self.business_code(),
+            message,
+            detail: match detail {
+                Value::Object(map) => map.into_iter().collect(),
+                _ => BTreeMap::new(),
+            },
+        }
+    }
+}
+
+pub(crate) fn describe(err: &ApiClientError) -> (ErrorKind, String, Value) {
+    use ApiClientError as E;
+    let none = || Value::Null;
+    match err {
+        #[cfg(feature = "client")]
+        E::ReqwestError(e) => (ErrorKind::Transport, e.to_string(), none()),
+        #[cfg(feature = "client")]
+        E::ConnectionClosed { connection, source } => (
+            ErrorKind::ConnectionClosed,
+            source.to_string(),
+            json!({ "connection": connection.as_str() }),
+        ),
+        #[cfg(feature = "client")]
+        E::AttemptTimeout { attempts } => (
+            ErrorKind::AttemptTimeout,
+            format!("attempt timed out after {} attempts", attempts),
+            json!({ "attempts": attempts }),
+        ),
+        #[cfg(feature = "client")]
+        E::OverallTimeout { attempts, elapsed } => (
+            ErrorKind::OverallTimeout,
+            format!(
+                "request timed out after {} ms and {} attempts",
+                elapsed.as_millis(),
+                attempts
+            ),
+            json!({ "attempts": attempts, "elapsed_ms": elapsed.as_millis() as u64 }),
+        ),
+        #[cfg(feature = "client")]
+        E::QueueTimeout { waited } => (
+            ErrorKind::QueueTimeout,
+            format!(
+                "gave up after waiting {} ms for a concurrency permit",
+                waited.as_millis()
+            ),
+            json!({ "waited_ms": waited.as_millis() as u64 }),
+        ),
+        #[cfg(feature = "client")]
+        E::NonceReuse { nonce } => (
+            ErrorKind::NonceReuse,
+            format!("nonce {} was issued already", nonce),
+            json!({ "nonce": nonce }),
+        ),
+        #[cfg(feature = "client")]
+        E::QuotaReserved { remaining, reserve } => (
+            ErrorKind::QuotaReserved,
+            format!(
+                "held back with {} requests of quota left, below the reserve of {}",
+                remaining, reserve
+            ),
+            json!({ "remaining": remaining, "reserve": reserve }),
+        ),
+        #[cfg(feature = "client")]
+        E::HttpStatus { status, body } => (
+            ErrorKind::HttpStatus,
+            format!("server answered {}", status),
+            json!({ "body": body }),
+        ),
+        #[cfg(feature = "client")]
+        E::RedirectRefused {
+            status,
+            location,
+            reason,
+        } => (
+            ErrorKind::RedirectRefused,
+            format!(
+                "did not follow {} redirect to {}: {}",
+                status.as_u16(),
+                location,
+                reason.as_str()
+            ),
+            json!({ "location": location, "reason": reason.as_str() }),
+        ),
+        #[cfg(feature = "client")]
+        E::Maintenance { notice } => (
+            ErrorKind::Maintenance,
+            format!("the gateway is under maintenance until {}", notice.ends_at),
+            json!({ "notice": notice }),
+        ),
+        #[cfg(feature = "client")]
+        E::JobFailed { job_id, state, detail } => (
+            ErrorKind::JobFailed,
+            format!("job {} ended {}", job_id, state.as_str()),
+            json!({ "job_id": job_id, "state": state.as_str(), "job_error": detail }),
+        ),
+        #[cfg(feature = "client")]
+        E::JobTimeout { job_id, elapsed } => (
+            ErrorKind::JobTimeout,
+            format!("job {} still unfinished after {} ms", job_id, elapsed.as_millis()),
+            json!({ "job_id": job_id, "elapsed_ms": elapsed.as_millis() as u64 }),
+        ),
+        #[cfg(feature = "client")]
+        E::SignatureRejected {
+            status,
+            reason,
+            components,
+            timestamp,
+            body_sha1,
+            body,
+        } => (
+            ErrorKind::SignatureRejected,
+            format!("server rejected the signature ({}): {}", status.as_u16(), reason.code()),
+            json!({
+                "reason": reason.code(),
+                "components": components,
+                "timestamp": timestamp,
+                "body_sha1": body_sha1,
+                "body": body,
+            }),
+        ),
+        #[cfg(feature = "client")]
+        E::ExportWrite(e) => (
+            ErrorKind::ExportWrite,
+            format!("cannot write the export: {}", e),
+            none(),
+        ),
+        #[cfg(feature = "client")]
+        E::UploadFailed {
+            upload_id,
+            chunk,
+            reason,
+        } => (
+            ErrorKind::UploadFailed,
+            format!("upload {} failed: {}", upload_id, reason),
+            json!({ "upload_id": upload_id, "chunk": chunk, "reason": reason }),
+        ),
+        #[cfg(feature = "client")]
+        E::InvalidArchive { entry, line, reason } => (
+            ErrorKind::InvalidArchive,
+            match (entry, line) {
+                (Some(entry), Some(line)) => format!("archive entry {} line {}: {}", entry, line, reason),
+                (Some(entry), None) => format!("archive entry {}: {}", entry, reason),
+                (None, _) => format!("invalid archive: {}", reason),
+            },
+            json!({ "entry": entry, "line": line, "reason": reason }),
+        ),
+        #[cfg(feature = "client")]
+        E::ChainStepFailed { step, uri, source } => (
+            ErrorKind::ChainStepFailed,
+            format!("step {} ({}) failed", step, uri),
+            json!({ "step": step, "uri": uri, "source": source.to_wire() }),
+        ),
+        E::SerdeJsonError(e) => (ErrorKind::Serialization, e.to_string(), none()),
+        E::AesError(e) => (ErrorKind::Decryption, format!("decryption failed: {}", e), none()),
+        E::BothKeysFailed { primary, secondary } => (
+            ErrorKind::BothKeysFailed,
+            "decryption failed with the primary and the secondary key".to_string(),
+            json!({ "primary": primary.to_wire(), "secondary": secondary.to_wire() }),
+        ),
+        E::Utf8Error(e) => (
+            ErrorKind::Utf8,
+            e.to_string(),
+            json!({ "offset": e.utf8_error().valid_up_to() }),
+        ),
+        E::InvalidCharset { charset, offset } => (
+            ErrorKind::Charset,
+            format!("invalid {} at byte {}", charset, offset),
+            json!({ "charset": charset.to_string(), "offset": offset }),
+        ),
+        E::HexError(e) => (ErrorKind::Hex, e.to_string(), none()),
+        E::InvalidHex(e) => (ErrorKind::Hex, e.to_string(), json!({ "len": e.len })),
+        E::InvalidCiphertextLength { len } => (
+            ErrorKind::CiphertextLength,
+            format!("ciphertext of {} bytes is not a whole number of AES blocks", len),
+            json!({ "len": len }),
+        ),
+        E::InvalidConfig(message) => (ErrorKind::Config, message.clone(), none()),
+        E::TruncatedStream(len) => (
+            ErrorKind::TruncatedStream,
+            format!("stream ended inside a {} byte line", len),
+            json!({ "len": len }),
+        ),
+        E::InvalidQuery(message) => (ErrorKind::InvalidQuery, message.clone(), none()),
+        E::InvalidUri { uri, reason } => (
+            ErrorKind::InvalidUri,
+            format!("invalid uri {:?}: {}", uri, reason),
+            json!({ "uri": uri, "reason": reason }),
+        ),
+        E::RequestValidation { field, constraint, got } => (
+            ErrorKind::RequestValidation,
+            format!("{} must be {}, got {}", field, constraint, got),
+            json!({ "field": field, "constraint": constraint, "got": got }),
+        ),
+        E::RequestTooLarge { limit } => (
+            ErrorKind::RequestTooLarge,
+            format!("request body exceeds {} bytes", limit),
+            json!({ "limit": limit }),
+        ),
+        E::SuspectedDoubleEncoding { kind, preview } => (
+            ErrorKind::SuspectedDoubleEncoding,
+            format!("the request body is a string holding a JSON {}", kind),
+            json!({ "kind": kind, "preview": preview }),
+        ),
+        E::InvalidEnvelope(message) => (ErrorKind::InvalidEnvelope, message.clone(), none()),
+        E::InvalidPayload { path, message } => (
+            ErrorKind::InvalidPayload,
+            format!("{}: {}", path, message),
+            json!({ "path": path, "message": message }),
+        ),
+        E::Truncated { uri, body } => (
+            ErrorKind::Truncated,
+            format!("the server cut the response to {} short", uri),
+            json!({ "uri": uri, "body": body }),
+        ),
+        E::ResponseProcessing { uri, stage, reason } => (
+            ErrorKind::ResponseProcessing,
+            format!("response processor {} rejected {}: {}", stage, uri, reason),
+            json!({ "uri": uri, "stage": stage, "reason": reason }),
+        ),
+        E::PaginationLoop { cursor, repeats } => (
+            ErrorKind::PaginationLoop,
+            format!("cursor {:?} repeated {} times", cursor, repeats),
+            json!({ "cursor": cursor, "repeats": repeats }),
+        ),
+        E::BatchFailed { failed } => (
+            ErrorKind::BatchFailed,
+            format!("all {} batch items failed", failed.len()),
+            json!({ "failed": failed }),
+        ),
+    }
+}
+
+impl ErrorWire {
+    /// Rebuilds the error where the wire form holds everything needed. Errors that wrap
+    /// another library's error (transport, connection, JSON, UTF-8, hex), composite errors and unknown
+    /// kinds give `None`.
+    pub fn try_into_error(&self) -> Option<ApiClientError> {
+        use ApiClientError as E;
+        let text = |key: &str| self.detail.get(key)?.as_str().map(str::to_string);
+        let number = |key: &str| self.detail.get(key)?.as_u64();
+        #[cfg(feature = "client")]
+        let millis = |key: &str| number(key).map(std::time::Duration::from_millis);
+        Some(match self.kind {
+            #[cfg(feature = "client")]
+            ErrorKind::AttemptTimeout => E::AttemptTimeout {
+                attempts: number("attempts")? as u32,
+            },
+            #[cfg(feature = "client")]
+            ErrorKind::OverallTimeout => E::OverallTimeout {
+                attempts: number("attempts")? as u32,
+                elapsed: millis("elapsed_ms")?,
+            },
+            #[cfg(feature = "client")]
+            ErrorKind::QueueTimeout => E::QueueTimeout {
+                waited: millis("waited_ms")?,
+            },
+            #[cfg(feature = "client")]
+            ErrorKind::NonceReuse => E::NonceReuse { nonce: text("nonce")? },
+            #[cfg(feature = "client")]
+            ErrorKind::QuotaReserved => E::QuotaReserved {
+                remaining: number("remaining")?,
+                reserve: number("reserve")?,
+            },
+            #[cfg(feature = "client")]
+            ErrorKind::HttpStatus => E::HttpStatus {
+                status: reqwest::StatusCode::from_u16(self.http_status?).ok()?,
+                body: text("body").unwrap_or_default(),
+            },
+            #[cfg(feature = "client")]
+            ErrorKind::RedirectRefused => E::RedirectRefused {
+                status: reqwest::StatusCode::from_u16(self.http_status?).ok()?,
+                location: text("location")?,
+                reason: crate::transport::RedirectRefusal::parse(&text("reason")?)?,
+            },
+            #[cfg(feature = "client")]
+            ErrorKind::Maintenance => E::Maintenance {
+                notice: serde_json::from_value(self.detail.get("notice")?.clone()).ok()?,
+            },
+            #[cfg(feature = "client")]
+            ErrorKind::JobFailed => E::JobFailed {
+                job_id: text("job_id")?,
+                state: crate::jobs::JobState::parse(&text("state")?),
+                detail: text("job_error"),
+            },
+            #[cfg(feature = "client")]
+            ErrorKind::JobTimeout => E::JobTimeout {
+                job_id: text("job_id")?,
+                elapsed: millis("elapsed_ms")?,
+            },
+            #[cfg(feature = "client")]
+            ErrorKind::SignatureRejected => E::SignatureRejected {
+                status: reqwest::StatusCode::from_u16(self.http_status?).ok()?,
+                reason: crate::triage::SignatureRejectionReason::parse(&text("reason")?),
+                components: serde_json::from_value(self.detail.get("components")?.clone()).ok()?,
+                timestamp: self.detail.get("timestamp")?.as_i64()?,
+                body_sha1: text("body_sha1"),
+                body: text("body").unwrap_or_default(),
+            },
+            #[cfg(feature = "client")]
+            ErrorKind::UploadFailed => E::UploadFailed {
+                upload_id: text("upload_id")?,
+                chunk: self.detail.get("chunk").and_then(Value::as_u64),
+                reason: text("reason")?,
+            },
+            #[cfg(feature = "client")]
+            ErrorKind::InvalidArchive => E::InvalidArchive {
+                entry: text("entry"),
+                line: self
+                    .detail
+                    .get("line")
+                    .and_then(Value::as_u64)
+                    .map(|line| line as usize),
+                reason: text("reason")?,
+            },
+            ErrorKind::Decryption => E::AesError(block_modes::BlockModeError),
+            ErrorKind::Charset => E::InvalidCharset {
+                charset: parse_charset(&text("charset")?)?,
+                offset: number("offset")? as usize,
+            },
+            ErrorKind::CiphertextLength => E::InvalidCiphertextLength {
+                len: number("len")? as usize,
+            },
+            ErrorKind::Config => E::InvalidConfig(self.message.clone()),
+            ErrorKind::TruncatedStream => E::TruncatedStream(number("len")? as usize),
+            ErrorKind::InvalidQuery => E::InvalidQuery(self.message.clone()),
+            ErrorKind::InvalidUri => E::InvalidUri {
+                uri: text("uri")?,
+                reason: text("reason")?,
+            },
+            ErrorKind::RequestValidation => E::RequestValidation {
+                field: text("field")?,
+                constraint: text("constraint")?,
+                got: text("got")?,
+            },
+            ErrorKind::RequestTooLarge => E::RequestTooLarge {
+                limit: number("limit")? as usize,
+            },
+            ErrorKind::SuspectedDoubleEncoding => E::SuspectedDoubleEncoding {
+                kind: text("kind")?,
+                preview: text("preview")?,
+            },
+            ErrorKind::InvalidEnvelope => E::InvalidEnvelope(self.message.clone()),
+            ErrorKind::InvalidPayload => E::InvalidPayload {
+                path: text("path")?,
+                message: text("message")?,
+            },
+            ErrorKind::Truncated => E::Truncated {
+                uri: text("uri")?,
+                body: text("body")?,
+            },
+            ErrorKind::ResponseProcessing => E::ResponseProcessing {
+                uri: text("uri")?,
+                stage: number("stage")? as usize,
+                reason: text("reason")?,
+            },
+            ErrorKind::PaginationLoop => E::PaginationLoop {
+                cursor: text("cursor")?,
+                repeats: number("repeats")? as usize,
+            },
+            ErrorKind::BatchFailed => E::BatchFailed {
+                failed: serde_json::from_value(self.detail.get("failed")?.clone()).ok()?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+fn parse_charset(name: &str) -> Option<Charset> {
+    let charsets = [
+        Charset::Utf8,
+        #[cfg(feature = "charset")]
+        Charset::Gbk,
+        #[cfg(feature = "charset")]
+        Charset::Auto,
+    ];
+    charsets.into_iter().find(|charset| charset.to_string() == name)
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::jobs::JobState;
+    use crate::maintenance::MaintenanceNotice;
+    use crate::payload::BatchItemError;
+    use crate::transport::RedirectRefusal;
+    use crate::triage::{SignComponents, SignatureRejectionReason};
+
+    fn snapshot(err: &ApiClientError) -> String {
+        serde_json::to_string(&err.to_wire()).unwrap()
+    }
+
+    fn hex_payload_error() -> ApiClientError {
+        let crypto = crate::testing::test_crypto();
+        crypto.decrypt_hex(b"abcd ef").unwrap_err()
+    }
+
+    fn maintenance_notice() -> MaintenanceNotice {
+        serde_json::from_value(json!({
+            "start": "2024-06-01 02:00:00",
+            "end": "2024-06-01 04:00:00",
+            "affected_paths": ["/v1/mall"],
+            "message": "mall upgrade",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_serialized_form_of_every_kind() {
+        let builder = reqwest::Client::new().get("not a url").build().unwrap_err();
+        let cases: Vec<(ApiClientError, &str)> = vec![
+            (
+                ApiClientError::ReqwestError(builder),
+                r#"{"version":1,"kind":"transport","retryable":false,"message":"builder error"}"#,
+            ),
+            (
+                ApiClientError::AttemptTimeout { attempts: 3 },
+                r#"{"version":1,"kind":"attempt_timeout","retryable":true,"message":"attempt timed out after 3 attempts","detail":{"attempts":3}}"#,
+            ),
+            (
+                ApiClientError::OverallTimeout {
+                    attempts: 2,
+                    elapsed: Duration::from_secs(6),
+                },
+                r#"{"version":1,"kind":"overall_timeout","retryable":true,"message":"request timed out after 6000 ms and 2 attempts","detail":{"attempts":2,"elapsed_ms":6000}}"#,
+            ),
+            (
+                ApiClientError::QueueTimeout {
+                    waited: Duration::from_millis(2500),
+                },
+                r#"{"version":1,"kind":"queue_timeout","retryable":false,"message":"gave up after waiting 2500 ms for a concurrency permit","detail":{"waited_ms":2500}}"#,
+            ),
+            (
+                ApiClientError::NonceReuse {
+                    nonce: "n-1".to_string(),
+                },
+                r#"{"version":1,"kind":"nonce_reuse","retryable":false,"message":"nonce n-1 was issued already","detail":{"nonce":"n-1"}}"#,
+            ),
+            (
+                ApiClientError::QuotaReserved {
+                    remaining: 40,
+                    reserve: 100,
+                },
+                r#"{"version":1,"kind":"quota_reserved","retryable":false,"message":"held back with 40 requests of quota left, below the reserve of 100","detail":{"remaining":40,"reserve":100}}"#,
+            ),
+            (
+                ApiClientError::HttpStatus {
+                    status: StatusCode::UNAUTHORIZED,
+                    body: r#"{"code":40102}"#.to_string(),
+                },
+                r#"{"version":1,"kind":"http_status","retryable":false,"http_status":401,"business_code":40102,"message":"server answered 401 Unauthorized","detail":{"body":"{\"code\":40102}"}}"#,
+            ),
+            (
+                ApiClientError::RedirectRefused {
+                    status: StatusCode::FOUND,
+                    location: "https://other.example.test/v1/x".to_string(),
+                    reason: RedirectRefusal::CrossHost,
+                },
+                r#"{"version":1,"kind":"redirect_refused","retryable":false,"http_status":302,"message":"did not follow 302 redirect to https://other.example.test/v1/x: cross_host","detail":{"location":"https://other.example.test/v1/x","reason":"cross_host"}}"#,
+            ),
+            (
+                ApiClientError::Maintenance {
+                    notice: maintenance_notice(),
+                },
+                r#"{"version":1,"kind":"maintenance","retryable":false,"message":"the gateway is under maintenance until 2024-06-01 04:00:00 +08:00","detail":{"notice":{"affected_paths":["/v1/mall"],"end":"2024-06-01 04:00:00","message":"mall upgrade","start":"2024-06-01 02:00:00"}}}"#,
+            ),
+            (
+                ApiClientError::SerdeJsonError(serde_json::from_str::<Value>("{").unwrap_err()),
+                r#"{"version":1,"kind":"serialization","retryable":false,"message":"EOF while parsing an object at line 1 column 1"}"#,
+            ),
+            (
+                ApiClientError::ExportWrite(std::io::Error::other("disk full")),
+                r#"{"version":1,"kind":"export_write","retryable":false,"message":"cannot write the export: disk full"}"#,
+            ),
+            (
+                ApiClientError::AesError(block_modes::BlockModeError),
+                r#"{"version":1,"kind":"decryption","retryable":false,"message":"decryption failed: BlockModeError"}"#,
+            ),
+            (
+                ApiClientError::BothKeysFailed {
+                    primary: Box::new(ApiClientError::AesError(block_modes::BlockModeError)),
+                    secondary: Box::new(ApiClientError::TruncatedStream(4)),
+                },
+                r#"{"version":1,"kind":"both_keys_failed","retryable":false,"message":"decryption failed with the primary and the secondary key","detail":{"primary":{"kind":"decryption","message":"decryption failed: BlockModeError","retryable":false,"version":1},"secondary":{"detail":{"len":4},"kind":"truncated_stream","message":"stream ended inside a 4 byte line","retryable":false,"version":1}}}"#,
+            ),
+            (
+                ApiClientError::Utf8Error(String::from_utf8(vec![b'a', 0xff]).unwrap_err()),
+                r#"{"version":1,"kind":"utf8","retryable":false,"message":"invalid utf-8 sequence of 1 bytes from index 1","detail":{"offset":1}}"#,
+            ),
+            (
+                ApiClientError::InvalidCharset {
+                    charset: Charset::Utf8,
+                    offset: 7,
+                },
+                r#"{"version":1,"kind":"charset","retryable":false,"message":"invalid UTF-8 at byte 7","detail":{"charset":"UTF-8","offset":7}}"#,
+            ),
+            (
+                ApiClientError::HexError(hex::FromHexError::OddLength),
+                r#"{"version":1,"kind":"hex","retryable":false,"message":"Odd number of digits"}"#,
+            ),
+            (
+                hex_payload_error(),
+                r#"{"version":1,"kind":"hex","retryable":false,"message":"invalid hex character ' ' at position 4 in payload \"abcd ef\"","detail":{"len":7}}"#,
+            ),
+            (
+                ApiClientError::InvalidCiphertextLength { len: 31 },
+                r#"{"version":1,"kind":"ciphertext_length","retryable":false,"message":"ciphertext of 31 bytes is not a whole number of AES blocks","detail":{"len":31}}"#,
+            ),
+            (
+                ApiClientError::InvalidConfig("AES config error".to_string()),
+                r#"{"version":1,"kind":"config","retryable":false,"message":"AES config error"}"#,
+            ),
+            (
+                ApiClientError::TruncatedStream(12),
+                r#"{"version":1,"kind":"truncated_stream","retryable":false,"message":"stream ended inside a 12 byte line","detail":{"len":12}}"#,
+            ),
+            (
+                ApiClientError::InvalidQuery("nested".to_string()),
+                r#"{"version":1,"kind":"invalid_query","retryable":false,"message":"nested"}"#,
+            ),
+            (
+                ApiClientError::InvalidUri {
+                    uri: "/v1/a#b".to_string(),
+                    reason: "contains a fragment".to_string(),
+                },
+                r#"{"version":1,"kind":"invalid_uri","retryable":false,"message":"invalid uri \"/v1/a#b\": contains a fragment","detail":{"reason":"contains a fragment","uri":"/v1/a#b"}}"#,
+            ),
+            (
+                ApiClientError::RequestValidation {
+                    field: "page_size".to_string(),
+                    constraint: "at most 200".to_string(),
+                    got: "500".to_string(),
+                },
+                r#"{"version":1,"kind":"request_validation","retryable":false,"message":"page_size must be at most 200, got 500","detail":{"constraint":"at most 200","field":"page_size","got":"500"}}"#,
+            ),
+            (
+                ApiClientError::RequestTooLarge { limit: 4096 },
+                r#"{"version":1,"kind":"request_too_large","retryable":false,"message":"request body exceeds 4096 bytes","detail":{"limit":4096}}"#,
+            ),
+            (
+                ApiClientError::SuspectedDoubleEncoding {
+                    kind: "array".to_string(),
+                    preview: "[1,2]".to_string(),
+                },
+                r#"{"version":1,"kind":"suspected_double_encoding","retryable":false,"message":"the request body is a string holding a JSON array","detail":{"kind":"array","preview":"[1,2]"}}"#,
+            ),
+            (
+                ApiClientError::InvalidEnvelope("payload has no `item` field".to_string()),
+                r#"{"version":1,"kind":"invalid_envelope","retryable":false,"message":"payload has no `item` field"}"#,
+            ),
+            (
+                ApiClientError::InvalidPayload {
+                    path: "list[1].id".to_string(),
+                    message: "invalid type".to_string(),
+                },
+                r#"{"version":1,"kind":"invalid_payload","retryable":false,"message":"list[1].id: invalid type","detail":{"message":"invalid type","path":"list[1].id"}}"#,
+            ),
+            (
+                ApiClientError::Truncated {
+                    uri: "/v1/list".to_string(),
+                    body: r#"{"list":[1]}"#.to_string(),
+                },
+                r#"{"version":1,"kind":"truncated","retryable":false,"message":"the server cut the response to /v1/list short","detail":{"body":"{\"list\":[1]}","uri":"/v1/list"}}"#,
+            ),
+            (
+                ApiClientError::ResponseProcessing {
+                    uri: "/v1/skin".to_string(),
+                    stage: 1,
+                    reason: "missing field".to_string(),
+                },
+                r#"{"version":1,"kind":"response_processing","retryable":false,"message":"response processor 1 rejected /v1/skin: missing field","detail":{"reason":"missing field","stage":1,"uri":"/v1/skin"}}"#,
+            ),
+            (
+                ApiClientError::PaginationLoop {
+                    cursor: "c2".to_string(),
+                    repeats: 3,
+                },
+                r#"{"version":1,"kind":"pagination_loop","retryable":false,"message":"cursor \"c2\" repeated 3 times","detail":{"cursor":"c2","repeats":3}}"#,
+            ),
+            (
+                ApiClientError::BatchFailed {
+                    failed: vec![BatchItemError {
+                        id: Some("266".to_string()),
+                        index: 0,
+                        code: "not_found".to_string(),
+                        message: None,
+                    }],
+                },
+                r#"{"version":1,"kind":"batch_failed","retryable":false,"message":"all 1 batch items failed","detail":{"failed":[{"code":"not_found","id":"266","index":0,"message":null}]}}"#,
+            ),
+            (
+                ApiClientError::JobFailed {
+                    job_id: "42".to_string(),
+                    state: JobState::Failed,
+                    detail: Some("quota exceeded".to_string()),
+                },
+                r#"{"version":1,"kind":"job_failed","retryable":false,"message":"job 42 ended failed","detail":{"job_error":"quota exceeded","job_id":"42","state":"failed"}}"#,
+            ),
+            (
+                ApiClientError::JobTimeout {
+                    job_id: "j-2".to_string(),
+                    elapsed: Duration::from_secs(10),
+                },
+                r#"{"version":1,"kind":"job_timeout","retryable":false,"message":"job j-2 still unfinished after 10000 ms","detail":{"elapsed_ms":10000,"job_id":"j-2"}}"#,
+            ),
+            (
+                ApiClientError::SignatureRejected {
+                    status: StatusCode::UNAUTHORIZED,
+                    reason: SignatureRejectionReason::BodyHash,
+                    components: SignComponents {
+                        app_id: 11,
+                        nonce: 36,
+                        timestamp: 13,
+                        uri: 9,
+                        body: 9,
+                    },
+                    timestamp: 1717171717171,
+                    body_sha1: Some("9c1b".to_string()),
+                    body: r#"{"code":40102}"#.to_string(),
+                },
+                r#"{"version":1,"kind":"signature_rejected","retryable":false,"http_status":401,"business_code":40102,"message":"server rejected the signature (401): body_hash","detail":{"body":"{\"code\":40102}","body_sha1":"9c1b","components":{"app_id":11,"body":9,"nonce":36,"timestamp":13,"uri":9},"reason":"body_hash","timestamp":1717171717171}}"#,
+            ),
+            (
+                ApiClientError::UploadFailed {
+                    upload_id: "u-1".to_string(),
+                    chunk: Some(1),
+                    reason: "cannot read the chunk".to_string(),
+                },
+                r#"{"version":1,"kind":"upload_failed","retryable":false,"message":"upload u-1 failed: cannot read the chunk","detail":{"chunk":1,"reason":"cannot read the chunk","upload_id":"u-1"}}"#,
+            ),
+            (
+                ApiClientError::InvalidArchive {
+                    entry: Some("2024-05.ndjson".to_string()),
+                    line: Some(3),
+                    reason: "missing field `win`".to_string(),
+                },
+                r#"{"version":1,"kind":"invalid_archive","retryable":false,"message":"archive entry 2024-05.ndjson line 3: missing field `win`","detail":{"entry":"2024-05.ndjson","line":3,"reason":"missing field `win`"}}"#,
+            ),
+            (
+                ApiClientError::ChainStepFailed {
+                    step: 2,
+                    uri: "/v1/orders/t".to_string(),
+                    source: Box::new(ApiClientError::InvalidQuery("nested".to_string())),
+                },
+                r#"{"version":1,"kind":"chain_step_failed","retryable":false,"message":"step 2 (/v1/orders/t) failed","detail":{"source":{"kind":"invalid_query","message":"nested","retryable":false,"version":1},"step":2,"uri":"/v1/orders/t"}}"#,
+            ),
+        ];
+        for (err, expected) in cases {
+            assert_eq!(snapshot(&err), expected, "{:?}", err);
+            let parsed: ErrorWire = serde_json::from_str(expected).unwrap();
+            assert_eq!(parsed, err.to_wire());
+        }
+    }
+
+    #[test]
+    fn test_reconstruction_is_best_effort() {
+        let rebuilt = [
+            ApiClientError::OverallTimeout {
+                attempts: 2,
+                elapsed: Duration::from_secs(6),
+            },
+            ApiClientError::HttpStatus {
+                status: StatusCode::BAD_GATEWAY,
+                body: "upstream".to_string(),
+            },
+            ApiClientError::RedirectRefused {
+                status: StatusCode::PERMANENT_REDIRECT,
+                location: "https://mock.zelaser.test/v1/c".to_string(),
+                reason: RedirectRefusal::TooManyRedirects,
+            },
+            ApiClientError::Maintenance {
+                notice: maintenance_notice(),
+            },
+            ApiClientError::QuotaReserved {
+                remaining: 0,
+                reserve: 10,
+            },
+            ApiClientError::NonceReuse {
+                nonce: "n-2".to_string(),
+            },
+            ApiClientError::InvalidUri {
+                uri: "../x".to_string(),
+                reason: "does not start with `/`".to_string(),
+            },
+            ApiClientError::RequestValidation {
+                field: "region".to_string(),
+                constraint: "required".to_string(),
+                got: "nothing".to_string(),
+            },
+            ApiClientError::SuspectedDoubleEncoding {
+                kind: "object".to_string(),
+                preview: "{}".to_string(),
+            },
+            ApiClientError::JobFailed {
+                job_id: "7".to_string(),
+                state: JobState::Expired,
+                detail: None,
+            },
+            ApiClientError::InvalidCharset {
+                charset: Charset::Utf8,
+                offset: 3,
+            },
+            ApiClientError::SignatureRejected {
+                status: StatusCode::FORBIDDEN,
+                reason: SignatureRejectionReason::Unknown("app_id_case".to_string()),
+                components: SignComponents::default(),
+                timestamp: 1,
+                body_sha1: None,
+                body: String::new(),
+            },
+            ApiClientError::UploadFailed {
+                upload_id: "u-1".to_string(),
+                chunk: None,
+                reason: "the file differs".to_string(),
+            },
+            ApiClientError::InvalidArchive {
+                entry: None,
+                line: None,
+                reason: "no end of central directory".to_string(),
+            },
+            ApiClientError::Truncated {
+                uri: "/v1/list".to_string(),
+                body: "{}".to_string(),
+            },
+            ApiClientError::ResponseProcessing {
+                uri: "/v1/skin".to_string(),
+                stage: 0,
+                reason: "bom".to_string(),
+            },
+            ApiClientError::BatchFailed {
+                failed: vec![BatchItemError {
+                    id: None,
+                    index: 4,
+                    code: "40401".to_string(),
+                    message: Some("no such champion".to_string()),
+                }],
+            },
+        ];
+        for err in rebuilt {
+            let wire = err.to_wire();
+            let back = wire.try_into_error().unwrap_or_else(|| panic!("{:?} not rebuilt", err));
+            assert_eq!(back.to_wire(), wire);
+        }
+
+        let transport = ApiClientError::SerdeJsonError(serde_json::from_str::<Value>("{").unwrap_err());
+        assert!(transport.to_wire().try_into_error().is_none());
+
+        let future: ErrorWire =
+            serde_json::from_str(r#"{"version":2,"kind":"quantum_flux","retryable":true,"message":"?","extra":1}"#)
+                .unwrap();
+        assert_eq!(future.kind, ErrorKind::Unknown);
+        assert!(future.try_into_error().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_connection_closed_keeps_the_connection_detail() {
+        let err = ApiClientError::ConnectionClosed {
+            connection: crate::transport::ConnectionReuse::MaybePooled,
+            source: crate::testing::connection_reset_error().await,
+        };
+        let wire = err.to_wire();
+        assert_eq!(wire.kind, ErrorKind::ConnectionClosed);
+        assert!(wire.retryable);
+        assert_eq!(wire.detail["connection"], "maybe_pooled");
+        assert!(wire.try_into_error().is_none());
+    }
+}